@@ -0,0 +1,292 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Byte-addressed access over a block driver. Unaligned writes read the edge
+// blocks, patch them and write them back, so callers can treat the device
+// like a file of `size_bytes()` bytes that cannot grow: reads stop at the
+// end and writes past it fail.
+
+use crate::{BlockDriver, DeviceInfo};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// Largest request a read or write is split into, however much the caller
+// passes
+const MAX_REQUEST_BLOCKS: u64 = 64;
+
+pub struct BlockAdapter<D: BlockDriver> {
+    driver: D,
+    position: u64,
+}
+
+impl<D: BlockDriver> BlockAdapter<D> {
+    pub fn new(driver: D) -> Self {
+        BlockAdapter {
+            driver,
+            position: 0,
+        }
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        self.driver.info()
+    }
+
+    pub fn into_inner(self) -> D {
+        self.driver
+    }
+
+    fn block_size(&self) -> u64 {
+        u64::from(self.info().block_size)
+    }
+
+    // Fills as much of `buf` as the device holds from `offset` on. Aligned
+    // blocks go straight into `buf`, at most MAX_REQUEST_BLOCKS per request,
+    // and only the edge blocks pass through a one-block buffer
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.info().size_bytes();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let block_size = self.block_size();
+        let mut edge = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let first = position / block_size;
+            let skip = (position % block_size) as usize;
+            let remaining = len - done;
+            if skip == 0 && remaining as u64 >= block_size {
+                let blocks = (remaining as u64 / block_size).min(MAX_REQUEST_BLOCKS);
+                let span = (blocks * block_size) as usize;
+                self.driver
+                    .read_blocks(first, &mut buf[done..done + span])?;
+                done += span;
+            } else {
+                edge.resize(block_size as usize, 0);
+                self.driver.read_blocks(first, &mut edge)?;
+                let take = (block_size as usize - skip).min(remaining);
+                buf[done..done + take].copy_from_slice(&edge[skip..skip + take]);
+                done += take;
+            }
+        }
+        Ok(len)
+    }
+
+    // Writes all of `buf` at `offset`, or nothing if it would run past the
+    // end. Aligned blocks go straight from `buf`, at most MAX_REQUEST_BLOCKS
+    // per request; a partial head or tail block is read, patched and written
+    // back on its own
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        offset
+            .checked_add(buf.len() as u64)
+            .filter(|end| *end <= self.info().size_bytes())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::WriteZero, "write past the end of the device")
+            })?;
+        let block_size = self.block_size();
+        let mut edge = Vec::new();
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let first = position / block_size;
+            let skip = (position % block_size) as usize;
+            let remaining = buf.len() - done;
+            if skip == 0 && remaining as u64 >= block_size {
+                let blocks = (remaining as u64 / block_size).min(MAX_REQUEST_BLOCKS);
+                let span = (blocks * block_size) as usize;
+                self.driver.write_blocks(first, &buf[done..done + span])?;
+                done += span;
+            } else {
+                edge.resize(block_size as usize, 0);
+                self.driver.read_blocks(first, &mut edge)?;
+                let take = (block_size as usize - skip).min(remaining);
+                edge[skip..skip + take].copy_from_slice(&buf[done..done + take]);
+                self.driver.write_blocks(first, &edge)?;
+                done += take;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDriver> Read for BlockAdapter<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<D: BlockDriver> Write for BlockAdapter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(self.position, buf)?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.driver.flush()
+    }
+}
+
+impl<D: BlockDriver> Seek for BlockAdapter<D> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.info().size_bytes().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts block I/O so tests can see the read-modify-write
+    struct MemDriver {
+        info: DeviceInfo,
+        disk: Vec<u8>,
+        block_reads: usize,
+        largest_read: usize,
+        largest_write: usize,
+    }
+
+    impl BlockDriver for MemDriver {
+        fn info(&self) -> &DeviceInfo {
+            &self.info
+        }
+
+        fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+            crate::check_range(&self.info, first, buf.len())?;
+            let start = (first * u64::from(self.info.block_size)) as usize;
+            buf.copy_from_slice(&self.disk[start..start + buf.len()]);
+            self.block_reads += 1;
+            self.largest_read = self.largest_read.max(buf.len());
+            Ok(())
+        }
+
+        fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+            crate::check_range(&self.info, first, buf.len())?;
+            let start = (first * u64::from(self.info.block_size)) as usize;
+            self.disk[start..start + buf.len()].copy_from_slice(buf);
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn adapter(blocks: u64) -> BlockAdapter<MemDriver> {
+        BlockAdapter::new(MemDriver {
+            info: DeviceInfo {
+                block_size: 512,
+                block_count: blocks,
+                read_only: false,
+            },
+            disk: vec![0; blocks as usize * 512],
+            block_reads: 0,
+            largest_read: 0,
+            largest_write: 0,
+        })
+    }
+
+    #[test]
+    fn test_unaligned_writes_patch_blocks() -> io::Result<()> {
+        let mut device = adapter(8);
+        device.write_at(0, &[0x11; 4096])?;
+        device.write_at(500, &[0x22; 600])?;
+        let driver = device.into_inner();
+        assert_eq!(driver.block_reads, 2, "only the edge blocks are read");
+        assert!(driver.disk[..500].iter().all(|byte| *byte == 0x11));
+        assert!(driver.disk[500..1100].iter().all(|byte| *byte == 0x22));
+        assert!(driver.disk[1100..].iter().all(|byte| *byte == 0x11));
+
+        let mut device = adapter(8);
+        device.write_at(1024, &[0x33; 512])?;
+        assert_eq!(device.into_inner().block_reads, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_interface() -> io::Result<()> {
+        let mut device = adapter(4);
+        device.seek(SeekFrom::Start(700))?;
+        device.write_all(b"hello, block device")?;
+        device.seek(SeekFrom::Current(-19))?;
+        let mut text = [0u8; 19];
+        device.read_exact(&mut text)?;
+        assert_eq!(&text, b"hello, block device");
+
+        // The device cannot grow, and reads stop at its end
+        assert_eq!(device.seek(SeekFrom::End(-10))?, 2038);
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest)?;
+        assert_eq!(rest.len(), 10);
+        assert!(device.write_all(&[0; 11]).is_err());
+        assert!(device.seek(SeekFrom::Current(-5000)).is_err());
+        assert_eq!(device.read_at(4096, &mut text)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_reads_are_chunked() -> io::Result<()> {
+        let blocks = 3 * MAX_REQUEST_BLOCKS + 2;
+        let mut device = adapter(blocks);
+        let data: Vec<u8> = (0..blocks as usize * 512)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        device.write_at(0, &data)?;
+
+        let mut buf = vec![0u8; data.len() - 300];
+        assert_eq!(device.read_at(100, &mut buf)?, buf.len());
+        assert!(buf == data[100..data.len() - 200]);
+        let driver = device.into_inner();
+        assert_eq!(driver.largest_read, MAX_REQUEST_BLOCKS as usize * 512);
+        // Two edge blocks plus the aligned middle in whole chunks
+        assert_eq!(driver.block_reads, 2 + 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_writes_are_chunked() -> io::Result<()> {
+        let blocks = 3 * MAX_REQUEST_BLOCKS + 2;
+        let mut device = adapter(blocks);
+        device.write_at(0, &vec![0xEE; blocks as usize * 512])?;
+        let data: Vec<u8> = (0..blocks as usize * 512 - 300)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        device.write_at(100, &data)?;
+
+        let driver = device.into_inner();
+        assert!(driver.disk[..100].iter().all(|byte| *byte == 0xEE));
+        assert!(driver.disk[100..100 + data.len()] == data[..]);
+        assert!(driver.disk[100 + data.len()..]
+            .iter()
+            .all(|byte| *byte == 0xEE));
+        assert_eq!(driver.block_reads, 2, "only the edge blocks are read");
+        assert_eq!(driver.largest_write, MAX_REQUEST_BLOCKS as usize * 512);
+        Ok(())
+    }
+}
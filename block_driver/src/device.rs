@@ -0,0 +1,95 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Opening the device the filesystem's BlockDevice sits on. Every `--device`
+// path goes through `open_device`, which asks each driver package with a
+// path-based config whether it handles the device, brings the first that
+// does up and puts a `BlockAdapter` over it. virtio-blk is reached through
+// its transport instead, so it is not probed here.
+
+use crate::{BlockAdapter, BlockDriver, DriverPackage, FileConfig, FileDriver};
+use std::io;
+use std::path::PathBuf;
+
+// What the filesystem reads and writes: bytes over whichever driver won
+pub type BlockDevice = BlockAdapter<Box<dyn BlockDriver>>;
+
+#[derive(Clone, Debug)]
+pub struct DeviceConfig {
+    pub path: PathBuf,
+    pub block_size: u32,
+    pub read_only: bool,
+}
+
+pub fn open_device(config: &DeviceConfig) -> io::Result<BlockDevice> {
+    let file = FileConfig {
+        path: config.path.clone(),
+        block_size: config.block_size,
+        read_only: config.read_only,
+    };
+    if FileDriver::probe(&file)? {
+        let driver: Box<dyn BlockDriver> = Box::new(FileDriver::init(file)?);
+        return Ok(BlockAdapter::new(driver));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "no driver package handles {:?} with {} byte blocks",
+            config.path, config.block_size
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::process;
+
+    fn config(name: &str, len: u64) -> io::Result<DeviceConfig> {
+        let path = env::temp_dir().join(format!("block_driver_{}_{}", process::id(), name));
+        File::create(&path)?.set_len(len)?;
+        Ok(DeviceConfig {
+            path,
+            block_size: 512,
+            read_only: false,
+        })
+    }
+
+    #[test]
+    fn test_open_device() -> io::Result<()> {
+        let config = config("open_device", 8 * 512)?;
+        let mut device = open_device(&config)?;
+        assert_eq!(device.info().block_count, 8);
+        device.seek(SeekFrom::Start(1000))?;
+        device.write_all(b"through the driver")?;
+        device.flush()?;
+        let mut image = Vec::new();
+        File::open(&config.path)?.read_to_end(&mut image)?;
+        assert_eq!(&image[1000..1018], b"through the driver");
+
+        let ragged = DeviceConfig {
+            block_size: 3000,
+            ..config.clone()
+        };
+        assert_eq!(
+            open_device(&ragged).err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        fs::remove_file(&config.path)
+    }
+}
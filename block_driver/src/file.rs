@@ -0,0 +1,175 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A plain image file as a block device, the way the filesystem has always
+// been used. The file must already exist and hold a whole number of blocks.
+
+use crate::{check_range, check_writable, shut_down_error, BlockDriver, DeviceInfo, DriverPackage};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+pub struct FileConfig {
+    pub path: PathBuf,
+    pub block_size: u32,
+    pub read_only: bool,
+}
+
+pub struct FileDriver {
+    file: Option<File>,
+    info: DeviceInfo,
+}
+
+impl FileDriver {
+    fn file(&mut self, first: u64) -> io::Result<&mut File> {
+        let offset = first * u64::from(self.info.block_size);
+        let file = self.file.as_mut().ok_or_else(shut_down_error)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file)
+    }
+}
+
+impl BlockDriver for FileDriver {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_range(&self.info, first, buf.len())?;
+        self.file(first)?.read_exact(buf)
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+        check_writable(&self.info)?;
+        check_range(&self.info, first, buf.len())?;
+        self.file(first)?.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let read_only = self.info.read_only;
+        let file = self.file.as_mut().ok_or_else(shut_down_error)?;
+        if read_only {
+            return Ok(());
+        }
+        file.sync_data()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl DriverPackage for FileDriver {
+    const NAME: &'static str = "file";
+
+    type Config = FileConfig;
+
+    fn probe(config: &FileConfig) -> io::Result<bool> {
+        let metadata = match fs::metadata(&config.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let block_size = u64::from(config.block_size);
+        Ok(metadata.is_file()
+            && block_size > 0
+            && metadata.len() > 0
+            && metadata.len().is_multiple_of(block_size))
+    }
+
+    fn init(config: FileConfig) -> io::Result<FileDriver> {
+        if !FileDriver::probe(&config)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} is not a file of whole {} byte blocks",
+                    config.path, config.block_size
+                ),
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!config.read_only)
+            .open(&config.path)?;
+        let block_count = file.metadata()?.len() / u64::from(config.block_size);
+        Ok(FileDriver {
+            file: Some(file),
+            info: DeviceInfo {
+                block_size: config.block_size,
+                block_count,
+                read_only: config.read_only,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::Path;
+    use std::process;
+
+    fn image(name: &str, len: u64) -> io::Result<PathBuf> {
+        let path = env::temp_dir().join(format!("block_driver_{}_{}", process::id(), name));
+        File::create(&path)?.set_len(len)?;
+        Ok(path)
+    }
+
+    fn config(path: &Path, read_only: bool) -> FileConfig {
+        FileConfig {
+            path: path.to_path_buf(),
+            block_size: 512,
+            read_only,
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip() -> io::Result<()> {
+        let path = image("round_trip", 16 * 512)?;
+        let mut driver = FileDriver::init(config(&path, false))?;
+        assert_eq!(driver.info().block_count, 16);
+        driver.write_blocks(3, &[0xAB; 1024])?;
+        let mut buf = [0u8; 1536];
+        driver.read_blocks(2, &mut buf)?;
+        assert!(buf[..512].iter().all(|byte| *byte == 0));
+        assert!(buf[512..].iter().all(|byte| *byte == 0xAB));
+        assert!(driver.read_blocks(15, &mut buf).is_err());
+        driver.shutdown()?;
+        assert_eq!(
+            driver.read_blocks(0, &mut buf[..512]).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        let mut read_only = FileDriver::init(config(&path, true))?;
+        assert!(read_only.write_blocks(0, &[0; 512]).is_err());
+        read_only.read_blocks(3, &mut buf[..512])?;
+        assert!(buf[..512].iter().all(|byte| *byte == 0xAB));
+        fs::remove_file(path)
+    }
+
+    #[test]
+    fn test_file_probe() -> io::Result<()> {
+        let ragged = image("ragged", 1000)?;
+        assert!(!FileDriver::probe(&config(&ragged, false))?);
+        assert!(FileDriver::init(config(&ragged, false)).is_err());
+        let missing = env::temp_dir().join("block_driver_missing_image");
+        assert!(!FileDriver::probe(&config(&missing, false))?);
+        fs::remove_file(ragged)
+    }
+}
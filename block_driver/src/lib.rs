@@ -0,0 +1,173 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Block device drivers behind one interface. Every driver package implements
+// `DriverPackage`: `probe` says whether it handles the device a config
+// describes, `init` brings it up, and whole blocks are then read and written
+// through `BlockDriver` until `shutdown`. `BlockAdapter` turns any driver into
+// a byte-addressed Read + Write + Seek stream, and `open_device` is how the
+// filesystem gets one for its BlockDevice layer, with a plain image file
+// going through `FileDriver` like any other device.
+
+mod adapter;
+mod device;
+mod file;
+mod virtio_blk;
+
+// In-memory and fault-injecting drivers for exercising the layers above;
+// dependents' tests enable them with the `test-util` feature
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+
+pub use adapter::BlockAdapter;
+pub use device::{open_device, BlockDevice, DeviceConfig};
+pub use file::{FileConfig, FileDriver};
+pub use virtio_blk::{VirtioBlk, VirtioTransport, VIRTIO_ID_BLOCK};
+
+use std::io;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub block_size: u32,
+    pub block_count: u64,
+    pub read_only: bool,
+}
+
+impl DeviceInfo {
+    pub fn size_bytes(&self) -> u64 {
+        u64::from(self.block_size) * self.block_count
+    }
+}
+
+// The I/O half of a driver, usable as a trait object once the device is up
+pub trait BlockDriver {
+    fn info(&self) -> &DeviceInfo;
+
+    // `buf` must hold a whole number of blocks, all inside the device
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()>;
+
+    // Returns once everything written so far is on stable storage
+    fn flush(&mut self) -> io::Result<()>;
+
+    // Flushes and releases the device; every later call fails
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+impl<D: BlockDriver + ?Sized> BlockDriver for Box<D> {
+    fn info(&self) -> &DeviceInfo {
+        (**self).info()
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_blocks(first, buf)
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+        (**self).write_blocks(first, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+}
+
+pub trait DriverPackage: BlockDriver + Sized {
+    const NAME: &'static str;
+
+    type Config;
+
+    // Whether `config` describes a device this driver handles
+    fn probe(config: &Self::Config) -> io::Result<bool>;
+
+    fn init(config: Self::Config) -> io::Result<Self>;
+}
+
+// Blocks covered by a request of `len` bytes at block `first`, refusing
+// partial blocks and anything past the end of the device
+pub(crate) fn check_range(info: &DeviceInfo, first: u64, len: usize) -> io::Result<u64> {
+    let block_size = u64::from(info.block_size);
+    if !(len as u64).is_multiple_of(block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes is not a whole number of blocks", len),
+        ));
+    }
+    let count = len as u64 / block_size;
+    if first
+        .checked_add(count)
+        .is_none_or(|end| end > info.block_count)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "blocks {}..{} are past the end of a {} block device",
+                first,
+                first.saturating_add(count),
+                info.block_count
+            ),
+        ));
+    }
+    Ok(count)
+}
+
+pub(crate) fn check_writable(info: &DeviceInfo) -> io::Result<()> {
+    if info.read_only {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the device is read-only",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn shut_down_error() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "the device has been shut down")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_range() {
+        let info = DeviceInfo {
+            block_size: 512,
+            block_count: 8,
+            read_only: false,
+        };
+        assert_eq!(info.size_bytes(), 4096);
+        assert_eq!(check_range(&info, 0, 4096).unwrap(), 8);
+        assert_eq!(check_range(&info, 7, 512).unwrap(), 1);
+        assert_eq!(check_range(&info, 8, 0).unwrap(), 0);
+        assert!(check_range(&info, 0, 100).is_err());
+        assert!(check_range(&info, 7, 1024).is_err());
+        assert!(check_range(&info, u64::MAX, 512).is_err());
+        assert!(check_writable(&info).is_ok());
+        let read_only = DeviceInfo {
+            read_only: true,
+            ..info
+        };
+        assert_eq!(
+            check_writable(&read_only).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+}
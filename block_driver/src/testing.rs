@@ -0,0 +1,444 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Drivers for tests. `MemDriver` keeps the whole device in memory.
+// `FaultyDriver` wraps any driver and injects one fault where its `Trigger`
+// says: the Nth block I/O, the Nth read or the Nth write (counting from
+// zero), or the first I/O covering a given block. A request the device
+// would refuse anyway, such as one past its end, fails as usual and leaves
+// the fault armed. The faults:
+//
+// - `Fail`: the call fails and the device is untouched; later calls work,
+//   as after a transient error.
+// - `Flip`: the call succeeds but one bit is wrong, in what a read returns
+//   or in what a write stores.
+// - `ShortWrite`: a write stores only its first blocks and fails, and every
+//   call after it fails too, as when power is lost mid-request. A read at
+//   that position passes the fault on to the next write.
+//
+// It counts reads and writes separately, and can add a fixed latency to
+// every call, faulted or not.
+//
+// Wrapped in a `BlockAdapter`, either one is a BlockDevice for the
+// filesystem, so its recovery paths can be driven without real hardware.
+
+use crate::{check_range, check_writable, shut_down_error, BlockDriver, DeviceInfo};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+pub struct MemDriver {
+    info: DeviceInfo,
+    disk: Vec<u8>,
+    shut_down: bool,
+}
+
+impl MemDriver {
+    pub fn new(block_size: u32, block_count: u64) -> MemDriver {
+        MemDriver::from_image(
+            block_size,
+            vec![0; (u64::from(block_size) * block_count) as usize],
+        )
+    }
+
+    // Trailing bytes short of a whole block are not part of the device
+    pub fn from_image(block_size: u32, disk: Vec<u8>) -> MemDriver {
+        MemDriver {
+            info: DeviceInfo {
+                block_size,
+                block_count: disk.len() as u64 / u64::from(block_size),
+                read_only: false,
+            },
+            disk,
+            shut_down: false,
+        }
+    }
+
+    pub fn disk(&self) -> &[u8] {
+        &self.disk
+    }
+
+    fn span(&self, first: u64, len: usize) -> io::Result<std::ops::Range<usize>> {
+        if self.shut_down {
+            return Err(shut_down_error());
+        }
+        check_range(&self.info, first, len)?;
+        let start = (first * u64::from(self.info.block_size)) as usize;
+        Ok(start..start + len)
+    }
+}
+
+impl BlockDriver for MemDriver {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        let span = self.span(first, buf.len())?;
+        buf.copy_from_slice(&self.disk[span]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+        check_writable(&self.info)?;
+        let span = self.span(first, buf.len())?;
+        self.disk[span].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.shut_down {
+            return Err(shut_down_error());
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.shut_down = true;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    Fail,
+    // Bit `bit` of byte `byte` in the request, wrapping past its end
+    Flip { byte: usize, bit: u8 },
+    // Blocks stored before the write fails; clamped to the request
+    ShortWrite { blocks: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Io(u64),
+    Read(u64),
+    Write(u64),
+    Block(u64),
+}
+
+pub struct FaultyDriver<D> {
+    inner: D,
+    fault: Fault,
+    trigger: Trigger,
+    latency: Duration,
+    reads: u64,
+    writes: u64,
+    triggered: bool,
+    dead: bool,
+}
+
+impl<D: BlockDriver> FaultyDriver<D> {
+    pub fn new(inner: D, fault: Fault, trigger: Trigger) -> FaultyDriver<D> {
+        FaultyDriver {
+            inner,
+            fault,
+            trigger,
+            latency: Duration::ZERO,
+            reads: 0,
+            writes: 0,
+            triggered: false,
+            dead: false,
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> FaultyDriver<D> {
+        self.latency = latency;
+        self
+    }
+
+    // Block I/O calls made so far, including failed ones
+    pub fn ios(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    pub fn triggered(&self) -> bool {
+        self.triggered
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    // Counts the call, and says whether it is the one to fault
+    fn arm(&mut self, first: u64, len: usize, is_write: bool) -> io::Result<bool> {
+        if self.dead {
+            return Err(injected("the device lost power"));
+        }
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+        let io = self.ios();
+        let index = if is_write {
+            self.writes += 1;
+            self.writes - 1
+        } else {
+            self.reads += 1;
+            self.reads - 1
+        };
+        if self.triggered {
+            return Ok(false);
+        }
+        // Checked before arming, so a refused request does not use it up
+        let Ok(count) = check_range(self.inner.info(), first, len) else {
+            return Ok(false);
+        };
+        let hit = match self.trigger {
+            Trigger::Io(at) => io >= at,
+            Trigger::Read(at) => !is_write && index >= at,
+            Trigger::Write(at) => is_write && index >= at,
+            Trigger::Block(block) => first <= block && block - first < count,
+        };
+        if !hit || (matches!(self.fault, Fault::ShortWrite { .. }) && !is_write) {
+            return Ok(false);
+        }
+        self.triggered = true;
+        Ok(true)
+    }
+}
+
+fn injected(what: &str) -> io::Error {
+    io::Error::other(format!("injected fault: {}", what))
+}
+
+fn flip(buf: &mut [u8], byte: usize, bit: u8) {
+    if !buf.is_empty() {
+        buf[byte % buf.len()] ^= 1 << (bit % 8);
+    }
+}
+
+impl<D: BlockDriver> BlockDriver for FaultyDriver<D> {
+    fn info(&self) -> &DeviceInfo {
+        self.inner.info()
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        if !self.arm(first, buf.len(), false)? {
+            return self.inner.read_blocks(first, buf);
+        }
+        match self.fault {
+            Fault::Fail => Err(injected("read failed")),
+            Fault::Flip { byte, bit } => {
+                self.inner.read_blocks(first, buf)?;
+                flip(buf, byte, bit);
+                Ok(())
+            }
+            Fault::ShortWrite { .. } => unreachable!("short writes only arm on writes"),
+        }
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+        if !self.arm(first, buf.len(), true)? {
+            return self.inner.write_blocks(first, buf);
+        }
+        match self.fault {
+            Fault::Fail => Err(injected("write failed")),
+            Fault::Flip { byte, bit } => {
+                let mut corrupted = buf.to_vec();
+                flip(&mut corrupted, byte, bit);
+                self.inner.write_blocks(first, &corrupted)
+            }
+            Fault::ShortWrite { blocks } => {
+                let block_size = self.inner.info().block_size as usize;
+                let kept = blocks.min((buf.len() / block_size) as u64) as usize * block_size;
+                self.dead = true;
+                if kept > 0 {
+                    self.inner.write_blocks(first, &buf[..kept])?;
+                }
+                Err(injected("the device lost power mid-write"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dead {
+            return Err(injected("the device lost power"));
+        }
+        self.inner.flush()
+    }
+
+    // Always reaches the inner driver, so it is released even after a fault
+    fn shutdown(&mut self) -> io::Result<()> {
+        let result = self.inner.shutdown();
+        if self.dead {
+            return Err(injected("the device lost power"));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockAdapter;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|index| (index * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn test_mem_driver() -> io::Result<()> {
+        let mut driver = MemDriver::from_image(512, vec![0; 4 * 512 + 100]);
+        assert_eq!(driver.info().block_count, 4);
+        driver.write_blocks(1, &pattern(1024))?;
+        let mut buf = vec![0; 1024];
+        driver.read_blocks(1, &mut buf)?;
+        assert_eq!(buf, pattern(1024));
+        assert_eq!(&driver.disk()[512..1536], &pattern(1024)[..]);
+        assert!(driver.read_blocks(4, &mut buf[..512]).is_err());
+        driver.shutdown()?;
+        assert!(driver.read_blocks(0, &mut buf[..512]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail_is_transient() -> io::Result<()> {
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), Fault::Fail, Trigger::Io(1));
+        driver.write_blocks(0, &pattern(512))?;
+        assert!(driver.write_blocks(1, &pattern(512)).is_err());
+        assert!(driver.triggered());
+        driver.write_blocks(2, &pattern(512))?;
+        assert_eq!(driver.ios(), 3);
+        let disk = driver.into_inner();
+        assert!(disk.disk()[512..1024].iter().all(|byte| *byte == 0));
+        assert_eq!(&disk.disk()[1024..1536], &pattern(512)[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flip_corrupts_one_bit() -> io::Result<()> {
+        let flip = Fault::Flip { byte: 515, bit: 2 };
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), flip, Trigger::Io(0));
+        driver.write_blocks(0, &pattern(1024))?;
+        let mut expected = pattern(1024);
+        expected[515] ^= 4;
+        assert_eq!(&driver.into_inner().disk()[..1024], &expected[..]);
+
+        // On a read the stored data is left alone
+        let mut inner = MemDriver::new(512, 8);
+        inner.write_blocks(0, &pattern(512))?;
+        let mut driver =
+            FaultyDriver::new(inner, Fault::Flip { byte: 700, bit: 9 }, Trigger::Io(0));
+        let mut buf = vec![0; 512];
+        driver.read_blocks(0, &mut buf)?;
+        assert_eq!(buf[700 % 512], pattern(512)[700 % 512] ^ 2);
+        driver.read_blocks(0, &mut buf)?;
+        assert_eq!(buf, pattern(512));
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_write_tears_and_kills() -> io::Result<()> {
+        let fault = Fault::ShortWrite { blocks: 2 };
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), fault, Trigger::Io(0));
+        let mut buf = vec![0; 512];
+        // A read at the fault's position does not use it up
+        driver.read_blocks(0, &mut buf)?;
+        assert!(!driver.triggered());
+        assert!(driver.write_blocks(1, &pattern(2048)).is_err());
+        assert!(driver.triggered());
+        assert!(driver.read_blocks(0, &mut buf).is_err());
+        assert!(driver.flush().is_err());
+        assert!(driver.shutdown().is_err());
+        let disk = driver.into_inner();
+        assert_eq!(&disk.disk()[512..1536], &pattern(2048)[..1024]);
+        assert!(disk.disk()[1536..].iter().all(|byte| *byte == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refused_request_keeps_fault_armed() -> io::Result<()> {
+        let fault = Fault::ShortWrite { blocks: 1 };
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), fault, Trigger::Io(0));
+        let error = driver.write_blocks(7, &pattern(1024)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!driver.triggered());
+        driver.flush()?;
+
+        assert!(driver.write_blocks(0, &pattern(1024)).is_err());
+        assert!(driver.triggered());
+        assert!(driver.flush().is_err(), "the torn write loses the device");
+        assert_eq!(driver.writes(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_triggers_and_counters() -> io::Result<()> {
+        // Keyed by block: only a request covering block 5 fails, once
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), Fault::Fail, Trigger::Block(5));
+        driver.write_blocks(0, &pattern(2048))?;
+        assert!(driver.write_blocks(4, &pattern(1024)).is_err());
+        driver.write_blocks(4, &pattern(1024))?;
+        let mut buf = vec![0; 512];
+        driver.read_blocks(5, &mut buf)?;
+        assert_eq!((driver.reads(), driver.writes(), driver.ios()), (1, 3, 4));
+
+        // The Nth read, however many writes come between
+        let flip = Fault::Flip { byte: 0, bit: 0 };
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), flip, Trigger::Read(1));
+        for _ in 0..3 {
+            driver.write_blocks(0, &pattern(512))?;
+        }
+        driver.read_blocks(0, &mut buf)?;
+        assert_eq!(buf, pattern(512));
+        driver.read_blocks(0, &mut buf)?;
+        assert_eq!(buf[0], pattern(512)[0] ^ 1);
+
+        // The Nth write, however many reads come between
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), Fault::Fail, Trigger::Write(2));
+        for index in 0..2 {
+            driver.read_blocks(0, &mut buf)?;
+            driver.write_blocks(index, &pattern(512))?;
+        }
+        driver.read_blocks(0, &mut buf)?;
+        assert!(driver.write_blocks(2, &pattern(512)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_latency_applies_to_every_call() -> io::Result<()> {
+        let latency = Duration::from_millis(5);
+        let mut driver = FaultyDriver::new(MemDriver::new(512, 8), Fault::Fail, Trigger::Io(2))
+            .with_latency(latency);
+        let started = std::time::Instant::now();
+        let mut buf = vec![0; 512];
+        driver.read_blocks(0, &mut buf)?;
+        driver.write_blocks(0, &buf)?;
+        assert!(driver.read_blocks(0, &mut buf).is_err());
+        assert!(started.elapsed() >= 3 * latency);
+        Ok(())
+    }
+
+    #[test]
+    fn test_faults_through_adapter() -> io::Result<()> {
+        let driver = FaultyDriver::new(MemDriver::new(512, 8), Fault::Fail, Trigger::Io(0));
+        let mut device = BlockAdapter::new(driver);
+        // The unaligned write's read of its edge block is the failing I/O
+        assert!(device.write_at(100, b"torn").is_err());
+        device.write_at(100, b"whole")?;
+        let mut buf = [0u8; 5];
+        device.read_at(100, &mut buf)?;
+        assert_eq!(&buf, b"whole");
+        Ok(())
+    }
+}
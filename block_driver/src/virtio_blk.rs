@@ -0,0 +1,525 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// virtio-blk, virtio 1.x only. `init` negotiates features, reads the
+// capacity and block size from config space and marks the driver ready.
+// Each request is one descriptor chain: a 16-byte header the device reads,
+// the data, and a status byte the device writes. `VirtioTransport` hides how
+// a chain reaches the device (a split virtqueue over MMIO or PCI), so the
+// same driver runs on either and on the in-memory device in the tests.
+
+use crate::{check_range, check_writable, shut_down_error, BlockDriver, DeviceInfo, DriverPackage};
+use std::io;
+
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 128;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const SUPPORTED_FEATURES: u64 =
+    VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | VIRTIO_F_VERSION_1;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// Config space offsets
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+// Capacity and request sectors are always 512 bytes, whatever the block size
+const SECTOR_SIZE: u64 = 512;
+// Larger transfers are split so no chain exceeds what small queues accept
+const MAX_REQUEST_BYTES: usize = 128 * 1024;
+
+pub trait VirtioTransport {
+    fn device_id(&self) -> u32;
+
+    fn device_features(&self) -> u64;
+
+    fn set_driver_features(&mut self, features: u64);
+
+    fn status(&self) -> u8;
+
+    // Writing 0 resets the device
+    fn set_status(&mut self, status: u8);
+
+    fn read_config(&self, offset: usize, buf: &mut [u8]);
+
+    // Queues one chain, notifies the device and waits until it has been
+    // used: the device reads `readable` in order, then fills `writable`
+    fn transact(&mut self, readable: &[&[u8]], writable: &mut [&mut [u8]]) -> io::Result<()>;
+}
+
+pub struct VirtioBlk<T: VirtioTransport> {
+    transport: T,
+    info: DeviceInfo,
+    features: u64,
+    running: bool,
+}
+
+fn request_header(kind: u32, sector: u64) -> [u8; 16] {
+    let mut header = [0u8; 16];
+    header[..4].copy_from_slice(&kind.to_le_bytes());
+    header[8..].copy_from_slice(&sector.to_le_bytes());
+    header
+}
+
+fn check_status(status: u8) -> io::Result<()> {
+    match status {
+        VIRTIO_BLK_S_OK => Ok(()),
+        VIRTIO_BLK_S_IOERR => Err(io::Error::other("virtio-blk device reported an I/O error")),
+        VIRTIO_BLK_S_UNSUPP => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "virtio-blk device does not support the request",
+        )),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("virtio-blk device returned status {}", other),
+        )),
+    }
+}
+
+impl<T: VirtioTransport> VirtioBlk<T> {
+    // The transport back, for reuse after shutdown
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn sector(&self, block: u64) -> u64 {
+        block * (u64::from(self.info.block_size) / SECTOR_SIZE)
+    }
+
+    fn request(&mut self, readable: &[&[u8]], writable: &mut [&mut [u8]]) -> io::Result<()> {
+        if !self.running {
+            return Err(shut_down_error());
+        }
+        self.transport.transact(readable, writable)
+    }
+
+    fn fail(&mut self, error: io::Error) -> io::Error {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_FAILED);
+        error
+    }
+}
+
+impl<T: VirtioTransport> BlockDriver for VirtioBlk<T> {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_range(&self.info, first, buf.len())?;
+        let blocks_per_request = MAX_REQUEST_BYTES / self.info.block_size as usize;
+        for (index, chunk) in buf
+            .chunks_mut(blocks_per_request * self.info.block_size as usize)
+            .enumerate()
+        {
+            let block = first + (index * blocks_per_request) as u64;
+            let header = request_header(VIRTIO_BLK_T_IN, self.sector(block));
+            let mut status = [0xFF];
+            self.request(&[&header], &mut [chunk, &mut status])?;
+            check_status(status[0])?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> io::Result<()> {
+        check_writable(&self.info)?;
+        check_range(&self.info, first, buf.len())?;
+        let blocks_per_request = MAX_REQUEST_BYTES / self.info.block_size as usize;
+        for (index, chunk) in buf
+            .chunks(blocks_per_request * self.info.block_size as usize)
+            .enumerate()
+        {
+            let block = first + (index * blocks_per_request) as u64;
+            let header = request_header(VIRTIO_BLK_T_OUT, self.sector(block));
+            let mut status = [0xFF];
+            self.request(&[&header, chunk], &mut [&mut status])?;
+            check_status(status[0])?;
+        }
+        Ok(())
+    }
+
+    // Without VIRTIO_BLK_F_FLUSH the device is write-through
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.running {
+            return Err(shut_down_error());
+        }
+        if self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            return Ok(());
+        }
+        let header = request_header(VIRTIO_BLK_T_FLUSH, 0);
+        let mut status = [0xFF];
+        self.request(&[&header], &mut [&mut status])?;
+        check_status(status[0])
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.transport.set_status(0);
+        self.running = false;
+        Ok(())
+    }
+}
+
+impl<T: VirtioTransport> DriverPackage for VirtioBlk<T> {
+    const NAME: &'static str = "virtio-blk";
+
+    type Config = T;
+
+    fn probe(transport: &T) -> io::Result<bool> {
+        Ok(transport.device_id() == VIRTIO_ID_BLOCK)
+    }
+
+    fn init(mut transport: T) -> io::Result<VirtioBlk<T>> {
+        if !Self::probe(&transport)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "virtio device {} is not a block device",
+                    transport.device_id()
+                ),
+            ));
+        }
+        transport.set_status(0);
+        let mut status = STATUS_ACKNOWLEDGE;
+        transport.set_status(status);
+        status |= STATUS_DRIVER;
+        transport.set_status(status);
+
+        let offered = transport.device_features();
+        let mut driver = VirtioBlk {
+            transport,
+            info: DeviceInfo {
+                block_size: SECTOR_SIZE as u32,
+                block_count: 0,
+                read_only: false,
+            },
+            features: offered & SUPPORTED_FEATURES,
+            running: false,
+        };
+        if offered & VIRTIO_F_VERSION_1 == 0 {
+            return Err(driver.fail(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "legacy virtio devices are not supported",
+            )));
+        }
+        driver.transport.set_driver_features(driver.features);
+        status |= STATUS_FEATURES_OK;
+        driver.transport.set_status(status);
+        if driver.transport.status() & STATUS_FEATURES_OK == 0 {
+            return Err(driver.fail(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the device rejected the negotiated features",
+            )));
+        }
+
+        let mut capacity = [0u8; 8];
+        driver.transport.read_config(CONFIG_CAPACITY, &mut capacity);
+        let capacity = u64::from_le_bytes(capacity);
+        if driver.features & VIRTIO_BLK_F_BLK_SIZE != 0 {
+            let mut block_size = [0u8; 4];
+            driver
+                .transport
+                .read_config(CONFIG_BLK_SIZE, &mut block_size);
+            let block_size = u32::from_le_bytes(block_size);
+            // A block must fit in one request, or transfers cannot be split
+            if !block_size.is_power_of_two()
+                || u64::from(block_size) < SECTOR_SIZE
+                || block_size as usize > MAX_REQUEST_BYTES
+            {
+                return Err(driver.fail(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unusable virtio-blk block size {}", block_size),
+                )));
+            }
+            driver.info.block_size = block_size;
+        }
+        let Some(capacity_bytes) = capacity.checked_mul(SECTOR_SIZE) else {
+            return Err(driver.fail(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("virtio-blk capacity of {} sectors overflows", capacity),
+            )));
+        };
+        driver.info.block_count = capacity_bytes / u64::from(driver.info.block_size);
+        driver.info.read_only = driver.features & VIRTIO_BLK_F_RO != 0;
+
+        status |= STATUS_DRIVER_OK;
+        driver.transport.set_status(status);
+        driver.running = true;
+        Ok(driver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A virtio-blk device over a byte vector, with a switch for each way a
+    // real device can refuse the driver
+    struct MemDevice {
+        id: u32,
+        features: u64,
+        driver_features: Option<u64>,
+        status: u8,
+        block_size: u32,
+        // Reported in place of the disk's real size when set
+        capacity: Option<u64>,
+        disk: Vec<u8>,
+        reject_features: bool,
+        fail_io: bool,
+        requests: Vec<u32>,
+    }
+
+    impl MemDevice {
+        fn new(sectors: usize, features: u64) -> MemDevice {
+            MemDevice {
+                id: VIRTIO_ID_BLOCK,
+                features: features | VIRTIO_F_VERSION_1,
+                driver_features: None,
+                status: 0,
+                block_size: 4096,
+                capacity: None,
+                disk: vec![0; sectors * SECTOR_SIZE as usize],
+                reject_features: false,
+                fail_io: false,
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl VirtioTransport for MemDevice {
+        fn device_id(&self) -> u32 {
+            self.id
+        }
+
+        fn device_features(&self) -> u64 {
+            self.features
+        }
+
+        fn set_driver_features(&mut self, features: u64) {
+            assert_eq!(features & !self.features, 0, "accepted unoffered features");
+            self.driver_features = Some(features);
+        }
+
+        fn status(&self) -> u8 {
+            self.status
+        }
+
+        fn set_status(&mut self, status: u8) {
+            if status == 0 {
+                self.driver_features = None;
+            }
+            let refused = self.reject_features && status & STATUS_FEATURES_OK != 0;
+            self.status = if refused {
+                status & !STATUS_FEATURES_OK
+            } else {
+                status
+            };
+        }
+
+        fn read_config(&self, offset: usize, buf: &mut [u8]) {
+            let mut config = [0u8; 24];
+            let capacity = self
+                .capacity
+                .unwrap_or(self.disk.len() as u64 / SECTOR_SIZE);
+            config[..8].copy_from_slice(&capacity.to_le_bytes());
+            config[20..].copy_from_slice(&self.block_size.to_le_bytes());
+            buf.copy_from_slice(&config[offset..offset + buf.len()]);
+        }
+
+        fn transact(&mut self, readable: &[&[u8]], writable: &mut [&mut [u8]]) -> io::Result<()> {
+            assert_ne!(
+                self.status & STATUS_DRIVER_OK,
+                0,
+                "request before DRIVER_OK"
+            );
+            let header = readable[0];
+            let kind = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let offset = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize
+                * SECTOR_SIZE as usize;
+            self.requests.push(kind);
+            let (status, data) = writable.split_last_mut().unwrap();
+            status[0] = if self.fail_io {
+                VIRTIO_BLK_S_IOERR
+            } else {
+                match kind {
+                    VIRTIO_BLK_T_IN => {
+                        let buf = &mut data[0];
+                        buf.copy_from_slice(&self.disk[offset..offset + buf.len()]);
+                        VIRTIO_BLK_S_OK
+                    }
+                    VIRTIO_BLK_T_OUT => {
+                        let buf = readable[1];
+                        self.disk[offset..offset + buf.len()].copy_from_slice(buf);
+                        VIRTIO_BLK_S_OK
+                    }
+                    VIRTIO_BLK_T_FLUSH => VIRTIO_BLK_S_OK,
+                    _ => VIRTIO_BLK_S_UNSUPP,
+                }
+            };
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_init_negotiates_geometry() -> io::Result<()> {
+        let device = MemDevice::new(1024, VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | 1 << 1);
+        assert!(VirtioBlk::probe(&device)?);
+        let driver = VirtioBlk::init(device)?;
+        assert_eq!(
+            driver.info(),
+            &DeviceInfo {
+                block_size: 4096,
+                block_count: 128,
+                read_only: false,
+            }
+        );
+        let device = driver.into_transport();
+        assert_eq!(
+            device.driver_features,
+            Some(VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | VIRTIO_F_VERSION_1),
+            "unknown feature bits must be declined"
+        );
+        assert_eq!(
+            device.status,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK
+        );
+
+        // Without VIRTIO_BLK_F_BLK_SIZE blocks are sectors
+        let driver = VirtioBlk::init(MemDevice::new(64, 0))?;
+        assert_eq!(
+            (driver.info().block_size, driver.info().block_count),
+            (512, 64)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_refusals() {
+        let mut device = MemDevice::new(64, 0);
+        device.id = 1;
+        assert!(!VirtioBlk::probe(&device).unwrap());
+        assert!(VirtioBlk::init(device).is_err());
+
+        let mut legacy = MemDevice::new(64, 0);
+        legacy.features = 0;
+        assert_eq!(
+            VirtioBlk::init(legacy).err().unwrap().kind(),
+            io::ErrorKind::Unsupported
+        );
+
+        let mut picky = MemDevice::new(64, 0);
+        picky.reject_features = true;
+        assert!(VirtioBlk::init(picky).is_err());
+
+        for block_size in [1000, 256, 2 * MAX_REQUEST_BYTES as u32] {
+            let mut odd = MemDevice::new(64, VIRTIO_BLK_F_BLK_SIZE);
+            odd.block_size = block_size;
+            assert_eq!(
+                VirtioBlk::init(odd).err().unwrap().kind(),
+                io::ErrorKind::InvalidData,
+                "block size {}",
+                block_size
+            );
+        }
+
+        let mut huge = MemDevice::new(64, 0);
+        huge.capacity = Some(u64::MAX / 2);
+        assert_eq!(
+            VirtioBlk::init(huge).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_read_write_round_trip() -> io::Result<()> {
+        let mut driver = VirtioBlk::init(MemDevice::new(2048, VIRTIO_BLK_F_BLK_SIZE))?;
+        let data: Vec<u8> = (0..4 * 4096).map(|index| (index % 251) as u8).collect();
+        driver.write_blocks(10, &data)?;
+        let mut back = vec![0u8; data.len()];
+        driver.read_blocks(10, &mut back)?;
+        assert_eq!(back, data);
+        let device = driver.into_transport();
+        assert_eq!(&device.disk[10 * 4096..14 * 4096], &data[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_transfers_split() -> io::Result<()> {
+        let mut driver = VirtioBlk::init(MemDevice::new(4096, 0))?;
+        let data = vec![0x5A; 3 * MAX_REQUEST_BYTES + 512];
+        driver.write_blocks(1, &data)?;
+        let mut back = vec![0u8; data.len()];
+        driver.read_blocks(1, &mut back)?;
+        assert_eq!(back, data);
+        let device = driver.into_transport();
+        assert_eq!(device.requests.len(), 8, "{:?}", device.requests);
+        assert!(device.disk[..512].iter().all(|byte| *byte == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_and_shutdown() -> io::Result<()> {
+        let mut driver = VirtioBlk::init(MemDevice::new(64, VIRTIO_BLK_F_FLUSH))?;
+        driver.write_blocks(0, &[1; 512])?;
+        driver.shutdown()?;
+        assert!(driver.read_blocks(0, &mut [0; 512]).is_err());
+        let device = driver.into_transport();
+        assert_eq!(device.requests, [VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_FLUSH]);
+        assert_eq!(device.status, 0, "shutdown must reset the device");
+
+        // A device without a cache is never sent a flush
+        let mut driver = VirtioBlk::init(MemDevice::new(64, 0))?;
+        driver.flush()?;
+        assert!(driver.into_transport().requests.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_errors_surface() -> io::Result<()> {
+        let mut driver = VirtioBlk::init(MemDevice::new(64, VIRTIO_BLK_F_RO))?;
+        assert!(driver.info().read_only);
+        assert_eq!(
+            driver.write_blocks(0, &[0; 512]).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(driver.read_blocks(63, &mut [0; 1024]).is_err());
+        let mut device = driver.into_transport();
+        assert!(
+            device.requests.is_empty(),
+            "refused requests reached the device"
+        );
+
+        device.fail_io = true;
+        device.status = 0;
+        let mut driver = VirtioBlk::init(device)?;
+        assert!(driver.read_blocks(0, &mut [0; 512]).is_err());
+        Ok(())
+    }
+}
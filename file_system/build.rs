@@ -0,0 +1,66 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Finds every source module that declares a `scenarios!` table and writes
+// the `scenario_modules!` call that adds it to the full suite, so a new test
+// file joins the suite without being listed by hand.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Declares `scenarios!` itself and has its SCENARIOS added first
+const HARNESS_MODULE: &str = "harness";
+
+fn declares_scenarios(path: &Path) -> io::Result<bool> {
+    let source = fs::read_to_string(path)?;
+    Ok(source.lines().any(|line| line.starts_with("scenarios! {")))
+}
+
+fn scenario_modules(src: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut modules = Vec::new();
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "rs") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if name != HARNESS_MODULE && declares_scenarios(&path)? {
+            modules.push((name.to_string(), path.clone()));
+        }
+    }
+    modules.sort();
+    Ok(modules)
+}
+
+fn main() -> io::Result<()> {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").expect("set by Cargo"));
+    let src = manifest_dir.join("src");
+    println!("cargo:rerun-if-changed={}", src.display());
+
+    // The file is include!d from OUT_DIR, so each module is given the
+    // absolute path of its source rather than one relative to OUT_DIR
+    let mut generated = String::from("scenario_modules! {\n");
+    for (name, path) in scenario_modules(&src)? {
+        generated.push_str(&format!("    #[path = {:?}]\n    {},\n", path, name));
+    }
+    generated.push_str("}\n");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("set by Cargo"));
+    fs::write(out_dir.join("scenario_modules.rs"), generated)
+}
@@ -0,0 +1,245 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Coreutils-style aliases and short flags. Each alias runs on its own copy of
+// the same tree as its canonical spelling, and the outputs and resulting
+// trees must be identical. `touch`, `chmod`, `mkdir` and `rmdir` are
+// subcommands under their coreutils names rather than aliases: `touch`
+// creates missing files and sets the times of existing ones (`--date`),
+// where `create` refuses an existing path.
+
+use crate::differential::listed_names;
+use crate::errors::EXIT_ALREADY_EXISTS;
+use crate::harness::{command_timeout, format_device, scenarios, write_file, TestContext};
+use assert_cmd::Command;
+use std::io;
+use std::process::Output;
+
+struct AliasCase {
+    alias: &'static [&'static str],
+    canonical: &'static [&'static str],
+}
+
+const ALIASES: &[AliasCase] = &[
+    AliasCase {
+        alias: &["ls", "--path", "/"],
+        canonical: &["list", "--path", "/"],
+    },
+    AliasCase {
+        alias: &["cat", "--path", "/file.txt"],
+        canonical: &["read", "--path", "/file.txt"],
+    },
+    AliasCase {
+        alias: &["rm", "--path", "/file.txt"],
+        canonical: &["remove", "--path", "/file.txt"],
+    },
+    AliasCase {
+        alias: &["ln", "--target", "/file.txt", "--path", "/hard.txt"],
+        canonical: &["link", "--target", "/file.txt", "--path", "/hard.txt"],
+    },
+    AliasCase {
+        alias: &["df"],
+        canonical: &["stats"],
+    },
+    AliasCase {
+        alias: &["mv", "--from", "/file.txt", "--to", "/moved.txt"],
+        canonical: &["move", "--from", "/file.txt", "--to", "/moved.txt"],
+    },
+    AliasCase {
+        alias: &["cp", "--from", "/file.txt", "--to", "/copy.txt"],
+        canonical: &["copy", "--from", "/file.txt", "--to", "/copy.txt"],
+    },
+];
+
+const SHORT_FLAGS: &[AliasCase] = &[
+    AliasCase {
+        alias: &["list", "-l", "--path", "/"],
+        canonical: &["list", "--long", "--path", "/"],
+    },
+    AliasCase {
+        alias: &["list", "-r", "--path", "/"],
+        canonical: &["list", "--recursive", "--path", "/"],
+    },
+    AliasCase {
+        alias: &["mkdir", "-p", "--path", "/x/y/z"],
+        canonical: &["mkdir", "--parents", "--path", "/x/y/z"],
+    },
+    AliasCase {
+        alias: &["remove", "-r", "--path", "/dir"],
+        canonical: &["remove", "--recursive", "--path", "/dir"],
+    },
+    AliasCase {
+        alias: &["format", "-f"],
+        canonical: &["format", "--force"],
+    },
+    AliasCase {
+        alias: &["link", "-s", "--target", "/file.txt", "--path", "/soft.txt"],
+        canonical: &[
+            "link",
+            "--symbolic",
+            "--target",
+            "/file.txt",
+            "--path",
+            "/soft.txt",
+        ],
+    },
+];
+
+// Subcommands that already carry their coreutils name, so no alias may take it
+const COREUTILS_SUBCOMMANDS: &[&str] = &["touch", "chmod", "mkdir", "rmdir"];
+
+fn fixture() -> io::Result<TestContext> {
+    let ctx = TestContext::new()?;
+    format_device(&ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/inner.txt"])?;
+    ctx.run_bellande_command(&["create", "--path", "/file.txt"])?;
+    let output = write_file(&ctx, "/file.txt", b"alias test\n")?;
+    assert!(output.status.success());
+    Ok(ctx)
+}
+
+fn root_names(ctx: &TestContext) -> io::Result<Vec<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .collect())
+}
+
+fn same_result(case: &AliasCase) -> io::Result<()> {
+    let (alias_ctx, canonical_ctx) = (fixture()?, fixture()?);
+    let alias: Output = alias_ctx.run_raw(case.alias)?;
+    let canonical: Output = canonical_ctx.run_raw(case.canonical)?;
+
+    assert!(
+        canonical.status.success(),
+        "{:?} failed: {}",
+        case.canonical,
+        String::from_utf8_lossy(&canonical.stderr)
+    );
+    assert_eq!(
+        alias.status.code(),
+        canonical.status.code(),
+        "{:?}",
+        case.alias
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&alias.stdout),
+        String::from_utf8_lossy(&canonical.stdout),
+        "{:?} printed something different from {:?}",
+        case.alias,
+        case.canonical
+    );
+    assert_eq!(root_names(&alias_ctx)?, root_names(&canonical_ctx)?);
+    Ok(())
+}
+
+pub(crate) fn aliases_dispatch_like_canonical() -> io::Result<()> {
+    for case in ALIASES.iter().chain(SHORT_FLAGS) {
+        same_result(case)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn coreutils_named_subcommands(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/file.txt"])?;
+    let output = write_file(ctx, "/file.txt", b"kept\n")?;
+    assert!(output.status.success());
+
+    // touch is not create: an existing file is kept, not refused
+    ctx.command(&["create", "--path", "/file.txt"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    ctx.run_bellande_command(&["touch", "/file.txt", "/new.txt"])?;
+    ctx.run_bellande_command(&["chmod", "--path", "/file.txt", "--mode", "0600"])?;
+    let output = ctx.run_bellande_command(&["read", "--path", "/file.txt"])?;
+    assert_eq!(output.stdout, b"kept\n");
+    assert_eq!(root_names(ctx)?, ["file.txt", "new.txt"]);
+    Ok(())
+}
+
+pub(crate) fn help_lists_aliases(ctx: &TestContext) -> io::Result<()> {
+    let output = Command::new(&ctx.binary_path)
+        .arg("--help")
+        .timeout(command_timeout())
+        .ok()
+        .map_err(|e| io::Error::other(format!("Command failed: {}", e)))?;
+    let help = String::from_utf8_lossy(&output.stdout).into_owned();
+    let words: Vec<&str> = help
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .collect();
+    for case in ALIASES {
+        assert!(
+            words.contains(&case.alias[0]),
+            "--help does not mention the {} alias",
+            case.alias[0]
+        );
+    }
+    for name in COREUTILS_SUBCOMMANDS {
+        assert!(words.contains(name), "--help does not list {}", name);
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    aliases_dispatch_like_canonical(),
+    #[contract]
+    coreutils_named_subcommands,
+    #[contract]
+    help_lists_aliases,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::SUBCOMMANDS;
+
+    // Every subcommand name the suite uses, not just those behind an alias
+    fn subcommand_names() -> Vec<&'static str> {
+        let mut names: Vec<&str> = SUBCOMMANDS.to_vec();
+        names.extend(COREUTILS_SUBCOMMANDS);
+        names.extend(
+            ALIASES
+                .iter()
+                .chain(SHORT_FLAGS)
+                .map(|case| case.canonical[0]),
+        );
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // Exact names are matched before abbreviations, so an alias that is a
+    // prefix of a subcommand (`rm` and `rmdir`) is not ambiguous; one that
+    // is itself a subcommand name hides that subcommand
+    #[test]
+    fn test_aliases_do_not_shadow_subcommands() {
+        let subcommands = subcommand_names();
+        for case in ALIASES {
+            let alias = case.alias[0];
+            assert!(
+                !subcommands.contains(&alias),
+                "{} is both an alias and a subcommand",
+                alias
+            );
+        }
+        let mut aliases: Vec<&str> = ALIASES.iter().map(|case| case.alias[0]).collect();
+        aliases.sort_unstable();
+        aliases.dedup();
+        assert_eq!(aliases.len(), ALIASES.len(), "an alias is listed twice");
+    }
+}
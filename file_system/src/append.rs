@@ -0,0 +1,173 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `write --append` writes stdin at the current end of file instead of
+// truncating. Appends fill the partial last block before allocating, so a
+// file built from many small appends uses exactly the blocks of the same
+// bytes written in one go.
+
+use crate::differential::content;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use predicates::prelude::*;
+use std::io;
+
+const BLOCK_SIZE: u32 = 4096;
+const CHUNKS: u64 = 1000;
+
+fn append_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn append(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<()> {
+    ctx.command(&["write", "--path", path, "--append"])
+        .write_stdin(data.to_vec())
+        .assert()
+        .success();
+    Ok(())
+}
+
+fn read_back(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+// Free blocks consumed by `build`, which must leave exactly one new file
+fn blocks_used(ctx: &TestContext, build: impl FnOnce() -> io::Result<()>) -> io::Result<u64> {
+    let before = read_stats(ctx)?.free_blocks;
+    build()?;
+    Ok(before - read_stats(ctx)?.free_blocks)
+}
+
+// The same bytes written in one go, as the reference for block usage
+fn one_shot(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<()> {
+    ctx.run_bellande_command(&["create", "--path", path])?;
+    assert!(
+        write_file(ctx, path, data)?.status.success(),
+        "write {}",
+        path
+    );
+    Ok(())
+}
+
+pub(crate) fn many_small_appends(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    // Chunk sizes vary so appends start at every kind of offset in a block
+    let chunks: Vec<Vec<u8>> = (0..CHUNKS)
+        .map(|seed| content(seed, 1 + (seed as usize * 37) % 211))
+        .collect();
+    let expected = chunks.concat();
+
+    let appended = blocks_used(ctx, || {
+        ctx.run_bellande_command(&["create", "--path", "/log.txt"])?;
+        for chunk in &chunks {
+            append(ctx, "/log.txt", chunk)?;
+        }
+        Ok(())
+    })?;
+    assert!(
+        read_back(ctx, "/log.txt")? == expected,
+        "appended log differs"
+    );
+
+    let written = blocks_used(ctx, || one_shot(ctx, "/whole.txt", &expected))?;
+    assert_eq!(
+        appended,
+        written,
+        "{} appends used {} blocks, one write of the same {} bytes used {}",
+        CHUNKS,
+        appended,
+        expected.len(),
+        written
+    );
+
+    // Removing it gives every block back
+    let before = read_stats(ctx)?.free_blocks;
+    ctx.run_bellande_command(&["remove", "--path", "/log.txt"])?;
+    assert_eq!(read_stats(ctx)?.free_blocks, before + appended);
+    Ok(())
+}
+
+pub(crate) fn appends_fill_partial_block(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let block = BLOCK_SIZE as usize;
+
+    for (index, existing) in [0, 1, block - 1, block, block + 1, 3 * block - 7]
+        .into_iter()
+        .enumerate()
+    {
+        for (step, extra) in [1, 7, block, 2 * block + 3].into_iter().enumerate() {
+            let seed = (index * 10 + step) as u64;
+            let base = content(seed, existing);
+            let tail = content(seed + 1000, extra);
+            let expected = [base.as_slice(), tail.as_slice()].concat();
+
+            let path = format!("/partial{}_{}", index, step);
+            let appended = blocks_used(ctx, || {
+                one_shot(ctx, &path, &base)?;
+                append(ctx, &path, &tail)
+            })?;
+            assert!(
+                read_back(ctx, &path)? == expected,
+                "{} + {} bytes",
+                existing,
+                extra
+            );
+
+            let reference = format!("{}.ref", path);
+            let written = blocks_used(ctx, || one_shot(ctx, &reference, &expected))?;
+            assert_eq!(
+                appended, written,
+                "appending {} bytes to {} bytes",
+                extra, existing
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn append_edge_cases(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/file", "--content", "kept"])?;
+
+    // An empty append changes nothing
+    let before = read_stats(ctx)?;
+    append(ctx, "/file", b"")?;
+    assert_eq!(read_back(ctx, "/file")?, b"kept");
+    assert_eq!(read_stats(ctx)?, before);
+
+    // A write without --append still replaces
+    assert!(write_file(ctx, "/file", b"new")?.status.success());
+    append(ctx, "/file", b" tail")?;
+    assert_eq!(read_back(ctx, "/file")?, b"new tail");
+
+    ctx.command(&["write", "--path", "/missing", "--append"])
+        .write_stdin("x")
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("/missing"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    many_small_appends(append_context()?),
+    #[contract]
+    appends_fill_partial_block(append_context()?),
+    #[contract]
+    append_edge_cases(append_context()?),
+}
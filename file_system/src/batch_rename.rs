@@ -0,0 +1,256 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `rename`: batch renames inside one directory. `--match` picks names by
+// glob, `--replace old=new` substitutes a substring (or, with `--regex`, a
+// pattern whose `$1`-style groups the replacement may use). Every collision
+// is found before anything moves, `--dry-run` only prints the mapping, and
+// renamed entries keep their inodes.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, inode_of, listed_names};
+use crate::errors::EXIT_ALREADY_EXISTS;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::io;
+
+const ARROW: &str = "\u{2192}";
+
+fn populated(ctx: &TestContext, dir: &str, names: &[&str]) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", dir])?;
+    for (seed, name) in names.iter().enumerate() {
+        let path = format!("{}/{}", dir, name);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let output = write_file(ctx, &path, &content(seed as u64, 100))?;
+        assert!(output.status.success(), "write {} failed", path);
+    }
+    Ok(())
+}
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn set(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+// One "old → new" line per renamed entry, each naming both sides in order
+fn assert_mapping(stdout: &str, mapping: &[(&str, &str)]) {
+    for (old, new) in mapping {
+        assert!(
+            stdout.lines().any(|line| {
+                line.find(old)
+                    .and_then(|at| line[at + old.len()..].find(ARROW))
+                    .is_some_and(|at| line[at..].contains(new))
+            }),
+            "no {} {} {} line in {:?}",
+            old,
+            ARROW,
+            new,
+            stdout
+        );
+    }
+    assert_eq!(
+        stdout.lines().filter(|line| line.contains(ARROW)).count(),
+        mapping.len(),
+        "unexpected mapping lines in {:?}",
+        stdout
+    );
+}
+
+pub(crate) fn substring_rename_with_dry_run(ctx: &TestContext) -> io::Result<()> {
+    let names = ["a.log", "b.log", "keep.txt", "notes.log.txt"];
+    populated(ctx, "/logs", &names)?;
+    let listing = ctx.run_bellande_command(&["list", "--path", "/logs"])?;
+    let inode = inode_of(&String::from_utf8_lossy(&listing.stdout), "a.log");
+
+    let args = [
+        "rename",
+        "--path",
+        "/logs",
+        "--match",
+        "*.log",
+        "--replace",
+        ".log=.log.old",
+    ];
+    let mapping = [("a.log", "a.log.old"), ("b.log", "b.log.old")];
+
+    let mut dry_run = args.to_vec();
+    dry_run.push("--dry-run");
+    let output = ctx.run_bellande_command(&dry_run)?;
+    assert_mapping(&String::from_utf8_lossy(&output.stdout), &mapping);
+    assert_eq!(names_in(ctx, "/logs")?, set(&names));
+
+    let output = ctx.run_bellande_command(&args)?;
+    assert_mapping(&String::from_utf8_lossy(&output.stdout), &mapping);
+    assert_eq!(
+        names_in(ctx, "/logs")?,
+        set(&["a.log.old", "b.log.old", "keep.txt", "notes.log.txt"])
+    );
+
+    // A directory-entry update: same inode, same bytes
+    let listing = ctx.run_bellande_command(&["list", "--path", "/logs"])?;
+    assert_eq!(
+        inode_of(&String::from_utf8_lossy(&listing.stdout), "a.log.old"),
+        inode
+    );
+    let output = ctx.run_bellande_command(&["read", "--path", "/logs/a.log.old"])?;
+    assert_eq!(output.stdout, content(0, 100));
+    Ok(())
+}
+
+pub(crate) fn regex_capture_groups(ctx: &TestContext) -> io::Result<()> {
+    populated(
+        ctx,
+        "/reports",
+        &[
+            "report-2024.txt",
+            "summary-2023.txt",
+            "notes.md",
+            "draft-x.txt",
+        ],
+    )?;
+
+    let output = ctx.run_bellande_command(&[
+        "rename",
+        "--path",
+        "/reports",
+        "--regex",
+        "--replace",
+        r"^(\w+)-(\d+)\.txt$=$2-$1.txt",
+    ])?;
+    assert_mapping(
+        &String::from_utf8_lossy(&output.stdout),
+        &[
+            ("report-2024.txt", "2024-report.txt"),
+            ("summary-2023.txt", "2023-summary.txt"),
+        ],
+    );
+    assert_eq!(
+        names_in(ctx, "/reports")?,
+        set(&[
+            "2024-report.txt",
+            "2023-summary.txt",
+            "notes.md",
+            "draft-x.txt"
+        ])
+    );
+    Ok(())
+}
+
+pub(crate) fn collisions_refused(ctx: &TestContext) -> io::Result<()> {
+    let names = ["apple.txt", "avocado.txt", "x.LOG", "x.log"];
+    populated(ctx, "/c", &names)?;
+
+    // Two sources onto one destination: refused whole, even with --force
+    for force in [false, true] {
+        let mut args = vec![
+            "rename",
+            "--path",
+            "/c",
+            "--regex",
+            "--replace",
+            r"^(\w)\w*\.txt$=$1.txt",
+        ];
+        if force {
+            args.push("--force");
+        }
+        ctx.command(&args)
+            .assert()
+            .code(EXIT_ALREADY_EXISTS)
+            .stderr(
+                predicate::str::contains("apple.txt")
+                    .and(predicate::str::contains("avocado.txt"))
+                    .and(predicate::str::contains("a.txt")),
+            );
+        assert_eq!(names_in(ctx, "/c")?, set(&names));
+    }
+
+    // Onto an existing entry: refused unless --force, which replaces it
+    let args = [
+        "rename",
+        "--path",
+        "/c",
+        "--match",
+        "*.LOG",
+        "--replace",
+        ".LOG=.log",
+    ];
+    ctx.command(&args)
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(predicate::str::contains("x.log"));
+    ctx.command(&args).assert().code(EXIT_ALREADY_EXISTS);
+    assert_eq!(names_in(ctx, "/c")?, set(&names));
+
+    let mut forced = args.to_vec();
+    forced.push("--force");
+    ctx.run_bellande_command(&forced)?;
+    assert_eq!(
+        names_in(ctx, "/c")?,
+        set(&["apple.txt", "avocado.txt", "x.log"])
+    );
+    let output = ctx.run_bellande_command(&["read", "--path", "/c/x.log"])?;
+    assert_eq!(output.stdout, content(2, 100), "x.LOG's bytes should win");
+    Ok(())
+}
+
+pub(crate) fn invalid_rename_arguments(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx, "/d", &["a.txt"])?;
+    for args in [
+        &["--replace", "no-separator"][..],
+        &["--regex", "--replace", "(unclosed=x"][..],
+        &["--regex", "--replace", "(a)=$2"][..],
+        &["--replace", ".txt=sub/dir.txt"][..],
+        &["--match", "*.txt"][..],
+    ] {
+        let mut full = vec!["rename", "--path", "/d"];
+        full.extend_from_slice(args);
+        ctx.command(&full)
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--replace").or(predicate::str::contains("--regex")));
+    }
+    assert_eq!(names_in(ctx, "/d")?, set(&["a.txt"]));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    substring_rename_with_dry_run,
+    #[contract]
+    regex_capture_groups,
+    #[contract]
+    collisions_refused,
+    #[contract]
+    invalid_rename_arguments,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_mapping() {
+        assert_mapping(
+            "/logs/a.log \u{2192} /logs/a.log.old\nrenamed 1 entry\n",
+            &[("a.log", "a.log.old")],
+        );
+    }
+}
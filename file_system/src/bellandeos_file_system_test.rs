@@ -13,215 +13,85 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::env;
-use std::fs::{self, File};
-use std::io::{self, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
-use tempfile::TempDir;
+// Integration tests for the BellandeOS filesystem binary. The shared harness
+// lives in `harness`; every feature has its own module listing its scenarios.
 
-#[cfg(test)]
-use predicates::prelude::*;
-
-fn get_bellande_fs_binary() -> PathBuf {
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-    println!("Current directory: {:?}", current_dir);
-
-    let mut path = current_dir;
-    path.push("bellandeos");
-    path.push("file_system");
-
-    println!("Constructed binary path: {:?}", path);
-    path
-}
-
-struct TestContext {
-    temp_dir: TempDir,
-    device_path: PathBuf,
-    binary_path: PathBuf,
-}
+mod harness;
+mod json;
+mod large_device;
+mod stress;
+mod template;
+mod times;
 
-impl TestContext {
-    fn new() -> io::Result<Self> {
-        let temp_dir = TempDir::new()?;
-        let device_path = temp_dir.path().join("test_device");
-        let binary_path = get_bellande_fs_binary();
+use harness::Scenario;
 
-        // Create a larger test device file (e.g., 10MB)
-        let mut file = File::create(&device_path)?;
-        let zeros = vec![0u8; 10 * 1024 * 1024];
-        file.write_all(&zeros)?;
-
-        Ok(TestContext {
-            temp_dir,
-            device_path,
-            binary_path,
-        })
-    }
+#[cfg(not(test))]
+use std::{env, io, time::Duration};
 
-    fn run_bellande_command(&self, args: &[&str]) -> io::Result<Output> {
-        let mut command = Command::new(&self.binary_path);
-        command.arg("--device").arg(&self.device_path).args(args);
+#[cfg(not(test))]
+const USAGE: &str = "Usage: bellandeos_file_system_test [COMMAND]
 
-        println!("Executing command: {:?}", command);
+With no command, runs the full suite. Contract scenarios, which specify
+commands the binary does not implement yet, join it when built with
+--features contract-tests; `run` runs them either way.
 
-        let output = command.output()?;
+Commands:
+  list                 Print every scenario name
+  run <name>...        Run only the named scenarios
+  stress               Run the concurrent stress tier
+  large-device         Run the 8 GiB sparse device tier
+  build-golden         Regenerate the golden images
+  replay <file>        Replay a saved .bfsrepro failure";
 
-        println!(
-            "Command stdout: {}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        println!(
-            "Command stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-
-        if !output.status.success() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Command failed: {:?}",
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ));
+#[cfg(not(test))]
+const SCENARIO_TIMEOUT_ENV: &str = "BELLANDE_FS_SCENARIO_TIMEOUT";
+#[cfg(not(test))]
+const DEFAULT_SCENARIO_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Declares each feature module and gathers its SCENARIOS. build.rs finds
+// every module with a `scenarios!` table and writes the call, so a new test
+// file joins the full suite on its own; those modules are only reachable
+// through the generated `#[path]`, so format them with `rustfmt src/*.rs`
+// rather than `cargo fmt`
+macro_rules! scenario_modules {
+    ($(#[path = $path:literal] $module:ident),* $(,)?) => {
+        $(#[path = $path] mod $module;)*
+
+        fn all_scenarios() -> Vec<&'static Scenario> {
+            let mut scenarios: Vec<&'static Scenario> = harness::SCENARIOS.iter().collect();
+            $(scenarios.extend($module::SCENARIOS.iter());)*
+            scenarios
         }
-
-        Ok(output)
-    }
-}
-
-fn format_device(ctx: &TestContext) -> io::Result<()> {
-    println!("Attempting to format device: {:?}", ctx.device_path);
-    let output = ctx.run_bellande_command(&["format"])?;
-
-    if !String::from_utf8_lossy(&output.stdout).contains("Device formatted successfully") {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to format device",
-        ));
-    }
-
-    Ok(())
-}
-
-fn create_and_list_files(ctx: &TestContext) -> io::Result<()> {
-    format_device(ctx)?;
-
-    let output = ctx.run_bellande_command(&["create", "--path", "/test.txt"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains("File created successfully"));
-
-    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains("test.txt"));
-
-    Ok(())
+    };
 }
 
-fn write_and_read_file(ctx: &TestContext) -> io::Result<()> {
-    let test_content = "Hello, Bellande filesystem!";
-    let test_file = "/test.txt";
+include!(concat!(env!("OUT_DIR"), "/scenario_modules.rs"));
 
-    format_device(ctx)?;
-
-    ctx.run_bellande_command(&["create", "--path", test_file])?;
-
-    let mut command = Command::new(&ctx.binary_path);
-    command
-        .arg("--device")
-        .arg(&ctx.device_path)
-        .args(&["write", "--path", test_file])
-        .stdin(std::process::Stdio::piped());
-
-    let mut child = command.spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(test_content.as_bytes())?;
-    }
-    let output = child.wait_with_output()?;
-    assert!(output.status.success());
-
-    let output = ctx.run_bellande_command(&["read", "--path", test_file])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains(test_content));
-
-    Ok(())
-}
-
-fn create_and_remove_directory(ctx: &TestContext) -> io::Result<()> {
-    format_device(ctx)?;
-
-    let output = ctx.run_bellande_command(&["mkdir", "--path", "/testdir"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains("Directory created successfully"));
-
-    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains("testdir"));
-
-    let output = ctx.run_bellande_command(&["rmdir", "--path", "/testdir"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains("Directory removed successfully"));
-
-    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
-    assert!(!String::from_utf8_lossy(&output.stdout).contains("testdir"));
-
-    Ok(())
-}
-
-fn filesystem_stats(ctx: &TestContext) -> io::Result<()> {
-    format_device(ctx)?;
-
-    let output = ctx.run_bellande_command(&["stats"])?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("Total blocks"));
-    assert!(stdout.contains("Free blocks"));
-    assert!(stdout.contains("Total inodes"));
-    assert!(stdout.contains("Free inodes"));
-
-    Ok(())
+// The full suite: every scenario, less the contract ones unless the
+// `contract-tests` feature is on
+fn default_scenarios() -> Vec<&'static Scenario> {
+    all_scenarios()
+        .into_iter()
+        .filter(|scenario| scenario.runs_by_default())
+        .collect()
 }
 
-fn error_handling(ctx: &TestContext) -> io::Result<()> {
-    // Try to use unformatted device first
-    let result = ctx.run_bellande_command(&["list", "--path", "/"]);
-    assert!(result.is_err());
-
-    format_device(ctx)?;
-
-    let result = ctx.run_bellande_command(&["remove", "--path", "/nonexistent.txt"]);
-    assert!(result.is_err());
-    if let Err(e) = result {
-        assert!(e.to_string().contains("File not found"));
+// The named scenarios in the given order, or the names that matched none
+fn select_scenarios(names: &[String]) -> Result<Vec<&'static Scenario>, Vec<String>> {
+    let scenarios = all_scenarios();
+    let mut selected = Vec::new();
+    let mut unknown = Vec::new();
+    for name in names {
+        match scenarios.iter().find(|scenario| scenario.name == name) {
+            Some(scenario) => selected.push(*scenario),
+            None => unknown.push(name.clone()),
+        }
     }
-
-    let result = ctx.run_bellande_command(&["create", "--path", "invalid/path/file.txt"]);
-    assert!(result.is_err());
-    if let Err(e) = result {
-        assert!(e.to_string().contains("Invalid path"));
+    if unknown.is_empty() && !selected.is_empty() {
+        Ok(selected)
+    } else {
+        Err(unknown)
     }
-
-    Ok(())
-}
-
-fn large_file_operations(ctx: &TestContext) -> io::Result<()> {
-    let large_content = "A".repeat(100_000);
-
-    format_device(ctx)?;
-
-    ctx.run_bellande_command(&["create", "--path", "/large.txt"])?;
-
-    let mut command = Command::new(&ctx.binary_path);
-    command
-        .arg("--device")
-        .arg(&ctx.device_path)
-        .args(&["write", "--path", "/large.txt"])
-        .stdin(std::process::Stdio::piped());
-
-    let mut child = command.spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(large_content.as_bytes())?;
-    }
-    let output = child.wait_with_output()?;
-    assert!(output.status.success());
-
-    let output = ctx.run_bellande_command(&["read", "--path", "/large.txt"])?;
-    assert!(String::from_utf8_lossy(&output.stdout).contains(&large_content));
-
-    Ok(())
 }
 
 #[cfg(test)]
@@ -229,92 +99,132 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_executable_exists() {
-        let ctx = TestContext::new().expect("Failed to create test context");
-        println!("Looking for executable at: {:?}", ctx.binary_path);
-        assert!(
-            ctx.binary_path.exists(),
-            "Executable does not exist at {:?}",
-            ctx.binary_path
-        );
-        assert!(
-            ctx.binary_path.is_file(),
-            "Path {:?} is not a file",
-            ctx.binary_path
-        );
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let metadata = fs::metadata(&ctx.binary_path).unwrap();
-            let permissions = metadata.permissions();
-            assert!(
-                permissions.mode() & 0o111 != 0,
-                "Executable {:?} does not have execute permissions",
-                ctx.binary_path
-            );
-        }
+    fn test_scenario_names_are_unique() {
+        let mut names: Vec<&str> = all_scenarios().iter().map(|s| s.name).collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count, "duplicate scenario names");
     }
 
     #[test]
-    fn test_format_device() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        format_device(&ctx)
-    }
-
-    #[test]
-    fn test_create_and_list_files() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        create_and_list_files(&ctx)
-    }
-
-    #[test]
-    fn test_write_and_read_file() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        write_and_read_file(&ctx)
-    }
-
-    #[test]
-    fn test_create_and_remove_directory() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        create_and_remove_directory(&ctx)
+    fn test_default_scenarios() {
+        let names: Vec<&str> = default_scenarios().iter().map(|s| s.name).collect();
+        assert!(names.contains(&"format_device"));
+        assert_eq!(
+            names.contains(&"move_file_and_directory"),
+            cfg!(feature = "contract-tests")
+        );
     }
 
     #[test]
-    fn test_filesystem_stats() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        filesystem_stats(&ctx)
+    fn test_select_scenarios() {
+        let names = vec!["error_handling".to_string(), "format_device".to_string()];
+        let selected = select_scenarios(&names).expect("known scenarios");
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].name, "error_handling");
+
+        let unknown = vec!["format_device".to_string(), "no_such".to_string()];
+        assert_eq!(
+            select_scenarios(&unknown).err(),
+            Some(vec!["no_such".to_string()])
+        );
+        assert!(select_scenarios(&[]).is_err());
     }
+}
 
-    #[test]
-    fn test_error_handling() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        error_handling(&ctx)
+#[cfg(not(test))]
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("stress") => {
+            println!("Running Bellande filesystem concurrent stress test...");
+            return stress::concurrent_stress_from_env();
+        }
+        Some("large-device") => {
+            println!("Running Bellande filesystem large device tier...");
+            return large_device::large_sparse_device();
+        }
+        Some("build-golden") => {
+            println!("Building Bellande filesystem golden images...");
+            return golden::build_golden_images();
+        }
+        Some("replay") => {
+            let path = args.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "replay needs a .bfsrepro file")
+            })?;
+            println!("Replaying {}...", path);
+            return replay::replay_file(std::path::Path::new(&path));
+        }
+        Some("list") => {
+            for scenario in all_scenarios() {
+                println!("{}", scenario.name);
+            }
+            return Ok(());
+        }
+        Some("run") => {
+            let names: Vec<String> = args.collect();
+            let scenarios = select_scenarios(&names).map_err(|unknown| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown scenarios {:?}; `list` shows them all", unknown),
+                )
+            })?;
+            return run_scenarios(&scenarios);
+        }
+        Some(other) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown command {:?}\n{}", other, USAGE),
+            ));
+        }
+        None => {}
     }
 
-    #[test]
-    fn test_large_file_operations() -> io::Result<()> {
-        let ctx = TestContext::new()?;
-        large_file_operations(&ctx)
+    println!("Running Bellande filesystem integration tests...");
+    let scenarios = default_scenarios();
+    let skipped = all_scenarios().len() - scenarios.len();
+    if skipped > 0 {
+        println!(
+            "Skipping {} contract scenarios; build with --features contract-tests to run them",
+            skipped
+        );
     }
+    run_scenarios(&scenarios)
 }
 
+// Commands already time out individually; this bounds a whole scenario so
+// a harness-side hang still ends the run with the scenario's name
 #[cfg(not(test))]
-fn main() -> io::Result<()> {
-    println!("Running Bellande filesystem integration tests...");
-    let ctx = TestContext::new()?;
-    run_full_test_suite(&ctx)
+fn run_scenario(scenario: &Scenario) -> io::Result<()> {
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::thread;
+
+    let timeout = harness::env_timeout(SCENARIO_TIMEOUT_ENV, DEFAULT_SCENARIO_TIMEOUT);
+    let (sender, receiver) = mpsc::channel();
+    let run = scenario.run;
+    thread::spawn(move || {
+        let _ = sender.send(run());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} timed out after {:?}", scenario.name, timeout),
+        )),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(io::Error::other(format!("{} panicked", scenario.name)))
+        }
+    }
 }
 
 #[cfg(not(test))]
-fn run_full_test_suite(ctx: &TestContext) -> io::Result<()> {
-    format_device(ctx)?;
-    create_and_list_files(ctx)?;
-    write_and_read_file(ctx)?;
-    create_and_remove_directory(ctx)?;
-    filesystem_stats(ctx)?;
-    error_handling(ctx)?;
-    large_file_operations(ctx)?;
+fn run_scenarios(scenarios: &[&Scenario]) -> io::Result<()> {
+    for scenario in scenarios {
+        println!("Running {}...", scenario.name);
+        run_scenario(scenario)?;
+    }
     println!("All tests passed successfully!");
     Ok(())
 }
@@ -0,0 +1,235 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `bench` measures the filesystem on the device it is pointed at. The
+// workloads are `seq-write` and `seq-read` over `--size` bytes, `random-4k`
+// (random 4 KiB reads and writes inside one preallocated file) and
+// `metadata` (creating and removing files in one directory), `--ops` each
+// for the last two. `--workload` may repeat; without it all four run in that
+// order. Everything happens in a scratch directory that is removed before
+// exit, so the image is left as it was.
+//
+// Timings are never asserted on; only their internal consistency is. Under
+// `--output json` the report is an array with one flat record per workload.
+
+use crate::capacity::EXIT_NO_SPACE;
+use crate::cli::EXIT_USAGE;
+use crate::differential::listed_names;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::journal::tree_state;
+use crate::json::json_records;
+use std::collections::BTreeMap;
+use std::io;
+
+const WORKLOADS: &[&str] = &["seq-write", "seq-read", "random-4k", "metadata"];
+const BENCH_SIZE: u64 = 1024 * 1024;
+const BENCH_OPS: u64 = 200;
+const RANDOM_IO_LEN: u64 = 4096;
+
+// A previously created tree the benchmark must leave alone
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/keep"])?;
+    ctx.run_bellande_command(&["create", "--path", "/keep/file"])?;
+    Ok(())
+}
+
+fn bench_json(ctx: &TestContext, extra: &[&str]) -> io::Result<Vec<BTreeMap<String, String>>> {
+    let size = BENCH_SIZE.to_string();
+    let ops = BENCH_OPS.to_string();
+    let mut args = vec!["--output", "json", "bench", "--size", &size, "--ops", &ops];
+    args.extend_from_slice(extra);
+    let output = ctx.run_bellande_command(&args)?;
+    let json = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        json.trim().starts_with('['),
+        "bench --output json is not an array: {:?}",
+        json
+    );
+    Ok(json_records(&json))
+}
+
+fn number(record: &BTreeMap<String, String>, key: &str) -> f64 {
+    record
+        .get(key)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("bench record has no numeric {}: {:?}", key, record))
+}
+
+// Checks a record's figures against each other, returning the workload name
+fn check_record(record: &BTreeMap<String, String>) -> String {
+    let name = record.get("workload").cloned().unwrap_or_default();
+    let seconds = number(record, "seconds");
+    let operations = number(record, "operations");
+    let bytes = number(record, "bytes");
+    assert!(seconds > 0.0, "{}: no elapsed time", name);
+    let close = |actual: f64, expected: f64| (actual - expected).abs() <= expected * 0.01 + 1e-9;
+    assert!(
+        close(number(record, "ops_per_second"), operations / seconds),
+        "{}: ops_per_second does not match operations / seconds",
+        name
+    );
+    assert!(
+        close(
+            number(record, "throughput_bytes_per_second"),
+            bytes / seconds
+        ),
+        "{}: throughput does not match bytes / seconds",
+        name
+    );
+
+    let percentiles: Vec<f64> = ["p50", "p95", "p99", "max"]
+        .iter()
+        .map(|p| number(record, &format!("latency_{}_us", p)))
+        .collect();
+    assert!(
+        percentiles.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{}: latency percentiles out of order: {:?}",
+        name,
+        percentiles
+    );
+
+    let hits = number(record, "cache_hits");
+    let misses = number(record, "cache_misses");
+    assert!(hits + misses > 0.0, "{}: no cache lookups counted", name);
+    assert!(
+        (number(record, "cache_hit_rate") - hits / (hits + misses)).abs() < 0.001,
+        "{}: cache_hit_rate does not match the hit and miss counts",
+        name
+    );
+    name
+}
+
+pub(crate) fn all_workloads_report(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let before_tree = tree_state(ctx)?;
+    let before_stats = read_stats(ctx)?;
+
+    let records = bench_json(ctx, &[])?;
+    let names: Vec<String> = records.iter().map(check_record).collect();
+    assert_eq!(names, WORKLOADS, "one record per workload, in order");
+    for record in &records {
+        let (operations, bytes) = match record["workload"].as_str() {
+            "seq-write" | "seq-read" => (None, BENCH_SIZE),
+            "random-4k" => (Some(BENCH_OPS), BENCH_OPS * RANDOM_IO_LEN),
+            _ => (Some(BENCH_OPS), 0),
+        };
+        if let Some(operations) = operations {
+            assert_eq!(number(record, "operations"), operations as f64);
+        }
+        if bytes > 0 {
+            assert_eq!(number(record, "bytes"), bytes as f64, "{:?}", record);
+        }
+    }
+
+    // Creating files in one directory keeps hitting the same few blocks
+    let metadata = &records[3];
+    assert!(
+        number(metadata, "cache_hit_rate") > 0.5,
+        "metadata workload hit rate {}",
+        metadata["cache_hit_rate"]
+    );
+
+    assert_eq!(tree_state(ctx)?, before_tree, "bench left files behind");
+    assert_eq!(read_stats(ctx)?, before_stats, "bench leaked space");
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn selected_workloads(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let records = bench_json(ctx, &["--workload", "metadata", "--workload", "seq-read"])?;
+    let names: Vec<String> = records.iter().map(check_record).collect();
+    assert_eq!(names, ["metadata", "seq-read"]);
+
+    // The text report has one line per workload, led by its name
+    let size = BENCH_SIZE.to_string();
+    let output =
+        ctx.run_bellande_command(&["bench", "--size", &size, "--workload", "seq-write"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+    assert_eq!(lines.len(), 1, "{:?}", stdout);
+    assert!(lines[0].starts_with("seq-write"), "{:?}", lines[0]);
+    assert_eq!(
+        listed_names(&String::from_utf8_lossy(
+            &ctx.run_bellande_command(&["list", "--path", "/"])?.stdout
+        )),
+        ["keep".to_string()].into()
+    );
+    Ok(())
+}
+
+pub(crate) fn bench_errors(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let before_tree = tree_state(ctx)?;
+    let before_stats = read_stats(ctx)?;
+
+    for args in [
+        &["bench", "--workload", "bogus"][..],
+        &["bench", "--workload"],
+        &["bench", "--size", "4Q"],
+        &["bench", "--size", "0"],
+        &["bench", "--ops", "0"],
+        &["bench", "--ops", "many"],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+
+    // More than the device holds fails up front and cleans up
+    let too_big = (2 * DEFAULT_DEVICE_SIZE).to_string();
+    ctx.command(&["bench", "--workload", "seq-write", "--size", &too_big])
+        .assert()
+        .code(EXIT_NO_SPACE);
+    assert_eq!(tree_state(ctx)?, before_tree);
+    assert_eq!(read_stats(ctx)?, before_stats);
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    all_workloads_report,
+    #[contract]
+    selected_workloads,
+    #[contract]
+    bench_errors,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_record() {
+        let record: BTreeMap<String, String> = [
+            ("workload", "seq-read"),
+            ("operations", "256"),
+            ("bytes", "1048576"),
+            ("seconds", "0.5"),
+            ("ops_per_second", "512"),
+            ("throughput_bytes_per_second", "2097152"),
+            ("latency_p50_us", "10"),
+            ("latency_p95_us", "20"),
+            ("latency_p99_us", "20"),
+            ("latency_max_us", "75"),
+            ("cache_hits", "3"),
+            ("cache_misses", "1"),
+            ("cache_hit_rate", "0.75"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(check_record(&record), "seq-read");
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Binary data round-trips. Every transport must hand back exactly the bytes
+// it was given; comparisons are on raw bytes and checksums, never strings.
+// With `--input`/`--output` the payload goes through host files and stdout
+// is left to diagnostics.
+
+use crate::differential::{bytes_contain, content};
+use crate::golden::checksum;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use std::fs;
+use std::io;
+
+const RANDOM_LEN: usize = 1024 * 1024;
+
+type Transport = fn(&TestContext, &str, &[u8]) -> io::Result<Vec<u8>>;
+
+// Each transport writes `data` to `path` and returns what reading it back gave
+const TRANSPORTS: &[(&str, Transport)] = &[("stdin/stdout", stdio_round_trip)];
+
+// Transports through options and commands the binary does not have yet
+const CONTRACT_TRANSPORTS: &[(&str, Transport)] = &[
+    ("--input/--output", file_round_trip),
+    ("import/export", import_export_round_trip),
+];
+
+fn transports() -> impl Iterator<Item = &'static (&'static str, Transport)> {
+    let contract: &[(&str, Transport)] = if cfg!(feature = "contract-tests") {
+        CONTRACT_TRANSPORTS
+    } else {
+        &[]
+    };
+    TRANSPORTS.iter().chain(contract)
+}
+
+fn stdio_round_trip(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    ctx.run_bellande_command(&["create", "--path", path])?;
+    let output = write_file(ctx, path, data)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "write {} failed: {:?}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+// Payload bytes must not leak into stdout when they go to a host file
+fn assert_no_payload(command: &str, stdout: &[u8], data: &[u8]) {
+    let probe = &data[..data.len().min(64)];
+    assert!(
+        std::str::from_utf8(stdout).is_ok() && !(probe.len() > 8 && bytes_contain(stdout, probe)),
+        "{} printed payload bytes on stdout",
+        command
+    );
+}
+
+fn file_round_trip(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let name = path.trim_start_matches('/');
+    let input = ctx.temp_dir.path().join(format!("{}.in", name));
+    let output = ctx.temp_dir.path().join(format!("{}.out", name));
+    fs::write(&input, data)?;
+
+    ctx.run_bellande_command(&["create", "--path", path])?;
+    let written =
+        ctx.run_bellande_command(&["write", "--path", path, "--input", &input.to_string_lossy()])?;
+    assert_no_payload("write --input", &written.stdout, data);
+
+    let read = ctx.run_bellande_command(&[
+        "read",
+        "--path",
+        path,
+        "--output",
+        &output.to_string_lossy(),
+    ])?;
+    assert_no_payload("read --output", &read.stdout, data);
+    fs::read(&output)
+}
+
+// Through a one-file host directory, imported next to `path` and exported again
+fn import_export_round_trip(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let name = path.trim_start_matches('/');
+    let source = ctx.temp_dir.path().join(format!("{}.import", name));
+    let exported = ctx.temp_dir.path().join(format!("{}.export", name));
+    fs::create_dir_all(&source)?;
+    fs::write(source.join(name), data)?;
+
+    let target = format!("{}.d", path);
+    ctx.run_bellande_command(&[
+        "import",
+        "--from",
+        &source.to_string_lossy(),
+        "--to",
+        &target,
+    ])?;
+    ctx.run_bellande_command(&[
+        "export",
+        "--from",
+        &target,
+        "--to",
+        &exported.to_string_lossy(),
+    ])?;
+    fs::read(exported.join(name))
+}
+
+// 0..=255 over and over, ending partway through a block and a cycle
+pub(crate) fn repeated_byte_cycle(len: usize) -> Vec<u8> {
+    (0..len).map(|index| index as u8).collect()
+}
+
+// Every byte value, long zero and 0xFF runs, and lone high bytes between them
+pub(crate) fn pathological_buffer() -> Vec<u8> {
+    let mut data: Vec<u8> = (0..=255u8).collect();
+    data.extend(std::iter::repeat_n(0u8, 64 * 1024));
+    data.extend((0..=255u8).rev());
+    data.extend(std::iter::repeat_n(0xFFu8, 64 * 1024));
+    for value in 0..4096u32 {
+        data.push(if value % 2 == 0 { 0x80 } else { 0x00 });
+    }
+    data.extend(b"\r\n\n\r\x1b[0m\0");
+    data
+}
+
+fn assert_same_bytes(transport: &str, label: &str, expected: &[u8], actual: &[u8]) {
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "{} round-trip of {} changed the length",
+        transport,
+        label
+    );
+    assert_eq!(
+        checksum(actual),
+        checksum(expected),
+        "{} round-trip of {} changed the checksum",
+        transport,
+        label
+    );
+    if let Some(offset) = expected.iter().zip(actual).position(|(a, b)| a != b) {
+        panic!(
+            "{} round-trip of {} differs first at byte {}",
+            transport, label, offset
+        );
+    }
+}
+
+pub(crate) fn binary_round_trips(ctx: &TestContext) -> io::Result<()> {
+    let buffers = [
+        ("random", content(0xB17E_5EED, RANDOM_LEN)),
+        ("pathological", pathological_buffer()),
+        ("cycle", repeated_byte_cycle(256 * 1024 + 4093)),
+        ("small_cycle", repeated_byte_cycle(300)),
+    ];
+
+    format_device(ctx)?;
+    for (index, (transport, round_trip)) in transports().enumerate() {
+        for (label, data) in &buffers {
+            let path = format!("/{}_{}.bin", label, index);
+            let actual = round_trip(ctx, &path, data)?;
+            assert_same_bytes(transport, label, data, &actual);
+        }
+    }
+    Ok(())
+}
+
+scenarios! {
+    binary_round_trips,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pathological_buffer_covers_every_byte() {
+        let data = pathological_buffer();
+        for value in 0..=255u8 {
+            assert!(data.contains(&value), "missing byte {:#04x}", value);
+        }
+    }
+
+    #[test]
+    fn test_repeated_byte_cycle() {
+        let data = repeated_byte_cycle(256 * 2 + 3);
+        assert_eq!(data.len(), 515);
+        assert_eq!(&data[..256], &data[256..512]);
+        assert_eq!(&data[512..], &[0, 1, 2]);
+    }
+}
@@ -0,0 +1,302 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// LRU block cache. Blocks stay cached for the whole command, so repeated
+// metadata lookups read the device once. The global `--cache-mode` picks
+// `write-back` (the default: sequential data writes are coalesced and
+// everything is flushed before the process exits successfully or on `sync`)
+// or `write-through`, where every block write goes straight to the device.
+// The cost is measured in read and write syscalls the binary makes, taken
+// from /proc/<pid>/io, since elapsed time is too noisy to assert on.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{bytes_contain, content};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, TestContext, DEFAULT_DEVICE_SIZE};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const BLOCK_SIZE: u32 = 1024;
+const CHUNK_LEN: usize = 1024;
+const TOTAL_LEN: usize = 1024 * 1024;
+// Without a cache every 1 KiB block costs at least one write syscall, plus
+// bitmap and inode updates; a cached run needs a small fraction of that
+const MAX_WRITE_SYSCALLS: u64 = (TOTAL_LEN / CHUNK_LEN / 8) as u64;
+// Files in one directory for the lookup test; many inodes share each inode
+// table block, so a cached listing reads far fewer blocks than it has entries
+const LOOKUP_FILES: usize = 128;
+const MAX_LOOKUP_READ_SYSCALLS: u64 = (LOOKUP_FILES / 2) as u64;
+
+const CACHE_MODES: &[&str] = &["write-back", "write-through"];
+
+// Runs the command in a shell that samples one counter of its own I/O
+// accounting (the first argument, e.g. `syscw`) before and after; a reaped
+// child's counts are added to its parent's, and the shell only uses builtins
+// in between
+const MEASURE_SCRIPT: &str = r#"
+counter="$1:"; shift
+while read -r key value; do [ "$key" = "$counter" ] && before=$value; done < /proc/$$/io
+"$@" > /dev/null || exit $?
+while read -r key value; do [ "$key" = "$counter" ] && after=$value; done < /proc/$$/io
+echo $((after - before))
+"#;
+
+fn cache_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// Syscalls counted by `counter` for one command, with `input` fed in
+// CHUNK_LEN pieces
+pub(crate) fn syscalls(
+    ctx: &TestContext,
+    counter: &str,
+    args: &[&str],
+    input: &[u8],
+) -> io::Result<u64> {
+    let mut child = Command::new("sh")
+        .args(["-c", MEASURE_SCRIPT, "sh", counter])
+        .arg(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        for chunk in input.chunks(CHUNK_LEN) {
+            stdin.write_all(chunk)?;
+            stdin.flush()?;
+        }
+    }
+    let output = child.wait_with_output()?;
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::other(format!("no syscall count: {:?}", output.stdout)))
+}
+
+fn write_syscalls(ctx: &TestContext, args: &[&str], input: &[u8]) -> io::Result<u64> {
+    syscalls(ctx, "syscw", args, input)
+}
+
+pub(crate) fn chunked_write_is_coalesced(ctx: &TestContext) -> io::Result<()> {
+    if !Path::new("/proc/self/io").exists() {
+        println!("Skipping write syscall count: /proc/self/io is not available");
+        return Ok(());
+    }
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/chunked.bin"])?;
+
+    let data = content(1, TOTAL_LEN);
+    let syscalls = write_syscalls(ctx, &["write", "--path", "/chunked.bin"], &data)?;
+    assert!(
+        syscalls <= MAX_WRITE_SYSCALLS,
+        "writing {} bytes in {} byte chunks took {} write syscalls (limit {})",
+        TOTAL_LEN,
+        CHUNK_LEN,
+        syscalls,
+        MAX_WRITE_SYSCALLS
+    );
+
+    let output = ctx.run_bellande_command(&["read", "--path", "/chunked.bin"])?;
+    assert!(
+        output.stdout == data,
+        "coalesced write read back differently"
+    );
+    Ok(())
+}
+
+pub(crate) fn flushed_before_exit(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/flushed.bin"])?;
+    let data = content(2, 64 * 1024);
+    ctx.command(&["write", "--path", "/flushed.bin"])
+        .write_stdin(data.clone())
+        .assert()
+        .success();
+
+    // Once the command has exited the bytes are in the device file itself
+    let device = fs::read(&ctx.device_path)?;
+    for block in data.chunks(BLOCK_SIZE as usize) {
+        assert!(
+            bytes_contain(&device, block),
+            "a written block never reached the device"
+        );
+    }
+
+    // An explicit sync with nothing pending changes nothing
+    ctx.run_bellande_command(&["sync"])?;
+    assert!(
+        fs::read(&ctx.device_path)? == device,
+        "sync rewrote a clean device"
+    );
+    Ok(())
+}
+
+pub(crate) fn no_stale_reads(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/rmw.bin"])?;
+    let mut model = content(3, 10 * BLOCK_SIZE as usize + 100);
+    ctx.command(&["write", "--path", "/rmw.bin"])
+        .write_stdin(model.clone())
+        .assert()
+        .success();
+
+    // Each of these reads a block the same command has just written
+    for (offset, len) in [(5, 10), (1020, 8), (1024 * 3 + 1, 2050)] {
+        let patch = content(offset, len);
+        ctx.command(&[
+            "write",
+            "--path",
+            "/rmw.bin",
+            "--offset",
+            &offset.to_string(),
+        ])
+        .write_stdin(patch.clone())
+        .assert()
+        .success();
+        model[offset as usize..offset as usize + len].copy_from_slice(&patch);
+    }
+    let tail = content(4, 3000);
+    ctx.command(&["write", "--append", "--path", "/rmw.bin"])
+        .write_stdin(tail.clone())
+        .assert()
+        .success();
+    model.extend_from_slice(&tail);
+
+    let output = ctx.run_bellande_command(&["read", "--path", "/rmw.bin"])?;
+    assert!(output.stdout == model, "a cached block was served stale");
+    Ok(())
+}
+
+pub(crate) fn cache_modes_agree(ctx: &TestContext) -> io::Result<()> {
+    if !Path::new("/proc/self/io").exists() {
+        println!("Skipping cache mode comparison: /proc/self/io is not available");
+        return Ok(());
+    }
+    format_device(ctx)?;
+    let data = content(5, TOTAL_LEN);
+    let mut counts = Vec::new();
+    for mode in CACHE_MODES {
+        let path = format!("/{}.bin", mode);
+        ctx.run_bellande_command(&["--cache-mode", mode, "create", "--path", &path])?;
+        let args = ["--cache-mode", mode, "write", "--path", &path];
+        counts.push(write_syscalls(ctx, &args, &data)?);
+
+        let output = ctx.run_bellande_command(&["--cache-mode", mode, "read", "--path", &path])?;
+        assert!(output.stdout == data, "{} read back differently", mode);
+    }
+    // Write-through cannot coalesce: each block is written as it is filled
+    assert!(
+        counts[1] > MAX_WRITE_SYSCALLS && counts[1] > counts[0],
+        "write-through took {} write syscalls, write-back {}",
+        counts[1],
+        counts[0]
+    );
+
+    // Nothing is left dirty in write-through mode either
+    let device = fs::read(&ctx.device_path)?;
+    ctx.run_bellande_command(&["--cache-mode", "write-through", "sync"])?;
+    assert!(fs::read(&ctx.device_path)? == device);
+    assert_fsck_clean(ctx)?;
+
+    ctx.command(&["--cache-mode", "write-around", "stats"])
+        .assert()
+        .code(EXIT_USAGE);
+    Ok(())
+}
+
+pub(crate) fn repeated_lookups_cached(ctx: &TestContext) -> io::Result<()> {
+    if !Path::new("/proc/self/io").exists() {
+        println!("Skipping lookup read count: /proc/self/io is not available");
+        return Ok(());
+    }
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/many"])?;
+    for index in 0..LOOKUP_FILES {
+        ctx.run_bellande_command(&["create", "--path", &format!("/many/f{:03}", index)])?;
+    }
+
+    for mode in CACHE_MODES {
+        let args = ["--cache-mode", mode, "list", "--recursive", "--path", "/"];
+        let reads = syscalls(ctx, "syscr", &args, &[])?;
+        assert!(
+            reads <= MAX_LOOKUP_READ_SYSCALLS,
+            "{}: listing {} files took {} read syscalls (limit {})",
+            mode,
+            LOOKUP_FILES,
+            reads,
+            MAX_LOOKUP_READ_SYSCALLS
+        );
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    chunked_write_is_coalesced(cache_context()?),
+    #[contract]
+    flushed_before_exit(cache_context()?),
+    #[contract]
+    no_stale_reads(cache_context()?),
+    #[contract]
+    cache_modes_agree(cache_context()?),
+    #[contract]
+    repeated_lookups_cached(cache_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_script_counts_writes() -> io::Result<()> {
+        if !Path::new("/proc/self/io").exists() {
+            return Ok(());
+        }
+        let output = Command::new("sh")
+            .args([
+                "-c",
+                MEASURE_SCRIPT,
+                "sh",
+                "syscw",
+                "dd",
+                "if=/dev/zero",
+                "of=/dev/null",
+            ])
+            .args(["bs=1", "count=100"])
+            .stderr(Stdio::null())
+            .output()?;
+        let count: u64 = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(
+            (100..200).contains(&count),
+            "counted {} write syscalls",
+            count
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Per-block data checksums. `format --checksums crc32` sets a superblock
+// feature flag that binaries without checksum support must refuse to write
+// under, and keeps a CRC for every data block in its own region, checked on
+// every read and updated on every write. A mismatch fails the read with
+// "Checksum mismatch at block N of /path", N being the device block, and
+// prints none of the data. `scrub` reads every allocated block and lists each
+// mismatch the same way, exiting like `fsck`; `fsck` reports them too. Images
+// formatted without `--checksums` behave exactly as before.
+//
+// Corruption is made without knowing the layout: files hold block-sized
+// pieces of distinct content, so a block can be found in the device by value.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_CHECKSUM_MISMATCH, EXIT_INVALID};
+use crate::fsck::{assert_fsck_clean, EXIT_FSCK_CLEAN, EXIT_FSCK_UNCORRECTED};
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+pub(crate) const CHECKSUM_FORMAT_ARGS: &[&str] = &["--checksums", "crc32"];
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+pub(crate) const FILE_BLOCKS: usize = 6;
+const FILES: &[(&str, u64)] = &[("/a.bin", 1), ("/dir/b.bin", 2), ("/dir/c.bin", 3)];
+
+pub(crate) fn assert_scrub_clean(ctx: &TestContext) -> io::Result<()> {
+    ctx.command(&["scrub"]).assert().code(EXIT_FSCK_CLEAN);
+    Ok(())
+}
+
+fn checksum_context() -> io::Result<TestContext> {
+    Ok(
+        TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?
+            .with_format_args(CHECKSUM_FORMAT_ARGS),
+    )
+}
+
+fn plain_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+pub(crate) fn mismatch(block: usize, path: &str) -> String {
+    format!("Checksum mismatch at block {} of {}", block, path)
+}
+
+pub(crate) fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, seed) in FILES {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &content(*seed, FILE_BLOCKS * BLOCK))?
+            .status
+            .success());
+    }
+    Ok(())
+}
+
+// Flips one byte of the device block holding `block`, returning its number
+fn corrupt(ctx: &TestContext, block: &[u8]) -> io::Result<usize> {
+    let mut device = fs::read(&ctx.device_path)?;
+    let index = device
+        .chunks(BLOCK)
+        .position(|candidate| candidate == block)
+        .expect("data block not found on the device");
+    device[index * BLOCK + 17] ^= 0x40;
+    fs::write(&ctx.device_path, device)?;
+    Ok(index)
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn checksummed_round_trip(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Checksums: crc32"));
+
+    // Every way of changing data keeps the checksums current
+    let mut expected = content(1, FILE_BLOCKS * BLOCK);
+    let patch = content(4, 100);
+    ctx.command(&["write", "--path", "/a.bin", "--offset", "1000"])
+        .write_stdin(patch.clone())
+        .assert()
+        .success();
+    expected[1000..1100].copy_from_slice(&patch);
+    let tail = content(5, 3000);
+    ctx.command(&["write", "--append", "--path", "/a.bin"])
+        .write_stdin(tail.clone())
+        .assert()
+        .success();
+    expected.extend_from_slice(&tail);
+    ctx.run_bellande_command(&["truncate", "--path", "/a.bin", "--size", "7000"])?;
+    expected.truncate(7000);
+    ctx.run_bellande_command(&["move", "--from", "/dir/c.bin", "--to", "/c.bin"])?;
+    ctx.run_bellande_command(&["remove", "--path", "/dir/b.bin"])?;
+
+    assert!(read(ctx, "/a.bin")? == expected);
+    assert!(read(ctx, "/c.bin")? == content(3, FILE_BLOCKS * BLOCK));
+    assert_scrub_clean(ctx)?;
+    assert_fsck_clean(ctx)?;
+
+    for value in ["sha1", "CRC32", ""] {
+        ctx.command(&["format", "--yes", "--checksums", value])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--checksums"));
+    }
+    Ok(())
+}
+
+pub(crate) fn bit_rot_detected(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let a = content(1, FILE_BLOCKS * BLOCK);
+    let b = content(2, FILE_BLOCKS * BLOCK);
+    let a_block = corrupt(ctx, &a[2 * BLOCK..3 * BLOCK])?;
+    let b_block = corrupt(ctx, &b[5 * BLOCK..])?;
+    let damaged = fs::read(&ctx.device_path)?;
+
+    for (path, block) in [("/a.bin", a_block), ("/dir/b.bin", b_block)] {
+        ctx.command(&["read", "--path", path])
+            .assert()
+            .code(EXIT_CHECKSUM_MISMATCH)
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::contains(mismatch(block, path)));
+    }
+    // Only the blocks a read touches are checked
+    let output = ctx.run_bellande_command(&[
+        "read",
+        "--path",
+        "/a.bin",
+        "--offset",
+        "0",
+        "--length",
+        &(2 * BLOCK).to_string(),
+    ])?;
+    assert!(output.stdout == a[..2 * BLOCK]);
+    assert!(read(ctx, "/dir/c.bin")? == content(3, FILE_BLOCKS * BLOCK));
+
+    // A full scan finds both without being told where to look, and writes nothing
+    for command in ["scrub", "fsck"] {
+        let output = ctx.run_raw(&[command])?;
+        assert_eq!(
+            output.status.code(),
+            Some(EXIT_FSCK_UNCORRECTED),
+            "{}",
+            command
+        );
+        let report = String::from_utf8_lossy(&output.stdout).into_owned()
+            + &String::from_utf8_lossy(&output.stderr);
+        for (path, block) in [("/a.bin", a_block), ("/dir/b.bin", b_block)] {
+            assert!(
+                report.contains(&mismatch(block, path)),
+                "{} did not report block {}: {:?}",
+                command,
+                block,
+                report
+            );
+        }
+        assert!(fs::read(&ctx.device_path)? == damaged, "{} wrote", command);
+    }
+
+    // Rewriting the data heals the file
+    assert!(write_file(ctx, "/a.bin", &a)?.status.success());
+    assert!(read(ctx, "/a.bin")? == a);
+    ctx.run_bellande_command(&["remove", "--path", "/dir/b.bin"])?;
+    assert_scrub_clean(ctx)
+}
+
+pub(crate) fn unchecksummed_images_unchanged(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Checksums: none"));
+
+    // Nothing to verify against, so rot reads back as stored
+    let mut a = content(1, FILE_BLOCKS * BLOCK);
+    let block = corrupt(ctx, &a[BLOCK..2 * BLOCK])?;
+    let device = fs::read(&ctx.device_path)?;
+    a[BLOCK + 17] ^= 0x40;
+    assert!(read(ctx, "/a.bin")? == a);
+    assert!(device[block * BLOCK..(block + 1) * BLOCK] == a[BLOCK..2 * BLOCK]);
+    assert_fsck_clean(ctx)?;
+
+    ctx.command(&["scrub"])
+        .assert()
+        .code(EXIT_INVALID)
+        .stderr(predicate::str::contains("--checksums"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    checksummed_round_trip(checksum_context()?),
+    #[contract]
+    bit_rot_detected(checksum_context()?),
+    #[contract]
+    unchecksummed_images_unchanged(plain_context()?),
+}
@@ -0,0 +1,241 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Behavior at capacity: running out of blocks or inodes must fail cleanly
+// with distinct errors, leave no partial files behind, and keep `stats` exact.
+
+use crate::differential::{bytes_contain, content};
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use std::io;
+
+pub(crate) const TINY_DEVICE_SIZE: u64 = 256 * 1024;
+pub(crate) const TINY_BLOCK_SIZE: u32 = 1024;
+const TINY_INODE_COUNT: &str = "16";
+
+// Exit status for both out-of-space conditions, matching errno ENOSPC
+pub(crate) const EXIT_NO_SPACE: i32 = 28;
+pub(crate) const NO_SPACE_MESSAGE: &str = "No space left on device";
+pub(crate) const NO_INODES_MESSAGE: &str = "No free inodes";
+
+pub(crate) fn tiny_context() -> io::Result<TestContext> {
+    TestContext::with_options(TINY_DEVICE_SIZE, Some(TINY_BLOCK_SIZE))
+}
+
+fn blocks(count: u64) -> usize {
+    count as usize * TINY_BLOCK_SIZE as usize
+}
+
+pub(crate) fn fill_device(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/fill.bin"])?;
+    let before = read_stats(ctx)?;
+
+    let data = vec![b'F'; blocks(before.free_blocks + 8)];
+    let output = write_file(ctx, "/fill.bin", &data)?;
+    assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(NO_SPACE_MESSAGE));
+
+    // The failed write must not leave a partial file or leak blocks
+    assert_eq!(read_stats(ctx)?, before);
+    let output = ctx.run_bellande_command(&["read", "--path", "/fill.bin"])?;
+    assert!(!bytes_contain(&output.stdout, &data[..blocks(1)]));
+
+    Ok(())
+}
+
+pub(crate) fn space_is_reusable(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = read_stats(ctx)?;
+
+    ctx.run_bellande_command(&["create", "--path", "/reuse.bin"])?;
+    let created = read_stats(ctx)?;
+    assert_eq!(created.free_inodes, empty.free_inodes - 1);
+    assert_eq!(created.free_blocks, empty.free_blocks);
+
+    let data = vec![b'R'; blocks(10)];
+    assert!(write_file(ctx, "/reuse.bin", &data)?.status.success());
+    let written = read_stats(ctx)?;
+    assert_eq!(written.free_blocks, created.free_blocks - 10);
+
+    ctx.run_bellande_command(&["remove", "--path", "/reuse.bin"])?;
+    assert_eq!(read_stats(ctx)?, empty);
+
+    ctx.run_bellande_command(&["create", "--path", "/again.bin"])?;
+    assert!(write_file(ctx, "/again.bin", &data)?.status.success());
+    assert_eq!(read_stats(ctx)?, written);
+
+    Ok(())
+}
+
+pub(crate) fn inode_exhaustion(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let mut stats = read_stats(ctx)?;
+    let mut index = 0;
+
+    while stats.free_inodes > 0 {
+        ctx.run_bellande_command(&["create", "--path", &format!("/inode{}", index)])?;
+        let next = read_stats(ctx)?;
+        assert_eq!(next.free_inodes, stats.free_inodes - 1);
+        assert_eq!(next.free_blocks, stats.free_blocks);
+        stats = next;
+        index += 1;
+    }
+
+    let output = ctx.run_raw(&["create", "--path", "/one_too_many"])?;
+    assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(NO_INODES_MESSAGE));
+    assert!(!stderr.contains(NO_SPACE_MESSAGE));
+
+    // Blocks are still available; only the inode table ran out
+    assert!(stats.free_blocks > 0);
+    assert_eq!(read_stats(ctx)?, stats);
+
+    Ok(())
+}
+
+pub(crate) fn failed_write_keeps_contents(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let previous = content(1, blocks(5) + 123);
+    ctx.run_bellande_command(&["create", "--path", "/victim.bin"])?;
+    assert!(write_file(ctx, "/victim.bin", &previous)?.status.success());
+    let before = read_stats(ctx)?;
+
+    // Replacing or appending past the free space fails and changes nothing;
+    // `--append` is a contract option
+    let oversized = vec![b'O'; blocks(before.free_blocks + 10)];
+    let appends: &[bool] = if cfg!(feature = "contract-tests") {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    for &append in appends {
+        let mut args = vec!["write", "--path", "/victim.bin"];
+        if append {
+            args.push("--append");
+        }
+        let output = ctx.command(&args).write_stdin(oversized.clone()).output()?;
+        assert_eq!(
+            output.status.code(),
+            Some(EXIT_NO_SPACE),
+            "append: {}",
+            append
+        );
+        assert!(String::from_utf8_lossy(&output.stderr).contains(NO_SPACE_MESSAGE));
+
+        let output = ctx.run_bellande_command(&["read", "--path", "/victim.bin"])?;
+        assert!(
+            output.stdout == previous,
+            "append {}: previous contents lost",
+            append
+        );
+        assert_eq!(read_stats(ctx)?, before, "append {}: blocks leaked", append);
+    }
+    Ok(())
+}
+
+pub(crate) fn files_survive_exhaustion(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let mut written = Vec::new();
+
+    // Fill with small files until a write no longer fits
+    for index in 0.. {
+        let path = format!("/fill{}.bin", index);
+        let data = content(index, blocks(3) + 17);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let output = write_file(ctx, &path, &data)?;
+        if !output.status.success() {
+            assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+            assert!(String::from_utf8_lossy(&output.stderr).contains(NO_SPACE_MESSAGE));
+            let output = ctx.run_bellande_command(&["read", "--path", &path])?;
+            assert!(
+                output.stdout.is_empty(),
+                "{} kept part of a failed write",
+                path
+            );
+            break;
+        }
+        written.push((path, data));
+    }
+    assert!(!written.is_empty());
+
+    for (path, data) in &written {
+        let output = ctx.run_bellande_command(&["read", "--path", path])?;
+        assert!(
+            output.stdout == *data,
+            "{} changed after the device filled",
+            path
+        );
+    }
+
+    // Freeing one file makes room for the same write again
+    let (path, data) = written.remove(0);
+    ctx.run_bellande_command(&["remove", "--path", &path])?;
+    ctx.run_bellande_command(&["create", "--path", "/refill.bin"])?;
+    assert!(write_file(ctx, "/refill.bin", &data)?.status.success());
+    Ok(())
+}
+
+pub(crate) fn mkdir_without_inodes(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/kept.txt"])?;
+    assert!(write_file(ctx, "/kept.txt", b"kept")?.status.success());
+    let mut index = 0;
+    while read_stats(ctx)?.free_inodes > 0 {
+        ctx.run_bellande_command(&["mkdir", "--path", &format!("/dir{}", index)])?;
+        index += 1;
+    }
+    let full = read_stats(ctx)?;
+
+    for args in [
+        &["mkdir", "--path", "/one_too_many"][..],
+        &["create", "--path", "/dir0/one_too_many"][..],
+    ] {
+        let output = ctx.run_raw(args)?;
+        assert_eq!(output.status.code(), Some(EXIT_NO_SPACE), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(NO_INODES_MESSAGE),
+            "{:?}: {:?}",
+            args,
+            stderr
+        );
+        assert!(!stderr.contains("panicked"), "{:?} panicked", args);
+    }
+    assert_eq!(read_stats(ctx)?, full);
+    let output = ctx.run_bellande_command(&["read", "--path", "/kept.txt"])?;
+    assert_eq!(output.stdout, b"kept");
+    Ok(())
+}
+
+// `--inodes` is a contract option; without it the tiny device runs out of
+// its default inode table instead, only later
+pub(crate) fn inode_limited_context() -> io::Result<TestContext> {
+    let ctx = tiny_context()?;
+    if cfg!(feature = "contract-tests") {
+        Ok(ctx.with_format_args(&["--inodes", TINY_INODE_COUNT]))
+    } else {
+        Ok(ctx)
+    }
+}
+
+scenarios! {
+    fill_device(tiny_context()?),
+    space_is_reusable(tiny_context()?),
+    inode_exhaustion(inode_limited_context()?),
+    failed_write_keeps_contents(tiny_context()?),
+    files_survive_exhaustion(tiny_context()?),
+    mkdir_without_inodes(inode_limited_context()?),
+}
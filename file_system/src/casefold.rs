@@ -0,0 +1,312 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Case-insensitive filenames. `format --casefold` sets a superblock feature
+// bit, shown by `stats` as "Casefold: on". On such an image every name is
+// stored NFC-normalized and lookups compare names after normalization and
+// Unicode full case folding, so `/Docs/ReadMe.TXT` and `/docs/readme.txt`
+// are one file and creating one while the other exists fails with
+// EXIT_ALREADY_EXISTS. Names keep the case they were created or last moved
+// with. Hashed directory indexes hash the folded name. Images formatted
+// without the flag compare names byte for byte, as before; `filenames`
+// covers that side.
+
+use crate::differential::listed_names;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const CASEFOLD: &str = "Casefold: on";
+const CASE_SENSITIVE: &str = "Casefold: off";
+const INDEXED_ENTRIES: usize = 300;
+
+struct FoldCase {
+    label: &'static str,
+    // The spelling the file is created with, and listed with afterwards
+    created: &'static str,
+    listed: &'static str,
+    // Other spellings that must find the same file
+    lookups: &'static [&'static str],
+}
+
+const FOLD_CASES: &[FoldCase] = &[
+    FoldCase {
+        label: "ASCII",
+        created: "ReadMe.TXT",
+        listed: "ReadMe.TXT",
+        lookups: &["readme.txt", "README.TXT", "rEADmE.tXT"],
+    },
+    FoldCase {
+        label: "Latin-1",
+        created: "R\u{00C9}SUM\u{00C9}.doc",
+        listed: "R\u{00C9}SUM\u{00C9}.doc",
+        lookups: &["r\u{00E9}sum\u{00E9}.doc", "re\u{0301}sume\u{0301}.DOC"],
+    },
+    FoldCase {
+        label: "decomposed input is stored composed",
+        created: "cafe\u{0301}.txt",
+        listed: "caf\u{00E9}.txt",
+        lookups: &["Caf\u{00E9}.txt", "CAF\u{00C9}.TXT", "CAFE\u{0301}.txt"],
+    },
+    FoldCase {
+        label: "Greek final sigma",
+        created: "\u{039F}\u{0394}\u{039F}\u{03A3}",
+        listed: "\u{039F}\u{0394}\u{039F}\u{03A3}",
+        lookups: &[
+            "\u{03BF}\u{03B4}\u{03BF}\u{03C2}",
+            "\u{03BF}\u{03B4}\u{03BF}\u{03C3}",
+        ],
+    },
+    FoldCase {
+        label: "Cyrillic",
+        created: "\u{041F}\u{0440}\u{0438}\u{0432}\u{0435}\u{0442}",
+        listed: "\u{041F}\u{0440}\u{0438}\u{0432}\u{0435}\u{0442}",
+        lookups: &["\u{043F}\u{0440}\u{0438}\u{0432}\u{0435}\u{0442}"],
+    },
+    FoldCase {
+        label: "full folding of sharp s",
+        created: "stra\u{00DF}e",
+        listed: "stra\u{00DF}e",
+        lookups: &["STRASSE", "strasse"],
+    },
+];
+
+fn casefold_context() -> io::Result<TestContext> {
+    Ok(
+        TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?
+            .with_format_args(&["--casefold"]),
+    )
+}
+
+fn plain_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn casefold_recorded(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(CASEFOLD));
+
+    // The bit lives on the device, not in the command line
+    let image = ctx.temp_dir.path().join("casefold.img");
+    fs::copy(&ctx.device_path, &image)?;
+    TestContext::from_image(&image)?
+        .command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(CASEFOLD));
+
+    let plain = plain_context()?;
+    format_device(&plain)?;
+    plain
+        .command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(CASE_SENSITIVE));
+    Ok(())
+}
+
+pub(crate) fn case_insensitive_lookup(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for (index, case) in FOLD_CASES.iter().enumerate() {
+        let dir = format!("/case{}", index);
+        let path = format!("{}/{}", dir, case.created);
+        let data = format!("{}\n", case.label).into_bytes();
+        ctx.run_bellande_command(&["mkdir", "--path", &dir])?;
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        assert!(write_file(ctx, &path, &data)?.status.success());
+        assert_eq!(
+            names_in(ctx, &dir)?,
+            BTreeSet::from([case.listed.to_string()]),
+            "{}: listed name",
+            case.label
+        );
+
+        for lookup in case.lookups {
+            let other = format!("{}/{}", dir, lookup);
+            assert!(
+                read(ctx, &other)? == data,
+                "{}: {:?} found another file",
+                case.label,
+                lookup
+            );
+            ctx.command(&["create", "--path", &other])
+                .assert()
+                .code(EXIT_ALREADY_EXISTS);
+            ctx.command(&["mkdir", "--path", &other])
+                .assert()
+                .code(EXIT_ALREADY_EXISTS);
+        }
+        assert_eq!(
+            names_in(ctx, &dir)?.len(),
+            1,
+            "{}: a duplicate was created",
+            case.label
+        );
+
+        // Any spelling removes it
+        let last = format!("{}/{}", dir, case.lookups[case.lookups.len() - 1]);
+        ctx.run_bellande_command(&["remove", "--path", &last])?;
+        assert!(names_in(ctx, &dir)?.is_empty());
+        ctx.command(&["read", "--path", &path])
+            .assert()
+            .code(EXIT_NOT_FOUND);
+    }
+
+    // Every component of a path folds, directories included
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", "/Docs/Notes"])?;
+    ctx.run_bellande_command(&["create", "--path", "/docs/NOTES/Todo.md"])?;
+    assert_eq!(
+        names_in(ctx, "/DOCS")?,
+        BTreeSet::from(["Notes".to_string()])
+    );
+    assert_eq!(
+        names_in(ctx, "/docs/notes")?,
+        BTreeSet::from(["Todo.md".to_string()])
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn case_only_renames(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/readme.txt"])?;
+    assert!(write_file(ctx, "/readme.txt", b"kept")?.status.success());
+
+    // Moving a file onto its own name in another case only changes the case
+    ctx.run_bellande_command(&["move", "--from", "/readme.txt", "--to", "/README.txt"])?;
+    assert_eq!(
+        names_in(ctx, "/")?,
+        BTreeSet::from(["README.txt".to_string()])
+    );
+    assert!(read(ctx, "/readme.TXT")? == b"kept");
+
+    // A different file under a case variant is an existing destination
+    ctx.run_bellande_command(&["create", "--path", "/Other.txt"])?;
+    assert!(write_file(ctx, "/Other.txt", b"other")?.status.success());
+    ctx.command(&["move", "--from", "/other.TXT", "--to", "/readme.txt"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    ctx.run_bellande_command(&[
+        "move",
+        "--force",
+        "--from",
+        "/other.TXT",
+        "--to",
+        "/readme.txt",
+    ])?;
+    assert_eq!(
+        names_in(ctx, "/")?,
+        BTreeSet::from(["readme.txt".to_string()])
+    );
+    assert!(read(ctx, "/README.TXT")? == b"other");
+
+    // Across directories the destination's spelling is used
+    ctx.run_bellande_command(&["mkdir", "--path", "/Dir"])?;
+    ctx.run_bellande_command(&["move", "--from", "/README.TXT", "--to", "/dir/Final.txt"])?;
+    assert_eq!(
+        names_in(ctx, "/DIR")?,
+        BTreeSet::from(["Final.txt".to_string()])
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn casefold_indexed_directory(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    // Populated in one import; names that collide after folding are refused
+    let host = ctx.temp_dir.path().join("many");
+    fs::create_dir_all(&host)?;
+    let mut expected = BTreeSet::new();
+    for index in 0..INDEXED_ENTRIES {
+        let name = format!("Entry\u{00C9}{:04}", index);
+        fs::write(host.join(&name), index.to_string())?;
+        expected.insert(name);
+    }
+    ctx.run_bellande_command(&["import", "--from", &host.to_string_lossy(), "--to", "/many"])?;
+    assert_eq!(names_in(ctx, "/many")?, expected);
+
+    for index in (0..INDEXED_ENTRIES).step_by(7) {
+        let lookup = format!("/MANY/entrye\u{0301}{:04}", index);
+        assert!(
+            read(ctx, &lookup)? == index.to_string().into_bytes(),
+            "{} did not find its entry",
+            lookup
+        );
+    }
+    ctx.command(&["create", "--path", "/many/ENTRYÉ0000"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn default_stays_case_sensitive(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let names = ["readme", "README", "caf\u{00E9}", "cafe\u{0301}"];
+    for name in names {
+        ctx.run_bellande_command(&["create", "--path", &format!("/{}", name)])?;
+    }
+    assert_eq!(
+        names_in(ctx, "/")?,
+        names.iter().map(|name| name.to_string()).collect()
+    );
+    ctx.command(&["read", "--path", "/ReadMe"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    casefold_recorded(casefold_context()?),
+    #[contract]
+    case_insensitive_lookup(casefold_context()?),
+    #[contract]
+    case_only_renames(casefold_context()?),
+    #[contract]
+    casefold_indexed_directory(casefold_context()?),
+    #[contract]
+    default_stays_case_sensitive(plain_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_cases_use_other_spellings() {
+        for case in FOLD_CASES {
+            assert!(!case.lookups.is_empty(), "{}", case.label);
+            for lookup in case.lookups {
+                assert_ne!(*lookup, case.created, "{}", case.label);
+                assert_ne!(*lookup, case.listed, "{}", case.label);
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `checksum` against coreutils sha256sum: digests print as "<hex>  <path>",
+// `--verify <list>` rechecks a list in that format (one or two spaces, LF or
+// CRLF) reporting OK/FAILED/MISSING per line, and `--expect <hex>` checks a
+// single file. `sha256_hex` is the reference digest.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+// What coreutils exits with when any line fails
+const EXIT_VERIFY_FAILED: i32 = 1;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    let mut state = INITIAL_STATE;
+    for block in message.chunks(64) {
+        let mut schedule = [0u32; 64];
+        for (index, word) in block.chunks(4).enumerate() {
+            schedule[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..64 {
+            let s0 = schedule[index - 15].rotate_right(7)
+                ^ schedule[index - 15].rotate_right(18)
+                ^ (schedule[index - 15] >> 3);
+            let s1 = schedule[index - 2].rotate_right(17)
+                ^ schedule[index - 2].rotate_right(19)
+                ^ (schedule[index - 2] >> 10);
+            schedule[index] = schedule[index - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[index - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for index in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(ROUND_CONSTANTS[index])
+                .wrapping_add(schedule[index]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (slot, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *slot = slot.wrapping_add(value);
+        }
+    }
+    state.iter().map(|word| format!("{:08x}", word)).collect()
+}
+
+const FILES: &[(&str, usize, u64)] = &[
+    ("/a.txt", 11, 1),
+    ("/empty", 0, 2),
+    ("/dir/b.bin", 70_000, 3),
+];
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, len, seed) in FILES {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        let output = write_file(ctx, path, &content(*seed, *len))?;
+        assert!(output.status.success(), "write {} failed", path);
+    }
+    Ok(())
+}
+
+fn digest(len: usize, seed: u64) -> String {
+    sha256_hex(&content(seed, len))
+}
+
+// Writes a digest list next to the device and returns its host path
+fn sums_file(ctx: &TestContext, name: &str, text: &str) -> io::Result<String> {
+    let path = ctx.temp_dir.path().join(name);
+    fs::write(&path, text)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+pub(crate) fn checksum_matches_sha256sum(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    for (path, len, seed) in FILES {
+        ctx.command(&["checksum", "--path", path])
+            .assert()
+            .success()
+            .stdout(predicate::str::diff(format!(
+                "{}  {}\n",
+                digest(*len, *seed),
+                path
+            )));
+    }
+    Ok(())
+}
+
+pub(crate) fn verify_accepts_list_formats(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let lines: Vec<String> = FILES
+        .iter()
+        .map(|(path, len, seed)| format!("{}  {}", digest(*len, *seed), path))
+        .collect();
+    let one_space: Vec<String> = lines
+        .iter()
+        .map(|line| line.replacen("  ", " ", 1))
+        .collect();
+
+    for (name, text) in [
+        ("two_space.sha256", lines.join("\n") + "\n"),
+        ("one_space.sha256", one_space.join("\n") + "\n"),
+        ("crlf.sha256", lines.join("\r\n") + "\r\n"),
+        ("no_final_newline.sha256", lines.join("\n")),
+        (
+            "mixed.sha256",
+            format!("{}\r\n{}\n{}", one_space[0], lines[1], lines[2]),
+        ),
+    ] {
+        let sums = sums_file(ctx, name, &text)?;
+        let output = ctx
+            .command(&["checksum", "--verify", &sums])
+            .assert()
+            .success();
+        let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+        for (path, _, _) in FILES {
+            assert!(
+                stdout.lines().any(|line| line == format!("{}: OK", path)),
+                "{}: no OK line for {} in {:?}",
+                name,
+                path,
+                stdout
+            );
+        }
+        assert!(
+            !stdout.contains('\r'),
+            "{}: CR leaked into {:?}",
+            name,
+            stdout
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn verify_reports_failures(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let mut text: String = FILES
+        .iter()
+        .map(|(path, len, seed)| format!("{}  {}\n", digest(*len, *seed), path))
+        .collect();
+    text.push_str(&format!("{}  /gone\n", digest(5, 5)));
+    text.push_str("this line is not a digest\n");
+    let sums = sums_file(ctx, "sums.sha256", &text)?;
+
+    // Corrupt one file after its digest was recorded
+    let output = write_file(ctx, "/dir/b.bin", &content(99, 70_000))?;
+    assert!(output.status.success());
+
+    let output = ctx
+        .command(&["checksum", "--verify", &sums])
+        .assert()
+        .code(EXIT_VERIFY_FAILED)
+        .stderr(predicate::str::contains(
+            "1 computed checksum did NOT match",
+        ))
+        .stderr(predicate::str::contains("1 line is improperly formatted"));
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    for expected in [
+        "/a.txt: OK",
+        "/empty: OK",
+        "/dir/b.bin: FAILED",
+        "/gone: MISSING",
+    ] {
+        assert!(
+            stdout.lines().any(|line| line == expected),
+            "no {:?} line in {:?}",
+            expected,
+            stdout
+        );
+    }
+
+    // --quiet keeps only the failures, like sha256sum --quiet
+    let output = ctx
+        .command(&["checksum", "--verify", &sums, "--quiet"])
+        .assert()
+        .code(EXIT_VERIFY_FAILED);
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    assert!(!stdout.contains(": OK"), "{:?}", stdout);
+    assert!(stdout.contains("/dir/b.bin: FAILED"));
+
+    ctx.command(&["checksum", "--verify", "/no/such/list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("/no/such/list"));
+    Ok(())
+}
+
+pub(crate) fn expect_single_digest(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let (path, len, seed) = FILES[0];
+    let good = digest(len, seed);
+
+    ctx.command(&["checksum", "--path", path, "--expect", &good])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("{}: OK", path)));
+    ctx.command(&["checksum", "--path", path, "--expect", &good.to_uppercase()])
+        .assert()
+        .success();
+    ctx.command(&[
+        "checksum",
+        "--path",
+        path,
+        "--expect",
+        &digest(len, seed + 1),
+    ])
+    .assert()
+    .code(EXIT_VERIFY_FAILED)
+    .stdout(predicate::str::contains(format!("{}: FAILED", path)));
+
+    for invalid in ["abc", &good[1..], "zz"] {
+        ctx.command(&["checksum", "--path", path, "--expect", invalid])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--expect"));
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    checksum_matches_sha256sum,
+    #[contract]
+    verify_accepts_list_formats,
+    #[contract]
+    verify_reports_failures,
+    #[contract]
+    expect_single_digest,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256_hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Command-line surface: help, version, global flags, and argument
+// validation. Usage errors exit with EX_USAGE (64) before the device is
+// touched, so they never collide with the errno exit codes.
+
+use crate::harness::{command_timeout, format_device, read_stats, scenarios, TestContext};
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io;
+
+pub(crate) const EXIT_USAGE: i32 = 64;
+
+// Every subcommand the rest of the suite relies on
+pub(crate) const SUBCOMMANDS: &[&str] = &[
+    "format", "create", "write", "read", "list", "mkdir", "rmdir", "remove", "stats",
+];
+
+// Runs the binary with no `--device`, for flags that must not need one
+fn bare_command(ctx: &TestContext, args: &[&str]) -> Command {
+    let mut command = Command::new(&ctx.binary_path);
+    command.args(args).timeout(command_timeout());
+    command
+}
+
+pub(crate) fn help_and_version(ctx: &TestContext) -> io::Result<()> {
+    let output = bare_command(ctx, &["--help"]).assert().success();
+    let help = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    for subcommand in SUBCOMMANDS {
+        assert!(
+            help.contains(subcommand),
+            "--help does not list {}",
+            subcommand
+        );
+    }
+    for flag in ["--device", "--read-only", "--log-level"] {
+        assert!(help.contains(flag), "--help does not describe {}", flag);
+    }
+
+    for subcommand in SUBCOMMANDS {
+        bare_command(ctx, &[subcommand, "--help"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Usage"));
+    }
+
+    bare_command(ctx, &["--version"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"\d+\.\d+\.\d+").unwrap());
+    Ok(())
+}
+
+fn assert_usage_summary(stderr: &str) {
+    assert!(stderr.contains("Usage"), "no usage summary in {:?}", stderr);
+    for subcommand in SUBCOMMANDS {
+        assert!(
+            stderr.contains(subcommand),
+            "usage summary does not list {}",
+            subcommand
+        );
+    }
+}
+
+pub(crate) fn missing_or_unknown_subcommand(ctx: &TestContext) -> io::Result<()> {
+    let output = bare_command(ctx, &[]).assert().code(EXIT_USAGE);
+    assert_usage_summary(&String::from_utf8_lossy(&output.get_output().stderr));
+
+    let output = ctx
+        .command(&["formt"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("did you mean format?"));
+    assert_usage_summary(&String::from_utf8_lossy(&output.get_output().stderr));
+
+    // No panics or backtraces for bad input
+    ctx.command(&["stats", "--bogus"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("panicked").not());
+    Ok(())
+}
+
+pub(crate) fn usage_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    ctx.command(&["lsit", "--path", "/"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("list"));
+    ctx.command(&["create", "--pth", "/typo.txt"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--path"));
+    ctx.command(&["create"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--path"));
+    ctx.command(&["create", "--path", ""])
+        .assert()
+        .code(EXIT_USAGE);
+    ctx.command(&["--log-level", "chatty", "list", "--path", "/"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--log-level"));
+
+    // Nothing above may have reached the device
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("typo.txt").not());
+    Ok(())
+}
+
+pub(crate) fn size_suffixes(ctx: &TestContext) -> io::Result<()> {
+    ctx.run_bellande_command(&["format", "--yes", "--block-size", "4096"])?;
+    let plain = read_stats(ctx)?;
+    ctx.run_bellande_command(&["format", "--yes", "--block-size", "4K"])?;
+    assert_eq!(read_stats(ctx)?, plain);
+
+    ctx.command(&["format", "--yes", "--block-size", "4Q"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--block-size"));
+    Ok(())
+}
+
+pub(crate) fn global_flags(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/kept.txt"])?;
+
+    ctx.command(&["--read-only", "list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("kept.txt"));
+    ctx.command(&["--read-only", "create", "--path", "/refused.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("read-only"));
+    ctx.command(&["--log-level", "debug", "stats"])
+        .assert()
+        .success();
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    help_and_version,
+    #[contract]
+    missing_or_unknown_subcommand,
+    #[contract]
+    usage_errors,
+    #[contract]
+    size_suffixes,
+    #[contract]
+    global_flags,
+}
@@ -0,0 +1,243 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copy-on-write clones. `clone --from --to` gives the destination its own
+// inode pointing at the source's blocks, with a reference count per shared
+// block, so cloning allocates nothing for data. A write or truncate on either
+// side copies or releases only the blocks it touches, and a block is freed
+// once the last file using it lets go. `stats` reports "Shared blocks": data
+// blocks referenced by more than one file, however many. fsck checks the
+// reference counts against the files that use each block.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, inode_of};
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::journal::{tree_state, FAIL_AFTER_ENV};
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const FILE_BLOCKS: u64 = 20;
+const MAX_CRASH_POINTS: u64 = 10_000;
+
+fn clone_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn clone(ctx: &TestContext, from: &str, to: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["clone", "--from", from, "--to", to])?;
+    Ok(())
+}
+
+fn shared_blocks(ctx: &TestContext) -> io::Result<u64> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    stat_field(&String::from_utf8_lossy(&output.stdout), "Shared blocks")
+}
+
+fn free_blocks(ctx: &TestContext) -> io::Result<u64> {
+    Ok(read_stats(ctx)?.free_blocks)
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+fn patch(ctx: &TestContext, path: &str, offset: usize, data: &[u8]) {
+    ctx.command(&["write", "--path", path, "--offset", &offset.to_string()])
+        .write_stdin(data.to_vec())
+        .assert()
+        .success();
+}
+
+// A formatted device holding /fixture.bin, returning its contents
+fn with_fixture(ctx: &TestContext) -> io::Result<Vec<u8>> {
+    format_device(ctx)?;
+    let data = content(1, FILE_BLOCKS as usize * BLOCK);
+    ctx.run_bellande_command(&["create", "--path", "/fixture.bin"])?;
+    assert!(write_file(ctx, "/fixture.bin", &data)?.status.success());
+    Ok(data)
+}
+
+pub(crate) fn clone_shares_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = free_blocks(ctx)?;
+    let data = with_fixture(ctx)?;
+    let written = free_blocks(ctx)?;
+    assert_eq!(shared_blocks(ctx)?, 0);
+
+    // The root directory block has room for the new entry
+    clone(ctx, "/fixture.bin", "/clone.bin")?;
+    assert_eq!(free_blocks(ctx)?, written, "cloning allocated data blocks");
+    assert_eq!(shared_blocks(ctx)?, FILE_BLOCKS);
+    assert!(read(ctx, "/clone.bin")? == data);
+    let listing = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    assert_ne!(
+        inode_of(&listing, "fixture.bin"),
+        inode_of(&listing, "clone.bin"),
+        "a clone is not a hard link"
+    );
+    assert_fsck_clean(ctx)?;
+
+    // Patching one block of the clone copies just that block
+    let patched_at = 4 * BLOCK + 10;
+    patch(ctx, "/clone.bin", patched_at, &[0xEE; 20]);
+    assert_eq!(free_blocks(ctx)?, written - 1);
+    assert_eq!(shared_blocks(ctx)?, FILE_BLOCKS - 1);
+    assert!(read(ctx, "/fixture.bin")? == data, "the source changed");
+    let mut expected = data.clone();
+    expected[patched_at..patched_at + 20].fill(0xEE);
+    assert!(read(ctx, "/clone.bin")? == expected);
+    assert_fsck_clean(ctx)?;
+
+    // Removing the source frees only the block the clone no longer uses
+    ctx.run_bellande_command(&["remove", "--path", "/fixture.bin"])?;
+    assert_eq!(free_blocks(ctx)?, written);
+    assert_eq!(shared_blocks(ctx)?, 0);
+    assert!(read(ctx, "/clone.bin")? == expected);
+    assert_fsck_clean(ctx)?;
+
+    ctx.run_bellande_command(&["remove", "--path", "/clone.bin"])?;
+    assert_eq!(free_blocks(ctx)?, empty, "shared blocks leaked");
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn clones_of_clones(ctx: &TestContext) -> io::Result<()> {
+    let data = with_fixture(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/copies"])?;
+    let after_mkdir = free_blocks(ctx)?;
+    clone(ctx, "/fixture.bin", "/copies/a")?;
+    clone(ctx, "/copies/a", "/copies/b")?;
+    assert_eq!(free_blocks(ctx)?, after_mkdir);
+    assert_eq!(
+        shared_blocks(ctx)?,
+        FILE_BLOCKS,
+        "blocks are counted once however many files share them"
+    );
+
+    // Truncating one file releases its references and leaves the rest shared
+    ctx.run_bellande_command(&["truncate", "--path", "/copies/a", "--size", "0"])?;
+    assert_eq!(free_blocks(ctx)?, after_mkdir);
+    assert_eq!(shared_blocks(ctx)?, FILE_BLOCKS);
+    assert!(read(ctx, "/copies/b")? == data);
+
+    // Appending to a clone allocates only the new tail
+    ctx.command(&["write", "--append", "--path", "/copies/b"])
+        .write_stdin(content(2, 3 * BLOCK))
+        .assert()
+        .success();
+    assert_eq!(free_blocks(ctx)?, after_mkdir - 3);
+    assert_eq!(shared_blocks(ctx)?, FILE_BLOCKS);
+    assert!(read(ctx, "/fixture.bin")? == data);
+    assert_fsck_clean(ctx)?;
+
+    // Fully overwriting the source leaves the old blocks with b alone
+    assert!(write_file(
+        ctx,
+        "/fixture.bin",
+        &content(3, FILE_BLOCKS as usize * BLOCK)
+    )?
+    .status
+    .success());
+    assert_eq!(shared_blocks(ctx)?, 0);
+    assert_eq!(free_blocks(ctx)?, after_mkdir - 3 - FILE_BLOCKS);
+    assert_eq!(read(ctx, "/copies/b")?[..data.len()], data[..]);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn clone_survives_crashes(base: &TestContext) -> io::Result<()> {
+    with_fixture(base)?;
+    let before = tree_state(base)?;
+    let image = base.temp_dir.path().join("before_clone.img");
+    fs::copy(&base.device_path, &image)?;
+    let args = ["clone", "--from", "/fixture.bin", "--to", "/clone.bin"];
+
+    let complete = TestContext::from_image(&image)?;
+    complete.run_bellande_command(&args)?;
+    let after = tree_state(&complete)?;
+
+    let mut crash_points = 0;
+    for limit in 0..MAX_CRASH_POINTS {
+        let ctx = TestContext::from_image(&image)?;
+        if ctx
+            .command(&args)
+            .env(FAIL_AFTER_ENV, limit.to_string())
+            .output()?
+            .status
+            .success()
+        {
+            break;
+        }
+        crash_points += 1;
+        ctx.run_bellande_command(&["stats"])?;
+        // fsck covers the reference counts as well as the tree
+        assert_fsck_clean(&ctx)?;
+        let state = tree_state(&ctx)?;
+        assert!(
+            state == before || state == after,
+            "clone crashed after {} writes: {:?}",
+            limit,
+            state
+        );
+    }
+    assert!(crash_points > 0, "clone never hit the failure hook");
+    Ok(())
+}
+
+pub(crate) fn clone_errors(ctx: &TestContext) -> io::Result<()> {
+    with_fixture(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/taken"])?;
+    let before = fs::read(&ctx.device_path)?;
+
+    for (from, to, exit) in [
+        ("/missing", "/new", EXIT_NOT_FOUND),
+        ("/fixture.bin", "/no/such/dir/new", EXIT_NOT_FOUND),
+        ("/fixture.bin", "/taken", EXIT_ALREADY_EXISTS),
+        ("/fixture.bin", "/dir", EXIT_ALREADY_EXISTS),
+        ("/dir", "/dir2", EXIT_IS_DIRECTORY),
+    ] {
+        ctx.command(&["clone", "--from", from, "--to", to])
+            .assert()
+            .code(exit);
+    }
+    ctx.command(&["clone", "--from", "/fixture.bin"])
+        .assert()
+        .code(EXIT_USAGE);
+    ctx.command(&["clone", "--to", "/new"])
+        .assert()
+        .code(EXIT_USAGE);
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a failed clone modified the image"
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    clone_shares_blocks(clone_context()?),
+    #[contract]
+    clones_of_clones(clone_context()?),
+    #[contract]
+    clone_survives_crashes(clone_context()?),
+    #[contract]
+    clone_errors(clone_context()?),
+}
@@ -0,0 +1,287 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Transparent per-file compression. `setattr --path P --compress MODE` with
+// `lz4`, `zstd`, `on` (meaning lz4) or `off` sets how data written to the
+// file from then on is stored; `read` decompresses, so only sizes show it.
+// `stat` reports `Compression` and the physical `Blocks`, and `stats` adds
+// `Logical bytes` and `Physical bytes` for all file data. Blocks that do not
+// shrink are stored as they are.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::stat::{number, stat};
+use std::io;
+
+const BLOCK_SIZE: u32 = 4096;
+const BLOCK: u64 = BLOCK_SIZE as u64;
+const ALGORITHMS: &[&str] = &["lz4", "zstd"];
+const TEXT_LEN: usize = 1024 * 1024;
+// Repetitive text compresses far better than this with either algorithm
+const MIN_TEXT_RATIO: u64 = 4;
+
+// Words cycled with a varying stride, so the text repeats but not in
+// block-sized runs
+pub(crate) fn text(len: usize) -> Vec<u8> {
+    const WORDS: &[&str] = &[
+        "block",
+        "inode",
+        "device",
+        "journal",
+        "extent",
+        "bitmap",
+        "driver",
+        "bellande",
+        "superblock",
+        "directory",
+    ];
+    let mut out = Vec::with_capacity(len + 16);
+    let mut index = 0;
+    while out.len() < len {
+        out.extend_from_slice(WORDS[(index * 7 + index / 13) % WORDS.len()].as_bytes());
+        out.push(if index % 11 == 10 { b'\n' } else { b' ' });
+        index += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+fn compression_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn set_compress(ctx: &TestContext, path: &str, mode: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["setattr", "--path", path, "--compress", mode])?;
+    Ok(())
+}
+
+fn physical_bytes(ctx: &TestContext, path: &str) -> io::Result<u64> {
+    Ok(number(&stat(ctx, path)?, "Blocks") * BLOCK)
+}
+
+// (logical, physical) file data bytes on the device
+fn device_bytes(ctx: &TestContext) -> io::Result<(u64, u64)> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok((
+        stat_field(&stdout, "Logical bytes")?,
+        stat_field(&stdout, "Physical bytes")?,
+    ))
+}
+
+fn read_all(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn text_files_shrink(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let data = text(TEXT_LEN);
+    ctx.run_bellande_command(&["create", "--path", "/plain.txt"])?;
+    assert!(write_file(ctx, "/plain.txt", &data)?.status.success());
+    let plain = physical_bytes(ctx, "/plain.txt")?;
+    assert!(
+        plain >= TEXT_LEN as u64,
+        "uncompressed file took {} bytes",
+        plain
+    );
+    assert_eq!(stat(ctx, "/plain.txt")?["Compression"], "none");
+
+    for algorithm in ALGORITHMS {
+        let path = format!("/{}.txt", algorithm);
+        let (_, physical_before) = device_bytes(ctx)?;
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        set_compress(ctx, &path, algorithm)?;
+        assert!(write_file(ctx, &path, &data)?.status.success());
+
+        let fields = stat(ctx, &path)?;
+        assert_eq!(fields["Compression"], *algorithm);
+        assert_eq!(number(&fields, "Size"), TEXT_LEN as u64);
+        let physical = physical_bytes(ctx, &path)?;
+        assert!(
+            physical * MIN_TEXT_RATIO <= TEXT_LEN as u64,
+            "{}: {} bytes of text took {} bytes",
+            algorithm,
+            TEXT_LEN,
+            physical
+        );
+        assert!(
+            read_all(ctx, &path)? == data,
+            "{} read back differently",
+            algorithm
+        );
+
+        let (_, physical_after) = device_bytes(ctx)?;
+        assert!(
+            physical_after - physical_before <= physical,
+            "{}: stats physical bytes grew by more than the file",
+            algorithm
+        );
+    }
+
+    // Three copies of the text, one stored in full
+    let (logical, physical) = device_bytes(ctx)?;
+    assert_eq!(logical, 3 * TEXT_LEN as u64);
+    assert!(physical < logical && physical >= TEXT_LEN as u64);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn compressed_random_access(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for algorithm in ALGORITHMS {
+        let path = format!("/{}.txt", algorithm);
+        let mut model = text(20 * BLOCK as usize + 123);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        set_compress(ctx, &path, algorithm)?;
+        assert!(write_file(ctx, &path, &model)?.status.success());
+
+        // Ranges inside one block and across block boundaries
+        for (offset, len) in [
+            (0, 10),
+            (BLOCK - 3, 6),
+            (5 * BLOCK + 1, 3 * BLOCK),
+            (19 * BLOCK, 200),
+        ] {
+            let output = ctx.run_bellande_command(&[
+                "read",
+                "--path",
+                &path,
+                "--offset",
+                &offset.to_string(),
+                "--length",
+                &len.to_string(),
+            ])?;
+            assert!(
+                output.stdout == model[offset as usize..(offset + len) as usize],
+                "{}: range {}+{} read back differently",
+                algorithm,
+                offset,
+                len
+            );
+        }
+
+        // Patching, appending and shrinking rewrite compressed blocks
+        let patch = content(1, 2 * BLOCK as usize);
+        let at = 3 * BLOCK as usize + 500;
+        ctx.command(&["write", "--path", &path, "--offset", &at.to_string()])
+            .write_stdin(patch.clone())
+            .assert()
+            .success();
+        model[at..at + patch.len()].copy_from_slice(&patch);
+        let tail = text(3 * BLOCK as usize);
+        ctx.command(&["write", "--append", "--path", &path])
+            .write_stdin(tail.clone())
+            .assert()
+            .success();
+        model.extend_from_slice(&tail);
+        assert!(
+            read_all(ctx, &path)? == model,
+            "{}: rewrites read back differently",
+            algorithm
+        );
+
+        let size = 12 * BLOCK + 7;
+        ctx.run_bellande_command(&["truncate", "--path", &path, "--size", &size.to_string()])?;
+        model.truncate(size as usize);
+        assert!(
+            read_all(ctx, &path)? == model,
+            "{}: truncation read back differently",
+            algorithm
+        );
+
+        // The attribute follows the inode
+        let moved = format!("/moved_{}.txt", algorithm);
+        ctx.run_bellande_command(&["move", "--from", &path, "--to", &moved])?;
+        assert_eq!(stat(ctx, &moved)?["Compression"], *algorithm);
+        assert!(read_all(ctx, &moved)? == model);
+    }
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn compress_attribute_changes(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    // Random bytes do not shrink and are stored in full, not inflated
+    let noise = content(2, 64 * BLOCK as usize);
+    ctx.run_bellande_command(&["create", "--path", "/noise.bin"])?;
+    set_compress(ctx, "/noise.bin", "on")?;
+    assert_eq!(stat(ctx, "/noise.bin")?["Compression"], "lz4");
+    assert!(write_file(ctx, "/noise.bin", &noise)?.status.success());
+    let physical = physical_bytes(ctx, "/noise.bin")?;
+    assert!(
+        physical <= noise.len() as u64 + BLOCK,
+        "incompressible data grew to {} bytes",
+        physical
+    );
+    assert!(read_all(ctx, "/noise.bin")? == noise);
+
+    // Turning compression off keeps what is stored readable and stores new
+    // data plainly
+    let data = text(16 * BLOCK as usize);
+    ctx.run_bellande_command(&["create", "--path", "/toggle.txt"])?;
+    set_compress(ctx, "/toggle.txt", "zstd")?;
+    assert!(write_file(ctx, "/toggle.txt", &data)?.status.success());
+    let compressed = physical_bytes(ctx, "/toggle.txt")?;
+    set_compress(ctx, "/toggle.txt", "off")?;
+    assert_eq!(stat(ctx, "/toggle.txt")?["Compression"], "none");
+    assert!(read_all(ctx, "/toggle.txt")? == data);
+    ctx.command(&["write", "--append", "--path", "/toggle.txt"])
+        .write_stdin(data.clone())
+        .assert()
+        .success();
+    assert!(physical_bytes(ctx, "/toggle.txt")? >= compressed + data.len() as u64);
+    assert!(read_all(ctx, "/toggle.txt")? == [data.clone(), data].concat());
+
+    ctx.command(&["setattr", "--path", "/missing", "--compress", "on"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    for args in [
+        &["setattr", "--path", "/toggle.txt", "--compress", "gzip"][..],
+        &["setattr", "--path", "/toggle.txt"][..],
+        &["setattr", "--compress", "on"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    text_files_shrink(compression_context()?),
+    #[contract]
+    compressed_random_access(compression_context()?),
+    #[contract]
+    compress_attribute_changes(compression_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_is_repetitive_text() {
+        let sample = text(10_000);
+        assert_eq!(sample.len(), 10_000);
+        assert!(sample
+            .iter()
+            .all(|byte| byte.is_ascii_lowercase() || b" \n".contains(byte)));
+        assert!(sample.contains(&b'\n'));
+        assert_eq!(text(100)[..], sample[..100]);
+    }
+}
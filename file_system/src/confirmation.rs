@@ -0,0 +1,115 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Confirmation for destructive operations. Without a TTY there is nobody to
+// answer a prompt, so these must refuse unless `--yes`/`--force` is given,
+// and must never block reading stdin.
+
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+const NEEDS_YES_MESSAGE: &str = "--yes";
+
+pub(crate) fn refuses_without_yes(ctx: &TestContext, args: &[&str]) {
+    // Even a "yes" on a piped stdin is not a confirmation
+    ctx.command(args)
+        .write_stdin("yes\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(NEEDS_YES_MESSAGE));
+}
+
+pub(crate) fn format_requires_confirmation(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/survivor.txt"])?;
+
+    refuses_without_yes(ctx, &["format"]);
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("survivor.txt"));
+
+    for flag in ["--yes", "--force"] {
+        ctx.command(&["format", flag])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Device formatted successfully"));
+        ctx.command(&["list", "--path", "/"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("survivor.txt").not());
+        ctx.run_bellande_command(&["create", "--path", "/survivor.txt"])?;
+    }
+    Ok(())
+}
+
+// The unattended first format a script would run: nothing on stdin, a blank
+// device, and no --yes. It must fail at once and leave the image as it was
+pub(crate) fn unattended_format_refused(ctx: &TestContext) -> io::Result<()> {
+    let blank = fs::read(&ctx.device_path)?;
+    for args in [&["format"][..], &["format", "--block-size", "4096"][..]] {
+        ctx.command(args)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("Device formatted successfully").not())
+            .stderr(predicate::str::contains(NEEDS_YES_MESSAGE));
+        assert!(
+            fs::read(&ctx.device_path)? == blank,
+            "{:?} without --yes wrote to the device",
+            args
+        );
+    }
+    ctx.command(&["list", "--path", "/"]).assert().failure();
+    format_device(ctx)
+}
+
+pub(crate) fn recursive_root_remove_requires_confirmation(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/file.txt"])?;
+
+    refuses_without_yes(ctx, &["remove", "--recursive", "--path", "/"]);
+    ctx.command(&["list", "--path", "/dir"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("file.txt"));
+
+    ctx.run_bellande_command(&["remove", "--recursive", "--yes", "--path", "/"])?;
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dir").not());
+    Ok(())
+}
+
+// Ordinary removals are not gated
+pub(crate) fn plain_remove_needs_no_confirmation(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/plain.txt"])?;
+    ctx.run_bellande_command(&["remove", "--path", "/plain.txt"])?;
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    format_requires_confirmation,
+    #[contract]
+    unattended_format_refused,
+    #[contract]
+    recursive_root_remove_requires_confirmation,
+    plain_remove_needs_no_confirmation,
+}
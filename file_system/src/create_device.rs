@@ -0,0 +1,153 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `format --create --size BYTES` makes the backing image itself instead of
+// needing a zero-filled file first: it refuses a path that already exists,
+// creates it sparse (or fully allocated with `--preallocate`) at exactly
+// that size and formats it. `--block-size` takes 1k, 4k or 64k as well as
+// bytes. A size below the minimum the geometry needs exits EXIT_INVALID,
+// and no failure leaves a file behind.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_INVALID, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{command_timeout, read_stats, scenarios, stat_field, write_file, TestContext};
+use crate::large_device::allocated_bytes;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+const IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+// Far below what any block size needs for its metadata
+const TOO_SMALL: &str = "8K";
+
+// A context whose device path does not exist yet
+fn uncreated_context() -> io::Result<TestContext> {
+    let ctx = TestContext::new()?;
+    fs::remove_file(&ctx.device_path)?;
+    Ok(ctx)
+}
+
+fn block_size(ctx: &TestContext) -> io::Result<u64> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    stat_field(&String::from_utf8_lossy(&output.stdout), "Block size")
+}
+
+pub(crate) fn create_makes_formatted_image() -> io::Result<()> {
+    let ctx = uncreated_context()?;
+    ctx.run_bellande_command(&["format", "--yes", "--create", "--size", "64M"])?;
+    assert_eq!(fs::metadata(&ctx.device_path)?.len(), IMAGE_SIZE);
+    // Sparse unless asked otherwise
+    if cfg!(unix) {
+        assert!(allocated_bytes(&ctx.device_path)? < IMAGE_SIZE / 2);
+    }
+    let stats = read_stats(&ctx)?;
+    assert!(stats.total_blocks * block_size(&ctx)? > IMAGE_SIZE * 9 / 10);
+
+    ctx.run_bellande_command(&["create", "--path", "/first.bin"])?;
+    assert!(write_file(&ctx, "/first.bin", &content(1, 50_000))?
+        .status
+        .success());
+    assert_fsck_clean(&ctx)?;
+
+    // An existing image is never replaced by --create
+    let before = fs::read(&ctx.device_path)?;
+    ctx.command(&["format", "--yes", "--create", "--size", "64M"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(predicate::str::contains("--create"));
+    assert!(fs::read(&ctx.device_path)? == before);
+
+    let ctx = uncreated_context()?;
+    ctx.run_bellande_command(&[
+        "format",
+        "--yes",
+        "--create",
+        "--preallocate",
+        "--size",
+        "4M",
+    ])?;
+    assert_eq!(fs::metadata(&ctx.device_path)?.len(), 4 * 1024 * 1024);
+    assert!(allocated_bytes(&ctx.device_path)? >= 4 * 1024 * 1024);
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn block_size_suffixes() -> io::Result<()> {
+    for (value, bytes) in [
+        ("1k", 1024),
+        ("4k", 4096),
+        ("64k", 65536),
+        ("4K", 4096),
+        ("2048", 2048),
+    ] {
+        let ctx = uncreated_context()?;
+        ctx.run_bellande_command(&[
+            "format",
+            "--yes",
+            "--create",
+            "--size",
+            "64M",
+            "--block-size",
+            value,
+        ])?;
+        assert_eq!(block_size(&ctx)?, bytes, "--block-size {}", value);
+        assert_fsck_clean(&ctx)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn create_errors() -> io::Result<()> {
+    let ctx = uncreated_context()?;
+    for (args, code) in [
+        (&["--size", TOO_SMALL][..], EXIT_INVALID),
+        (&["--size", "1M", "--block-size", "64k"][..], EXIT_INVALID),
+        (&["--size", "64M", "--block-size", "3k"][..], EXIT_USAGE),
+        (&["--size", "64M", "--block-size", "1kb2"][..], EXIT_USAGE),
+        (&["--size", "lots"][..], EXIT_USAGE),
+        (&[][..], EXIT_USAGE),
+    ] {
+        let mut full = vec!["format", "--yes", "--create"];
+        full.extend(args);
+        ctx.command(&full).assert().code(code);
+        assert!(!ctx.device_path.exists(), "{:?} left a file behind", args);
+    }
+    // --size only means something with --create
+    ctx.command(&["format", "--yes", "--size", "64M"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--create"));
+
+    let nested = ctx.temp_dir.path().join("no_such_dir").join("image");
+    Command::new(&ctx.binary_path)
+        .arg("--device")
+        .arg(&nested)
+        .args(["format", "--yes", "--create", "--size", "64M"])
+        .timeout(command_timeout())
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert!(!nested.exists());
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    create_makes_formatted_image(),
+    #[contract]
+    block_size_suffixes(),
+    #[contract]
+    create_errors(),
+}
@@ -0,0 +1,193 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `--mode` on `mkdir` and `create` sets permissions when the inode is
+// allocated, after the process umask, so no reader ever sees the default
+// mode first. `stat --format json` reports permissions as an octal string
+// such as "0750".
+
+use crate::cli::EXIT_USAGE;
+use crate::harness::{command_timeout, format_device, scenarios, TestContext};
+use crate::json::json_field;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+// (command, --mode, umask, resulting permissions)
+const MODE_CASES: &[(&str, &str, &str, u32)] = &[
+    ("mkdir", "0750", "022", 0o750),
+    ("mkdir", "0777", "027", 0o750),
+    ("mkdir", "1777", "022", 0o1755),
+    ("create", "644", "077", 0o600),
+    ("create", "0600", "000", 0o600),
+    ("create", "0", "022", 0),
+];
+
+pub(crate) const INVALID_MODES: &[&str] = &["0789", "rwx", "", "77777", "-1", "0o755", "u+x"];
+
+const RACE_ENTRIES: usize = 40;
+
+// One to four octal digits, optionally with a leading 0
+pub(crate) fn parse_octal_mode(text: &str) -> Option<u32> {
+    let digits = text
+        .strip_prefix('0')
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(text);
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| ('0'..='7').contains(&c)) {
+        return None;
+    }
+    u32::from_str_radix(digits, 8).ok()
+}
+
+// The binary under `sh` so the umask applies to it alone
+fn with_umask(ctx: &TestContext, umask: &str, args: &[&str]) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "umask \"$1\" && shift && exec \"$@\"", "sh", umask])
+        .arg(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(args)
+        .timeout(command_timeout());
+    command
+}
+
+fn stat_mode(ctx: &TestContext, path: &str) -> Option<u32> {
+    let output = ctx
+        .run_raw(&["stat", "--format", "json", "--path", path])
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mode = json_field(&String::from_utf8_lossy(&output.stdout), "mode")?;
+    Some(parse_octal_mode(&mode).unwrap_or_else(|| panic!("stat mode {:?} is not octal", mode)))
+}
+
+pub(crate) fn mode_applied_after_umask(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    for (index, (command, mode, umask, expected)) in MODE_CASES.iter().enumerate() {
+        let path = format!("/entry{}", index);
+        with_umask(ctx, umask, &[command, "--mode", mode, "--path", &path])
+            .assert()
+            .success();
+        assert_eq!(
+            stat_mode(ctx, &path),
+            Some(*expected),
+            "{} --mode {} under umask {}",
+            command,
+            mode,
+            umask
+        );
+    }
+
+    for mode in INVALID_MODES {
+        for command in ["mkdir", "create"] {
+            ctx.command(&[command, "--mode", mode, "--path", "/invalid"])
+                .assert()
+                .code(EXIT_USAGE)
+                .stderr(predicate::str::contains("--mode"));
+        }
+    }
+    assert_eq!(stat_mode(ctx, "/invalid"), None);
+    Ok(())
+}
+
+pub(crate) fn no_default_mode_window(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/race"])?;
+    let paths: Vec<(String, &str, u32)> = (0..RACE_ENTRIES)
+        .map(|index| {
+            if index % 2 == 0 {
+                (format!("/race/file{}", index), "create", 0o600)
+            } else {
+                (format!("/race/dir{}", index), "mkdir", 0o700)
+            }
+        })
+        .collect();
+
+    let done = AtomicBool::new(false);
+    let reader = thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            let mut observed = Vec::new();
+            while !done.load(Ordering::SeqCst) {
+                for (path, _, _) in &paths {
+                    if let Some(mode) = stat_mode(ctx, path) {
+                        observed.push((path.clone(), mode));
+                    }
+                }
+            }
+            observed
+        });
+        for (path, command, mode) in &paths {
+            let mode = format!("{:04o}", mode);
+            let result = ctx.run_bellande_command(&[command, "--mode", &mode, "--path", path]);
+            if result.is_err() {
+                done.store(true, Ordering::SeqCst);
+                result?;
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+        Ok::<_, io::Error>(reader.join().expect("reader thread panicked"))
+    })?;
+
+    for (path, mode) in reader {
+        let expected = paths
+            .iter()
+            .find(|(candidate, _, _)| *candidate == path)
+            .map(|(_, _, mode)| *mode);
+        assert_eq!(
+            Some(mode),
+            expected,
+            "{} was visible with mode {:o}",
+            path,
+            mode
+        );
+    }
+    for (path, _, mode) in &paths {
+        assert_eq!(stat_mode(ctx, path), Some(*mode), "{}", path);
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    mode_applied_after_umask,
+    #[contract]
+    no_default_mode_window,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_octal_mode() {
+        assert_eq!(parse_octal_mode("0750"), Some(0o750));
+        assert_eq!(parse_octal_mode("750"), Some(0o750));
+        assert_eq!(parse_octal_mode("1777"), Some(0o1777));
+        assert_eq!(parse_octal_mode("01777"), Some(0o1777));
+        assert_eq!(parse_octal_mode("0"), Some(0));
+        for invalid in INVALID_MODES {
+            assert_eq!(parse_octal_mode(invalid), None, "{:?}", invalid);
+        }
+        for (_, mode, umask, expected) in MODE_CASES {
+            let mode = parse_octal_mode(mode).unwrap();
+            assert_eq!(mode & !parse_octal_mode(umask).unwrap(), *expected);
+        }
+    }
+}
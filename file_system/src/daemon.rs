@@ -0,0 +1,366 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Daemon mode. `serve --socket PATH` opens the device once, holding its lock,
+// and answers requests on a Unix socket until `shutdown` arrives, then
+// removes the socket and exits 0. The global `--remote PATH` replaces
+// `--device` and sends any subcommand to the daemon, with the same stdout,
+// stderr and exit status as running it directly. On the wire each message is
+// a 4-byte big-endian length and a JSON object: requests carry "args" and
+// optionally "stdin", responses "exit", "stdout" and "stderr". One
+// connection may carry any number of requests, answered in order.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{command_timeout, env_timeout, format_device, scenarios, TestContext};
+use crate::journal::tree_state;
+use crate::json::{json_field, json_u64};
+use crate::locking::EXIT_DEVICE_BUSY;
+use assert_cmd::Command;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVE_TIMEOUT_ENV: &str = "BELLANDE_FS_SERVE_TIMEOUT";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PIPELINED_REQUESTS: usize = 100;
+
+// (args, stdin) run both directly and through the daemon; `stats` is left
+// out since its mount state names the daemon's pid
+const COMMANDS: &[(&[&str], Option<&str>)] = &[
+    (&["mkdir", "--path", "/dir"], None),
+    (&["create", "--path", "/dir/file.txt"], None),
+    (
+        &["write", "--path", "/dir/file.txt"],
+        Some("hello over the socket\n"),
+    ),
+    (&["read", "--path", "/dir/file.txt"], None),
+    (&["list", "--path", "/dir"], None),
+    (&["stat", "--path", "/base.bin"], None),
+    (&["read", "--path", "/missing"], None),
+    (&["remove", "--path", "/dir"], None),
+    (
+        &["move", "--from", "/dir/file.txt", "--to", "/moved.txt"],
+        None,
+    ),
+    (&["list", "--recursive", "--path", "/"], None),
+    (&["bogus-subcommand"], None),
+];
+
+pub(crate) fn serve_timeout() -> Duration {
+    env_timeout(SERVE_TIMEOUT_ENV, Duration::from_secs(10))
+}
+
+// A running daemon; killed on drop so a failed test leaves nothing behind
+pub(crate) struct Daemon {
+    child: Child,
+    pub(crate) socket: PathBuf,
+}
+
+impl Daemon {
+    pub(crate) fn start(ctx: &TestContext, name: &str) -> io::Result<Daemon> {
+        let socket = ctx.temp_dir.path().join(name);
+        let mut child = process::Command::new(&ctx.binary_path)
+            .arg("--device")
+            .arg(&ctx.device_path)
+            .arg("serve")
+            .arg("--socket")
+            .arg(&socket)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let started = Instant::now();
+        while !socket.exists() {
+            if let Some(status) = child.try_wait()? {
+                let output = child.wait_with_output()?;
+                return Err(io::Error::other(format!(
+                    "serve exited {}: {}",
+                    status,
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+            if started.elapsed() > serve_timeout() {
+                return Err(io::Error::other("serve did not come up in time"));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(Daemon { child, socket })
+    }
+
+    pub(crate) fn remote(&self, ctx: &TestContext, args: &[&str]) -> Command {
+        let mut command = Command::new(&ctx.binary_path);
+        command
+            .arg("--remote")
+            .arg(&self.socket)
+            .args(args)
+            .timeout(command_timeout());
+        command
+    }
+
+    pub(crate) fn shutdown(mut self, ctx: &TestContext) -> io::Result<()> {
+        self.remote(ctx, &["shutdown"]).assert().success();
+        let exit = self.child.wait()?;
+        assert!(exit.success(), "serve exited {} after shutdown", exit);
+        assert!(!self.socket.exists(), "serve left its socket behind");
+        Ok(())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub(crate) fn request(args: &[&str]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| json_string(arg)).collect();
+    format!("{{\"args\": [{}]}}", args.join(", "))
+}
+
+// Sends every message on one connection and returns the responses in order
+#[cfg(unix)]
+pub(crate) fn exchange(socket: &Path, messages: &[String]) -> io::Result<Vec<String>> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(command_timeout()))?;
+    for message in messages {
+        stream.write_all(&(message.len() as u32).to_be_bytes())?;
+        stream.write_all(message.as_bytes())?;
+    }
+    let mut responses = Vec::new();
+    for _ in messages {
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+        let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut body)?;
+        responses.push(String::from_utf8_lossy(&body).into_owned());
+    }
+    Ok(responses)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn exchange(_socket: &Path, _messages: &[String]) -> io::Result<Vec<String>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "serve listens on Unix sockets only",
+    ))
+}
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/base.bin"])?;
+    ctx.command(&["write", "--path", "/base.bin"])
+        .write_stdin(content(1, 9000))
+        .assert()
+        .success();
+    Ok(())
+}
+
+pub(crate) fn remote_matches_direct(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let direct = TestContext::from_image(&ctx.device_path)?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+
+    for (args, stdin) in COMMANDS {
+        let mut local = direct.command(args);
+        let mut remote = daemon.remote(ctx, args);
+        if let Some(stdin) = stdin {
+            local.write_stdin(stdin.as_bytes().to_vec());
+            remote.write_stdin(stdin.as_bytes().to_vec());
+        }
+        let local = local.output()?;
+        let remote = remote.output()?;
+        assert_eq!(
+            remote.status.code(),
+            local.status.code(),
+            "{:?}: exit differs through the daemon",
+            args
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&remote.stdout),
+            String::from_utf8_lossy(&local.stdout),
+            "{:?}: stdout differs through the daemon",
+            args
+        );
+        assert_eq!(
+            remote.stderr.is_empty(),
+            local.stderr.is_empty(),
+            "{:?}: stderr differs through the daemon",
+            args
+        );
+    }
+
+    daemon.shutdown(ctx)?;
+    assert_eq!(tree_state(ctx)?, tree_state(&direct)?);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn daemon_holds_device(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .code(EXIT_DEVICE_BUSY);
+    let second = ctx.temp_dir.path().join("second.sock");
+    ctx.command(&["serve", "--socket", &second.to_string_lossy()])
+        .assert()
+        .code(EXIT_DEVICE_BUSY);
+    assert!(!second.exists());
+
+    // Remote clients share the daemon's open device freely
+    let clients: Vec<_> = (0..4)
+        .map(|index| {
+            daemon
+                .remote(ctx, &["create", "--path", &format!("/client{}", index)])
+                .output()
+        })
+        .collect::<io::Result<_>>()?;
+    assert!(clients.iter().all(|output| output.status.success()));
+
+    daemon.shutdown(ctx)?;
+    let state = tree_state(ctx)?;
+    assert!((0..4).all(|index| state.contains_key(&format!("/client{}", index))));
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn pipelined_requests(ctx: &TestContext) -> io::Result<()> {
+    if cfg!(not(unix)) {
+        println!("Skipping the wire protocol test: no Unix sockets on this host");
+        return Ok(());
+    }
+    populated(ctx)?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+
+    let mut messages: Vec<String> = (0..PIPELINED_REQUESTS)
+        .map(|index| request(&["create", "--path", &format!("/f{}", index)]))
+        .collect();
+    messages.push("not json".to_string());
+    messages.push(format!(
+        "{{\"args\": [\"write\", \"--path\", \"/f0\"], \"stdin\": {}}}",
+        json_string("line one\n\"two\"")
+    ));
+    messages.push(request(&["read", "--path", "/f0"]));
+    let responses = exchange(&daemon.socket, &messages)?;
+
+    for response in &responses[..PIPELINED_REQUESTS] {
+        assert_eq!(json_u64(response, "exit"), Some(0), "{}", response);
+    }
+    // A malformed request is answered, and the connection stays usable
+    let malformed = &responses[PIPELINED_REQUESTS];
+    assert_eq!(json_u64(malformed, "exit"), Some(EXIT_USAGE as u64));
+    assert!(!json_field(malformed, "stderr")
+        .unwrap_or_default()
+        .is_empty());
+    assert_eq!(
+        json_u64(&responses[PIPELINED_REQUESTS + 1], "exit"),
+        Some(0)
+    );
+    let read = &responses[PIPELINED_REQUESTS + 2];
+    assert_eq!(
+        json_field(read, "stdout").as_deref(),
+        Some("line one\n\"two\"")
+    );
+
+    daemon.shutdown(ctx)?;
+    assert_eq!(tree_state(ctx)?.len(), PIPELINED_REQUESTS + 1);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn serve_errors(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    ctx.command(&["serve"]).assert().code(EXIT_USAGE);
+
+    let taken = ctx.temp_dir.path().join("taken.sock");
+    fs::write(&taken, b"not a socket")?;
+    ctx.command(&["serve", "--socket", &taken.to_string_lossy()])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    assert_eq!(fs::read(&taken)?, b"not a socket");
+
+    let missing = ctx.temp_dir.path().join("missing.sock");
+    Command::new(&ctx.binary_path)
+        .arg("--remote")
+        .arg(&missing)
+        .args(["list", "--path", "/"])
+        .timeout(command_timeout())
+        .assert()
+        .code(EXIT_NOT_FOUND);
+
+    // --remote stands in for --device, so both at once is ambiguous
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    Command::new(&ctx.binary_path)
+        .arg("--remote")
+        .arg(&daemon.socket)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(["list", "--path", "/"])
+        .timeout(command_timeout())
+        .assert()
+        .code(EXIT_USAGE);
+    daemon.shutdown(ctx)
+}
+
+scenarios! {
+    #[contract]
+    remote_matches_direct,
+    #[contract]
+    daemon_holds_device,
+    #[contract]
+    pipelined_requests,
+    #[contract]
+    serve_errors,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_encoding() {
+        let message = request(&["write", "--path", "/a \"quoted\"\\name\n"]);
+        assert_eq!(
+            message,
+            r#"{"args": ["write", "--path", "/a \"quoted\"\\name\n"]}"#
+        );
+        assert_eq!(
+            json_field(&format!("{{\"v\": {}}}", json_string("tab\there")), "v").as_deref(),
+            Some("tab\there")
+        );
+    }
+}
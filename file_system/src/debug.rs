@@ -0,0 +1,422 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `debug`, for looking at on-disk structures directly. All but `set-field`
+// are read-only and print `Key: value` lines unless noted:
+//
+// - `debug superblock`: Magic, Format version, Block size and the block and
+//   inode counts, agreeing with `stats`.
+// - `debug inode N`: Inode, Type (file, directory, symlink or free), Size,
+//   Links and `Block map`, the device blocks holding its data in file order.
+// - `debug block N`: the raw block on stdout, or with `--hex` a `hexdump -C`
+//   style dump, 16 bytes per line and no lines squeezed.
+// - `debug dirents PATH`: one `inode<TAB>type<TAB>name` line per raw
+//   directory entry, `.` and `..` included.
+// - `debug set-field superblock FIELD VALUE` and `debug set-field inode N
+//   FIELD VALUE`: overwrite one field, named in snake case after the keys
+//   above, recomputing any checksum so the damage is purely logical. It
+//   needs `--yes`, and exists to corrupt images on purpose in tests.
+//
+// Block and inode numbers outside the device exit EXIT_INVALID.
+
+use crate::cli::EXIT_USAGE;
+use crate::confirmation::refuses_without_yes;
+use crate::differential::{content, inode_of};
+use crate::errors::{EXIT_INVALID, EXIT_NOT_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::{BAD_LINK_COUNT, EXIT_FSCK_CLEAN, EXIT_FSCK_CORRECTED, EXIT_FSCK_UNCORRECTED};
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::stat::{number, parse_key_values};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const SUPERBLOCK_KEYS: &[&str] = &[
+    "Magic",
+    "Format version",
+    "Block size",
+    "Total blocks",
+    "Free blocks",
+    "Total inodes",
+    "Free inodes",
+];
+// Few inodes are in use, so a free one turns up well before this
+const MAX_INODE_PROBES: u64 = 64;
+const INODE_KEYS: &[&str] = &["Inode", "Type", "Size", "Links", "Block map"];
+
+fn debug_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn debug_fields(
+    ctx: &TestContext,
+    args: &[&str],
+    keys: &[&str],
+) -> io::Result<BTreeMap<String, String>> {
+    let output = ctx.run_bellande_command(&[&["debug"], args].concat())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields = parse_key_values(&stdout)
+        .unwrap_or_else(|| panic!("debug {:?} is not Key: value lines: {:?}", args, stdout));
+    for key in keys {
+        assert!(
+            fields.contains_key(*key),
+            "debug {:?} has no {}: {:?}",
+            args,
+            key,
+            fields
+        );
+    }
+    Ok(fields)
+}
+
+fn superblock(ctx: &TestContext) -> io::Result<BTreeMap<String, String>> {
+    debug_fields(ctx, &["superblock"], SUPERBLOCK_KEYS)
+}
+
+fn inode(ctx: &TestContext, number: u64) -> io::Result<BTreeMap<String, String>> {
+    debug_fields(ctx, &["inode", &number.to_string()], INODE_KEYS)
+}
+
+fn block_map(fields: &BTreeMap<String, String>) -> Vec<u64> {
+    fields["Block map"]
+        .split_whitespace()
+        .map(|block| {
+            block
+                .parse()
+                .unwrap_or_else(|_| panic!("Block map is not block numbers: {:?}", fields))
+        })
+        .collect()
+}
+
+fn inode_number(ctx: &TestContext, path: &str) -> io::Result<u64> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let dir = if dir.is_empty() { "/" } else { dir };
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(inode_of(&String::from_utf8_lossy(&output.stdout), name)
+        .unwrap_or_else(|| panic!("{} is not listed in {}", name, dir)))
+}
+
+// Decodes `hexdump -C` lines: an offset, up to 16 hex bytes in two groups
+// and the bytes again as text between bars; the trailing offset-only line
+// gives the length
+fn parse_hexdump(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut end = None;
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let (offset, rest) = line.split_once("  ").unwrap_or((line, ""));
+        if usize::from_str_radix(offset, 16).ok()? != bytes.len() || end.is_some() {
+            return None;
+        }
+        if rest.is_empty() {
+            end = Some(bytes.len());
+            continue;
+        }
+        let hex = rest.split('|').next()?;
+        let line_bytes = hex
+            .split_whitespace()
+            .map(|byte| {
+                u8::from_str_radix(byte, 16)
+                    .ok()
+                    .filter(|_| byte.len() == 2)
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        if line_bytes.is_empty() || line_bytes.len() > 16 {
+            return None;
+        }
+        bytes.extend(line_bytes);
+    }
+    (end == Some(bytes.len())).then_some(bytes)
+}
+
+fn device_block(ctx: &TestContext, number: u64) -> io::Result<Vec<u8>> {
+    let device = fs::read(&ctx.device_path)?;
+    let start = number as usize * BLOCK;
+    Ok(device[start..start + BLOCK].to_vec())
+}
+
+fn populate(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, seed, len) in [("/dir/data.bin", 1, 3 * BLOCK + 10), ("/empty.txt", 2, 0)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &content(seed, len))?.status.success());
+    }
+    ctx.run_bellande_command(&[
+        "link",
+        "--symbolic",
+        "--target",
+        "/dir/data.bin",
+        "--path",
+        "/link",
+    ])?;
+    Ok(())
+}
+
+pub(crate) fn superblock_and_inodes(ctx: &TestContext) -> io::Result<()> {
+    populate(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+
+    let fields = superblock(ctx)?;
+    let stats = read_stats(ctx)?;
+    assert_eq!(number(&fields, "Block size"), u64::from(BLOCK_SIZE));
+    assert_eq!(number(&fields, "Total blocks"), stats.total_blocks);
+    assert_eq!(number(&fields, "Free blocks"), stats.free_blocks);
+    assert_eq!(number(&fields, "Total inodes"), stats.total_inodes);
+    assert_eq!(number(&fields, "Free inodes"), stats.free_inodes);
+
+    let data = inode(ctx, inode_number(ctx, "/dir/data.bin")?)?;
+    assert_eq!(data["Type"], "file");
+    assert_eq!(number(&data, "Size"), 3 * BLOCK as u64 + 10);
+    assert_eq!(number(&data, "Links"), 1);
+    assert_eq!(block_map(&data).len(), 4);
+    let empty = inode(ctx, inode_number(ctx, "/empty.txt")?)?;
+    assert!(block_map(&empty).is_empty());
+    assert_eq!(inode(ctx, inode_number(ctx, "/dir")?)?["Type"], "directory");
+    assert_eq!(inode(ctx, inode_number(ctx, "/link")?)?["Type"], "symlink");
+
+    // Some inode is free, and numbers past the table are refused
+    let free = (1..stats.total_inodes.min(MAX_INODE_PROBES))
+        .find(|number| inode(ctx, *number).is_ok_and(|fields| fields["Type"] == "free"));
+    assert!(free.is_some(), "no inode reports Type: free");
+    ctx.command(&[
+        "debug",
+        "inode",
+        &stats.total_inodes.saturating_add(1).to_string(),
+    ])
+    .assert()
+    .code(EXIT_INVALID);
+
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a read-only debug command modified the image"
+    );
+    Ok(())
+}
+
+pub(crate) fn blocks_match_device(ctx: &TestContext) -> io::Result<()> {
+    populate(ctx)?;
+    let data = content(1, 3 * BLOCK + 10);
+    let map = block_map(&inode(ctx, inode_number(ctx, "/dir/data.bin")?)?);
+
+    // The Block map points at the file's data, read straight from the image
+    for (index, &number) in map.iter().enumerate() {
+        let raw = ctx
+            .run_bellande_command(&["debug", "block", &number.to_string()])?
+            .stdout;
+        assert_eq!(raw.len(), BLOCK, "debug block {} length", number);
+        assert!(
+            raw == device_block(ctx, number)?,
+            "block {} differs from the image",
+            number
+        );
+        let end = data.len().min((index + 1) * BLOCK);
+        assert!(
+            raw[..end - index * BLOCK] == data[index * BLOCK..end],
+            "block {} is not part {} of the file",
+            number,
+            index
+        );
+
+        let hex = ctx.run_bellande_command(&["debug", "block", &number.to_string(), "--hex"])?;
+        let decoded = parse_hexdump(&String::from_utf8_lossy(&hex.stdout));
+        assert_eq!(decoded, Some(raw), "--hex dump of block {}", number);
+    }
+
+    // Block 0 exists on every device; the first block past the end does not
+    let total = read_stats(ctx)?.total_blocks;
+    assert!(ctx.run_bellande_command(&["debug", "block", "0"])?.stdout == device_block(ctx, 0)?);
+    for args in [
+        &["debug", "block", &total.to_string()][..],
+        &["debug", "block", "-1"],
+        &["debug", "block", "many"],
+    ] {
+        let code = ctx.run_raw(args)?.status.code();
+        assert!(
+            code == Some(EXIT_INVALID) || code == Some(EXIT_USAGE),
+            "{:?} exited {:?}",
+            args,
+            code
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn dirents_match_listing(ctx: &TestContext) -> io::Result<()> {
+    populate(ctx)?;
+    let dir = inode_number(ctx, "/dir")?;
+    let output = ctx.run_bellande_command(&["debug", "dirents", "/dir"])?;
+    let entries: BTreeSet<(u64, String, String)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(
+            |line| match line.split('\t').collect::<Vec<_>>().as_slice() {
+                [number, kind, name] => (
+                    number
+                        .parse()
+                        .unwrap_or_else(|_| panic!("bad dirent: {:?}", line)),
+                    kind.to_string(),
+                    name.to_string(),
+                ),
+                _ => panic!("dirent line is not inode, type and name: {:?}", line),
+            },
+        )
+        .collect();
+    let names: BTreeSet<&str> = entries.iter().map(|(_, _, name)| name.as_str()).collect();
+    assert_eq!(names, BTreeSet::from([".", "..", "data.bin"]));
+    let data = inode_number(ctx, "/dir/data.bin")?;
+    assert!(entries.contains(&(data, "file".to_string(), "data.bin".to_string())));
+    assert!(entries.contains(&(dir, "directory".to_string(), ".".to_string())));
+
+    ctx.command(&["debug", "dirents", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["debug", "dirents", "/empty.txt"])
+        .assert()
+        .code(EXIT_NOT_DIRECTORY);
+    Ok(())
+}
+
+pub(crate) fn set_field_corrupts_on_purpose(ctx: &TestContext) -> io::Result<()> {
+    populate(ctx)?;
+    let image = ctx.temp_dir.path().join("healthy.img");
+    fs::copy(&ctx.device_path, &image)?;
+    let target = inode_number(ctx, "/dir/data.bin")?.to_string();
+
+    refuses_without_yes(ctx, &["debug", "set-field", "inode", &target, "links", "7"]);
+    assert!(fs::read(&ctx.device_path)? == fs::read(&image)?);
+
+    // A wrong link count is exactly what fsck then finds and repairs
+    ctx.run_bellande_command(&[
+        "debug",
+        "set-field",
+        "--yes",
+        "inode",
+        &target,
+        "links",
+        "7",
+    ])?;
+    assert_eq!(number_field(ctx, &target, "Links")?, 7);
+    let output = ctx.run_raw(&["fsck"])?;
+    assert_eq!(output.status.code(), Some(EXIT_FSCK_UNCORRECTED));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .to_ascii_lowercase()
+        .contains(BAD_LINK_COUNT));
+    ctx.command(&["fsck", "--repair"])
+        .assert()
+        .code(EXIT_FSCK_CORRECTED);
+    assert_eq!(number_field(ctx, &target, "Links")?, 1);
+
+    // So is a wrong free count in the superblock
+    let copy = TestContext::from_image(&image)?;
+    let free = read_stats(&copy)?.free_blocks;
+    copy.run_bellande_command(&[
+        "debug",
+        "set-field",
+        "--yes",
+        "superblock",
+        "free_blocks",
+        &(free + 5).to_string(),
+    ])?;
+    assert_eq!(number(&superblock(&copy)?, "Free blocks"), free + 5);
+    copy.command(&["fsck"]).assert().code(EXIT_FSCK_UNCORRECTED);
+    copy.command(&["fsck", "--repair"])
+        .assert()
+        .code(EXIT_FSCK_CORRECTED);
+    copy.command(&["fsck"]).assert().code(EXIT_FSCK_CLEAN);
+    assert_eq!(read_stats(&copy)?.free_blocks, free);
+
+    let before = fs::read(&copy.device_path)?;
+    for args in [
+        &[
+            "debug",
+            "set-field",
+            "--yes",
+            "superblock",
+            "no_such_field",
+            "1",
+        ][..],
+        &[
+            "debug",
+            "set-field",
+            "--yes",
+            "superblock",
+            "free_blocks",
+            "lots",
+        ],
+        &["debug", "set-field", "--yes", "inode", &target, "links"],
+        &["debug", "set-field", "--yes", "bitmap", "0", "1"],
+        &["debug", "frobnicate"],
+        &["debug"],
+    ] {
+        copy.command(args).assert().code(EXIT_USAGE);
+    }
+    copy.command(&[
+        "--read-only",
+        "debug",
+        "set-field",
+        "--yes",
+        "inode",
+        &target,
+        "links",
+        "2",
+    ])
+    .assert()
+    .failure();
+    assert!(
+        fs::read(&copy.device_path)? == before,
+        "a refused set-field modified the image"
+    );
+    Ok(())
+}
+
+fn number_field(ctx: &TestContext, inode: &str, key: &str) -> io::Result<u64> {
+    Ok(number(
+        &debug_fields(ctx, &["inode", inode], INODE_KEYS)?,
+        key,
+    ))
+}
+
+scenarios! {
+    #[contract]
+    superblock_and_inodes(debug_context()?),
+    #[contract]
+    blocks_match_device(debug_context()?),
+    #[contract]
+    dirents_match_listing(debug_context()?),
+    #[contract]
+    set_field_corrupts_on_purpose(debug_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hexdump() {
+        let dump = "00000000  48 65 6c 6c 6f 2c 20 62  6c 6f 63 6b 20 64 65 76  |Hello, block dev|
+00000010  69 63 65 0a                                       |ice.|
+00000014
+";
+        assert_eq!(parse_hexdump(dump), Some(b"Hello, block device\n".to_vec()));
+        assert_eq!(parse_hexdump("00000000\n"), Some(Vec::new()));
+        // Wrong offsets, a missing end line, squeezed lines and bad bytes
+        assert_eq!(parse_hexdump("00000004  41  |A|\n00000001\n"), None);
+        assert_eq!(parse_hexdump("00000000  41  |A|\n"), None);
+        assert_eq!(parse_hexdump("00000000  41  |A|\n*\n00000001\n"), None);
+        assert_eq!(parse_hexdump("00000000  4g  |A|\n00000001\n"), None);
+    }
+}
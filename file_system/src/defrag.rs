@@ -0,0 +1,225 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `defrag` relocates file blocks into contiguous runs, for the whole image
+// or, with `--path`, one file or the files below one directory. It takes the
+// exclusive device lock for its whole run, so it refuses to start while any
+// other command has the device open, and it works through the journal, so a
+// crash mid-move leaves every file readable and unchanged. It prints
+// "Extents before: N", "Extents after: N" and "Files moved: N", the extent
+// counts being those `stats --fragmentation` totals for the files it covered;
+// contents, sizes and free space do not change.
+
+use crate::differential::content;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::extents::{fragmentation, Fragments};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::journal::{tree_state, FAIL_AFTER_ENV};
+use crate::locking::{DEVICE_BUSY_MESSAGE, EXIT_DEVICE_BUSY};
+use predicates::prelude::*;
+use std::fs::{self, File};
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const FILES: &[&str] = &["/dir/a.bin", "/dir/b.bin", "/outside.bin"];
+// Appends per file, interleaved so that no two land next to each other
+const ROUNDS: u64 = 12;
+// Gaps between crash points; every one of them is a separate run
+const CRASH_STEP: usize = 5;
+const MAX_CRASH_POINTS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+struct Report {
+    extents_before: u64,
+    extents_after: u64,
+    files_moved: u64,
+}
+
+fn defrag_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn defrag(ctx: &TestContext, args: &[&str]) -> io::Result<Report> {
+    let mut full = vec!["defrag"];
+    full.extend(args);
+    let output = ctx.run_bellande_command(&full)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(Report {
+        extents_before: stat_field(&stdout, "Extents before")?,
+        extents_after: stat_field(&stdout, "Extents after")?,
+        files_moved: stat_field(&stdout, "Files moved")?,
+    })
+}
+
+fn extents(ctx: &TestContext, path: &str) -> io::Result<Fragments> {
+    Ok(fragmentation(ctx)?.0[path])
+}
+
+// Every file ends up holding ROUNDS blocks in ROUNDS separate runs
+fn fragmented(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for path in FILES {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+    }
+    for round in 0..ROUNDS {
+        for (index, path) in FILES.iter().enumerate() {
+            ctx.command(&["write", "--append", "--path", path])
+                .write_stdin(content(round * 10 + index as u64, BLOCK))
+                .assert()
+                .success();
+        }
+    }
+    for path in FILES {
+        let fragments = extents(ctx, path)?;
+        assert!(
+            fragments.extents > 1,
+            "interleaved appends left {} contiguous: {:?}",
+            path,
+            fragments
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn defrag_makes_files_contiguous(ctx: &TestContext) -> io::Result<()> {
+    fragmented(ctx)?;
+    let tree = tree_state(ctx)?;
+    let stats = read_stats(ctx)?;
+    let (_, before) = fragmentation(ctx)?;
+
+    let report = defrag(ctx, &[])?;
+    assert_eq!(
+        report,
+        Report {
+            extents_before: before.extents,
+            extents_after: FILES.len() as u64,
+            files_moved: FILES.len() as u64,
+        }
+    );
+    for path in FILES {
+        assert_eq!(
+            extents(ctx, path)?,
+            Fragments {
+                extents: 1,
+                blocks: ROUNDS
+            },
+            "{}",
+            path
+        );
+    }
+    assert_eq!(tree_state(ctx)?, tree, "defrag changed contents");
+    assert_eq!(read_stats(ctx)?, stats, "defrag changed free space");
+    assert_fsck_clean(ctx)?;
+
+    // Nothing left to do: nothing written
+    let settled = fs::read(&ctx.device_path)?;
+    let report = defrag(ctx, &[])?;
+    assert_eq!((report.extents_before, report.files_moved), (3, 0));
+    assert!(
+        fs::read(&ctx.device_path)? == settled,
+        "an idle defrag wrote"
+    );
+    Ok(())
+}
+
+pub(crate) fn defrag_limited_to_path(ctx: &TestContext) -> io::Result<()> {
+    fragmented(ctx)?;
+    let outside = extents(ctx, "/outside.bin")?;
+    let a = extents(ctx, "/dir/a.bin")?;
+    let b = extents(ctx, "/dir/b.bin")?;
+
+    let report = defrag(ctx, &["--path", "/dir"])?;
+    assert_eq!(report.extents_before, a.extents + b.extents);
+    assert_eq!((report.extents_after, report.files_moved), (2, 2));
+    assert_eq!(extents(ctx, "/outside.bin")?, outside);
+    assert_eq!(extents(ctx, "/dir/a.bin")?.extents, 1);
+
+    let report = defrag(ctx, &["--path", "/outside.bin"])?;
+    assert_eq!(
+        (report.extents_before, report.extents_after),
+        (outside.extents, 1)
+    );
+
+    ctx.command(&["defrag", "--path", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn defrag_requires_quiesced_device(ctx: &TestContext) -> io::Result<()> {
+    fragmented(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+
+    // A reader holding the device is enough to keep it from starting
+    let device = File::open(&ctx.device_path)?;
+    device.lock_shared()?;
+    ctx.command(&["defrag"])
+        .assert()
+        .code(EXIT_DEVICE_BUSY)
+        .stderr(predicate::str::contains(DEVICE_BUSY_MESSAGE));
+    device.unlock()?;
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a refused defrag wrote"
+    );
+    Ok(())
+}
+
+pub(crate) fn defrag_survives_crashes(base: &TestContext) -> io::Result<()> {
+    fragmented(base)?;
+    let tree = tree_state(base)?;
+    let image = base.temp_dir.path().join("fragmented.img");
+    fs::copy(&base.device_path, &image)?;
+
+    let mut crash_points = 0;
+    for limit in (0..MAX_CRASH_POINTS).step_by(CRASH_STEP) {
+        let ctx = TestContext::from_image(&image)?;
+        let output = ctx
+            .command(&["defrag"])
+            .env(FAIL_AFTER_ENV, limit.to_string())
+            .output()?;
+        if output.status.success() {
+            break;
+        }
+        crash_points += 1;
+        // Replayed on the next open; files may be moved or not, never lost
+        ctx.run_bellande_command(&["stats"])?;
+        assert_fsck_clean(&ctx)?;
+        assert_eq!(
+            tree_state(&ctx)?,
+            tree,
+            "defrag crashed after {} writes and changed contents",
+            limit
+        );
+    }
+    assert!(crash_points > 0, "defrag never hit the failure hook");
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    defrag_makes_files_contiguous(defrag_context()?),
+    #[contract]
+    defrag_limited_to_path(defrag_context()?),
+    #[contract]
+    defrag_requires_quiesced_device(defrag_context()?),
+    #[contract]
+    defrag_survives_crashes(defrag_context()?),
+}
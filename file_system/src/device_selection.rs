@@ -0,0 +1,131 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// How the device is chosen when `--device` is absent: `--device` beats
+// BELLANDE_FS_DEVICE, which beats `--auto` discovery of a single image with a
+// valid superblock in the current directory.
+
+use crate::harness::{command_timeout, format_device, scenarios, TestContext};
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEVICE_ENV: &str = "BELLANDE_FS_DEVICE";
+
+// A formatted device holding one file named after it
+fn marked_device(marker: &str) -> io::Result<TestContext> {
+    let ctx = TestContext::new()?;
+    format_device(&ctx)?;
+    ctx.run_bellande_command(&["create", "--path", &format!("/{}", marker)])?;
+    Ok(ctx)
+}
+
+// A command with no `--device`, run from `cwd` with the variable cleared
+fn deviceless(ctx: &TestContext, cwd: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(&ctx.binary_path);
+    command
+        .args(args)
+        .current_dir(cwd)
+        .env_remove(DEVICE_ENV)
+        .timeout(command_timeout());
+    command
+}
+
+fn empty_dir(ctx: &TestContext, name: &str) -> io::Result<PathBuf> {
+    let dir = ctx.temp_dir.path().join(name);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub(crate) fn env_fallback_and_precedence() -> io::Result<()> {
+    let from_env = marked_device("from_env")?;
+    let from_flag = marked_device("from_flag")?;
+    let cwd = empty_dir(&from_env, "cwd")?;
+
+    deviceless(&from_env, &cwd, &["list", "--path", "/"])
+        .env(DEVICE_ENV, &from_env.device_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from_env"));
+
+    deviceless(&from_env, &cwd, &["--device"])
+        .arg(&from_flag.device_path)
+        .args(["list", "--path", "/"])
+        .env(DEVICE_ENV, &from_env.device_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from_flag"));
+    Ok(())
+}
+
+pub(crate) fn missing_device_lists_options() -> io::Result<()> {
+    let ctx = TestContext::new()?;
+    let cwd = empty_dir(&ctx, "nothing_here")?;
+    deviceless(&ctx, &cwd, &["list", "--path", "/"])
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::contains("--device")
+                .and(predicate::str::contains(DEVICE_ENV))
+                .and(predicate::str::contains("--auto")),
+        );
+    Ok(())
+}
+
+pub(crate) fn auto_discovery() -> io::Result<()> {
+    let image = marked_device("discovered")?;
+    let other = marked_device("other")?;
+    let cwd = empty_dir(&image, "images")?;
+
+    fs::copy(&image.device_path, cwd.join("rootfs.img"))?;
+    // Same extension but no superblock, and an unrelated file
+    File::create(cwd.join("blank.img"))?.set_len(1024 * 1024)?;
+    fs::write(cwd.join("notes.txt"), "not an image")?;
+
+    // Discovery is opt-in
+    deviceless(&image, &cwd, &["list", "--path", "/"])
+        .assert()
+        .failure();
+    deviceless(&image, &cwd, &["--auto", "list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("discovered"));
+
+    // Two valid images are ambiguous, and the error names both
+    fs::copy(&other.device_path, cwd.join("second.bfs"))?;
+    deviceless(&image, &cwd, &["--auto", "list", "--path", "/"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rootfs.img").and(predicate::str::contains("second.bfs")));
+
+    // The variable still wins over discovery
+    deviceless(&image, &cwd, &["--auto", "list", "--path", "/"])
+        .env(DEVICE_ENV, &other.device_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("other"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    env_fallback_and_precedence(),
+    #[contract]
+    missing_device_lists_options(),
+    #[contract]
+    auto_discovery(),
+}
@@ -0,0 +1,94 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Device geometry: tiny devices near capacity and large sparse devices.
+
+use crate::harness::{
+    format_device, scenarios, TestContext, SMALL_DEVICE_SIZE, SPARSE_DEVICE_SIZE,
+};
+use predicates::prelude::*;
+use std::io;
+
+pub(crate) fn small_device_context() -> io::Result<TestContext> {
+    TestContext::with_options(SMALL_DEVICE_SIZE, Some(1024))
+}
+
+pub(crate) fn sparse_device_context() -> io::Result<TestContext> {
+    TestContext::with_options(SPARSE_DEVICE_SIZE, Some(4096))
+}
+
+pub(crate) fn small_device_operations(ctx: &TestContext) -> io::Result<()> {
+    let content = "B".repeat(256 * 1024);
+
+    format_device(ctx)?;
+
+    ctx.command(&["create", "--path", "/tight.txt"])
+        .assert()
+        .success();
+    ctx.command(&["write", "--path", "/tight.txt"])
+        .write_stdin(content.clone())
+        .assert()
+        .success();
+    ctx.command(&["read", "--path", "/tight.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(content.clone()));
+
+    // A write larger than the whole device can never succeed
+    ctx.command(&["create", "--path", "/overflow.txt"])
+        .assert()
+        .success();
+    ctx.command(&["write", "--path", "/overflow.txt"])
+        .write_stdin(vec![b'C'; 2 * 1024 * 1024])
+        .assert()
+        .failure();
+
+    ctx.command(&["read", "--path", "/tight.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(content));
+
+    Ok(())
+}
+
+pub(crate) fn sparse_device_operations(ctx: &TestContext) -> io::Result<()> {
+    let test_content = "Hello, large Bellande device!";
+
+    format_device(ctx)?;
+
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total blocks"));
+
+    ctx.command(&["create", "--path", "/sparse.txt"])
+        .assert()
+        .success();
+    ctx.command(&["write", "--path", "/sparse.txt"])
+        .write_stdin(test_content)
+        .assert()
+        .success();
+    ctx.command(&["read", "--path", "/sparse.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(test_content));
+
+    Ok(())
+}
+
+scenarios! {
+    small_device_operations(small_device_context()?),
+    sparse_device_operations(sparse_device_context()?),
+}
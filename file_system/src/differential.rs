@@ -0,0 +1,581 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Differential testing: random operation sequences are applied both to a
+// Bellande device (through the binary) and to a host tempdir through std::fs,
+// which acts as the model. After every step the two trees must agree.
+
+use crate::errors::error_kind_for_exit;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::replay::{save_repro, Repro};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+const SEED_ENV: &str = "BELLANDE_FS_DIFF_SEED";
+const CASES_ENV: &str = "BELLANDE_FS_DIFF_CASES";
+const DEFAULT_CASES: u64 = 8;
+const OPS_PER_CASE: usize = 24;
+const MAX_WRITE_LEN: u64 = 8192;
+const MAX_PATCH_LEN: u64 = 1024;
+
+// A deliberately small namespace so operations collide with each other
+pub(crate) const PATHS: [&str; 8] = ["/a", "/b", "/f", "/g", "/a/c", "/a/f", "/a/c/f", "/b/g"];
+
+#[derive(Clone, Debug)]
+pub(crate) enum Op {
+    Create(&'static str),
+    Mkdir(&'static str),
+    Write {
+        path: &'static str,
+        seed: u64,
+        len: usize,
+    },
+    Remove(&'static str),
+    Rmdir(&'static str),
+    Read(&'static str),
+    Move {
+        from: &'static str,
+        to: &'static str,
+    },
+    WriteAt {
+        path: &'static str,
+        offset: u64,
+        seed: u64,
+        len: usize,
+    },
+    ReadAt {
+        path: &'static str,
+        offset: u64,
+        len: u64,
+    },
+    Truncate {
+        path: &'static str,
+        size: u64,
+    },
+}
+
+impl Op {
+    // Commands outside the baseline CLI; generated cases only include them
+    // with the `contract-tests` feature
+    fn is_contract(&self) -> bool {
+        matches!(
+            self,
+            Op::Move { .. } | Op::WriteAt { .. } | Op::ReadAt { .. } | Op::Truncate { .. }
+        )
+    }
+}
+
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    // SplitMix64
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+pub(crate) fn content(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    (0..len).map(|_| rng.next_u64() as u8).collect()
+}
+
+pub(crate) fn generate(seed: u64) -> Vec<Op> {
+    let mut rng = Rng::new(seed);
+    iter::repeat_with(|| {
+        let path = PATHS[rng.below(PATHS.len() as u64) as usize];
+        match rng.below(10) {
+            0 => Op::Create(path),
+            1 => Op::Mkdir(path),
+            2 => Op::Write {
+                path,
+                seed: rng.next_u64(),
+                len: rng.below(MAX_WRITE_LEN) as usize,
+            },
+            3 => Op::Remove(path),
+            4 => Op::Rmdir(path),
+            5 => Op::Read(path),
+            6 => Op::Move {
+                from: path,
+                to: PATHS[rng.below(PATHS.len() as u64) as usize],
+            },
+            7 => Op::WriteAt {
+                path,
+                offset: rng.below(MAX_WRITE_LEN),
+                seed: rng.next_u64(),
+                len: rng.below(MAX_PATCH_LEN) as usize,
+            },
+            8 => Op::ReadAt {
+                path,
+                offset: rng.below(MAX_WRITE_LEN),
+                len: rng.below(MAX_PATCH_LEN),
+            },
+            _ => Op::Truncate {
+                path,
+                size: rng.below(MAX_WRITE_LEN),
+            },
+        }
+    })
+    .filter(|op| cfg!(feature = "contract-tests") || !op.is_contract())
+    .take(OPS_PER_CASE)
+    .collect()
+}
+
+// Maps the binary's exit code, or failing that its error output, onto the
+// error kinds std::fs reports; the errno exit codes are part of the contract
+// suite, so without it only the output counts
+fn classify_error(code: Option<i32>, stderr: &str) -> ErrorKind {
+    let by_code = code.filter(|_| cfg!(feature = "contract-tests"));
+    if let Some(kind) = by_code.and_then(error_kind_for_exit) {
+        return kind;
+    }
+    let stderr = stderr.to_lowercase();
+    if stderr.contains("already exists") || stderr.contains("alreadyexists") {
+        ErrorKind::AlreadyExists
+    } else if stderr.contains("not a directory") || stderr.contains("notdirectory") {
+        ErrorKind::NotADirectory
+    } else if stderr.contains("not empty") || stderr.contains("notempty") {
+        ErrorKind::DirectoryNotEmpty
+    } else if stderr.contains("is a directory") || stderr.contains("notfile") {
+        ErrorKind::IsADirectory
+    } else if stderr.contains("not found") || stderr.contains("notfound") {
+        ErrorKind::NotFound
+    } else {
+        ErrorKind::Other
+    }
+}
+
+pub(crate) fn bytes_contain(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+pub(crate) struct Model {
+    root: TempDir,
+}
+
+impl Model {
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Model {
+            root: TempDir::new()?,
+        })
+    }
+
+    fn host(&self, path: &str) -> PathBuf {
+        self.root.path().join(path.trim_start_matches('/'))
+    }
+
+    pub(crate) fn apply(&self, op: &Op) -> Result<Option<Vec<u8>>, ErrorKind> {
+        let result = match op {
+            Op::Create(path) => OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.host(path))
+                .map(|_| None),
+            Op::Mkdir(path) => fs::create_dir(self.host(path)).map(|_| None),
+            Op::Write { path, seed, len } => OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(self.host(path))
+                .and_then(|mut file| file.write_all(&content(*seed, *len)))
+                .map(|_| None),
+            Op::Remove(path) => fs::remove_file(self.host(path)).map(|_| None),
+            Op::Rmdir(path) => fs::remove_dir(self.host(path)).map(|_| None),
+            Op::Read(path) => fs::read(self.host(path)).map(Some),
+            Op::Move { from, to } => return self.apply_move(from, to),
+            Op::WriteAt {
+                path,
+                offset,
+                seed,
+                len,
+            } => OpenOptions::new()
+                .write(true)
+                .open(self.host(path))
+                .and_then(|mut file| {
+                    file.seek(SeekFrom::Start(*offset))?;
+                    file.write_all(&content(*seed, *len))
+                })
+                .map(|_| None),
+            Op::ReadAt { path, offset, len } => return self.apply_read_at(path, *offset, *len),
+            Op::Truncate { path, size } => OpenOptions::new()
+                .write(true)
+                .open(self.host(path))
+                .and_then(|file| file.set_len(*size))
+                .map(|_| None),
+        };
+        result.map_err(|e| e.kind())
+    }
+
+    // Unlike rename(2), `move` never replaces an existing destination
+    // without --force, and refuses to move a directory into itself
+    fn apply_move(&self, from: &str, to: &str) -> Result<Option<Vec<u8>>, ErrorKind> {
+        fs::symlink_metadata(self.host(from)).map_err(|e| e.kind())?;
+        if fs::symlink_metadata(self.host(to)).is_ok() {
+            return Err(ErrorKind::AlreadyExists);
+        }
+        if to.starts_with(&format!("{}/", from)) {
+            return Err(ErrorKind::InvalidInput);
+        }
+        fs::rename(self.host(from), self.host(to))
+            .map(|_| None)
+            .map_err(|e| e.kind())
+    }
+
+    // A range reaching past the end of the file is an error, not zeros
+    fn apply_read_at(
+        &self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, ErrorKind> {
+        let data = fs::read(self.host(path)).map_err(|e| e.kind())?;
+        let end = offset.checked_add(len).ok_or(ErrorKind::InvalidInput)?;
+        if end > data.len() as u64 {
+            return Err(ErrorKind::InvalidInput);
+        }
+        Ok(Some(data[offset as usize..end as usize].to_vec()))
+    }
+}
+
+pub(crate) fn apply_bellande(
+    ctx: &TestContext,
+    op: &Op,
+) -> io::Result<Result<Option<Vec<u8>>, ErrorKind>> {
+    let output = match op {
+        Op::Create(path) => ctx.run_raw(&["create", "--path", path])?,
+        Op::Mkdir(path) => ctx.run_raw(&["mkdir", "--path", path])?,
+        Op::Write { path, seed, len } => write_file(ctx, path, &content(*seed, *len))?,
+        Op::Remove(path) => ctx.run_raw(&["remove", "--path", path])?,
+        Op::Rmdir(path) => ctx.run_raw(&["rmdir", "--path", path])?,
+        Op::Read(path) => ctx.run_raw(&["read", "--path", path])?,
+        Op::Move { from, to } => ctx.run_raw(&["move", "--from", from, "--to", to])?,
+        Op::WriteAt {
+            path,
+            offset,
+            seed,
+            len,
+        } => ctx
+            .command(&["write", "--path", path, "--offset", &offset.to_string()])
+            .write_stdin(content(*seed, *len))
+            .output()?,
+        Op::ReadAt { path, offset, len } => ctx.run_raw(&[
+            "read",
+            "--path",
+            path,
+            "--offset",
+            &offset.to_string(),
+            "--length",
+            &len.to_string(),
+        ])?,
+        Op::Truncate { path, size } => {
+            ctx.run_raw(&["truncate", "--path", path, "--size", &size.to_string()])?
+        }
+    };
+
+    if !output.status.success() {
+        return Ok(Err(classify_error(
+            output.status.code(),
+            &String::from_utf8_lossy(&output.stderr),
+        )));
+    }
+    match op {
+        Op::Read(_) | Op::ReadAt { .. } => Ok(Ok(Some(output.stdout))),
+        _ => Ok(Ok(None)),
+    }
+}
+
+// Names are kept byte-exact (no trimming) so whitespace in names survives
+pub(crate) fn listed_names(stdout: &str) -> BTreeSet<String> {
+    stdout
+        .lines()
+        .map(|line| line.split(" (inode").next().unwrap_or(""))
+        .filter(|name| !name.is_empty() && !name.ends_with(':') && *name != "." && *name != "..")
+        .map(|name| name.to_string())
+        .collect()
+}
+
+// From listing lines like "a.log (inode 7)"
+pub(crate) fn inode_of(listing: &str, name: &str) -> Option<u64> {
+    listing.lines().find_map(|line| {
+        let rest = line.strip_prefix(name)?.strip_prefix(" (inode ")?;
+        rest.trim_end_matches(')').parse().ok()
+    })
+}
+
+// Walks the model tree and checks every directory listing and file content
+fn compare_trees(ctx: &TestContext, model: &Model) -> io::Result<Result<(), String>> {
+    let mut pending = vec![String::from("/")];
+    while let Some(dir) = pending.pop() {
+        let mut expected = BTreeSet::new();
+        for entry in fs::read_dir(model.host(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if dir == "/" {
+                format!("/{}", name)
+            } else {
+                format!("{}/{}", dir, name)
+            };
+
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                let expected_content = fs::read(entry.path())?;
+                let output = ctx.run_raw(&["read", "--path", &path])?;
+                if !output.status.success() {
+                    return Ok(Err(format!("read {} failed", path)));
+                }
+                if output.stdout.len() != expected_content.len() {
+                    return Ok(Err(format!(
+                        "size of {} differs: expected {} bytes, got {}",
+                        path,
+                        expected_content.len(),
+                        output.stdout.len()
+                    )));
+                }
+                if output.stdout != expected_content {
+                    return Ok(Err(format!("content of {} differs", path)));
+                }
+            }
+            expected.insert(name);
+        }
+
+        let output = ctx.run_raw(&["list", "--path", &dir])?;
+        if !output.status.success() {
+            return Ok(Err(format!("list {} failed", dir)));
+        }
+        let actual = listed_names(&String::from_utf8_lossy(&output.stdout));
+        if actual != expected {
+            return Ok(Err(format!(
+                "listing of {} differs: expected {:?}, got {:?}",
+                dir, expected, actual
+            )));
+        }
+    }
+    Ok(Ok(()))
+}
+
+// Runs a sequence on a fresh device and model, returning the first divergence
+pub(crate) fn run_sequence(ops: &[Op]) -> io::Result<Result<(), String>> {
+    let ctx = TestContext::new()?;
+    let model = Model::new()?;
+    format_device(&ctx)?;
+
+    for (step, op) in ops.iter().enumerate() {
+        let expected = model.apply(op);
+        let actual = apply_bellande(&ctx, op)?;
+
+        let agrees = match (&expected, &actual) {
+            (Ok(Some(expected)), Ok(Some(actual))) => actual == expected,
+            (Ok(None), Ok(None)) => true,
+            (Err(expected), Err(actual)) => expected == actual,
+            _ => false,
+        };
+        if !agrees {
+            return Ok(Err(format!(
+                "step {} {:?}: model returned {:?}, filesystem returned {:?}",
+                step,
+                op,
+                expected.map(|data| data.map(|d| d.len())),
+                actual.map(|data| data.map(|d| d.len()))
+            )));
+        }
+
+        if let Err(message) = compare_trees(&ctx, &model)? {
+            return Ok(Err(format!("after step {} {:?}: {}", step, op, message)));
+        }
+    }
+    Ok(Ok(()))
+}
+
+// Greedily drops operations while the sequence keeps failing
+fn shrink(mut ops: Vec<Op>) -> io::Result<(Vec<Op>, String)> {
+    let mut failure = match run_sequence(&ops)? {
+        Err(message) => message,
+        Ok(()) => return Ok((ops, String::from("failure did not reproduce"))),
+    };
+
+    let mut index = 0;
+    while index < ops.len() {
+        let mut candidate = ops.clone();
+        candidate.remove(index);
+        match run_sequence(&candidate)? {
+            Err(message) => {
+                ops = candidate;
+                failure = message;
+            }
+            Ok(()) => index += 1,
+        }
+    }
+    Ok((ops, failure))
+}
+
+pub(crate) fn env_u64(name: &str) -> Option<u64> {
+    env::var(name).ok().map(|value| {
+        value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be an integer, got {:?}", name, value))
+    })
+}
+
+pub(crate) fn differential_against_std_fs() -> io::Result<()> {
+    let base_seed = env_u64(SEED_ENV).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0)
+    });
+    let cases = env_u64(CASES_ENV).unwrap_or(DEFAULT_CASES);
+    println!(
+        "Differential test base seed: {} ({} cases)",
+        base_seed, cases
+    );
+
+    for case in 0..cases {
+        let seed = base_seed.wrapping_add(case);
+        let ops = generate(seed);
+        if run_sequence(&ops)?.is_ok() {
+            continue;
+        }
+
+        let (minimal, failure) = shrink(ops)?;
+        let repro = Repro::record(Some(seed), &minimal)?;
+        let saved = save_repro(&format!("differential-{}", seed), &repro, &failure)?;
+        return Err(io::Error::other(format!(
+            "Differential test failed; reproduce with {}={} {}=1 or replay {}\n{}\nMinimal sequence:\n{:#?}",
+            SEED_ENV,
+            seed,
+            CASES_ENV,
+            saved.display(),
+            failure,
+            minimal
+        )));
+    }
+    Ok(())
+}
+
+scenarios! {
+    differential_against_std_fs(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let first = format!("{:?}", generate(42));
+        let second = format!("{:?}", generate(42));
+        assert_eq!(first, second);
+        assert_eq!(content(7, 64), content(7, 64));
+    }
+
+    #[test]
+    fn test_listed_names() {
+        let names = listed_names("Contents of /:\na (inode 2)\n f  (inode 3)\n\n..\n");
+        let expected: BTreeSet<String> = ["a", " f "].iter().map(|s| s.to_string()).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_inode_of() {
+        let listing = "Contents of /logs:\na.log (inode 7)\na.log.old (inode 9)\n";
+        assert_eq!(inode_of(listing, "a.log"), Some(7));
+        assert_eq!(inode_of(listing, "a.log.old"), Some(9));
+        assert_eq!(inode_of(listing, "b.log"), None);
+    }
+
+    #[test]
+    fn test_model_move() {
+        let model = Model::new().unwrap();
+        model.apply(&Op::Mkdir("/a")).unwrap();
+        model.apply(&Op::Create("/f")).unwrap();
+        let moved = |from, to| model.apply(&Op::Move { from, to });
+        assert_eq!(moved("/g", "/b"), Err(ErrorKind::NotFound));
+        assert_eq!(moved("/f", "/a"), Err(ErrorKind::AlreadyExists));
+        assert_eq!(moved("/a", "/a/c"), Err(ErrorKind::InvalidInput));
+        assert_eq!(moved("/f", "/a/f"), Ok(None));
+        assert_eq!(moved("/a", "/b"), Ok(None));
+        assert!(model.host("/b/f").is_file());
+    }
+
+    #[test]
+    fn test_model_partial_io() {
+        let model = Model::new().unwrap();
+        model.apply(&Op::Create("/f")).unwrap();
+        let write_at = |offset, seed, len| Op::WriteAt {
+            path: "/f",
+            offset,
+            seed,
+            len,
+        };
+        let read_at = |offset, len| {
+            model.apply(&Op::ReadAt {
+                path: "/f",
+                offset,
+                len,
+            })
+        };
+
+        model.apply(&write_at(0, 1, 100)).unwrap();
+        model.apply(&write_at(10, 2, 5)).unwrap();
+        let mut expected = content(1, 100);
+        expected[10..15].copy_from_slice(&content(2, 5));
+        assert_eq!(read_at(0, 100), Ok(Some(expected.clone())));
+        assert_eq!(read_at(98, 2), Ok(Some(expected[98..].to_vec())));
+        assert_eq!(read_at(100, 0), Ok(Some(Vec::new())));
+        assert_eq!(read_at(99, 2), Err(ErrorKind::InvalidInput));
+        assert_eq!(read_at(u64::MAX, 2), Err(ErrorKind::InvalidInput));
+
+        // Past EOF the gap reads back as zeros
+        model.apply(&write_at(120, 3, 4)).unwrap();
+        assert_eq!(read_at(100, 20), Ok(Some(vec![0; 20])));
+
+        // Shrinking drops the tail for good
+        let truncate = |size| model.apply(&Op::Truncate { path: "/f", size });
+        assert_eq!(truncate(50), Ok(None));
+        assert_eq!(truncate(60), Ok(None));
+        let mut expected = expected[..50].to_vec();
+        expected.resize(60, 0);
+        assert_eq!(read_at(0, 60), Ok(Some(expected)));
+        model.apply(&Op::Mkdir("/a")).unwrap();
+        assert_eq!(
+            model.apply(&Op::Truncate {
+                path: "/a",
+                size: 0
+            }),
+            Err(ErrorKind::IsADirectory)
+        );
+    }
+}
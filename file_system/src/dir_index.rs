@@ -0,0 +1,218 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Hashed directory indexes. New images set a superblock feature bit, shown
+// by `stats` as "Directory index: hashed", and keep an index of each
+// directory's names in its inode so a lookup reads a few blocks however
+// large the directory is. `format --dir-index off` makes an image without
+// the bit, like those from before the feature: its directories stay linear
+// lists and every command still works on them, without converting them.
+
+use crate::block_cache::syscalls;
+use crate::cli::EXIT_USAGE;
+use crate::differential::listed_names;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, TestContext, DEFAULT_DEVICE_SIZE};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BLOCK_SIZE: u32 = 1024;
+const LARGE_DIR_ENTRIES: usize = 20_000;
+const LARGE_DEVICE_SIZE: u64 = 64 * 1024 * 1024;
+// A linear scan of 20,000 entries reads hundreds of blocks
+const MAX_LOOKUP_READ_SYSCALLS: u64 = 64;
+const MIXED_ENTRIES: usize = 600;
+
+const INDEXED: &str = "Directory index: hashed";
+const LINEAR: &str = "Directory index: none";
+
+fn index_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn linear_context() -> io::Result<TestContext> {
+    Ok(index_context()?.with_format_args(&["--dir-index", "off"]))
+}
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Names chosen to share prefixes, lengths and case, so a weak hash collides
+fn mixed_name(index: usize) -> String {
+    match index % 4 {
+        0 => format!("entry{}", index),
+        1 => format!("ENTRY{}", index),
+        2 => format!("{:0>200}", index),
+        _ => format!("e.{}.{}", index % 7, index),
+    }
+}
+
+// Creates, removes every third, renames every fifth and re-creates some,
+// returning the names that should remain
+fn churn(ctx: &TestContext) -> io::Result<BTreeSet<String>> {
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let mut expected = BTreeSet::new();
+    for index in 0..MIXED_ENTRIES {
+        let name = mixed_name(index);
+        ctx.run_bellande_command(&["create", "--path", &format!("/dir/{}", name)])?;
+        expected.insert(name);
+    }
+    for index in (0..MIXED_ENTRIES).step_by(3) {
+        let name = mixed_name(index);
+        ctx.run_bellande_command(&["remove", "--path", &format!("/dir/{}", name)])?;
+        expected.remove(&name);
+    }
+    for index in (1..MIXED_ENTRIES).step_by(5) {
+        let name = mixed_name(index);
+        if expected.remove(&name) {
+            let renamed = format!("renamed_{}", index);
+            ctx.run_bellande_command(&[
+                "move",
+                "--from",
+                &format!("/dir/{}", name),
+                "--to",
+                &format!("/dir/{}", renamed),
+            ])?;
+            expected.insert(renamed);
+        }
+    }
+    for index in (0..MIXED_ENTRIES).step_by(9) {
+        let name = mixed_name(index);
+        ctx.run_bellande_command(&["create", "--path", &format!("/dir/{}", name)])?;
+        expected.insert(name);
+    }
+    Ok(expected)
+}
+
+pub(crate) fn large_directory_lookups(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(INDEXED));
+
+    // Populated in one import rather than 20,000 invocations
+    let host = ctx.temp_dir.path().join("large");
+    fs::create_dir_all(&host)?;
+    for index in 0..LARGE_DIR_ENTRIES {
+        fs::write(host.join(format!("file{:05}", index)), b"")?;
+    }
+    ctx.run_bellande_command(&[
+        "import",
+        "--from",
+        &host.to_string_lossy(),
+        "--to",
+        "/large",
+    ])?;
+    assert_eq!(names_in(ctx, "/large")?.len(), LARGE_DIR_ENTRIES);
+
+    if Path::new("/proc/self/io").exists() {
+        let last = format!("/large/file{:05}", LARGE_DIR_ENTRIES - 1);
+        for args in [
+            &["stat", "--path", last.as_str()][..],
+            &["create", "--path", "/large/new"][..],
+        ] {
+            let reads = syscalls(ctx, "syscr", args, &[])?;
+            assert!(
+                reads <= MAX_LOOKUP_READ_SYSCALLS,
+                "{:?} in a {} entry directory took {} read syscalls",
+                args,
+                LARGE_DIR_ENTRIES,
+                reads
+            );
+        }
+    } else {
+        println!("Skipping lookup read counts: /proc/self/io is not available");
+    }
+
+    ctx.run_bellande_command(&["create", "--path", "/large/another"])?;
+    ctx.command(&["create", "--path", "/large/file00042"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    ctx.command(&["stat", "--path", "/large/absent"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert_eq!(names_in(ctx, "/large")?.len(), LARGE_DIR_ENTRIES + 2);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn index_matches_linear(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let expected = churn(ctx)?;
+    assert_eq!(names_in(ctx, "/dir")?, expected);
+    for name in expected.iter().step_by(17) {
+        ctx.run_bellande_command(&["stat", "--path", &format!("/dir/{}", name)])?;
+    }
+    assert_fsck_clean(ctx)?;
+
+    // The same operations on a linear directory end the same way
+    let linear = linear_context()?;
+    format_device(&linear)?;
+    assert_eq!(churn(&linear)?, expected);
+    assert_eq!(names_in(&linear, "/dir")?, expected);
+    assert_fsck_clean(&linear)
+}
+
+pub(crate) fn unindexed_images_stay_linear(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(LINEAR));
+
+    let expected = churn(ctx)?;
+    assert_eq!(names_in(ctx, "/dir")?, expected);
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/dir"])?;
+    assert!(names_in(ctx, "/")?.is_empty());
+
+    // Nothing upgraded the image behind its back
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(LINEAR));
+    ctx.command(&["format", "--yes", "--dir-index", "sideways"])
+        .assert()
+        .code(EXIT_USAGE);
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    large_directory_lookups(TestContext::with_options(LARGE_DEVICE_SIZE, Some(BLOCK_SIZE))?),
+    #[contract]
+    index_matches_linear(index_context()?),
+    #[contract]
+    unindexed_images_stay_linear(linear_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixed_names_distinct() {
+        let names: BTreeSet<String> = (0..MIXED_ENTRIES).map(mixed_name).collect();
+        assert_eq!(names.len(), MIXED_ENTRIES);
+        assert!(names
+            .iter()
+            .all(|name| name.len() <= 255 && !name.contains('/')));
+    }
+}
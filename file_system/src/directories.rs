@@ -0,0 +1,50 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Directory creation, listing, and removal.
+
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+pub(crate) fn create_and_remove_directory(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    ctx.command(&["mkdir", "--path", "/testdir"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Directory created successfully"));
+
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("testdir"));
+
+    ctx.command(&["rmdir", "--path", "/testdir"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Directory removed successfully"));
+
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("testdir").not());
+
+    Ok(())
+}
+
+scenarios! {
+    create_and_remove_directory,
+}
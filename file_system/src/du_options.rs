@@ -0,0 +1,253 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `du` aggregation options, following GNU du: `--max-depth` hides deeper
+// lines without changing any total, `--summarize` prints one line per
+// argument, and `--threshold` drops smaller entries. Each is checked against
+// the plain per-directory output, which is the arithmetic reference.
+
+use crate::cli::EXIT_USAGE;
+use crate::du_usage::DU_TOTAL;
+use crate::fixtures::{populate, FixtureSpec};
+use crate::harness::{scenarios, TestContext};
+use crate::json::json_scalars;
+use crate::sizes::{check_human_sizes, parse_size};
+use predicates::prelude::*;
+use std::collections::BTreeMap;
+use std::io;
+
+// Plain `du` lists directories only, like GNU du without --all
+const DU_DIRS: &[&str] = &["/", "/a", "/a/b", "/a/b/c", "/d"];
+
+fn du_spec() -> FixtureSpec {
+    FixtureSpec::new("du-tree")
+        .dir("/a")
+        .file("/a/x", 10_000, 1)
+        .dir("/a/b")
+        .file("/a/b/y", 3000, 2)
+        .dir("/a/b/c")
+        .file("/a/b/c/z", 50_000, 3)
+        .dir("/d")
+        .file("/d/w", 1, 4)
+        .file("/top", 700, 5)
+}
+
+// Allocated bytes from "<bytes>\t<blocks>\t<path>" lines, keyed by path;
+// the closing total line is left out
+fn du_lines(stdout: &str) -> BTreeMap<String, u64> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let size = fields.next()?.trim().parse().ok()?;
+            let path = fields.nth(1)?;
+            (path != DU_TOTAL).then(|| (path.to_string(), size))
+        })
+        .collect()
+}
+
+// Levels below `root`: the root itself is 0
+fn depth(root: &str, path: &str) -> usize {
+    let rest = path.strip_prefix(root).unwrap_or(path);
+    rest.split('/').filter(|part| !part.is_empty()).count()
+}
+
+fn du(ctx: &TestContext, args: &[&str]) -> io::Result<BTreeMap<String, u64>> {
+    let mut full = vec!["du"];
+    full.extend_from_slice(args);
+    let output = ctx.run_bellande_command(&full)?;
+    Ok(du_lines(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn full_listing(ctx: &TestContext) -> io::Result<BTreeMap<String, u64>> {
+    populate(ctx, &du_spec())?;
+    let full = du(ctx, &["--path", "/"])?;
+    for dir in ["/", "/a", "/a/b", "/a/b/c", "/d"] {
+        assert!(
+            full.contains_key(dir),
+            "du does not list {}: {:?}",
+            dir,
+            full
+        );
+    }
+    Ok(full)
+}
+
+pub(crate) fn max_depth_aggregates(ctx: &TestContext) -> io::Result<()> {
+    let full = full_listing(ctx)?;
+
+    // A directory's total covers its subdirectories' totals plus its own files
+    for (dir, files) in [
+        ("/a/b/c", 50_000),
+        ("/a/b", 3000),
+        ("/a", 10_000),
+        ("/", 700),
+    ] {
+        let children: u64 = DU_DIRS
+            .iter()
+            .filter(|path| path.starts_with(dir) && depth(dir, path) == 1)
+            .map(|path| full[*path])
+            .sum();
+        assert!(
+            full[dir] >= children + files,
+            "{} totals {} but holds {} in subdirectories and {} in files",
+            dir,
+            full[dir],
+            children,
+            files
+        );
+    }
+
+    for max_depth in 0..=3 {
+        let expected: BTreeMap<String, u64> = full
+            .iter()
+            .filter(|(path, _)| depth("/", path) <= max_depth)
+            .map(|(path, size)| (path.clone(), *size))
+            .collect();
+        let depth_arg = max_depth.to_string();
+        assert_eq!(
+            du(ctx, &["--max-depth", &depth_arg, "--path", "/"])?,
+            expected,
+            "--max-depth {}",
+            max_depth
+        );
+    }
+
+    let summary = du(ctx, &["--summarize", "--path", "/a", "--path", "/d"])?;
+    let expected: BTreeMap<String, u64> = [("/a", full["/a"]), ("/d", full["/d"])]
+        .iter()
+        .map(|(path, size)| (path.to_string(), *size))
+        .collect();
+    assert_eq!(summary, expected);
+
+    ctx.command(&["du", "--max-depth", "-1", "--path", "/"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--max-depth"));
+    Ok(())
+}
+
+pub(crate) fn threshold_filters(ctx: &TestContext) -> io::Result<()> {
+    let full = full_listing(ctx)?;
+
+    for threshold in ["1", "4K", "20K", "1M"] {
+        let limit = parse_size(threshold).expect("valid size");
+        let expected: BTreeMap<String, u64> = full
+            .iter()
+            .filter(|(_, size)| **size >= limit)
+            .map(|(path, size)| (path.clone(), *size))
+            .collect();
+        assert_eq!(
+            du(ctx, &["--threshold", threshold, "--path", "/"])?,
+            expected,
+            "--threshold {}",
+            threshold
+        );
+    }
+    ctx.command(&["du", "--threshold", "4Q", "--path", "/"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--threshold"));
+    Ok(())
+}
+
+pub(crate) fn options_compose_with_output_modes(ctx: &TestContext) -> io::Result<()> {
+    let full = full_listing(ctx)?;
+
+    let output = ctx
+        .command(&["--human", "du", "--summarize", "--path", "/a"])
+        .assert()
+        .success();
+    let human = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    // The /a line and the total
+    assert_eq!(check_human_sizes(&human), 2, "{:?}", human);
+
+    let output =
+        ctx.run_bellande_command(&["du", "--format", "json", "--max-depth", "1", "--path", "/"])?;
+    let mut from_json = BTreeMap::new();
+    let mut path = None;
+    for (key, value) in json_scalars(&String::from_utf8_lossy(&output.stdout)) {
+        match key.as_str() {
+            "path" => path = Some(value),
+            "size" => {
+                let path = path.take().expect("size before path in du JSON");
+                from_json.insert(path, value.parse::<u64>().expect("numeric size"));
+            }
+            _ => {}
+        }
+    }
+    let expected: BTreeMap<String, u64> = full
+        .iter()
+        .filter(|(path, _)| depth("/", path) <= 1)
+        .map(|(path, size)| (path.clone(), *size))
+        .collect();
+    assert_eq!(from_json, expected);
+    Ok(())
+}
+
+// Overlapping arguments and hard links both reach an inode a second time:
+// one already counted by this invocation must not count again
+pub(crate) fn seen_inodes_counted_once(ctx: &TestContext) -> io::Result<()> {
+    let full = full_listing(ctx)?;
+    let summary = du(ctx, &["--summarize", "--path", "/a", "--path", "/a/b"])?;
+    assert_eq!(
+        summary.values().sum::<u64>(),
+        full["/a"],
+        "/a/b counted twice: {:?}",
+        summary
+    );
+
+    ctx.run_bellande_command(&["link", "--target", "/a/b/c/z", "--path", "/d/z"])?;
+    assert_eq!(
+        du(ctx, &["--path", "/"])?["/"],
+        full["/"],
+        "/d/z counted twice"
+    );
+    let summary = du(ctx, &["--summarize", "--path", "/a", "--path", "/d"])?;
+    assert_eq!(
+        summary.values().sum::<u64>(),
+        full["/a"] + full["/d"],
+        "/d/z counted twice: {:?}",
+        summary
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    max_depth_aggregates,
+    #[contract]
+    threshold_filters,
+    #[contract]
+    options_compose_with_output_modes,
+    #[contract]
+    seen_inodes_counted_once,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_du_lines_and_depth() {
+        let lines =
+            du_lines("4096\t4\t/\n1024\t1\t/a\n512\t1\t/a/b c\nnot a line\n5632\t6\ttotal\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines["/a/b c"], 512);
+        assert_eq!(depth("/", "/"), 0);
+        assert_eq!(depth("/", "/a/b"), 2);
+        assert_eq!(depth("/a", "/a/b/c"), 2);
+    }
+}
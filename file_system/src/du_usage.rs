@@ -0,0 +1,279 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Where the space went. `du --path` prints "<bytes>\t<blocks>\t<path>" for
+// every directory under each argument, children before their parent, with
+// the bytes being allocated blocks times the block size rather than logical
+// sizes, and ends with a `total` line; an inode is counted once per run. On a
+// file it reports just that file. `stats` splits the device's blocks into
+// metadata (with the inode table, bitmaps and journal listed on their own),
+// data and free, which together add up to the total.
+
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use std::io;
+
+pub(crate) const DU_TOTAL: &str = "total";
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: u64 = BLOCK_SIZE as u64;
+
+#[derive(Debug, PartialEq)]
+struct Usage {
+    path: String,
+    bytes: u64,
+    blocks: u64,
+}
+
+// Entries in output order and the total; None unless every line parses and
+// exactly the last one is the total
+fn parse_du(stdout: &str) -> Option<(Vec<Usage>, Usage)> {
+    let mut entries = Vec::new();
+    for line in stdout.lines() {
+        let mut fields = line.splitn(3, '\t');
+        entries.push(Usage {
+            bytes: fields.next()?.parse().ok()?,
+            blocks: fields.next()?.parse().ok()?,
+            path: fields.next()?.to_string(),
+        });
+    }
+    let total = entries.pop()?;
+    let well_formed =
+        total.path == DU_TOTAL && entries.iter().all(|entry| entry.path.starts_with('/'));
+    well_formed.then_some((entries, total))
+}
+
+fn du(ctx: &TestContext, paths: &[&str]) -> io::Result<(Vec<Usage>, Usage)> {
+    let mut args = vec!["du"];
+    for path in paths {
+        args.extend(["--path", path]);
+    }
+    let output = ctx.run_bellande_command(&args)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (entries, total) = parse_du(&stdout).unwrap_or_else(|| {
+        panic!(
+            "du {:?} is not usage lines and a total: {:?}",
+            paths, stdout
+        )
+    });
+    for entry in entries.iter().chain([&total]) {
+        assert_eq!(entry.bytes, entry.blocks * BLOCK, "{:?}", entry);
+    }
+    Ok((entries, total))
+}
+
+fn usage_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn build_tree(ctx: &TestContext) -> io::Result<()> {
+    for dir in ["/dir", "/dir/sub", "/dir/sub/deeper"] {
+        ctx.run_bellande_command(&["mkdir", "--path", dir])?;
+    }
+    for (path, len) in [
+        ("/dir/empty", 0),
+        ("/dir/one_byte", 1),
+        ("/dir/just_over", 1025),
+        ("/dir/sub/five", 5000),
+        ("/dir/sub/deeper/many", 40_000),
+    ] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &vec![0x5A; len])?.status.success());
+    }
+    // Large logically, but a hole takes no blocks
+    ctx.run_bellande_command(&["create", "--path", "/dir/sparse"])?;
+    ctx.run_bellande_command(&[
+        "truncate",
+        "--path",
+        "/dir/sparse",
+        "--size",
+        "1000000",
+        "--sparse",
+    ])?;
+    Ok(())
+}
+
+pub(crate) fn du_counts_allocated_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let before = read_stats(ctx)?.free_blocks;
+    build_tree(ctx)?;
+    let allocated = before - read_stats(ctx)?.free_blocks;
+
+    // Everything the tree allocated, directory blocks included, and no more
+    let (entries, total) = du(ctx, &["/dir"])?;
+    assert_eq!(total.blocks, allocated);
+    let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+    assert_eq!(paths, ["/dir/sub/deeper", "/dir/sub", "/dir"]);
+    assert_eq!(entries[2].blocks, total.blocks);
+    assert!(entries[0].blocks >= 40, "{:?}", entries[0]);
+    assert!(
+        entries[1].blocks >= entries[0].blocks + 5,
+        "{:?}",
+        entries[1]
+    );
+
+    // Stable from run to run
+    let first = ctx.run_bellande_command(&["du", "--path", "/"])?.stdout;
+    assert_eq!(
+        ctx.run_bellande_command(&["du", "--path", "/"])?.stdout,
+        first
+    );
+    Ok(())
+}
+
+pub(crate) fn du_single_file(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    build_tree(ctx)?;
+
+    for (path, blocks) in [
+        ("/dir/empty", 0),
+        ("/dir/one_byte", 1),
+        ("/dir/just_over", 2),
+        ("/dir/sub/five", 5),
+        ("/dir/sparse", 0),
+    ] {
+        let (entries, total) = du(ctx, &[path])?;
+        assert_eq!(
+            entries,
+            [Usage {
+                path: path.to_string(),
+                bytes: blocks * BLOCK,
+                blocks,
+            }]
+        );
+        assert_eq!(total.blocks, blocks, "{}", path);
+        let output = ctx.run_bellande_command(&["stat", "--path", path])?;
+        assert_eq!(
+            stat_field(&String::from_utf8_lossy(&output.stdout), "Blocks")?,
+            blocks
+        );
+    }
+
+    // A second link to a file adds nothing, within one argument or across two
+    let (_, before) = du(ctx, &["/"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/other"])?;
+    let (_, with_dir) = du(ctx, &["/"])?;
+    ctx.run_bellande_command(&[
+        "link",
+        "--target",
+        "/dir/sub/deeper/many",
+        "--path",
+        "/other/many",
+    ])?;
+    assert_eq!(du(ctx, &["/"])?.1, with_dir);
+    let other_dir = with_dir.blocks - before.blocks;
+    let (_, dir_only) = du(ctx, &["/dir"])?;
+    let (_, across) = du(ctx, &["/dir", "/other"])?;
+    assert_eq!(across.blocks, dir_only.blocks + other_dir);
+    Ok(())
+}
+
+const METADATA_PARTS: &[&str] = &["Inode table blocks", "Bitmap blocks", "Journal blocks"];
+
+struct Breakdown {
+    total: u64,
+    metadata: u64,
+    parts: Vec<u64>,
+    data: u64,
+    free: u64,
+}
+
+fn read_breakdown(ctx: &TestContext) -> io::Result<Breakdown> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let breakdown = Breakdown {
+        total: stat_field(&stdout, "Total blocks")?,
+        metadata: stat_field(&stdout, "Metadata blocks")?,
+        parts: METADATA_PARTS
+            .iter()
+            .map(|part| stat_field(&stdout, part))
+            .collect::<io::Result<_>>()?,
+        data: stat_field(&stdout, "Data blocks")?,
+        free: stat_field(&stdout, "Free blocks")?,
+    };
+    assert_eq!(
+        breakdown.metadata + breakdown.data + breakdown.free,
+        breakdown.total,
+        "the breakdown does not add up: {:?}",
+        stdout
+    );
+    assert!(
+        breakdown.parts.iter().sum::<u64>() <= breakdown.metadata,
+        "metadata parts exceed the metadata total: {:?}",
+        stdout
+    );
+    Ok(breakdown)
+}
+
+pub(crate) fn stats_breakdown(ctx: &TestContext) -> io::Result<()> {
+    let mut inode_tables = Vec::new();
+    for inodes in ["64", "4096"] {
+        ctx.run_bellande_command(&[
+            "format",
+            "--yes",
+            "--block-size",
+            "1024",
+            "--inodes",
+            inodes,
+        ])?;
+        let fresh = read_breakdown(ctx)?;
+        assert!(
+            fresh.parts.iter().all(|blocks| *blocks > 0),
+            "every part is formatted"
+        );
+        inode_tables.push(fresh.parts[0]);
+
+        // File data moves only the data count
+        build_tree(ctx)?;
+        let (_, usage) = du(ctx, &["/"])?;
+        let built = read_breakdown(ctx)?;
+        assert_eq!(built.metadata, fresh.metadata);
+        assert_eq!(built.parts, fresh.parts);
+        assert_eq!(built.data, usage.blocks, "data blocks disagree with du /");
+        assert_eq!(built.free, fresh.free - (built.data - fresh.data));
+    }
+    assert!(
+        inode_tables[1] > inode_tables[0],
+        "more inodes, same inode table"
+    );
+
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    du_counts_allocated_blocks(usage_context()?),
+    #[contract]
+    du_single_file(usage_context()?),
+    #[contract]
+    stats_breakdown(usage_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_du() {
+        let (entries, total) = parse_du("2048\t2\t/a b\n3072\t3\t/\n3072\t3\ttotal\n").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "/a b");
+        assert_eq!(total.blocks, 3);
+        assert!(parse_du("2048\t2\t/a\n").is_none());
+        assert!(parse_du("2048\t/a\n0\t0\ttotal\n").is_none());
+        assert!(parse_du("").is_none());
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Encryption at rest. `format --encrypt aes-256-gcm|xchacha20-poly1305`
+// generates a random data key, wraps it with a key derived from the
+// passphrase and stores the wrapped key in the superblock; every data block
+// is then written encrypted and authenticated, and `read`/`write` work as
+// before. The passphrase comes from the file named by the global
+// `--key-file`, or a prompt when stdin is a terminal. Without a key the
+// command exits EXIT_KEY_REQUIRED, with the wrong one EXIT_KEY_REJECTED,
+// and a tampered data block fails its read like a checksum mismatch; none
+// of them print any data or write to the device. `stats` prints
+// "Encryption: <cipher>" or "Encryption: none".
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_CHECKSUM_MISMATCH, EXIT_KEY_REJECTED, EXIT_KEY_REQUIRED};
+use crate::fsck::{assert_fsck_clean, changed_blocks};
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::partial_io::writes_patch_in_place;
+use crate::stat::stat_reports_file;
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const CIPHERS: &[&str] = &["aes-256-gcm", "xchacha20-poly1305"];
+const PASSPHRASE: &str = "correct horse battery staple";
+const WRONG_PASSPHRASE: &str = "correct horse battery stapler";
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+// Long enough that the file's data is the longest run of blocks it changes
+const TAMPER_BLOCKS: usize = 64;
+const SECRET: &[u8] = b"the launch code is 0000; tell nobody. ";
+
+fn key_path(ctx: &TestContext) -> PathBuf {
+    ctx.temp_dir.path().join("passphrase")
+}
+
+pub(crate) fn encrypted_context(cipher: &str, block_size: u32) -> io::Result<TestContext> {
+    let ctx = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(block_size))?
+        .with_format_args(&["--encrypt", cipher]);
+    let path = key_path(&ctx);
+    fs::write(&path, PASSPHRASE)?;
+    Ok(ctx.with_key_file(path))
+}
+
+fn secret_text(len: usize) -> Vec<u8> {
+    SECRET.iter().copied().cycle().take(len).collect()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+// Middle block of the longest run of consecutive block numbers
+fn middle_of_longest_run(blocks: &[usize]) -> Option<usize> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = 0;
+    for index in 0..blocks.len() {
+        let run_ends = index + 1 == blocks.len() || blocks[index + 1] != blocks[index] + 1;
+        if run_ends {
+            if best.is_none_or(|(_, len)| index + 1 - start > len) {
+                best = Some((start, index + 1 - start));
+            }
+            start = index + 1;
+        }
+    }
+    best.map(|(start, len)| blocks[start + len / 2])
+}
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, len) in [("/secret.txt", 20 * BLOCK + 3), ("/dir/small.txt", 100)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &secret_text(len))?.status.success());
+    }
+    Ok(())
+}
+
+pub(crate) fn encrypted_round_trip() -> io::Result<()> {
+    // The search for plaintext finds it on an unencrypted image
+    let plain = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?;
+    populated(&plain)?;
+    assert!(contains(&fs::read(&plain.device_path)?, SECRET));
+    plain
+        .command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Encryption: none"));
+
+    for cipher in CIPHERS {
+        let ctx = encrypted_context(cipher, BLOCK_SIZE)?;
+        populated(&ctx)?;
+        ctx.command(&["stats"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("Encryption: {}", cipher)));
+
+        let mut expected = secret_text(20 * BLOCK + 3);
+        let patch = content(1, 2 * BLOCK);
+        ctx.command(&["write", "--path", "/secret.txt", "--offset", "1500"])
+            .write_stdin(patch.clone())
+            .assert()
+            .success();
+        expected[1500..1500 + patch.len()].copy_from_slice(&patch);
+        let tail = secret_text(3 * BLOCK);
+        ctx.command(&["write", "--append", "--path", "/secret.txt"])
+            .write_stdin(tail.clone())
+            .assert()
+            .success();
+        expected.extend_from_slice(&tail);
+
+        let output = ctx.run_bellande_command(&["read", "--path", "/secret.txt"])?;
+        assert!(output.stdout == expected, "{}: contents differ", cipher);
+        let output = ctx.run_bellande_command(&["read", "--path", "/dir/small.txt"])?;
+        assert!(output.stdout == secret_text(100));
+
+        // Neither the data nor the passphrase is anywhere on the device
+        let device = fs::read(&ctx.device_path)?;
+        assert!(
+            !contains(&device, SECRET),
+            "{}: plaintext on the device",
+            cipher
+        );
+        assert!(!contains(&device, PASSPHRASE.as_bytes()));
+        assert_fsck_clean(&ctx)?;
+    }
+
+    // Unchanged scenarios, every command carrying `--key-file`
+    stat_reports_file(&encrypted_context(CIPHERS[0], BLOCK_SIZE)?)?;
+    writes_patch_in_place(&encrypted_context(CIPHERS[1], 4096)?)
+}
+
+pub(crate) fn wrong_key_fails_cleanly() -> io::Result<()> {
+    let ctx = encrypted_context(CIPHERS[0], BLOCK_SIZE)?;
+    populated(&ctx)?;
+    let sealed = fs::read(&ctx.device_path)?;
+
+    fs::write(key_path(&ctx), WRONG_PASSPHRASE)?;
+    for (args, stdin) in [
+        (&["read", "--path", "/secret.txt"][..], None),
+        (&["list", "--path", "/dir"][..], None),
+        (
+            &["write", "--path", "/secret.txt"][..],
+            Some(content(2, BLOCK)),
+        ),
+        (&["create", "--path", "/new.txt"][..], None),
+    ] {
+        let mut command = ctx.command(args);
+        if let Some(stdin) = stdin {
+            command.write_stdin(stdin);
+        }
+        command
+            .assert()
+            .code(EXIT_KEY_REJECTED)
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::contains("key"));
+    }
+    assert!(
+        fs::read(&ctx.device_path)? == sealed,
+        "a rejected key wrote"
+    );
+
+    // Without any key, and no terminal to prompt on
+    let keyless = TestContext::from_image(&ctx.device_path)?;
+    keyless
+        .command(&["read", "--path", "/secret.txt"])
+        .assert()
+        .code(EXIT_KEY_REQUIRED)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("--key-file"));
+    keyless
+        .command(&["format", "--yes", "--encrypt", CIPHERS[1]])
+        .assert()
+        .code(EXIT_KEY_REQUIRED);
+    for cipher in ["aes-128-ecb", "AES-256-GCM", ""] {
+        ctx.command(&["format", "--yes", "--encrypt", cipher])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--encrypt"));
+    }
+    assert!(fs::read(&ctx.device_path)? == sealed);
+
+    // The right key still opens it
+    fs::write(key_path(&ctx), PASSPHRASE)?;
+    let output = ctx.run_bellande_command(&["read", "--path", "/secret.txt"])?;
+    assert!(output.stdout == secret_text(20 * BLOCK + 3));
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn tampered_ciphertext_detected() -> io::Result<()> {
+    for cipher in CIPHERS {
+        let ctx = encrypted_context(cipher, BLOCK_SIZE)?;
+        format_device(&ctx)?;
+        ctx.run_bellande_command(&["create", "--path", "/victim.bin"])?;
+        let before = fs::read(&ctx.device_path)?;
+        let data = content(3, TAMPER_BLOCKS * BLOCK);
+        assert!(write_file(&ctx, "/victim.bin", &data)?.status.success());
+
+        let mut device = fs::read(&ctx.device_path)?;
+        let block = middle_of_longest_run(&changed_blocks(&before, &device, BLOCK))
+            .expect("the write changed no blocks");
+        device[block * BLOCK + 29] ^= 0x01;
+        fs::write(&ctx.device_path, &device)?;
+
+        ctx.command(&["read", "--path", "/victim.bin"])
+            .assert()
+            .code(EXIT_CHECKSUM_MISMATCH)
+            .stdout(predicate::str::is_empty());
+        // Blocks before the damaged one still decrypt
+        let output = ctx.run_bellande_command(&[
+            "read",
+            "--path",
+            "/victim.bin",
+            "--length",
+            &BLOCK.to_string(),
+        ])?;
+        assert!(output.stdout == data[..BLOCK], "{}", cipher);
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    encrypted_round_trip(),
+    #[contract]
+    wrong_key_fails_cleanly(),
+    #[contract]
+    tampered_ciphertext_detected(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_middle_of_longest_run() {
+        assert_eq!(middle_of_longest_run(&[1, 5, 6, 7, 8, 9, 20, 21]), Some(7));
+        assert_eq!(middle_of_longest_run(&[3]), Some(3));
+        assert_eq!(middle_of_longest_run(&[]), None);
+        assert!(contains(&secret_text(3 * SECRET.len()), SECRET));
+        assert!(!contains(b"short", SECRET));
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Error reporting. Under the contract, failures exit with the matching errno
+// value, and stderr names the operation and the offending path, with a
+// suggestion where one applies; tests match on exit codes and on those
+// details, not full strings. The binary today only promises a failure and
+// its message, which `error_handling` holds it to.
+
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io::{self, ErrorKind};
+
+pub(crate) const EXIT_NOT_PERMITTED: i32 = 1;
+pub(crate) const EXIT_NOT_FOUND: i32 = 2;
+pub(crate) const EXIT_IO_ERROR: i32 = 5;
+pub(crate) const EXIT_PERMISSION_DENIED: i32 = 13;
+pub(crate) const EXIT_ALREADY_EXISTS: i32 = 17;
+pub(crate) const EXIT_NOT_DIRECTORY: i32 = 20;
+pub(crate) const EXIT_IS_DIRECTORY: i32 = 21;
+pub(crate) const EXIT_INVALID: i32 = 22;
+pub(crate) const EXIT_NAME_TOO_LONG: i32 = 36;
+pub(crate) const EXIT_NOT_EMPTY: i32 = 39;
+pub(crate) const EXIT_LOOP: i32 = 40;
+pub(crate) const EXIT_NO_ATTRIBUTE: i32 = 61;
+// EBADMSG, as Linux filesystems report a failed checksum, so it is never
+// mistaken for EIO from the device
+pub(crate) const EXIT_CHECKSUM_MISMATCH: i32 = 74;
+// sysexits' EX_NOPERM and EX_CONFIG, like EXIT_USAGE: ENOKEY (126) and
+// EKEYREJECTED (129) would read as "not executable" and a signal to a shell
+pub(crate) const EXIT_KEY_REJECTED: i32 = 77;
+pub(crate) const EXIT_KEY_REQUIRED: i32 = 78;
+pub(crate) const EXIT_NOT_SUPPORTED: i32 = 95;
+pub(crate) const EXIT_QUOTA_EXCEEDED: i32 = 122;
+
+pub(crate) fn error_kind_for_exit(code: i32) -> Option<ErrorKind> {
+    match code {
+        EXIT_NOT_FOUND => Some(ErrorKind::NotFound),
+        EXIT_PERMISSION_DENIED => Some(ErrorKind::PermissionDenied),
+        EXIT_ALREADY_EXISTS => Some(ErrorKind::AlreadyExists),
+        EXIT_NOT_DIRECTORY => Some(ErrorKind::NotADirectory),
+        EXIT_IS_DIRECTORY => Some(ErrorKind::IsADirectory),
+        EXIT_INVALID => Some(ErrorKind::InvalidInput),
+        EXIT_NOT_EMPTY => Some(ErrorKind::DirectoryNotEmpty),
+        _ => None,
+    }
+}
+
+fn mentions(parts: &[&str]) -> impl Predicate<str> {
+    let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+    predicate::function(move |stderr: &str| parts.iter().all(|part| stderr.contains(part.as_str())))
+}
+
+// The errors the binary reports today: a failure with its message
+pub(crate) fn error_handling(ctx: &TestContext) -> io::Result<()> {
+    // Try to use unformatted device first
+    ctx.command(&["list", "--path", "/"]).assert().failure();
+
+    format_device(ctx)?;
+
+    ctx.command(&["remove", "--path", "/nonexistent.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("File not found"));
+
+    ctx.command(&["create", "--path", "invalid/path/file.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid path"));
+
+    Ok(())
+}
+
+// The same errors held to their exit codes and context
+pub(crate) fn error_handling_exit_codes(ctx: &TestContext) -> io::Result<()> {
+    let device = ctx.device_path.to_string_lossy().into_owned();
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .failure()
+        .stderr(mentions(&[&device]));
+
+    format_device(ctx)?;
+
+    ctx.command(&["remove", "--path", "/nonexistent.txt"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(mentions(&["remove", "/nonexistent.txt"]));
+
+    ctx.command(&["create", "--path", "invalid/path/file.txt"])
+        .assert()
+        .code(EXIT_INVALID)
+        .stderr(mentions(&["Invalid path", "invalid/path/file.txt"]));
+
+    Ok(())
+}
+
+pub(crate) fn errors_carry_context(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/a"])?;
+    ctx.run_bellande_command(&["create", "--path", "/test.txt"])?;
+
+    ctx.command(&["create", "--path", "/a/b/file.txt"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(mentions(&["create", "/a/b", "mkdir --parents"]));
+    ctx.command(&["create", "--path", "/test.txt"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(mentions(&["create", "/test.txt"]));
+    ctx.command(&["create", "--path", "/test.txt/inner"])
+        .assert()
+        .code(EXIT_NOT_DIRECTORY)
+        .stderr(mentions(&["/test.txt"]));
+    ctx.command(&["read", "--path", "/a"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY)
+        .stderr(mentions(&["read", "/a"]));
+
+    ctx.run_bellande_command(&["create", "--path", "/a/inside.txt"])?;
+    ctx.command(&["rmdir", "--path", "/a"])
+        .assert()
+        .code(EXIT_NOT_EMPTY)
+        .stderr(mentions(&["rmdir", "/a"]));
+    Ok(())
+}
+
+pub(crate) fn nearest_name_suggestions(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/docs"])?;
+    ctx.run_bellande_command(&["create", "--path", "/test.txt"])?;
+    ctx.run_bellande_command(&["create", "--path", "/docs/readme.md"])?;
+
+    ctx.command(&["read", "--path", "/tset.txt"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("did you mean /test.txt?"));
+    ctx.command(&["remove", "--path", "/docs/reedme.md"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("did you mean /docs/readme.md?"));
+
+    // Suggestions only come from the same directory, and only when close
+    ctx.command(&["read", "--path", "/readme.md"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("did you mean").not());
+    ctx.command(&["read", "--path", "/completely-different"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("did you mean").not());
+    Ok(())
+}
+
+scenarios! {
+    error_handling,
+    #[contract]
+    error_handling_exit_codes,
+    #[contract]
+    errors_carry_context,
+    #[contract]
+    nearest_name_suggestions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 126 and up mean "not executable", "not found" and signals to a shell
+    #[test]
+    fn test_exit_codes_stay_below_126() {
+        let codes = [
+            EXIT_NOT_PERMITTED,
+            EXIT_NOT_FOUND,
+            EXIT_IO_ERROR,
+            EXIT_PERMISSION_DENIED,
+            EXIT_ALREADY_EXISTS,
+            EXIT_NOT_DIRECTORY,
+            EXIT_IS_DIRECTORY,
+            EXIT_INVALID,
+            EXIT_NAME_TOO_LONG,
+            EXIT_NOT_EMPTY,
+            EXIT_LOOP,
+            EXIT_NO_ATTRIBUTE,
+            EXIT_CHECKSUM_MISMATCH,
+            EXIT_KEY_REJECTED,
+            EXIT_KEY_REQUIRED,
+            EXIT_NOT_SUPPORTED,
+            EXIT_QUOTA_EXCEEDED,
+            crate::cli::EXIT_USAGE,
+        ];
+        let mut distinct = codes.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), codes.len());
+        assert!(codes.iter().all(|&code| code > 0 && code < 126));
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Extent-based allocation, format version 4 and the default for new images:
+// a file maps runs of contiguous blocks rather than one pointer per block,
+// so large files cost a handful of metadata entries; older images keep their
+// block maps. `stats --fragmentation` prints "<extents>\t<blocks>\t<path>"
+// for every regular file, sorted by path, then a `total` line. A hole splits
+// a file into separate extents and takes no blocks.
+
+use crate::differential::content;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::large_device::{read_stream, stream_checksum, write_stream};
+use std::collections::BTreeMap;
+use std::io;
+
+pub(crate) const EXTENT_FORMAT_VERSION: u32 = 4;
+const FRAGMENTATION_TOTAL: &str = "total";
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: u64 = BLOCK_SIZE as u64;
+
+const LARGE_DEVICE_SIZE: u64 = 128 * 1024 * 1024;
+const LARGE_BLOCK_SIZE: u32 = 4096;
+const LARGE_FILE_LEN: u64 = 100 * 1024 * 1024;
+// A fresh device has one free run, split at most by metadata groups
+const MAX_LARGE_FILE_EXTENTS: u64 = 8;
+// Blocks a 100 MiB file may take beyond its data; a block map would need
+// around fifty just for pointers
+const MAX_EXTENT_TREE_BLOCKS: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Fragments {
+    pub(crate) extents: u64,
+    pub(crate) blocks: u64,
+}
+
+// Files by path and the total; None unless every line parses, paths are
+// sorted and exactly the last line is the total
+fn parse_fragmentation(stdout: &str) -> Option<(Vec<(String, Fragments)>, Fragments)> {
+    let mut entries = Vec::new();
+    for line in stdout.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let fragments = Fragments {
+            extents: fields.next()?.parse().ok()?,
+            blocks: fields.next()?.parse().ok()?,
+        };
+        entries.push((fields.next()?.to_string(), fragments));
+    }
+    let (name, total) = entries.pop()?;
+    let well_formed = name == FRAGMENTATION_TOTAL
+        && entries.iter().all(|(path, _)| path.starts_with('/'))
+        && entries.windows(2).all(|pair| pair[0].0 < pair[1].0);
+    well_formed.then_some((entries, total))
+}
+
+pub(crate) fn fragmentation(
+    ctx: &TestContext,
+) -> io::Result<(BTreeMap<String, Fragments>, Fragments)> {
+    let output = ctx.run_bellande_command(&["stats", "--fragmentation"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (entries, total) = parse_fragmentation(&stdout).unwrap_or_else(|| {
+        panic!(
+            "stats --fragmentation is not per-file lines and a total: {:?}",
+            stdout
+        )
+    });
+    for (path, fragments) in &entries {
+        assert!(
+            fragments.extents <= fragments.blocks
+                && (fragments.extents == 0) == (fragments.blocks == 0),
+            "{}: {:?}",
+            path,
+            fragments
+        );
+    }
+    let sum = |field: fn(&Fragments) -> u64| entries.iter().map(|(_, f)| field(f)).sum::<u64>();
+    assert_eq!(
+        total,
+        Fragments {
+            extents: sum(|f| f.extents),
+            blocks: sum(|f| f.blocks),
+        },
+        "the total is not the sum of the files"
+    );
+    Ok((entries.into_iter().collect(), total))
+}
+
+fn extent_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+pub(crate) fn large_file_few_extents(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = read_stats(ctx)?;
+    let block = u64::from(ctx.options.block_size.unwrap_or(LARGE_BLOCK_SIZE));
+
+    ctx.run_bellande_command(&["create", "--path", "/large.bin"])?;
+    write_stream(ctx, "/large.bin", 7, LARGE_FILE_LEN)?;
+    assert_eq!(
+        read_stream(ctx, "/large.bin")?,
+        (LARGE_FILE_LEN, stream_checksum(7, LARGE_FILE_LEN))
+    );
+
+    let (files, _) = fragmentation(ctx)?;
+    let large = files["/large.bin"];
+    assert_eq!(large.blocks, LARGE_FILE_LEN / block);
+    assert!(
+        large.extents <= MAX_LARGE_FILE_EXTENTS,
+        "a 100 MiB file on an empty device took {} extents",
+        large.extents
+    );
+    let used = empty.free_blocks - read_stats(ctx)?.free_blocks;
+    assert!(
+        used <= large.blocks + MAX_EXTENT_TREE_BLOCKS,
+        "{} data blocks used {} blocks in all",
+        large.blocks,
+        used
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn fragmentation_reported(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let (files, total) = fragmentation(ctx)?;
+    assert!(files.is_empty(), "directories listed: {:?}", files);
+    assert_eq!(
+        total,
+        Fragments {
+            extents: 0,
+            blocks: 0
+        }
+    );
+
+    ctx.run_bellande_command(&["create", "--path", "/dir/empty"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/whole.bin"])?;
+    assert!(
+        write_file(ctx, "/dir/whole.bin", &content(1, 40 * BLOCK as usize))?
+            .status
+            .success()
+    );
+
+    // One block, a nine block hole, then one more
+    ctx.run_bellande_command(&["create", "--path", "/holed.bin"])?;
+    assert!(write_file(ctx, "/holed.bin", &content(2, BLOCK as usize))?
+        .status
+        .success());
+    ctx.command(&[
+        "write",
+        "--path",
+        "/holed.bin",
+        "--offset",
+        &(10 * BLOCK).to_string(),
+    ])
+    .write_stdin(content(3, BLOCK as usize))
+    .assert()
+    .success();
+    ctx.run_bellande_command(&["create", "--path", "/sparse.bin"])?;
+    ctx.run_bellande_command(&[
+        "truncate",
+        "--path",
+        "/sparse.bin",
+        "--size",
+        "1000000",
+        "--sparse",
+    ])?;
+
+    // Blocks handed out alternately; however the allocator places them,
+    // each file keeps its own count
+    for path in ["/a.bin", "/b.bin"] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+    }
+    for round in 0..5 {
+        for (seed, path) in [(10, "/a.bin"), (20, "/b.bin")] {
+            ctx.command(&["write", "--append", "--path", path])
+                .write_stdin(content(seed + round, BLOCK as usize))
+                .assert()
+                .success();
+        }
+    }
+
+    let (files, _) = fragmentation(ctx)?;
+    assert_eq!(
+        files.keys().map(String::as_str).collect::<Vec<_>>(),
+        [
+            "/a.bin",
+            "/b.bin",
+            "/dir/empty",
+            "/dir/whole.bin",
+            "/holed.bin",
+            "/sparse.bin"
+        ]
+    );
+    let expect = |extents, blocks| Fragments { extents, blocks };
+    assert_eq!(files["/dir/empty"], expect(0, 0));
+    assert_eq!(files["/dir/whole.bin"], expect(1, 40));
+    assert_eq!(files["/holed.bin"], expect(2, 2));
+    assert_eq!(files["/sparse.bin"], expect(0, 0));
+    for path in ["/a.bin", "/b.bin"] {
+        assert_eq!(files[path].blocks, 5, "{}", path);
+    }
+
+    // The view is read-only
+    let before = std::fs::read(&ctx.device_path)?;
+    fragmentation(ctx)?;
+    assert!(std::fs::read(&ctx.device_path)? == before);
+    Ok(())
+}
+
+pub(crate) fn extents_follow_rewrites(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let mut model = content(4, 40 * BLOCK as usize);
+    ctx.run_bellande_command(&["create", "--path", "/file.bin"])?;
+    assert!(write_file(ctx, "/file.bin", &model)?.status.success());
+    let full = read_stats(ctx)?;
+    assert_eq!(
+        fragmentation(ctx)?.0["/file.bin"],
+        Fragments {
+            extents: 1,
+            blocks: 40
+        }
+    );
+
+    // Overwriting in place reuses the mapped blocks
+    let patch = content(5, 3000);
+    model[1000..4000].copy_from_slice(&patch);
+    ctx.command(&["write", "--path", "/file.bin", "--offset", "1000"])
+        .write_stdin(patch)
+        .assert()
+        .success();
+    assert_eq!(
+        fragmentation(ctx)?.0["/file.bin"],
+        Fragments {
+            extents: 1,
+            blocks: 40
+        }
+    );
+
+    // Shrinking trims the extent and frees its tail
+    ctx.run_bellande_command(&[
+        "truncate",
+        "--path",
+        "/file.bin",
+        "--size",
+        &(10 * BLOCK).to_string(),
+    ])?;
+    model.truncate(10 * BLOCK as usize);
+    assert_eq!(
+        fragmentation(ctx)?.0["/file.bin"],
+        Fragments {
+            extents: 1,
+            blocks: 10
+        }
+    );
+    assert_eq!(read_stats(ctx)?.free_blocks, full.free_blocks + 30);
+    let output = ctx.run_bellande_command(&["read", "--path", "/file.bin"])?;
+    assert!(
+        output.stdout == model,
+        "contents changed after the rewrites"
+    );
+
+    ctx.run_bellande_command(&["remove", "--path", "/file.bin"])?;
+    let (files, total) = fragmentation(ctx)?;
+    assert!(files.is_empty());
+    assert_eq!(
+        total,
+        Fragments {
+            extents: 0,
+            blocks: 0
+        }
+    );
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    large_file_few_extents(TestContext::with_options(LARGE_DEVICE_SIZE, Some(LARGE_BLOCK_SIZE))?),
+    #[contract]
+    fragmentation_reported(extent_context()?),
+    #[contract]
+    extents_follow_rewrites(extent_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fragmentation() {
+        let (entries, total) =
+            parse_fragmentation("1\t40\t/a\n2\t2\t/b/c\n3\t42\ttotal\n").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, "/b/c");
+        assert_eq!(
+            total,
+            Fragments {
+                extents: 3,
+                blocks: 42
+            }
+        );
+        assert!(parse_fragmentation("0\t0\ttotal\n").is_some());
+        // Unsorted, missing total, junk
+        assert!(parse_fragmentation("1\t1\t/b\n1\t1\t/a\n2\t2\ttotal\n").is_none());
+        assert!(parse_fragmentation("1\t1\t/a\n").is_none());
+        assert!(parse_fragmentation("one\t1\t/a\n1\t1\ttotal\n").is_none());
+        assert!(parse_fragmentation("").is_none());
+    }
+}
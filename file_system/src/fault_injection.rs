@@ -0,0 +1,312 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Fault injection below the filesystem. With BELLANDE_FS_FAULT set to
+// `fail:N`, `flip:N` or `short-write:N` the binary opens its device through
+// block_driver's `FaultyDriver`, which faults the Nth block I/O of the
+// command (counting from 0): `fail` fails that one call, `flip` flips one
+// bit in what it reads or writes, and `short-write` stores the first half
+// of that write's blocks and then loses the device, as a power cut would. A
+// failed I/O exits EXIT_IO_ERROR, and a flipped bit caught by a checksum
+// EXIT_CHECKSUM_MISMATCH. A malformed spec is a usage error, made before
+// the device is opened.
+//
+// Random operation sequences come from the differential generator, so a
+// seed reproduces a case. After every injected fault the device must replay
+// to a clean fsck with the tree and the free block and inode counts of the
+// state before or after the operation, so nothing is leaked or half-done.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{apply_bellande, content, env_u64, generate, Op};
+use crate::errors::{EXIT_CHECKSUM_MISMATCH, EXIT_IO_ERROR};
+use crate::fsck::{EXIT_FSCK_CLEAN, EXIT_FSCK_CORRECTED, EXIT_FSCK_UNCORRECTED};
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, FsStats, TestContext, SMALL_DEVICE_SIZE,
+};
+use crate::journal::tree_state;
+use crate::metadata_checksums::CRC32C_FORMAT_ARGS;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const FAULT_ENV: &str = "BELLANDE_FS_FAULT";
+
+const SEED_ENV: &str = "BELLANDE_FS_FAULT_SEED";
+const CASES_ENV: &str = "BELLANDE_FS_FAULT_CASES";
+const DEFAULT_CASES: u64 = 2;
+// A prefix of each generated sequence, since every step is run once per I/O
+const OPS_PER_CASE: usize = 6;
+// Far more block I/Os than any single operation below needs
+const MAX_FAULT_POINTS: u64 = 2_000;
+const FLIP_POINTS: u64 = 64;
+const BLOCK_SIZE: u32 = 1024;
+const FILE_LEN: usize = 6 * BLOCK_SIZE as usize;
+// What a panicking Rust binary exits with
+const EXIT_PANIC: i32 = 101;
+
+type State = (BTreeMap<String, Option<u64>>, FsStats);
+
+fn fault_context() -> io::Result<TestContext> {
+    TestContext::with_options(SMALL_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn checksum_context() -> io::Result<TestContext> {
+    Ok(fault_context()?.with_format_args(CRC32C_FORMAT_ARGS))
+}
+
+// A fresh copy of `image` whose commands inject `spec`
+fn faulty_copy(image: &Path, spec: &str) -> io::Result<TestContext> {
+    Ok(TestContext::from_image(image)?.with_fault(spec))
+}
+
+// The tree and counts of a device, after the open has replayed any journal
+fn recovered_state(device: &Path) -> io::Result<(State, i32)> {
+    let ctx = TestContext::from_image(device)?;
+    ctx.run_bellande_command(&["stats"])?;
+    let fsck = ctx.run_raw(&["fsck"])?.status.code().unwrap_or(-1);
+    Ok(((tree_state(&ctx)?, read_stats(&ctx)?), fsck))
+}
+
+fn state(ctx: &TestContext) -> io::Result<State> {
+    Ok((tree_state(ctx)?, read_stats(ctx)?))
+}
+
+// Runs `op` with a short write at every I/O it makes, from `image`
+fn tear_every_write(image: &Path, op: &Op, label: &str) -> io::Result<()> {
+    let before = state(&TestContext::from_image(image)?)?;
+    let complete = TestContext::from_image(image)?;
+    if apply_bellande(&complete, op)?.is_err() {
+        // Refused operations are the differential test's business
+        return Ok(());
+    }
+    let after = state(&complete)?;
+
+    for at in 0..MAX_FAULT_POINTS {
+        let ctx = faulty_copy(image, &format!("short-write:{}", at))?;
+        if apply_bellande(&ctx, op)?.is_ok() {
+            return Ok(());
+        }
+        let (state, fsck) = recovered_state(&ctx.device_path)?;
+        assert_eq!(
+            fsck, EXIT_FSCK_CLEAN,
+            "{}: torn write at I/O {} left fsck unclean",
+            label, at
+        );
+        assert!(
+            state == before || state == after,
+            "{}: torn write at I/O {} left neither the state before nor after it \
+             (free blocks {} vs {} or {})",
+            label,
+            at,
+            state.1.free_blocks,
+            before.1.free_blocks,
+            after.1.free_blocks
+        );
+    }
+    panic!("{} never completed under torn writes", label);
+}
+
+pub(crate) fn torn_writes_stay_consistent() -> io::Result<()> {
+    let base_seed = env_u64(SEED_ENV).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0)
+    });
+    let cases = env_u64(CASES_ENV).unwrap_or(DEFAULT_CASES);
+    println!("Fault injection base seed: {} ({} cases)", base_seed, cases);
+
+    for case in 0..cases {
+        let seed = base_seed.wrapping_add(case);
+        let ctx = fault_context()?;
+        format_device(&ctx)?;
+        let image = ctx.temp_dir.path().join("step.img");
+        for (step, op) in generate(seed).iter().take(OPS_PER_CASE).enumerate() {
+            fs::copy(&ctx.device_path, &image)?;
+            let label = format!("{}={} step {} {:?}", SEED_ENV, seed, step, op);
+            tear_every_write(&image, op, &label)?;
+            apply_bellande(&ctx, op)?.ok();
+        }
+    }
+    Ok(())
+}
+
+// Each changes data and several metadata structures at once
+const OPERATIONS: &[&[&str]] = &[
+    &["create", "--parents", "--path", "/x/y/new.txt"],
+    &["move", "--from", "/dir/file.bin", "--to", "/moved.bin"],
+    &["truncate", "--path", "/big.bin", "--size", "100"],
+    &["remove", "--recursive", "--path", "/dir"],
+];
+
+fn base_state(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, seed) in [("/big.bin", 1), ("/dir/file.bin", 2), ("/dir/other.bin", 3)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &content(seed, FILE_LEN))?
+            .status
+            .success());
+    }
+    Ok(())
+}
+
+pub(crate) fn failed_io_is_all_or_nothing(base: &TestContext) -> io::Result<()> {
+    base_state(base)?;
+    let image = base.temp_dir.path().join("base.img");
+    fs::copy(&base.device_path, &image)?;
+    let before = state(base)?;
+
+    for args in OPERATIONS {
+        let complete = TestContext::from_image(&image)?;
+        complete.run_bellande_command(args)?;
+        let after = state(&complete)?;
+
+        let mut failures = 0;
+        for at in 0..MAX_FAULT_POINTS {
+            let ctx = faulty_copy(&image, &format!("fail:{}", at))?;
+            let output = ctx.run_raw(args)?;
+            let (state, fsck) = recovered_state(&ctx.device_path)?;
+            assert_eq!(fsck, EXIT_FSCK_CLEAN, "{:?} failing I/O {}", args, at);
+            if output.status.success() {
+                // Either past its last I/O, or the binary retried
+                assert!(
+                    state == after,
+                    "{:?} succeeded despite failing I/O {}",
+                    args,
+                    at
+                );
+                break;
+            }
+            failures += 1;
+            assert_eq!(
+                output.status.code(),
+                Some(EXIT_IO_ERROR),
+                "{:?} failing I/O {}: {}",
+                args,
+                at,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            assert!(
+                state == before || state == after,
+                "{:?} failing I/O {} was half-applied",
+                args,
+                at
+            );
+        }
+        assert!(failures > 0, "{:?} never hit the fault", args);
+    }
+    Ok(())
+}
+
+// With every block checksummed, a flipped bit may fail a command but must
+// never come back as data
+pub(crate) fn flipped_bits_never_read_back(base: &TestContext) -> io::Result<()> {
+    format_device(base)?;
+    let data = content(4, FILE_LEN);
+    base.run_bellande_command(&["create", "--path", "/data.bin"])?;
+    let image = base.temp_dir.path().join("base.img");
+    fs::copy(&base.device_path, &image)?;
+
+    for at in 0..FLIP_POINTS {
+        let spec = format!("flip:{}", at);
+        // Corrupted on the way in
+        let ctx = faulty_copy(&image, &spec)?;
+        let output = write_file(&ctx, "/data.bin", &data)?;
+        assert_ne!(
+            output.status.code(),
+            Some(EXIT_PANIC),
+            "write with {}",
+            spec
+        );
+        let clean = TestContext::from_image(&ctx.device_path)?;
+        let read = clean.run_raw(&["read", "--path", "/data.bin"])?;
+        assert_ne!(read.status.code(), Some(EXIT_PANIC), "read after {}", spec);
+        if output.status.success() && read.status.success() {
+            assert!(
+                read.stdout == data,
+                "a bit flipped by {} while writing was read back",
+                spec
+            );
+        }
+        let fsck = clean.run_raw(&["fsck"])?.status.code();
+        assert!(
+            [EXIT_FSCK_CLEAN, EXIT_FSCK_CORRECTED, EXIT_FSCK_UNCORRECTED]
+                .map(Some)
+                .contains(&fsck),
+            "fsck after {} exited {:?}",
+            spec,
+            fsck
+        );
+
+        // Corrupted on the way out
+        let written = TestContext::from_image(&image)?;
+        assert!(write_file(&written, "/data.bin", &data)?.status.success());
+        let ctx = faulty_copy(&written.device_path, &spec)?;
+        let read = ctx.run_raw(&["read", "--path", "/data.bin"])?;
+        if read.status.success() {
+            assert!(
+                read.stdout == data,
+                "a bit flipped by {} while reading was returned",
+                spec
+            );
+        } else {
+            assert_eq!(
+                read.status.code(),
+                Some(EXIT_CHECKSUM_MISMATCH),
+                "read with {} was not caught by its checksum",
+                spec
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn fault_spec_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+    for spec in [
+        "",
+        "fail",
+        "fail:",
+        "fail:x",
+        "fail:-1",
+        "melt:3",
+        "short-write:1:2",
+    ] {
+        ctx.command(&["create", "--path", "/never"])
+            .env(FAULT_ENV, spec)
+            .assert()
+            .code(EXIT_USAGE);
+    }
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a malformed fault spec touched the device"
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    torn_writes_stay_consistent(),
+    #[contract]
+    failed_io_is_all_or_nothing(fault_context()?),
+    #[contract]
+    flipped_bits_never_read_back(checksum_context()?),
+    #[contract]
+    fault_spec_errors(fault_context()?),
+}
@@ -0,0 +1,412 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Filename handling matrix. The expected behavior for every category lives in
+// FILENAME_CASES, so a policy change is an edit to that table. Accepted names
+// must survive create, list, lookup, a move (in the contract suite), `export`
+// to the host and `export --format tar`, whose entries are named relative to
+// `--from` (with a pax path record for names ustar cannot hold). A host may
+// refuse a name the filesystem takes; export must then name it on stderr and
+// fail rather than write it under another name.
+
+use crate::differential::listed_names;
+use crate::errors::{EXIT_INVALID, EXIT_NAME_TOO_LONG, EXIT_NOT_DIRECTORY};
+use crate::harness::{format_device, scenarios, TestContext};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Output;
+
+const MAX_NAME_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Policy {
+    Accepted,
+    // The create exit code
+    Rejected(i32),
+}
+
+struct NameCase {
+    label: &'static str,
+    // Every name in a case is created side by side in the same directory
+    names: fn() -> Vec<String>,
+    policy: Policy,
+    // Regular files created in the directory first
+    existing: &'static [&'static str],
+    // Pass the path through a `--paths-from` file, for bytes argv can't carry
+    from_file: bool,
+}
+
+fn names(list: &[&str]) -> Vec<String> {
+    list.iter().map(|name| name.to_string()).collect()
+}
+
+const FILENAME_CASES: &[NameCase] = &[
+    NameCase {
+        label: "maximum length",
+        names: || vec!["n".repeat(MAX_NAME_LEN)],
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "one over maximum length",
+        names: || vec!["n".repeat(MAX_NAME_LEN + 1)],
+        policy: Policy::Rejected(EXIT_NAME_TOO_LONG),
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "spaces",
+        names: || names(&["with space.txt", "two  spaces"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "leading dots",
+        names: || names(&[".hidden", "..twice", "...three"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "leading and trailing whitespace",
+        names: || names(&[" leading", "trailing ", "\tboth\t"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "emoji",
+        names: || names(&["\u{1F4C1} folder \u{1F389}", "\u{1F468}\u{200D}\u{1F469}"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "right-to-left text",
+        names: || {
+            names(&[
+                "\u{05E9}\u{05DC}\u{05D5}\u{05DD}.txt",
+                "\u{0645}\u{0631}\u{062D}\u{0628}\u{0627}",
+            ])
+        },
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        label: "combining characters",
+        names: || names(&["a\u{0301}\u{0302}", "\u{0915}\u{094D}\u{0937}"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        // Names are stored as given, so NFC and NFD spellings are distinct
+        label: "normalization forms",
+        names: || names(&["caf\u{00E9}.txt", "cafe\u{0301}.txt"]),
+        policy: Policy::Accepted,
+        existing: &[],
+        from_file: false,
+    },
+    NameCase {
+        // The slash always separates components: with a file named "a" in
+        // place, "a/b" walks through it instead of naming one entry
+        label: "embedded slash",
+        names: || names(&["a/b"]),
+        policy: Policy::Rejected(EXIT_NOT_DIRECTORY),
+        existing: &["a"],
+        from_file: false,
+    },
+    NameCase {
+        // argv cannot carry NUL, so the name reaches the binary in a file
+        label: "embedded NUL",
+        names: || names(&["a\0b"]),
+        policy: Policy::Rejected(EXIT_INVALID),
+        existing: &[],
+        from_file: true,
+    },
+    NameCase {
+        label: "empty name",
+        names: || names(&[""]),
+        policy: Policy::Rejected(EXIT_INVALID),
+        existing: &[],
+        from_file: false,
+    },
+];
+
+fn create(ctx: &TestContext, case: &NameCase, path: &str) -> io::Result<Output> {
+    if case.from_file {
+        let list = ctx.temp_dir.path().join("names.txt");
+        fs::write(&list, format!("{}\n", path))?;
+        ctx.run_raw(&["create", "--paths-from", &list.to_string_lossy()])
+    } else {
+        ctx.run_raw(&["create", "--path", path])
+    }
+}
+
+// The names of the regular files in a tar archive, in archive order. A pax
+// extended header's path record replaces the name of the entry after it.
+fn tar_names(archive: &[u8]) -> io::Result<Vec<String>> {
+    let field = |header: &[u8], start: usize, len: usize| {
+        let bytes = &header[start..start + len];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let mut names = Vec::new();
+    let mut pax_path = None;
+    let mut offset = 0;
+    while offset + 512 <= archive.len() {
+        let header = &archive[offset..offset + 512];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = u64::from_str_radix(field(header, 124, 12).trim(), 8)
+            .map_err(|_| io::Error::other(format!("bad tar size at {}", offset)))?
+            as usize;
+        let data = offset + 512;
+        let body = archive
+            .get(data..data + size)
+            .ok_or_else(|| io::Error::other("tar entry runs past the archive"))?;
+        match header[156] {
+            b'x' => {
+                // Records are "LEN key=value\n"
+                let text = String::from_utf8_lossy(body);
+                pax_path = text.split('\n').find_map(|record| {
+                    let (_, pair) = record.split_once(' ')?;
+                    pair.strip_prefix("path=").map(str::to_string)
+                });
+            }
+            b'0' | 0 => {
+                let prefix = field(header, 345, 155);
+                let name = pax_path.take().unwrap_or_else(|| {
+                    if prefix.is_empty() {
+                        field(header, 0, 100)
+                    } else {
+                        format!("{}/{}", prefix, field(header, 0, 100))
+                    }
+                });
+                names.push(name.trim_start_matches("./").to_string());
+            }
+            _ => pax_path = None,
+        }
+        offset = data + size.div_ceil(512) * 512;
+    }
+    Ok(names)
+}
+
+fn host_names(dir: &Path) -> io::Result<BTreeSet<String>> {
+    if !dir.exists() {
+        return Ok(BTreeSet::new());
+    }
+    fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn check_export(
+    ctx: &TestContext,
+    dir: &str,
+    case: &NameCase,
+    expected: &BTreeSet<String>,
+) -> io::Result<()> {
+    let host = ctx
+        .temp_dir
+        .path()
+        .join(format!("export{}", dir.replace('/', "_")));
+    let output = ctx.run_raw(&["export", "--from", dir, "--to", &host.to_string_lossy()])?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let exported = host_names(&host)?;
+    assert!(
+        exported.is_subset(expected),
+        "{}: export wrote names that were never created: {:?}",
+        case.label,
+        exported.difference(expected).collect::<Vec<_>>()
+    );
+    for name in expected.difference(&exported) {
+        assert!(
+            !output.status.success() && stderr.contains(name.as_str()),
+            "{}: export dropped {:?} without reporting it",
+            case.label,
+            name
+        );
+    }
+
+    let archive = host.with_extension("tar");
+    ctx.run_bellande_command(&[
+        "export",
+        "--from",
+        dir,
+        "--to",
+        &archive.to_string_lossy(),
+        "--format",
+        "tar",
+    ])?;
+    let mut archived = tar_names(&fs::read(&archive)?)?;
+    archived.sort();
+    assert_eq!(
+        archived,
+        expected.iter().cloned().collect::<Vec<_>>(),
+        "{}: tar entry names differ",
+        case.label
+    );
+    Ok(())
+}
+
+fn check_case(ctx: &TestContext, dir: &str, case: &NameCase) -> io::Result<()> {
+    let names = (case.names)();
+    ctx.run_bellande_command(&["mkdir", "--path", dir])?;
+    for existing in case.existing {
+        ctx.run_bellande_command(&["create", "--path", &format!("{}/{}", dir, existing)])?;
+    }
+
+    for name in &names {
+        let path = format!("{}/{}", dir, name);
+        let output = create(ctx, case, &path)?;
+        match case.policy {
+            Policy::Accepted => assert!(
+                output.status.success(),
+                "{}: create refused {:?}: {}",
+                case.label,
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            // The exit code itself is only held to under the contract suite
+            Policy::Rejected(code) if cfg!(feature = "contract-tests") => assert_eq!(
+                output.status.code(),
+                Some(code),
+                "{}: unexpected create result for {:?}",
+                case.label,
+                name
+            ),
+            Policy::Rejected(_) => assert!(
+                !output.status.success(),
+                "{}: create accepted {:?}",
+                case.label,
+                name
+            ),
+        }
+    }
+
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    let listed = listed_names(&String::from_utf8_lossy(&output.stdout));
+    let mut expected: BTreeSet<String> =
+        case.existing.iter().map(|name| name.to_string()).collect();
+    if case.policy == Policy::Accepted {
+        expected.extend(names.iter().cloned());
+    }
+    assert_eq!(
+        listed, expected,
+        "{}: listing did not round-trip",
+        case.label
+    );
+
+    if case.policy == Policy::Accepted {
+        for name in &names {
+            let path = format!("{}/{}", dir, name);
+            ctx.run_bellande_command(&["read", "--path", &path])?;
+        }
+
+        // `move` and `export` are only in the contract suite; without them
+        // listing and reading back is the whole check
+        if cfg!(feature = "contract-tests") {
+            let moved_dir = check_move(ctx, dir, case, &names, &expected)?;
+            check_export(ctx, &moved_dir, case, &expected)?;
+        }
+    }
+    Ok(())
+}
+
+// Names survive a move into another directory unchanged; returns that
+// directory
+fn check_move(
+    ctx: &TestContext,
+    dir: &str,
+    case: &NameCase,
+    names: &[String],
+    expected: &BTreeSet<String>,
+) -> io::Result<String> {
+    let moved_dir = format!("{}/moved", dir);
+    ctx.run_bellande_command(&["mkdir", "--path", &moved_dir])?;
+    for name in names {
+        let from = format!("{}/{}", dir, name);
+        let to = format!("{}/{}", moved_dir, name);
+        ctx.run_bellande_command(&["move", "--from", &from, "--to", &to])?;
+    }
+    let output = ctx.run_bellande_command(&["list", "--path", &moved_dir])?;
+    assert_eq!(
+        &listed_names(&String::from_utf8_lossy(&output.stdout)),
+        expected,
+        "{}: names changed by move",
+        case.label
+    );
+    Ok(moved_dir)
+}
+
+pub(crate) fn filename_matrix(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for (index, case) in FILENAME_CASES.iter().enumerate() {
+        // Names argv cannot carry need `--paths-from`, a contract option
+        if case.from_file && !cfg!(feature = "contract-tests") {
+            println!("Skipping filename case {}: needs --paths-from", case.label);
+            continue;
+        }
+        println!("Filename case: {}", case.label);
+        check_case(ctx, &format!("/case{}", index), case)?;
+    }
+    Ok(())
+}
+
+scenarios! {
+    filename_matrix,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_header(name: &str, typeflag: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}", size);
+        header[124..124 + size.len()].copy_from_slice(size.as_bytes());
+        header[156] = typeflag;
+        header
+    }
+
+    fn padded(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.resize(bytes.len().div_ceil(512) * 512, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_tar_names() -> io::Result<()> {
+        let long = format!("{}\u{1F4C1}", "n".repeat(120));
+        let record = format!("path={}\n", long);
+        let record = format!("{} {}", record.len() + 4, record);
+        let mut archive = tar_header("./plain.txt", b'0', 3);
+        archive.extend(padded(b"abc".to_vec()));
+        archive.extend(tar_header("PaxHeaders/long", b'x', record.len()));
+        archive.extend(padded(record.into_bytes()));
+        archive.extend(tar_header("truncated-name", b'0', 0));
+        archive.extend(tar_header("dir/", b'5', 0));
+        archive.extend(vec![0u8; 1024]);
+        assert_eq!(tar_names(&archive)?, ["plain.txt".to_string(), long]);
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Regular file creation, listing, and whole-file write/read round-trips.
+
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+pub(crate) fn create_and_list_files(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    ctx.command(&["create", "--path", "/test.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("File created successfully"));
+
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test.txt"));
+
+    Ok(())
+}
+
+pub(crate) fn write_and_read_file(ctx: &TestContext) -> io::Result<()> {
+    let test_content = "Hello, Bellande filesystem!";
+    let test_file = "/test.txt";
+
+    format_device(ctx)?;
+
+    ctx.command(&["create", "--path", test_file])
+        .assert()
+        .success();
+
+    ctx.command(&["write", "--path", test_file])
+        .write_stdin(test_content)
+        .assert()
+        .success();
+
+    ctx.command(&["read", "--path", test_file])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(test_content));
+
+    Ok(())
+}
+
+pub(crate) fn large_file_operations(ctx: &TestContext) -> io::Result<()> {
+    let large_content = "A".repeat(100_000);
+
+    format_device(ctx)?;
+
+    ctx.command(&["create", "--path", "/large.txt"])
+        .assert()
+        .success();
+
+    ctx.command(&["write", "--path", "/large.txt"])
+        .write_stdin(large_content.clone())
+        .assert()
+        .success();
+
+    ctx.command(&["read", "--path", "/large.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(large_content));
+
+    Ok(())
+}
+
+scenarios! {
+    create_and_list_files,
+    write_and_read_file,
+    large_file_operations,
+}
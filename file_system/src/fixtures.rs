@@ -0,0 +1,694 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Pre-populated fixture images. A FixtureSpec declares a tree of
+// directories, seeded files, symlinks and hard links, plus modes and
+// extended attributes set on entries declared before them; `populate` builds
+// it on a device, and `cached_fixture` keeps a built image on disk, keyed by
+// the spec hash, so slow tests start from a copy instead. Host trees only
+// carry what std::fs can make: xattrs stay in the image, and symlinks must
+// point at files.
+
+use crate::create_mode::parse_octal_mode;
+use crate::differential::{content, listed_names};
+use crate::golden::checksum;
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::json::json_field;
+use crate::stat::stat;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CACHE_ENV: &str = "BELLANDE_FS_FIXTURE_CACHE";
+
+#[derive(Clone, Debug)]
+pub(crate) enum FixtureEntry {
+    Dir(String),
+    File {
+        path: String,
+        len: usize,
+        seed: u64,
+    },
+    Symlink {
+        path: String,
+        target: String,
+    },
+    HardLink {
+        path: String,
+        target: String,
+    },
+    Mode {
+        path: String,
+        mode: u32,
+    },
+    Xattr {
+        path: String,
+        name: String,
+        value: Vec<u8>,
+    },
+}
+
+impl FixtureEntry {
+    fn path(&self) -> &str {
+        match self {
+            FixtureEntry::Dir(path)
+            | FixtureEntry::File { path, .. }
+            | FixtureEntry::Symlink { path, .. }
+            | FixtureEntry::HardLink { path, .. }
+            | FixtureEntry::Mode { path, .. }
+            | FixtureEntry::Xattr { path, .. } => path,
+        }
+    }
+
+    // Whether the entry adds a name to its directory
+    fn is_name(&self) -> bool {
+        !matches!(self, FixtureEntry::Mode { .. } | FixtureEntry::Xattr { .. })
+    }
+
+    // One line per entry; the cache key hashes these, so for a given tree
+    // they must never change, whatever happens to the enum
+    fn serialize(&self) -> String {
+        match self {
+            FixtureEntry::Dir(path) => format!("dir {}", path),
+            FixtureEntry::File { path, len, seed } => format!("file {} {} {}", path, len, seed),
+            FixtureEntry::Symlink { path, target } => format!("symlink {} {}", path, target),
+            FixtureEntry::HardLink { path, target } => format!("link {} {}", path, target),
+            FixtureEntry::Mode { path, mode } => format!("mode {} {:04o}", path, mode),
+            FixtureEntry::Xattr { path, name, value } => {
+                let hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("xattr {} {} {}", path, name, hex)
+            }
+        }
+    }
+
+    fn prefixed(&self, prefix: &str) -> FixtureEntry {
+        let under = |path: &str| format!("{}{}", prefix, path);
+        // Relative symlink targets resolve the same from anywhere
+        let target_under = |target: &str| {
+            if target.starts_with('/') {
+                under(target)
+            } else {
+                target.to_string()
+            }
+        };
+        match self {
+            FixtureEntry::Dir(path) => FixtureEntry::Dir(under(path)),
+            FixtureEntry::File { path, len, seed } => FixtureEntry::File {
+                path: under(path),
+                len: *len,
+                seed: *seed,
+            },
+            FixtureEntry::Symlink { path, target } => FixtureEntry::Symlink {
+                path: under(path),
+                target: target_under(target),
+            },
+            FixtureEntry::HardLink { path, target } => FixtureEntry::HardLink {
+                path: under(path),
+                target: under(target),
+            },
+            FixtureEntry::Mode { path, mode } => FixtureEntry::Mode {
+                path: under(path),
+                mode: *mode,
+            },
+            FixtureEntry::Xattr { path, name, value } => FixtureEntry::Xattr {
+                path: under(path),
+                name: name.clone(),
+                value: value.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FixtureSpec {
+    pub(crate) name: &'static str,
+    pub(crate) device_size: u64,
+    pub(crate) entries: Vec<FixtureEntry>,
+}
+
+impl FixtureSpec {
+    pub(crate) fn new(name: &'static str) -> Self {
+        FixtureSpec {
+            name,
+            device_size: DEFAULT_DEVICE_SIZE,
+            entries: Vec::new(),
+        }
+    }
+
+    pub(crate) fn dir(mut self, path: &str) -> Self {
+        self.entries.push(FixtureEntry::Dir(path.to_string()));
+        self
+    }
+
+    pub(crate) fn file(mut self, path: &str, len: usize, seed: u64) -> Self {
+        self.entries.push(FixtureEntry::File {
+            path: path.to_string(),
+            len,
+            seed,
+        });
+        self
+    }
+
+    // `target` is stored as given, so it may be relative
+    pub(crate) fn symlink(mut self, path: &str, target: &str) -> Self {
+        self.entries.push(FixtureEntry::Symlink {
+            path: path.to_string(),
+            target: target.to_string(),
+        });
+        self
+    }
+
+    pub(crate) fn hard_link(mut self, path: &str, target: &str) -> Self {
+        self.entries.push(FixtureEntry::HardLink {
+            path: path.to_string(),
+            target: target.to_string(),
+        });
+        self
+    }
+
+    pub(crate) fn mode(mut self, path: &str, mode: u32) -> Self {
+        self.entries.push(FixtureEntry::Mode {
+            path: path.to_string(),
+            mode,
+        });
+        self
+    }
+
+    pub(crate) fn xattr(mut self, path: &str, name: &str, value: &[u8]) -> Self {
+        self.entries.push(FixtureEntry::Xattr {
+            path: path.to_string(),
+            name: name.to_string(),
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    // The same tree rooted at `prefix` instead of `/`
+    pub(crate) fn under(&self, prefix: &str) -> FixtureSpec {
+        let mut spec = FixtureSpec::new(self.name).dir(prefix);
+        spec.device_size = self.device_size;
+        for entry in &self.entries {
+            spec.entries.push(entry.prefixed(prefix));
+        }
+        spec
+    }
+
+    // Stable across runs and releases, so it can key the on-disk cache
+    pub(crate) fn hash(&self) -> u64 {
+        let mut text = format!("{}\n{}\n", self.name, self.device_size);
+        for entry in &self.entries {
+            text.push_str(&entry.serialize());
+            text.push('\n');
+        }
+        checksum(text.as_bytes())
+    }
+
+    // The seeded content a path reads back with, following links
+    fn content_of(&self, path: &str) -> Option<Vec<u8>> {
+        self.entries.iter().find_map(|entry| match entry {
+            FixtureEntry::File {
+                path: file,
+                len,
+                seed,
+            } if file == path => Some(content(*seed, *len)),
+            FixtureEntry::HardLink { path: link, target } if link == path => {
+                self.content_of(target)
+            }
+            FixtureEntry::Symlink { path: link, target } if link == path => {
+                self.content_of(&resolve(link, target))
+            }
+            _ => None,
+        })
+    }
+
+    // Expected names per directory, for comparing against `list`
+    pub(crate) fn directories(&self) -> Vec<(String, Vec<String>)> {
+        let mut dirs: Vec<(String, Vec<String>)> = vec![("/".to_string(), Vec::new())];
+        for entry in self.entries.iter().filter(|entry| entry.is_name()) {
+            let path = entry.path();
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let parent = if parent.is_empty() { "/" } else { parent };
+            match dirs.iter_mut().find(|(dir, _)| dir == parent) {
+                Some((_, names)) => names.push(name.to_string()),
+                None => dirs.push((parent.to_string(), vec![name.to_string()])),
+            }
+            if let FixtureEntry::Dir(path) = entry {
+                if !dirs.iter().any(|(dir, _)| dir == path) {
+                    dirs.push((path.clone(), Vec::new()));
+                }
+            }
+        }
+        dirs
+    }
+}
+
+// An absolute symlink target, or a relative one from the link's directory
+fn resolve(link: &str, target: &str) -> String {
+    if target.starts_with('/') {
+        return target.to_string();
+    }
+    let parent = link.rsplit_once('/').map_or("", |(parent, _)| parent);
+    format!("{}/{}", parent, target)
+}
+
+pub(crate) fn tiny_spec() -> FixtureSpec {
+    FixtureSpec::new("tiny")
+        .dir("/etc")
+        .file("/etc/hostname", 9, 1)
+        .file("/readme.txt", 600, 2)
+        .file("/empty", 0, 3)
+}
+
+pub(crate) fn medium_spec() -> FixtureSpec {
+    let mut spec = FixtureSpec::new("medium");
+    for dir in 0..10 {
+        spec = spec.dir(&format!("/dir{}", dir));
+        for file in 0..100 {
+            let index = dir * 100 + file;
+            spec = spec.file(
+                &format!("/dir{}/file{}.dat", dir, file),
+                (index * 37) % 3000,
+                index as u64,
+            );
+        }
+    }
+    spec
+}
+
+pub(crate) fn pathological_names_spec() -> FixtureSpec {
+    let names = [
+        " leading",
+        "trailing ",
+        ".hidden",
+        "with space",
+        "caf\u{00E9}",
+        "cafe\u{0301}",
+        "\u{1F4C1}",
+        "\u{05E9}\u{05DC}\u{05D5}\u{05DD}",
+    ];
+    let mut spec = FixtureSpec::new("pathological-names").dir("/names");
+    for (index, name) in names.iter().enumerate() {
+        spec = spec.file(&format!("/names/{}", name), 64, index as u64);
+    }
+    spec
+}
+
+// Every kind of entry and attribute the builder knows
+pub(crate) fn decorated_spec() -> FixtureSpec {
+    FixtureSpec::new("decorated")
+        .dir("/bin")
+        .file("/bin/tool", 2000, 7)
+        .mode("/bin/tool", 0o755)
+        .file("/secret", 100, 8)
+        .mode("/secret", 0o600)
+        .xattr("/secret", "user.origin", b"fixture")
+        .dir("/shared")
+        .mode("/shared", 0o1777)
+        .hard_link("/shared/tool", "/bin/tool")
+        .symlink("/current", "/bin/tool")
+        .symlink("/bin/alias", "tool")
+}
+
+pub(crate) fn deep_nesting_spec() -> FixtureSpec {
+    let mut spec = FixtureSpec::new("deep-nesting");
+    let mut path = String::new();
+    for depth in 0..32 {
+        path.push_str(&format!("/level{}", depth));
+        spec = spec.dir(&path);
+    }
+    spec.file(&format!("{}/bottom.txt", path), 128, 99)
+}
+
+fn run_entry(ctx: &TestContext, entry: &FixtureEntry) -> io::Result<()> {
+    match entry {
+        FixtureEntry::Dir(path) => {
+            ctx.run_bellande_command(&["mkdir", "--path", path])?;
+        }
+        FixtureEntry::File { path, len, seed } => {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            let output = write_file(ctx, path, &content(*seed, *len))?;
+            if !output.status.success() {
+                return Err(io::Error::other(format!(
+                    "Failed to write fixture file {}: {}",
+                    path,
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+        }
+        FixtureEntry::Symlink { path, target } => {
+            ctx.run_bellande_command(&["link", "--symbolic", "--target", target, "--path", path])?;
+        }
+        FixtureEntry::HardLink { path, target } => {
+            ctx.run_bellande_command(&["link", "--target", target, "--path", path])?;
+        }
+        FixtureEntry::Mode { path, mode } => {
+            let mode = format!("{:04o}", mode);
+            ctx.run_bellande_command(&["chmod", "--path", path, "--mode", &mode])?;
+        }
+        FixtureEntry::Xattr { path, name, value } => {
+            let output = ctx
+                .command(&["xattr", "set", "--path", path, "--name", name])
+                .write_stdin(value.clone())
+                .output()?;
+            if !output.status.success() {
+                return Err(io::Error::other(format!(
+                    "Failed to set {} on fixture path {}",
+                    name, path
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Formats the context's device and builds the spec's tree on it
+pub(crate) fn populate(ctx: &TestContext, spec: &FixtureSpec) -> io::Result<()> {
+    format_device(ctx)?;
+    for entry in &spec.entries {
+        run_entry(ctx, entry)?;
+    }
+    Ok(())
+}
+
+fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(CACHE_ENV) {
+        return PathBuf::from(dir);
+    }
+    let target = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .or_else(|| option_env!("CARGO_MANIFEST_DIR").map(|dir| PathBuf::from(dir).join("target")))
+        .unwrap_or_else(env::temp_dir);
+    target.join("bellande_fixtures")
+}
+
+// The binary's size and mtime are part of the key so a rebuilt binary (and
+// possibly a changed on-disk format) never reuses a stale image
+fn cache_key(ctx: &TestContext, spec: &FixtureSpec) -> String {
+    let binary = fs::metadata(&ctx.binary_path)
+        .ok()
+        .map(|metadata| format!("{}:{:?}", metadata.len(), metadata.modified().ok()))
+        .unwrap_or_default();
+    let key = checksum(format!("{:016x}{}", spec.hash(), binary).as_bytes());
+    format!("{}-{:016x}.img", spec.name, key)
+}
+
+// A fresh copy of a prebuilt image for `spec`, building it on first use
+pub(crate) fn cached_fixture(spec: &FixtureSpec) -> io::Result<TestContext> {
+    let builder = TestContext::with_options(spec.device_size, None)?;
+    let dir = cache_dir();
+    let image = dir.join(cache_key(&builder, spec));
+
+    if !image.is_file() {
+        populate(&builder, spec)?;
+        fs::create_dir_all(&dir)?;
+        // Copy then rename so concurrent tests never see a half-written image
+        let partial = image.with_extension(format!("partial{}", std::process::id()));
+        fs::copy(&builder.device_path, &partial)?;
+        fs::rename(&partial, &image)?;
+    }
+
+    TestContext::from_image(&image)
+}
+
+// Checks that every directory of the spec lists exactly the expected names
+pub(crate) fn verify_tree(ctx: &TestContext, spec: &FixtureSpec) -> io::Result<()> {
+    for (dir, names) in spec.directories() {
+        let output = ctx.run_bellande_command(&["list", "--path", &dir])?;
+        let listed = listed_names(&String::from_utf8_lossy(&output.stdout));
+        let expected = names.into_iter().collect();
+        assert_eq!(
+            listed, expected,
+            "{}: listing of {} differs",
+            spec.name, dir
+        );
+    }
+    Ok(())
+}
+
+// Checks that every file and link of the spec reads back with its seeded
+// content, and that modes and xattrs were applied
+pub(crate) fn verify_contents(ctx: &TestContext, spec: &FixtureSpec) -> io::Result<()> {
+    for entry in &spec.entries {
+        match entry {
+            FixtureEntry::File { path, .. }
+            | FixtureEntry::HardLink { path, .. }
+            | FixtureEntry::Symlink { path, .. } => {
+                let Some(expected) = spec.content_of(path) else {
+                    continue;
+                };
+                let output = ctx.run_bellande_command(&["read", "--path", path])?;
+                assert!(
+                    output.stdout == expected,
+                    "{}: content of {} differs",
+                    spec.name,
+                    path
+                );
+            }
+            FixtureEntry::Mode { path, mode } => {
+                let output =
+                    ctx.run_bellande_command(&["stat", "--format", "json", "--path", path])?;
+                let actual = json_field(&String::from_utf8_lossy(&output.stdout), "mode")
+                    .and_then(|mode| parse_octal_mode(&mode));
+                assert_eq!(actual, Some(*mode), "{}: mode of {}", spec.name, path);
+            }
+            FixtureEntry::Xattr { path, name, value } => {
+                let output =
+                    ctx.run_bellande_command(&["xattr", "get", "--path", path, "--name", name])?;
+                assert!(
+                    output.stdout == *value,
+                    "{}: {} of {} differs",
+                    spec.name,
+                    name,
+                    path
+                );
+            }
+            FixtureEntry::Dir(_) => {}
+        }
+        if let FixtureEntry::Symlink { path, target } = entry {
+            assert_eq!(
+                stat(ctx, path)?.get("Target"),
+                Some(target),
+                "{}: target of {}",
+                spec.name,
+                path
+            );
+        }
+    }
+    Ok(())
+}
+
+// Builds the spec's tree on the host under `root`
+pub(crate) fn write_host_tree(spec: &FixtureSpec, root: &Path) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+    for entry in &spec.entries {
+        match entry {
+            FixtureEntry::Dir(path) => fs::create_dir_all(root.join(&path[1..]))?,
+            FixtureEntry::File { path, len, seed } => {
+                fs::write(root.join(&path[1..]), content(*seed, *len))?
+            }
+            FixtureEntry::HardLink { path, target } => {
+                fs::hard_link(root.join(&target[1..]), root.join(&path[1..]))?
+            }
+            #[cfg(unix)]
+            FixtureEntry::Symlink { path, target } => {
+                // Absolute targets are rebased onto the host root
+                let host_target = match target.strip_prefix('/') {
+                    Some(relative) => root.join(relative),
+                    None => PathBuf::from(target),
+                };
+                std::os::unix::fs::symlink(host_target, root.join(&path[1..]))?
+            }
+            #[cfg(unix)]
+            FixtureEntry::Mode { path, mode } => {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(root.join(&path[1..]), fs::Permissions::from_mode(*mode))?
+            }
+            #[cfg(not(unix))]
+            FixtureEntry::Symlink { .. } | FixtureEntry::Mode { .. } => {}
+            FixtureEntry::Xattr { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+// Every path below `root` on the host, as "/"-rooted relative paths, with the
+// contents of files; directories map to None
+pub(crate) fn read_host_tree(root: &Path) -> io::Result<BTreeMap<String, Option<Vec<u8>>>> {
+    let mut tree = BTreeMap::new();
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                pending.push((entry.path(), path.clone()));
+                tree.insert(path, None);
+            } else {
+                tree.insert(path, Some(fs::read(entry.path())?));
+            }
+        }
+    }
+    Ok(tree)
+}
+
+// What read_host_tree must return for a tree written from `spec`
+pub(crate) fn expected_host_tree(spec: &FixtureSpec) -> BTreeMap<String, Option<Vec<u8>>> {
+    spec.entries
+        .iter()
+        .filter(|entry| entry.is_name())
+        .map(|entry| match entry {
+            FixtureEntry::Dir(path) => (path.clone(), None),
+            _ => (entry.path().to_string(), spec.content_of(entry.path())),
+        })
+        .collect()
+}
+
+// Files and bytes in the spec, as a copy summary would count them
+pub(crate) fn file_totals(spec: &FixtureSpec) -> (usize, usize) {
+    spec.entries
+        .iter()
+        .filter_map(|entry| match entry {
+            FixtureEntry::File { len, .. } => Some(*len),
+            _ => None,
+        })
+        .fold((0, 0), |(files, bytes), len| (files + 1, bytes + len))
+}
+
+pub(crate) fn standard_fixtures() -> io::Result<()> {
+    for spec in [
+        tiny_spec(),
+        pathological_names_spec(),
+        deep_nesting_spec(),
+        medium_spec(),
+    ] {
+        let ctx = cached_fixture(&spec)?;
+        verify_tree(&ctx, &spec)?;
+    }
+
+    // Building the decorated tree takes link, chmod, xattr and stat, which
+    // are all contract commands
+    if cfg!(feature = "contract-tests") {
+        let decorated = decorated_spec();
+        let ctx = cached_fixture(&decorated)?;
+        verify_tree(&ctx, &decorated)?;
+        verify_contents(&ctx, &decorated)?;
+        assert_eq!(stat(&ctx, "/shared/tool")?["Links"], "2");
+    }
+    Ok(())
+}
+
+scenarios! {
+    standard_fixtures(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_hash_tracks_changes() {
+        assert_eq!(tiny_spec().hash(), tiny_spec().hash());
+        assert_ne!(tiny_spec().hash(), tiny_spec().file("/extra", 1, 1).hash());
+        let decorated = decorated_spec();
+        assert_ne!(
+            decorated.hash(),
+            decorated.clone().mode("/secret", 0o640).hash()
+        );
+        assert_ne!(
+            decorated.hash(),
+            decorated
+                .clone()
+                .xattr("/secret", "user.origin", b"other")
+                .hash()
+        );
+        // Pinned: a new variant or a derive change must not move existing keys
+        assert_eq!(
+            FixtureSpec::new("pinned")
+                .dir("/d")
+                .file("/d/f", 3, 4)
+                .hash(),
+            checksum(b"pinned\n10485760\ndir /d\nfile /d/f 3 4\n")
+        );
+    }
+
+    #[test]
+    fn test_decorated_spec_links() {
+        let spec = decorated_spec();
+        let tool = spec.content_of("/bin/tool");
+        assert_eq!(tool, Some(content(7, 2000)));
+        assert_eq!(spec.content_of("/shared/tool"), tool);
+        assert_eq!(spec.content_of("/current"), tool);
+        assert_eq!(spec.content_of("/bin/alias"), tool);
+        let dirs = spec.directories();
+        assert!(dirs
+            .iter()
+            .any(|(dir, names)| dir == "/bin" && names == &["tool", "alias"]));
+        assert!(dirs
+            .iter()
+            .any(|(dir, names)| dir == "/" && names == &["bin", "secret", "shared", "current"]));
+        let under = spec.under("/copy");
+        assert_eq!(under.content_of("/copy/current"), tool);
+        assert_eq!(under.content_of("/copy/bin/alias"), tool);
+    }
+
+    #[test]
+    fn test_decorated_host_tree() -> io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let spec = decorated_spec();
+        write_host_tree(&spec, dir.path())?;
+        assert_eq!(read_host_tree(dir.path())?, expected_host_tree(&spec));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_under_prefix() {
+        let spec = tiny_spec().under("/src");
+        let dirs = spec.directories();
+        assert_eq!(dirs[0], ("/".to_string(), vec!["src".to_string()]));
+        assert!(dirs
+            .iter()
+            .any(|(dir, names)| dir == "/src/etc" && names == &["hostname"]));
+        assert_eq!(spec.entries.len(), tiny_spec().entries.len() + 1);
+    }
+
+    #[test]
+    fn test_medium_spec_size() {
+        let files = medium_spec()
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry, FixtureEntry::File { .. }))
+            .count();
+        assert_eq!(files, 1000);
+    }
+
+    #[test]
+    fn test_host_tree_round_trip() -> io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let spec = tiny_spec().dir("/etc/empty");
+        write_host_tree(&spec, dir.path())?;
+        assert_eq!(read_host_tree(dir.path())?, expected_host_tree(&spec));
+        assert_eq!(file_totals(&spec), (3, 609));
+        Ok(())
+    }
+
+    #[test]
+    fn test_populate_tiny() -> io::Result<()> {
+        let ctx = TestContext::new()?;
+        populate(&ctx, &tiny_spec())?;
+        verify_tree(&ctx, &tiny_spec())
+    }
+}
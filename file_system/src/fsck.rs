@@ -0,0 +1,396 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `fsck` checks the superblock, inode table, directory tree and bitmaps
+// against each other, and `--repair` fixes what it safely can. Exit codes
+// follow e2fsck: 0 clean, 1 errors corrected, 4 errors left uncorrected. A
+// check without `--repair` never writes to the device.
+//
+// The harness does not know the on-disk layout, so damage is made without
+// it: images with one block of a later state rolled back to an earlier one
+// (a torn multi-block update), and a write stopped dead halfway through its
+// data with BELLANDE_FS_FAIL_AFTER_WRITES. Reports name what they found
+// with the terms in FINDINGS.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::harness::{
+    format_device, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::journal::FAIL_AFTER_ENV;
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+pub(crate) const EXIT_FSCK_CLEAN: i32 = 0;
+pub(crate) const EXIT_FSCK_CORRECTED: i32 = 1;
+pub(crate) const EXIT_FSCK_UNCORRECTED: i32 = 4;
+
+const BLOCK_SIZE: u32 = 4096;
+const KEEP_CONTENT_LEN: usize = 9000;
+const NEW_CONTENT_LEN: usize = 20_000;
+const KILLED_WRITE_LEN: usize = 6 * 1024 * 1024;
+// Stops the binary dead halfway through the write's data blocks
+const KILL_AFTER_WRITES: usize = KILLED_WRITE_LEN / BLOCK_SIZE as usize / 2;
+
+// Wording a report uses for each kind of inconsistency
+const ORPHAN_INODE: &str = "orphan inode";
+const DOUBLE_ALLOCATED: &str = "double-allocated block";
+pub(crate) const BAD_LINK_COUNT: &str = "link count";
+const FINDINGS: &[&str] = &[ORPHAN_INODE, DOUBLE_ALLOCATED, BAD_LINK_COUNT];
+
+fn fsck_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// A read-only check that must pass on any healthy device
+pub(crate) fn assert_fsck_clean(ctx: &TestContext) -> io::Result<()> {
+    ctx.command(&["fsck"]).assert().code(EXIT_FSCK_CLEAN);
+    Ok(())
+}
+
+// Runs a read-only check, asserting the device bytes did not change
+fn check_read_only(ctx: &TestContext) -> io::Result<i32> {
+    Ok(check_report(ctx)?.0)
+}
+
+// A read-only check's exit code and its lowercased report
+fn check_report(ctx: &TestContext) -> io::Result<(i32, String)> {
+    let before = fs::read(&ctx.device_path)?;
+    let output = ctx.run_raw(&["fsck"])?;
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "fsck without --repair modified the device"
+    );
+    let code = output.status.code().unwrap_or(-1);
+    assert!(
+        code == EXIT_FSCK_CLEAN || code == EXIT_FSCK_UNCORRECTED,
+        "read-only fsck exited {}: {}",
+        code,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if code == EXIT_FSCK_UNCORRECTED {
+        assert!(
+            !output.stdout.is_empty() || !output.stderr.is_empty(),
+            "fsck found problems but reported none"
+        );
+    }
+    let report = String::from_utf8_lossy(&output.stdout).to_string()
+        + &String::from_utf8_lossy(&output.stderr);
+    Ok((code, report.to_ascii_lowercase()))
+}
+
+// Repairs, then the device must check clean and keep working
+fn repair(ctx: &TestContext) -> io::Result<()> {
+    let output = ctx.run_raw(&["fsck", "--repair"])?;
+    let code = output.status.code().unwrap_or(-1);
+    assert!(
+        code == EXIT_FSCK_CLEAN || code == EXIT_FSCK_CORRECTED,
+        "fsck --repair left damage (exit {}): {}{}",
+        code,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        check_read_only(ctx)?,
+        EXIT_FSCK_CLEAN,
+        "still damaged after --repair"
+    );
+    Ok(())
+}
+
+fn assert_keep_intact(ctx: &TestContext) -> io::Result<()> {
+    let output = ctx.run_bellande_command(&["read", "--path", "/keep.bin"])?;
+    assert!(
+        output.stdout == content(1, KEEP_CONTENT_LEN),
+        "/keep.bin was damaged"
+    );
+    Ok(())
+}
+
+// New allocations after a repair must not land on blocks still in use
+fn assert_allocations_safe(ctx: &TestContext) -> io::Result<()> {
+    let data = content(3, NEW_CONTENT_LEN);
+    ctx.run_bellande_command(&["create", "--path", "/after_repair.bin"])?;
+    assert!(write_file(ctx, "/after_repair.bin", &data)?
+        .status
+        .success());
+    assert_keep_intact(ctx)?;
+    let output = ctx.run_bellande_command(&["read", "--path", "/after_repair.bin"])?;
+    assert!(output.stdout == data, "/after_repair.bin was damaged");
+
+    // Anything repair recovered is readable
+    let listing = ctx.run_raw(&["list", "--path", "/lost+found"])?;
+    if listing.status.success() {
+        ctx.run_bellande_command(&["list", "--recursive", "--path", "/lost+found"])?;
+    }
+    Ok(())
+}
+
+fn base_state(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/keep.bin"])?;
+    assert!(write_file(ctx, "/keep.bin", &content(1, KEEP_CONTENT_LEN))?
+        .status
+        .success());
+    Ok(())
+}
+
+// Indexes of the blocks that differ between two images of the same size
+pub(crate) fn changed_blocks(before: &[u8], after: &[u8], block_size: usize) -> Vec<usize> {
+    before
+        .chunks(block_size)
+        .zip(after.chunks(block_size))
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+pub(crate) fn healthy_devices_check_clean(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    assert_eq!(check_read_only(ctx)?, EXIT_FSCK_CLEAN);
+
+    base_state(ctx)?;
+    for index in 0..20 {
+        let path = format!("/dir/file{}", index);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        assert!(
+            write_file(ctx, &path, &content(index, index as usize * 3001))?
+                .status
+                .success()
+        );
+    }
+    ctx.run_bellande_command(&["remove", "--path", "/dir/file3"])?;
+    let (code, report) = check_report(ctx)?;
+    assert_eq!(code, EXIT_FSCK_CLEAN);
+    for finding in FINDINGS {
+        assert!(!report.contains(finding), "healthy device: {:?}", report);
+    }
+
+    // Repairing a healthy device changes nothing
+    let before = fs::read(&ctx.device_path)?;
+    ctx.command(&["fsck", "--repair"])
+        .assert()
+        .code(EXIT_FSCK_CLEAN);
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "--repair rewrote a clean device"
+    );
+    Ok(())
+}
+
+// One image per block `change` wrote, each with that block rolled back
+fn torn_images(
+    ctx: &TestContext,
+    change: impl FnOnce(&TestContext) -> io::Result<()>,
+) -> io::Result<Vec<TestContext>> {
+    let before = fs::read(&ctx.device_path)?;
+    change(ctx)?;
+    let after = fs::read(&ctx.device_path)?;
+
+    let changed = changed_blocks(&before, &after, BLOCK_SIZE as usize);
+    assert!(!changed.is_empty(), "the change wrote no blocks");
+    let mut images = Vec::new();
+    for block in changed {
+        let mut torn = after.clone();
+        let range = block * BLOCK_SIZE as usize..(block + 1) * BLOCK_SIZE as usize;
+        torn[range.clone()].copy_from_slice(&before[range]);
+        let image = ctx.temp_dir.path().join(format!("torn{}.img", block));
+        fs::write(&image, &torn)?;
+        images.push(TestContext::from_image(&image)?);
+    }
+    Ok(images)
+}
+
+fn create_new_file(ctx: &TestContext) -> io::Result<()> {
+    ctx.run_bellande_command(&["create", "--path", "/dir/new.bin"])?;
+    assert!(
+        write_file(ctx, "/dir/new.bin", &content(2, NEW_CONTENT_LEN))?
+            .status
+            .success()
+    );
+    Ok(())
+}
+
+pub(crate) fn torn_updates_detected_and_repaired(ctx: &TestContext) -> io::Result<()> {
+    base_state(ctx)?;
+    let torn = torn_images(ctx, create_new_file)?;
+
+    let mut detected = 0;
+    for torn_ctx in &torn {
+        if check_read_only(torn_ctx)? == EXIT_FSCK_UNCORRECTED {
+            detected += 1;
+        }
+        repair(torn_ctx)?;
+        assert_keep_intact(torn_ctx)?;
+        assert_allocations_safe(torn_ctx)?;
+    }
+    // Rolling back bitmaps or counters alone is always detectable
+    assert!(
+        detected > 0,
+        "none of {} torn images was reported",
+        torn.len()
+    );
+    Ok(())
+}
+
+// Whether any torn image's report names `finding`; every one must then
+// repair clean
+fn finding_reported(torn: &[TestContext], finding: &str) -> io::Result<bool> {
+    let mut reported = false;
+    for torn_ctx in torn {
+        let (code, report) = check_report(torn_ctx)?;
+        if code == EXIT_FSCK_UNCORRECTED && report.contains(finding) {
+            reported = true;
+        }
+        repair(torn_ctx)?;
+        assert_keep_intact(torn_ctx)?;
+    }
+    Ok(reported)
+}
+
+pub(crate) fn inconsistencies_named(ctx: &TestContext) -> io::Result<()> {
+    // An inode rolled back under a new hard link keeps the old count
+    base_state(ctx)?;
+    let torn = torn_images(ctx, |ctx| {
+        ctx.run_bellande_command(&["link", "--target", "/keep.bin", "--path", "/dir/alias"])?;
+        Ok(())
+    })?;
+    assert!(
+        finding_reported(&torn, BAD_LINK_COUNT)?,
+        "no torn hard link reported a bad {}",
+        BAD_LINK_COUNT
+    );
+    for torn_ctx in &torn {
+        let names = 1 + u64::from(
+            torn_ctx
+                .run_raw(&["stat", "--path", "/dir/alias"])?
+                .status
+                .success(),
+        );
+        let output = torn_ctx.run_bellande_command(&["stat", "--path", "/keep.bin"])?;
+        assert_eq!(
+            stat_field(&String::from_utf8_lossy(&output.stdout), "Links")?,
+            names,
+            "--repair left a link count that does not match the names"
+        );
+    }
+
+    // A directory block rolled back under a new file leaves its inode
+    // allocated with no name
+    let ctx = fsck_context()?;
+    base_state(&ctx)?;
+    let torn = torn_images(&ctx, create_new_file)?;
+    assert!(
+        finding_reported(&torn, ORPHAN_INODE)?,
+        "no torn create reported an {}",
+        ORPHAN_INODE
+    );
+
+    // A bitmap rolled back under a new file hands its blocks out again to
+    // the next write
+    let ctx = fsck_context()?;
+    base_state(&ctx)?;
+    let torn = torn_images(&ctx, create_new_file)?;
+    for torn_ctx in &torn {
+        torn_ctx.run_raw(&["create", "--path", "/second.bin"])?;
+        torn_ctx
+            .command(&["write", "--path", "/second.bin"])
+            .write_stdin(content(5, NEW_CONTENT_LEN))
+            .output()?;
+    }
+    assert!(
+        finding_reported(&torn, DOUBLE_ALLOCATED)?,
+        "no reused bitmap reported a {}",
+        DOUBLE_ALLOCATED
+    );
+    Ok(())
+}
+
+pub(crate) fn killed_write_repaired(ctx: &TestContext) -> io::Result<()> {
+    base_state(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/killed.bin"])?;
+
+    let output = ctx
+        .command(&["write", "--path", "/dir/killed.bin"])
+        .env(FAIL_AFTER_ENV, KILL_AFTER_WRITES.to_string())
+        .write_stdin(content(4, KILLED_WRITE_LEN))
+        .output()?;
+    assert!(
+        !output.status.success(),
+        "the write finished before it was stopped"
+    );
+
+    check_read_only(ctx)?;
+    repair(ctx)?;
+    assert_keep_intact(ctx)?;
+    assert_allocations_safe(ctx)?;
+    Ok(())
+}
+
+pub(crate) fn fsck_argument_errors(ctx: &TestContext) -> io::Result<()> {
+    ctx.command(&["fsck"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            ctx.device_path.to_string_lossy().into_owned(),
+        ));
+    format_device(ctx)?;
+    ctx.command(&["fsck", "--bogus"]).assert().code(EXIT_USAGE);
+    ctx.command(&["--read-only", "fsck", "--repair"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("read-only"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    healthy_devices_check_clean(fsck_context()?),
+    #[contract]
+    torn_updates_detected_and_repaired(fsck_context()?),
+    #[contract]
+    inconsistencies_named(fsck_context()?),
+    #[contract]
+    killed_write_repaired(fsck_context()?),
+    #[contract]
+    fsck_argument_errors(fsck_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_blocks() {
+        let before = vec![0u8; 16];
+        let mut after = before.clone();
+        after[5] = 1;
+        after[15] = 1;
+        assert_eq!(changed_blocks(&before, &after, 4), [1, 3]);
+        assert!(changed_blocks(&before, &before, 4).is_empty());
+    }
+
+    #[test]
+    fn test_findings_distinct() {
+        for (index, finding) in FINDINGS.iter().enumerate() {
+            for other in &FINDINGS[index + 1..] {
+                assert!(!finding.contains(other) && !other.contains(finding));
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `mount --mountpoint <dir>`, built with the binary's `fuse` feature, serves
+// the device through FUSE in the foreground until unmounted, holding the
+// device lock throughout. Without the feature `mount` is a usage error that
+// names it. Host tools then work on the tree; here std::fs stands in for
+// `ls` and `cat`, and `cp` is run for real. Skipped when the host has no
+// /dev/fuse or fusermount, or the binary was built without the feature.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::fixtures::{expected_host_tree, populate, read_host_tree, FixtureSpec};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{env_timeout, format_device, scenarios, TestContext};
+use crate::locking::{DEVICE_BUSY_MESSAGE, EXIT_DEVICE_BUSY};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MOUNT_TIMEOUT_ENV: &str = "BELLANDE_FS_MOUNT_TIMEOUT";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn mount_spec() -> FixtureSpec {
+    FixtureSpec::new("mount-tree")
+        .file("/hello.txt", 13, 1)
+        .dir("/docs")
+        .file("/docs/readme.txt", 5000, 2)
+        .dir("/docs/nested")
+        .file("/docs/nested/data.bin", 70_000, 3)
+        .file("/empty.txt", 0, 4)
+}
+
+fn mount_timeout() -> Duration {
+    env_timeout(MOUNT_TIMEOUT_ENV, Duration::from_secs(10))
+}
+
+fn host_has_fuse() -> bool {
+    Path::new("/dev/fuse").exists()
+        && Command::new("fusermount")
+            .arg("-V")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+}
+
+// A mounted device; unmounting on drop keeps a failed test from leaving a
+// dead mount behind, and is harmless after `unmount`
+struct Mount {
+    child: Child,
+    mountpoint: PathBuf,
+}
+
+impl Mount {
+    // None when the binary was built without FUSE support
+    fn start(ctx: &TestContext) -> io::Result<Option<Mount>> {
+        let mountpoint = ctx.temp_dir.path().join("mnt");
+        fs::create_dir_all(&mountpoint)?;
+        let mut child = Command::new(&ctx.binary_path)
+            .arg("--device")
+            .arg(&ctx.device_path)
+            .arg("mount")
+            .arg("--mountpoint")
+            .arg(&mountpoint)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Mounted once the root shows the tree's entries
+        let started = Instant::now();
+        while fs::read_dir(&mountpoint)?.next().is_none() {
+            if let Some(status) = child.try_wait()? {
+                let output = child.wait_with_output()?;
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if status.code() == Some(EXIT_USAGE) && stderr.contains("fuse") {
+                    return Ok(None);
+                }
+                return Err(io::Error::other(format!(
+                    "mount exited {}: {}",
+                    status, stderr
+                )));
+            }
+            if started.elapsed() > mount_timeout() {
+                return Err(io::Error::other("mount did not come up in time"));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(Some(Mount { child, mountpoint }))
+    }
+
+    fn unmount(mut self) -> io::Result<()> {
+        let status = Command::new("fusermount")
+            .arg("-u")
+            .arg(&self.mountpoint)
+            .status()?;
+        assert!(status.success(), "fusermount -u failed");
+        let exit = self.child.wait()?;
+        assert!(exit.success(), "mount exited {} after unmounting", exit);
+        Ok(())
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let _ = Command::new("fusermount")
+            .args(["-u", "-z"])
+            .arg(&self.mountpoint)
+            .status();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub(crate) fn mounted_tree_browsable(ctx: &TestContext) -> io::Result<()> {
+    if !host_has_fuse() {
+        println!("Skipping FUSE mount: no /dev/fuse or fusermount on this host");
+        return Ok(());
+    }
+    let spec = mount_spec();
+    populate(ctx, &spec)?;
+    let Some(mount) = Mount::start(ctx)? else {
+        println!("Skipping FUSE mount: the binary was built without the fuse feature");
+        return Ok(());
+    };
+
+    // Directory listings and file contents, through the kernel
+    assert!(read_host_tree(&mount.mountpoint)? == expected_host_tree(&spec));
+    let metadata = fs::metadata(mount.mountpoint.join("docs/nested/data.bin"))?;
+    assert!(metadata.is_file());
+    assert_eq!(metadata.len(), 70_000);
+    assert!(fs::metadata(mount.mountpoint.join("docs"))?.is_dir());
+
+    // cp out of and into the image
+    let copied_out = ctx.temp_dir.path().join("copied_out.txt");
+    let copied_in = ctx.temp_dir.path().join("copied_in.bin");
+    fs::write(&copied_in, content(5, 9000))?;
+    for (from, to) in [
+        (mount.mountpoint.join("docs/readme.txt"), copied_out.clone()),
+        (
+            copied_in.clone(),
+            mount.mountpoint.join("docs/copied_in.bin"),
+        ),
+    ] {
+        let status = Command::new("cp").arg(&from).arg(&to).status()?;
+        assert!(status.success(), "cp {:?} {:?} failed", from, to);
+    }
+    assert!(fs::read(&copied_out)? == content(2, 5000));
+
+    // The mount holds the device, so the CLI waits its turn
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .code(EXIT_DEVICE_BUSY)
+        .stderr(predicate::str::contains(DEVICE_BUSY_MESSAGE));
+
+    mount.unmount()?;
+    let output = ctx.run_bellande_command(&["read", "--path", "/docs/copied_in.bin"])?;
+    assert!(
+        output.stdout == content(5, 9000),
+        "cp into the mount was lost"
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn mount_argument_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.command(&["mount"]).assert().code(EXIT_USAGE);
+    let missing = ctx.temp_dir.path().join("no_such_dir");
+    ctx.command(&["mount", "--mountpoint", &missing.to_string_lossy()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            missing.to_string_lossy().into_owned(),
+        ));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    mounted_tree_browsable,
+    #[contract]
+    mount_argument_errors,
+}
@@ -0,0 +1,153 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `format --block-size` and `--inodes`. The geometry is recorded in the
+// superblock, so every later command takes it from the device, and `stats`
+// reports the block size and the bytes-per-inode ratio. Malformed values are
+// usage errors; values that do not fit the device exit 22 before anything
+// is written.
+
+use crate::capacity::{tiny_context, TINY_DEVICE_SIZE};
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_INVALID;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use predicates::prelude::*;
+use std::io;
+
+const BLOCK_SIZES: &[u64] = &[1024, 2048, 4096];
+const INODE_COUNTS: &[Option<u64>] = &[None, Some(64), Some(4096)];
+
+struct Geometry {
+    block_size: u64,
+    bytes_per_inode: u64,
+}
+
+fn read_geometry(ctx: &TestContext) -> io::Result<Geometry> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(Geometry {
+        block_size: stat_field(&stdout, "Block size")?,
+        bytes_per_inode: stat_field(&stdout, "Bytes per inode")?,
+    })
+}
+
+fn format_with(ctx: &TestContext, block_size: u64, inodes: Option<u64>) -> io::Result<()> {
+    let block_size = block_size.to_string();
+    let inodes = inodes.map(|count| count.to_string());
+    let mut args = vec!["format", "--yes", "--block-size", &block_size];
+    if let Some(inodes) = &inodes {
+        args.extend(["--inodes", inodes]);
+    }
+    ctx.run_bellande_command(&args)?;
+    Ok(())
+}
+
+pub(crate) fn geometry_recorded(ctx: &TestContext) -> io::Result<()> {
+    for &block_size in BLOCK_SIZES {
+        for &inodes in INODE_COUNTS {
+            let label = format!("--block-size {} --inodes {:?}", block_size, inodes);
+            format_with(ctx, block_size, inodes)?;
+            let stats = read_stats(ctx)?;
+            let geometry = read_geometry(ctx)?;
+
+            assert_eq!(geometry.block_size, block_size, "{}", label);
+            let capacity = stats.total_blocks * block_size;
+            assert!(
+                capacity <= DEFAULT_DEVICE_SIZE && capacity > DEFAULT_DEVICE_SIZE * 9 / 10,
+                "{}: {} blocks do not cover the device",
+                label,
+                stats.total_blocks
+            );
+            if let Some(inodes) = inodes {
+                assert_eq!(stats.total_inodes, inodes, "{}", label);
+            }
+            assert_eq!(
+                geometry.bytes_per_inode,
+                DEFAULT_DEVICE_SIZE / stats.total_inodes,
+                "{}",
+                label
+            );
+
+            // Allocation follows the recorded block size
+            ctx.run_bellande_command(&["create", "--path", "/sized.bin"])?;
+            assert!(write_file(ctx, "/sized.bin", &content(1, 10_000))?
+                .status
+                .success());
+            let used = stats.free_blocks - read_stats(ctx)?.free_blocks;
+            assert_eq!(used, 10_000u64.div_ceil(block_size), "{}", label);
+            let output = ctx.run_bellande_command(&["read", "--path", "/sized.bin"])?;
+            assert!(output.stdout == content(1, 10_000), "{}", label);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn defaults_are_explicit_geometry(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let stats = read_stats(ctx)?;
+    let geometry = read_geometry(ctx)?;
+    assert!(BLOCK_SIZES.contains(&geometry.block_size));
+
+    format_with(ctx, geometry.block_size, Some(stats.total_inodes))?;
+    assert_eq!(read_stats(ctx)?, stats);
+    assert_eq!(
+        read_geometry(ctx)?.bytes_per_inode,
+        geometry.bytes_per_inode
+    );
+    Ok(())
+}
+
+pub(crate) fn invalid_geometry_rejected(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/survivor.txt"])?;
+    let before = read_stats(ctx)?;
+
+    let too_big = (2 * TINY_DEVICE_SIZE).to_string();
+    let too_many = (TINY_DEVICE_SIZE / 16).to_string();
+    for (flag, value, code) in [
+        ("--block-size", "1000", EXIT_USAGE),
+        ("--block-size", "3072", EXIT_USAGE),
+        ("--block-size", "0", EXIT_USAGE),
+        ("--block-size", too_big.as_str(), EXIT_INVALID),
+        ("--inodes", "0", EXIT_USAGE),
+        ("--inodes", "-5", EXIT_USAGE),
+        ("--inodes", too_many.as_str(), EXIT_INVALID),
+    ] {
+        ctx.command(&["format", "--yes", flag, value])
+            .assert()
+            .code(code)
+            .stderr(predicate::str::contains(flag));
+    }
+
+    // Nothing reached the device
+    assert_eq!(read_stats(ctx)?, before);
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("survivor.txt"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    geometry_recorded(TestContext::with_options(DEFAULT_DEVICE_SIZE, None)?),
+    #[contract]
+    defaults_are_explicit_geometry,
+    #[contract]
+    invalid_geometry_rejected(tiny_context()?),
+}
@@ -0,0 +1,375 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Golden image regression tests. Each frozen image in tests/golden has a
+// manifest next to it describing the tree it must contain; the current binary
+// has to keep mounting and reading every one of them, and every spec below
+// must have its image. Images are produced by the ignored
+// `test_build_golden_images` test and checked in unchanged, so only the
+// current on-disk version has specs; an older version's spec is added with
+// the image its last release captured, never ahead of it.
+
+use crate::block_checksums::assert_scrub_clean;
+use crate::differential::{content, listed_names};
+use crate::extents::EXTENT_FORMAT_VERSION;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::metadata_checksums::CRC32C_FORMAT_ARGS;
+use crate::stat::parse_key_values;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_HEADER: &str = "# bellande golden manifest v1";
+// The only version the current binary may build images of
+const CURRENT_FORMAT_VERSION: u32 = EXTENT_FORMAT_VERSION;
+
+struct GoldenSpec {
+    name: &'static str,
+    format_version: u32,
+    device_size: u64,
+    block_size: Option<u32>,
+    // Extra `format` options selecting features, as passed to the release
+    // that wrote the image
+    format_args: &'static [&'static str],
+    dirs: &'static [&'static str],
+    // (path, length, content seed)
+    files: &'static [(&'static str, usize, u64)],
+}
+
+// One entry per on-disk format version and feature combination
+const SPECS: &[GoldenSpec] = &[
+    GoldenSpec {
+        name: "v4-extents",
+        format_version: EXTENT_FORMAT_VERSION,
+        device_size: 1024 * 1024,
+        block_size: Some(1024),
+        format_args: &[],
+        dirs: GOLDEN_DIRS,
+        files: GOLDEN_FILES,
+    },
+    GoldenSpec {
+        name: "v4-crc32c",
+        format_version: EXTENT_FORMAT_VERSION,
+        device_size: 1024 * 1024,
+        block_size: Some(1024),
+        format_args: CRC32C_FORMAT_ARGS,
+        dirs: GOLDEN_DIRS,
+        files: GOLDEN_FILES,
+    },
+];
+
+const GOLDEN_DIRS: &[&str] = &["/docs", "/docs/nested", "/empty_dir"];
+// Includes a file reaching into double indirect blocks
+const GOLDEN_FILES: &[(&str, usize, u64)] = &[
+    ("/hello.txt", 27, 1),
+    ("/empty.txt", 0, 2),
+    ("/docs/readme.txt", 1500, 3),
+    ("/docs/nested/data.bin", 5000, 4),
+    ("/docs/nested/indirect.bin", 300_000, 5),
+];
+
+// On-disk format versions the checked-in images cover
+pub(crate) fn golden_format_versions() -> Vec<u32> {
+    SPECS.iter().map(|spec| spec.format_version).collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Entry {
+    Dir,
+    File {
+        len: usize,
+        seed: u64,
+        checksum: u64,
+    },
+}
+
+// FNV-1a, 64-bit
+pub(crate) const CHECKSUM_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+pub(crate) fn checksum_update(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+pub(crate) fn checksum(data: &[u8]) -> u64 {
+    checksum_update(CHECKSUM_INIT, data)
+}
+
+fn golden_dir() -> PathBuf {
+    let crate_dir = option_env!("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    crate_dir.join("tests").join("golden")
+}
+
+fn render_manifest(spec: &GoldenSpec) -> String {
+    let mut lines = vec![MANIFEST_HEADER.to_string()];
+    for dir in spec.dirs {
+        lines.push(format!("dir {}", dir));
+    }
+    for (path, len, seed) in spec.files {
+        let checksum = checksum(&content(*seed, *len));
+        lines.push(format!("file {} {} {} {:016x}", path, len, seed, checksum));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn parse_manifest(text: &str) -> io::Result<BTreeMap<String, Entry>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid manifest line: {:?}", line),
+        )
+    };
+
+    let mut entries = BTreeMap::new();
+    for line in text.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(' ').collect();
+        match fields.as_slice() {
+            ["dir", path] => {
+                entries.insert(path.to_string(), Entry::Dir);
+            }
+            ["file", path, len, seed, sum] => {
+                let entry = Entry::File {
+                    len: len.parse().map_err(|_| invalid(line))?,
+                    seed: seed.parse().map_err(|_| invalid(line))?,
+                    checksum: u64::from_str_radix(sum, 16).map_err(|_| invalid(line))?,
+                };
+                entries.insert(path.to_string(), entry);
+            }
+            _ => return Err(invalid(line)),
+        }
+    }
+    Ok(entries)
+}
+
+fn parent_and_name(path: &str) -> (String, String) {
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let parent = if parent.is_empty() { "/" } else { parent };
+    (parent.to_string(), name.to_string())
+}
+
+fn spec_named(name: &str) -> Option<&'static GoldenSpec> {
+    SPECS.iter().find(|spec| spec.name == name)
+}
+
+fn image_format_version(ctx: &TestContext) -> io::Result<Option<u32>> {
+    let output = ctx.run_bellande_command(&["--read-only", "debug", "superblock"])?;
+    let fields = parse_key_values(&String::from_utf8_lossy(&output.stdout));
+    Ok(fields.and_then(|fields| fields.get("Format version")?.parse().ok()))
+}
+
+fn verify_image(
+    image: &Path,
+    spec: &GoldenSpec,
+    manifest: &BTreeMap<String, Entry>,
+) -> io::Result<()> {
+    let ctx = TestContext::from_image(image)?;
+    // Two names for one image would cover one version twice
+    assert_eq!(
+        image_format_version(&ctx)?,
+        Some(spec.format_version),
+        "{:?} is not a version {} image",
+        image,
+        spec.format_version
+    );
+
+    let mut children: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    children.insert("/".to_string(), BTreeSet::new());
+    for (path, entry) in manifest {
+        let (parent, name) = parent_and_name(path);
+        children.entry(parent).or_default().insert(name);
+        if *entry == Entry::Dir {
+            children.entry(path.clone()).or_default();
+        }
+    }
+
+    for (dir, expected) in &children {
+        let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+        let actual = listed_names(&String::from_utf8_lossy(&output.stdout));
+        assert_eq!(&actual, expected, "{:?}: listing of {} changed", image, dir);
+    }
+
+    for (path, entry) in manifest {
+        if let Entry::File {
+            len,
+            seed,
+            checksum: expected_checksum,
+        } = entry
+        {
+            let expected = content(*seed, *len);
+            assert_eq!(
+                checksum(&expected),
+                *expected_checksum,
+                "Content generator no longer matches the manifest for {}",
+                path
+            );
+            // Read into a host file so the comparison is exact
+            let host_copy = ctx.temp_dir.path().join("golden_read.bin");
+            ctx.run_bellande_command(&[
+                "read",
+                "--path",
+                path,
+                "--output",
+                &host_copy.to_string_lossy(),
+            ])?;
+            assert!(
+                fs::read(&host_copy)? == expected,
+                "{:?}: contents of {} changed",
+                image,
+                path
+            );
+        }
+    }
+    assert_fsck_clean(&ctx)?;
+
+    if spec.format_args == CRC32C_FORMAT_ARGS {
+        assert_scrub_clean(&ctx)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn golden_images() -> io::Result<()> {
+    let dir = golden_dir();
+    let mut images = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "img") {
+            images.push(path);
+        }
+    }
+    images.sort();
+
+    // A missing image is a format version nothing checks any more
+    let missing: Vec<&str> = SPECS
+        .iter()
+        .map(|spec| spec.name)
+        .filter(|name| !dir.join(format!("{}.img", name)).exists())
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "No golden image in {:?} for {:?}",
+        dir,
+        missing
+    );
+
+    for image in images {
+        println!("Verifying golden image {:?}", image);
+        let name = image
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let spec =
+            spec_named(&name).unwrap_or_else(|| panic!("{:?} has no spec in golden.rs", image));
+        let manifest = fs::read_to_string(image.with_extension("manifest"))?;
+        verify_image(&image, spec, &parse_manifest(&manifest)?)?;
+    }
+    Ok(())
+}
+
+// Builds the current version's images that do not exist yet; only run this
+// when intentionally adding a format version or feature combination, then
+// review and commit the new files. Existing images are left alone, and an
+// older version's image is never built: the current binary would only write
+// its own format under an old name.
+pub(crate) fn build_golden_images() -> io::Result<()> {
+    let dir = golden_dir();
+    fs::create_dir_all(&dir)?;
+
+    for spec in SPECS {
+        if dir.join(format!("{}.img", spec.name)).exists() {
+            continue;
+        }
+        if spec.format_version != CURRENT_FORMAT_VERSION {
+            println!(
+                "Not building {}: capture it with the last release writing version {}",
+                spec.name, spec.format_version
+            );
+            continue;
+        }
+        let ctx = TestContext::with_options(spec.device_size, spec.block_size)?
+            .with_format_args(spec.format_args);
+        format_device(&ctx)?;
+        for path in spec.dirs {
+            ctx.run_bellande_command(&["mkdir", "--path", path])?;
+        }
+        for (path, len, seed) in spec.files {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            let output = write_file(&ctx, path, &content(*seed, *len))?;
+            if !output.status.success() {
+                return Err(io::Error::other(format!("Failed to write {}", path)));
+            }
+        }
+
+        fs::copy(&ctx.device_path, dir.join(format!("{}.img", spec.name)))?;
+        fs::write(
+            dir.join(format!("{}.manifest", spec.name)),
+            render_manifest(spec),
+        )?;
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    golden_images(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_round_trip() -> io::Result<()> {
+        for spec in SPECS {
+            let manifest = parse_manifest(&render_manifest(spec))?;
+            assert_eq!(manifest.len(), spec.dirs.len() + spec.files.len());
+        }
+        assert!(parse_manifest("file /broken").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_specs_are_distinct() {
+        for (index, spec) in SPECS.iter().enumerate() {
+            assert!(spec.format_version <= CURRENT_FORMAT_VERSION);
+            for other in &SPECS[index + 1..] {
+                assert_ne!(spec.name, other.name);
+                assert!(
+                    (spec.format_version, spec.format_args)
+                        != (other.format_version, other.format_args),
+                    "{} and {} describe the same image",
+                    spec.name,
+                    other.name
+                );
+            }
+        }
+        assert!(SPECS
+            .iter()
+            .any(|spec| spec.format_version == CURRENT_FORMAT_VERSION));
+    }
+
+    #[test]
+    #[ignore = "rewrites tests/golden; run only when freezing a new format"]
+    fn test_build_golden_images() -> io::Result<()> {
+        build_golden_images()
+    }
+}
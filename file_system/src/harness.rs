@@ -0,0 +1,594 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Shared test harness: locating the binary, building test devices, and
+// running commands against them through assert_cmd.
+
+use crate::fault_injection::FAULT_ENV;
+use crate::sizes::parse_size;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{self, ChildStdin, ChildStdout, ExitStatus, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const BINARY_NAME: &str = "file_system";
+const BINARY_ENV: &str = "BELLANDE_FS_BINARY";
+
+// A hung binary is killed and fails its test instead of wedging the suite
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+const COMMAND_TIMEOUT_ENV: &str = "BELLANDE_FS_COMMAND_TIMEOUT";
+// How often a streaming command's watchdog checks whether it has exited
+const WATCHDOG_POLL: Duration = Duration::from_millis(10);
+
+pub(crate) const DEFAULT_DEVICE_SIZE: u64 = 10 * 1024 * 1024;
+pub(crate) const SMALL_DEVICE_SIZE: u64 = 1024 * 1024;
+pub(crate) const SPARSE_DEVICE_SIZE: u64 = 1024 * 1024 * 1024;
+const DEVICE_SIZE_ENV: &str = "BELLANDE_FS_TEST_DEVICE_SIZE";
+
+// A named scenario that builds its own device; each feature module lists
+// its scenarios with `scenarios!` so the full suite can run them all
+pub(crate) struct Scenario {
+    pub(crate) name: &'static str,
+    pub(crate) run: fn() -> io::Result<()>,
+    // Specifies a command or option the binary does not implement yet; only
+    // run by default with the `contract-tests` feature
+    pub(crate) contract: bool,
+}
+
+impl Scenario {
+    // Whether the full suite and `cargo test` run it without being asked
+    pub(crate) fn runs_by_default(&self) -> bool {
+        !self.contract || cfg!(feature = "contract-tests")
+    }
+}
+
+// Declares a module's SCENARIOS and one `#[test]` per entry, from a single
+// table. Each entry names a scenario function and what it is called with:
+// `name` passes a fresh `TestContext::new()?`, `name(expr)` passes `&expr`
+// and `name()` calls a function that builds its own devices. An entry
+// marked `#[contract]` belongs to the contract suite and its test is
+// ignored unless the `contract-tests` feature is on.
+macro_rules! scenarios {
+    ($($(#[$marker:ident])? $name:ident $(($($context:expr)?))?),* $(,)?) => {
+        pub(crate) const SCENARIOS: &[$crate::harness::Scenario] = &[$(
+            $crate::harness::Scenario {
+                name: stringify!($name),
+                run: $crate::harness::scenarios!(@run $name $(($($context)?))?),
+                contract: $crate::harness::scenarios!(@contract $($marker)?),
+            },
+        )*];
+
+        // Named after the scenario, so `cargo test <name>` runs the same
+        // thing as `bellandeos_file_system_test run <name>`
+        #[cfg(test)]
+        mod scenario_tests {
+            $($crate::harness::scenarios!(@test $name $($marker)?);)*
+        }
+    };
+    (@run $name:ident) => {
+        || $name(&$crate::harness::TestContext::new()?)
+    };
+    (@run $name:ident ()) => {
+        $name
+    };
+    (@run $name:ident ($context:expr)) => {
+        || $name(&$context)
+    };
+    (@contract) => {
+        false
+    };
+    (@contract contract) => {
+        true
+    };
+    (@test $name:ident) => {
+        $crate::harness::scenarios!(@test_fn $name);
+    };
+    (@test $name:ident contract) => {
+        $crate::harness::scenarios!(
+            @test_fn $name
+            #[cfg_attr(
+                not(feature = "contract-tests"),
+                ignore = "contract suite; run with --features contract-tests"
+            )]
+        );
+    };
+    (@test_fn $name:ident $(#[$attr:meta])*) => {
+        #[test]
+        $(#[$attr])*
+        fn $name() -> std::io::Result<()> {
+            $crate::harness::run_named(super::SCENARIOS, stringify!($name))
+        }
+    };
+}
+pub(crate) use scenarios;
+
+#[cfg(test)]
+pub(crate) fn run_named(scenarios: &[Scenario], name: &str) -> io::Result<()> {
+    let scenario = scenarios
+        .iter()
+        .find(|scenario| scenario.name == name)
+        .expect("scenarios! lists every test it generates");
+    (scenario.run)()
+}
+
+fn binary_file_name() -> String {
+    format!("{}{}", BINARY_NAME, env::consts::EXE_SUFFIX)
+}
+
+// Places the binary may live, relative to the crate directory, the workspace
+// root, or an out-of-tree Cargo target directory.
+fn binary_candidates() -> Vec<PathBuf> {
+    let file_name = binary_file_name();
+
+    let mut roots = Vec::new();
+    if let Ok(current_dir) = env::current_dir() {
+        roots.push(current_dir);
+    }
+    if let Some(manifest_dir) = option_env!("CARGO_MANIFEST_DIR") {
+        roots.push(PathBuf::from(manifest_dir));
+    }
+
+    let mut candidates = Vec::new();
+    for root in &roots {
+        for base in [root.clone(), root.join("file_system")] {
+            candidates.push(base.join("bellandeos").join(&file_name));
+            for profile in ["debug", "release"] {
+                candidates.push(base.join("target").join(profile).join(&file_name));
+            }
+        }
+    }
+    if let Some(target_dir) = env::var_os("CARGO_TARGET_DIR") {
+        for profile in ["debug", "release"] {
+            candidates.push(PathBuf::from(&target_dir).join(profile).join(&file_name));
+        }
+    }
+
+    let mut unique = Vec::new();
+    for candidate in candidates {
+        if !unique.contains(&candidate) {
+            unique.push(candidate);
+        }
+    }
+    unique
+}
+
+pub(crate) fn get_bellande_fs_binary() -> PathBuf {
+    if let Some(path) = env::var_os(BINARY_ENV) {
+        return PathBuf::from(path);
+    }
+
+    // Only set by Cargo when the binary is built as part of the same package
+    if let Some(path) = option_env!("CARGO_BIN_EXE_file_system") {
+        return PathBuf::from(path);
+    }
+
+    let candidates = binary_candidates();
+    if let Some(path) = candidates.iter().find(|candidate| candidate.is_file()) {
+        return path.clone();
+    }
+
+    let searched: Vec<String> = candidates
+        .iter()
+        .map(|candidate| format!("  {}", candidate.display()))
+        .collect();
+    panic!(
+        "Could not find the Bellande filesystem binary. Set {} to its path or build it. Looked in:\n{}",
+        BINARY_ENV,
+        searched.join("\n")
+    );
+}
+
+fn default_device_size() -> u64 {
+    match env::var(DEVICE_SIZE_ENV) {
+        Ok(value) => parse_size(&value).unwrap_or_else(|| {
+            panic!(
+                "{} must be a size such as 10485760 or 10M, got {:?}",
+                DEVICE_SIZE_ENV, value
+            )
+        }),
+        Err(_) => DEFAULT_DEVICE_SIZE,
+    }
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+        _ => None,
+    }
+}
+
+// Seconds from `name`, or `default` when it is unset
+pub(crate) fn env_timeout(name: &str, default: Duration) -> Duration {
+    match env::var(name) {
+        Ok(value) => parse_seconds(&value).unwrap_or_else(|| {
+            panic!(
+                "{} must be a positive number of seconds, got {:?}",
+                name, value
+            )
+        }),
+        Err(_) => default,
+    }
+}
+
+pub(crate) fn command_timeout() -> Duration {
+    env_timeout(COMMAND_TIMEOUT_ENV, DEFAULT_COMMAND_TIMEOUT)
+}
+
+// How a context formats its device and what every command is given besides
+// `--device`; the `with_*` builders on TestContext fill it in
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeviceOptions {
+    // Passed to `format` as `--block-size` when set
+    pub(crate) block_size: Option<u32>,
+    // Extra arguments appended to every `format` of the device
+    pub(crate) format_args: Vec<String>,
+    // `--partition`, `--key-file`, `--io-backend` and `--overlay`, each
+    // passed to every command when set
+    pub(crate) partition: Option<u32>,
+    pub(crate) key_file: Option<PathBuf>,
+    pub(crate) io_backend: Option<&'static str>,
+    pub(crate) overlay: Option<PathBuf>,
+    // Set as BELLANDE_FS_FAULT for every command when set
+    pub(crate) fault: Option<String>,
+}
+
+impl DeviceOptions {
+    fn apply(&self, command: &mut process::Command) {
+        if let Some(index) = self.partition {
+            command.arg("--partition").arg(index.to_string());
+        }
+        if let Some(path) = &self.key_file {
+            command.arg("--key-file").arg(path);
+        }
+        if let Some(name) = self.io_backend {
+            command.arg("--io-backend").arg(name);
+        }
+        if let Some(path) = &self.overlay {
+            command.arg("--overlay").arg(path);
+        }
+        if let Some(spec) = &self.fault {
+            command.env(FAULT_ENV, spec);
+        }
+    }
+}
+
+pub(crate) struct TestContext {
+    // Owns the directory holding the device; dropped with the context
+    pub(crate) temp_dir: TempDir,
+    pub(crate) device_path: PathBuf,
+    pub(crate) binary_path: PathBuf,
+    pub(crate) options: DeviceOptions,
+}
+
+impl TestContext {
+    pub(crate) fn new() -> io::Result<Self> {
+        Self::with_options(default_device_size(), None)
+    }
+
+    // The backing file is sized with set_len so it stays sparse on hosts
+    // that support it; `block_size` is passed through to `format`.
+    pub(crate) fn with_options(size_bytes: u64, block_size: Option<u32>) -> io::Result<Self> {
+        let mut ctx =
+            Self::with_device(|device_path| File::create(device_path)?.set_len(size_bytes))?;
+        ctx.options.block_size = block_size;
+        Ok(ctx)
+    }
+
+    // Works on a private copy so checked-in images are never modified
+    pub(crate) fn from_image(image: &Path) -> io::Result<Self> {
+        Self::with_device(|device_path| fs::copy(image, device_path).map(drop))
+    }
+
+    // A fresh temporary directory whose device `create` puts in place
+    fn with_device(create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<Self> {
+        let temp_dir = TempDir::new()?;
+        let device_path = temp_dir.path().join("test_device");
+        create(&device_path)?;
+        Ok(TestContext {
+            temp_dir,
+            device_path,
+            binary_path: get_bellande_fs_binary(),
+            options: DeviceOptions::default(),
+        })
+    }
+
+    // Extra arguments appended to every `format` of this device
+    pub(crate) fn with_format_args(mut self, args: &[&str]) -> Self {
+        self.options
+            .format_args
+            .extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    // Every command of this context then runs inside partition `index`
+    pub(crate) fn with_partition(mut self, index: u32) -> Self {
+        self.options.partition = Some(index);
+        self
+    }
+
+    // Every command of this context then unlocks the device with `path`
+    pub(crate) fn with_key_file(mut self, path: PathBuf) -> Self {
+        self.options.key_file = Some(path);
+        self
+    }
+
+    // Every command of this context then does its device I/O through `name`
+    pub(crate) fn with_io_backend(mut self, name: &'static str) -> Self {
+        self.options.io_backend = Some(name);
+        self
+    }
+
+    // Every command of this context then writes to the delta image at `path`
+    pub(crate) fn with_overlay(mut self, path: PathBuf) -> Self {
+        self.options.overlay = Some(path);
+        self
+    }
+
+    // Every command of this context then injects the block I/O fault `spec`
+    pub(crate) fn with_fault(mut self, spec: &str) -> Self {
+        self.options.fault = Some(spec.to_string());
+        self
+    }
+
+    fn std_command(&self, args: &[&str]) -> process::Command {
+        let mut command = process::Command::new(&self.binary_path);
+        command.arg("--device").arg(&self.device_path);
+        self.options.apply(&mut command);
+        command.args(args);
+        command
+    }
+
+    // A command against this device; callers add stdin and assertions
+    pub(crate) fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::from_std(self.std_command(args));
+        command.timeout(command_timeout());
+        command
+    }
+
+    // Starts the same command as `command` for a caller that streams its
+    // stdin or stdout instead of buffering them
+    pub(crate) fn spawn(
+        &self,
+        args: &[&str],
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> io::Result<StreamingChild> {
+        let mut child = self
+            .std_command(args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let id = child.id();
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let mut stderr_pipe = child.stderr.take();
+
+        // Read as it arrives, so a chatty child never blocks on a full pipe
+        let stderr = thread::spawn(move || {
+            let mut stderr = Vec::new();
+            if let Some(pipe) = &mut stderr_pipe {
+                pipe.read_to_end(&mut stderr)?;
+            }
+            Ok(stderr)
+        });
+
+        // The watchdog owns the child and kills it at the command timeout,
+        // which also breaks a caller blocked on one of its pipes
+        let timeout = command_timeout();
+        let status = thread::spawn(move || {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    return Ok(Some(status));
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    child.wait()?;
+                    return Ok(None);
+                }
+                thread::sleep(WATCHDOG_POLL);
+            }
+        });
+
+        Ok(StreamingChild {
+            stdin,
+            stdout,
+            id,
+            timeout,
+            stderr,
+            status,
+        })
+    }
+
+    // Runs a command and returns its output whether or not it succeeded
+    pub(crate) fn run_raw(&self, args: &[&str]) -> io::Result<Output> {
+        self.command(args).output()
+    }
+
+    // Runs a command that must succeed; the error carries the full output
+    pub(crate) fn run_bellande_command(&self, args: &[&str]) -> io::Result<Output> {
+        self.command(args)
+            .ok()
+            .map_err(|e| io::Error::other(format!("Command failed: {}", e)))
+    }
+}
+
+// A command started by `TestContext::spawn`; the caller takes stdin or
+// stdout, streams through them and then calls `wait`
+pub(crate) struct StreamingChild {
+    pub(crate) stdin: Option<ChildStdin>,
+    pub(crate) stdout: Option<ChildStdout>,
+    id: u32,
+    timeout: Duration,
+    stderr: JoinHandle<io::Result<Vec<u8>>>,
+    status: JoinHandle<io::Result<Option<ExitStatus>>>,
+}
+
+impl StreamingChild {
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Closes whatever pipes the caller left open and waits for the child;
+    // the output has its status and stderr, and a child killed at the
+    // command timeout is an error
+    pub(crate) fn wait(mut self) -> io::Result<Output> {
+        drop(self.stdin.take());
+        drop(self.stdout.take());
+        let joined = |what| io::Error::other(format!("streaming command {} thread panicked", what));
+        let status = self.status.join().map_err(|_| joined("watchdog"))??;
+        let stderr = self.stderr.join().map_err(|_| joined("stderr"))??;
+        match status {
+            Some(status) => Ok(Output {
+                status,
+                stdout: Vec::new(),
+                stderr,
+            }),
+            None => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "Command killed after {:?}: {}",
+                    self.timeout,
+                    String::from_utf8_lossy(&stderr)
+                ),
+            )),
+        }
+    }
+}
+
+// Commands run without a TTY, so under the confirmation contract destructive
+// ones must be confirmed with --yes; the binary itself does not take it yet
+pub(crate) fn format_device(ctx: &TestContext) -> io::Result<()> {
+    let block_size = ctx.options.block_size.map(|size| size.to_string());
+    let mut args = vec!["format"];
+    if cfg!(feature = "contract-tests") {
+        args.push("--yes");
+    }
+    if let Some(block_size) = &block_size {
+        args.push("--block-size");
+        args.push(block_size);
+    }
+    args.extend(ctx.options.format_args.iter().map(String::as_str));
+
+    ctx.command(&args)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Device formatted successfully"));
+    Ok(())
+}
+
+pub(crate) fn write_file(ctx: &TestContext, path: &str, content: &[u8]) -> io::Result<Output> {
+    ctx.command(&["write", "--path", path])
+        .write_stdin(content.to_vec())
+        .output()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FsStats {
+    pub(crate) total_blocks: u64,
+    pub(crate) free_blocks: u64,
+    pub(crate) total_inodes: u64,
+    pub(crate) free_inodes: u64,
+}
+
+pub(crate) fn stat_field(stdout: &str, label: &str) -> io::Result<u64> {
+    stdout
+        .split(label)
+        .nth(1)
+        .and_then(|rest| {
+            let digits: String = rest
+                .trim_start_matches([':', ' '])
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            digits.parse().ok()
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Missing {:?} in stats output: {:?}", label, stdout),
+            )
+        })
+}
+
+pub(crate) fn read_stats(ctx: &TestContext) -> io::Result<FsStats> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(FsStats {
+        total_blocks: stat_field(&stdout, "Total blocks")?,
+        free_blocks: stat_field(&stdout, "Free blocks")?,
+        total_inodes: stat_field(&stdout, "Total inodes")?,
+        free_inodes: stat_field(&stdout, "Free inodes")?,
+    })
+}
+
+scenarios! {
+    format_device,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executable_exists() {
+        let ctx = TestContext::new().expect("Failed to create test context");
+        assert!(
+            ctx.binary_path.exists(),
+            "Executable does not exist at {:?}",
+            ctx.binary_path
+        );
+        assert!(
+            ctx.binary_path.is_file(),
+            "Path {:?} is not a file",
+            ctx.binary_path
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(&ctx.binary_path).unwrap();
+            let permissions = metadata.permissions();
+            assert!(
+                permissions.mode() & 0o111 != 0,
+                "Executable {:?} does not have execute permissions",
+                ctx.binary_path
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_seconds(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_seconds("0"), None);
+        assert_eq!(parse_seconds("soon"), None);
+    }
+
+    #[test]
+    fn test_stat_field() -> io::Result<()> {
+        let stdout = "Total blocks: 2560, Free blocks: 2550, Total inodes: 128";
+        assert_eq!(stat_field(stdout, "Total blocks")?, 2560);
+        assert_eq!(stat_field(stdout, "Free blocks")?, 2550);
+        assert!(stat_field(stdout, "Free inodes").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `import` copies a host directory tree into the image and `export` copies
+// one back out, in a single invocation each. Sizes and bytes are exact,
+// empty files and directories survive, host entries the filesystem cannot
+// represent are refused up front, and a tree that does not fit is refused
+// without touching the image. Both end with a files/bytes summary.
+// Permission bits and modification times are carried across in both
+// directions, and `import HOST_DIR FS_PATH` / `export FS_PATH HOST_DIR` are
+// the positional forms of `--from`/`--to`.
+
+use crate::capacity::{tiny_context, EXIT_NO_SPACE, NO_SPACE_MESSAGE};
+use crate::errors::{EXIT_INVALID, EXIT_NOT_FOUND};
+use crate::fixtures::{
+    expected_host_tree, file_totals, medium_spec, read_host_tree, tiny_spec, verify_contents,
+    verify_tree, write_host_tree, FixtureSpec,
+};
+use crate::harness::{format_device, read_stats, scenarios, TestContext};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn host_dir(ctx: &TestContext, name: &str) -> PathBuf {
+    ctx.temp_dir.path().join(name)
+}
+
+// `spec` written out on the host
+fn host_dir_with(ctx: &TestContext, name: &str, spec: &FixtureSpec) -> io::Result<PathBuf> {
+    let dir = host_dir(ctx, name);
+    write_host_tree(spec, &dir)?;
+    Ok(dir)
+}
+
+fn import(ctx: &TestContext, from: &Path, to: &str) -> io::Result<String> {
+    let output =
+        ctx.run_bellande_command(&["import", "--from", &from.to_string_lossy(), "--to", to])?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn export(ctx: &TestContext, from: &str, to: &Path) -> io::Result<String> {
+    let output =
+        ctx.run_bellande_command(&["export", "--from", from, "--to", &to.to_string_lossy()])?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The summary line counts exactly the files and bytes of the spec
+fn assert_summary(command: &str, stdout: &str, spec: &FixtureSpec) {
+    let (files, bytes) = file_totals(spec);
+    let summary = stdout
+        .lines()
+        .rev()
+        .find(|line| line.contains("files") && line.contains("bytes"))
+        .unwrap_or_else(|| panic!("{} printed no summary: {:?}", command, stdout));
+    let numbers: Vec<usize> = summary
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|word| word.parse().ok())
+        .collect();
+    assert_eq!(
+        numbers,
+        [files, bytes],
+        "{} summary {:?} should count {} files and {} bytes",
+        command,
+        summary,
+        files,
+        bytes
+    );
+}
+
+fn host_spec() -> FixtureSpec {
+    let mut spec = medium_spec();
+    for entry in tiny_spec().under("/tiny").entries {
+        spec.entries.push(entry);
+    }
+    spec.dir("/empty_dir").dir("/empty_dir/nested_empty")
+}
+
+pub(crate) fn import_export_round_trip(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let spec = host_spec();
+    let source = host_dir_with(ctx, "source", &spec)?;
+
+    let stdout = import(ctx, &source, "/target")?;
+    assert_summary("import", &stdout, &spec);
+    let imported = spec.under("/target");
+    verify_tree(ctx, &imported)?;
+    verify_contents(ctx, &imported)?;
+
+    let exported = host_dir(ctx, "exported");
+    let stdout = export(ctx, "/target", &exported)?;
+    assert_summary("export", &stdout, &spec);
+    assert!(
+        read_host_tree(&exported)? == expected_host_tree(&spec),
+        "exported tree differs from the imported one"
+    );
+
+    // A subtree exports on its own
+    let subtree = host_dir(ctx, "subtree");
+    export(ctx, "/target/tiny", &subtree)?;
+    assert!(read_host_tree(&subtree)? == expected_host_tree(&tiny_spec()));
+    Ok(())
+}
+
+pub(crate) fn import_argument_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let missing = host_dir(ctx, "missing");
+    ctx.command(&["import", "--from", &missing.to_string_lossy(), "--to", "/t"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains(
+            missing.to_string_lossy().into_owned(),
+        ));
+    ctx.command(&[
+        "export",
+        "--from",
+        "/missing",
+        "--to",
+        &host_dir(ctx, "out").to_string_lossy(),
+    ])
+    .assert()
+    .code(EXIT_NOT_FOUND)
+    .stderr(predicate::str::contains("/missing"));
+    assert!(
+        !host_dir(ctx, "out").exists(),
+        "failed export created its target"
+    );
+    Ok(())
+}
+
+// (path, mode, modification time) for the metadata test, directories last
+// so their times are those set after their children were written
+#[cfg(unix)]
+const METADATA_ENTRIES: &[(&str, u32, u64)] = &[
+    ("/script.sh", 0o755, 1_000_000_000),
+    ("/private/key", 0o600, 1_100_000_000),
+    ("/shared.txt", 0o664, 1_200_000_000),
+    ("/private", 0o750, 1_300_000_000),
+];
+
+#[cfg(unix)]
+fn image_mode_and_mtime(ctx: &TestContext, path: &str) -> io::Result<(u32, i64)> {
+    use crate::create_mode::parse_octal_mode;
+    use crate::json::json_field;
+    use crate::stat::stat;
+    use crate::times::parse_rfc3339;
+
+    let output = ctx.run_bellande_command(&["stat", "--format", "json", "--path", path])?;
+    let json = String::from_utf8_lossy(&output.stdout);
+    let mode = json_field(&json, "mode")
+        .and_then(|mode| parse_octal_mode(&mode))
+        .unwrap_or_else(|| panic!("stat {} has no mode: {:?}", path, json));
+    let modified = parse_rfc3339(&stat(ctx, path)?["Modified"])
+        .unwrap_or_else(|| panic!("stat {} has no modification time", path));
+    Ok((mode & 0o7777, modified))
+}
+
+#[cfg(unix)]
+fn host_mode_and_mtime(path: &Path) -> io::Result<(u32, i64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.mode() & 0o7777, metadata.mtime()))
+}
+
+#[cfg(unix)]
+pub(crate) fn metadata_preserved(ctx: &TestContext) -> io::Result<()> {
+    use crate::cli::EXIT_USAGE;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    format_device(ctx)?;
+    let source = host_dir(ctx, "source");
+    fs::create_dir_all(source.join("private"))?;
+    for (path, mode, mtime) in METADATA_ENTRIES {
+        let host = source.join(&path[1..]);
+        if !host.exists() {
+            fs::write(&host, path.as_bytes())?;
+        }
+        fs::set_permissions(&host, fs::Permissions::from_mode(*mode))?;
+        fs::File::open(&host)?.set_modified(UNIX_EPOCH + Duration::from_secs(*mtime))?;
+    }
+
+    ctx.run_bellande_command(&["import", &source.to_string_lossy(), "/in"])?;
+    for (path, mode, mtime) in METADATA_ENTRIES {
+        assert_eq!(
+            image_mode_and_mtime(ctx, &format!("/in{}", path))?,
+            (*mode, *mtime as i64),
+            "import changed the mode or time of {}",
+            path
+        );
+    }
+
+    let exported = host_dir(ctx, "exported");
+    ctx.run_bellande_command(&["export", "/in", &exported.to_string_lossy()])?;
+    for (path, mode, mtime) in METADATA_ENTRIES {
+        let host = exported.join(&path[1..]);
+        assert_eq!(
+            host_mode_and_mtime(&host)?,
+            (*mode, *mtime as i64),
+            "export changed the mode or time of {}",
+            path
+        );
+        if host.is_file() {
+            assert!(fs::read(&host)? == path.as_bytes());
+        }
+    }
+
+    // Both spellings name the same arguments; mixing them is ambiguous
+    ctx.command(&["import", &source.to_string_lossy(), "/in2", "--to", "/in3"])
+        .assert()
+        .code(EXIT_USAGE);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn metadata_preserved(_ctx: &TestContext) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn unrepresentable_entries_refused(ctx: &TestContext) -> io::Result<()> {
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+
+    format_device(ctx)?;
+    let before = read_stats(ctx)?;
+
+    let source = host_dir_with(ctx, "special", &tiny_spec())?;
+    symlink("readme.txt", source.join("link"))?;
+    let socket = source.join("etc").join("control.sock");
+    let _listener = UnixListener::bind(&socket)?;
+
+    for name in ["link", "control.sock"] {
+        ctx.command(&["import", "--from", &source.to_string_lossy(), "--to", "/t"])
+            .assert()
+            .code(EXIT_INVALID)
+            .stderr(predicate::str::contains(name));
+    }
+    assert_eq!(read_stats(ctx)?, before, "refused import changed the image");
+
+    // Once they are gone the same tree imports
+    fs::remove_file(source.join("link"))?;
+    fs::remove_file(&socket)?;
+    import(ctx, &source, "/t")?;
+    verify_tree(ctx, &tiny_spec().under("/t"))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unrepresentable_entries_refused(_ctx: &TestContext) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn oversized_import_refused(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/kept.txt", "--content", "kept"])?;
+    let before = read_stats(ctx)?;
+
+    let spec = medium_spec();
+    let source = host_dir_with(ctx, "too_big", &spec)?;
+
+    ctx.command(&[
+        "import",
+        "--from",
+        &source.to_string_lossy(),
+        "--to",
+        "/target",
+    ])
+    .assert()
+    .code(EXIT_NO_SPACE)
+    .stderr(predicate::str::contains(NO_SPACE_MESSAGE));
+    assert_eq!(
+        read_stats(ctx)?,
+        before,
+        "failed import leaked blocks or inodes"
+    );
+    ctx.command(&["list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("kept.txt").and(predicate::str::contains("target").not()));
+
+    // The image is still fully usable
+    import(ctx, &host_dir_with(ctx, "fits", &tiny_spec())?, "/small")?;
+    verify_contents(ctx, &tiny_spec().under("/small"))?;
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    import_export_round_trip,
+    #[contract]
+    import_argument_errors,
+    #[contract]
+    unrepresentable_entries_refused,
+    #[contract]
+    metadata_preserved,
+    #[contract]
+    oversized_import_refused(tiny_context()?),
+}
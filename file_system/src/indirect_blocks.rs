@@ -0,0 +1,148 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Files that outgrow their inode's direct block pointers continue through
+// single and then double indirect blocks, with 64-bit block numbers
+// throughout; this is format version 3. The harness does not know how many
+// direct pointers an inode has or how wide a pointer is, so it probes the
+// boundaries of every plausible layout, each of which must read back exactly.
+
+use crate::differential::content;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use std::io;
+
+// Plausible direct pointer counts and on-disk pointer widths in bytes
+const DIRECT_POINTERS: &[u64] = &[10, 12, 15];
+const POINTER_WIDTHS: &[u64] = &[4, 8];
+// Bytes read and patched on each side of a boundary
+const STRADDLE: u64 = 7;
+
+// Byte offsets where a file switches from direct to single indirect and from
+// single to double indirect blocks, for every candidate layout
+fn boundaries(block_size: u64) -> Vec<u64> {
+    let mut offsets = Vec::new();
+    for direct in DIRECT_POINTERS {
+        for width in POINTER_WIDTHS {
+            offsets.push(direct * block_size);
+            offsets.push((direct + block_size / width) * block_size);
+        }
+    }
+    offsets.sort();
+    offsets.dedup();
+    offsets
+}
+
+fn read_range(ctx: &TestContext, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    let output = ctx.run_bellande_command(&[
+        "read",
+        "--path",
+        "/big.bin",
+        "--offset",
+        &offset.to_string(),
+        "--length",
+        &length.to_string(),
+    ])?;
+    Ok(output.stdout)
+}
+
+pub(crate) fn indirect_boundaries(block_size: u32) -> io::Result<()> {
+    let ctx = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(block_size))?;
+    let block_size = u64::from(block_size);
+    format_device(&ctx)?;
+    let empty = read_stats(&ctx)?;
+
+    let offsets = boundaries(block_size);
+    let len = offsets.last().copied().unwrap_or(0) + 4 * block_size;
+    let mut model = content(block_size, len as usize);
+    ctx.run_bellande_command(&["create", "--path", "/big.bin"])?;
+    assert!(write_file(&ctx, "/big.bin", &model)?.status.success());
+
+    for &offset in &offsets {
+        let start = offset - STRADDLE;
+        let expected = &model[start as usize..(offset + STRADDLE) as usize];
+        assert!(
+            read_range(&ctx, start, 2 * STRADDLE)? == expected,
+            "block size {}: bytes around offset {} differ",
+            block_size,
+            offset
+        );
+    }
+
+    // Patch across every boundary in place, then check the whole file
+    for (index, &offset) in offsets.iter().enumerate() {
+        let patch = content(1000 + index as u64, 2 * STRADDLE as usize);
+        let start = offset - STRADDLE;
+        ctx.command(&[
+            "write",
+            "--path",
+            "/big.bin",
+            "--offset",
+            &start.to_string(),
+        ])
+        .write_stdin(patch.clone())
+        .assert()
+        .success();
+        model[start as usize..(offset + STRADDLE) as usize].copy_from_slice(&patch);
+    }
+    let output = ctx.run_bellande_command(&["read", "--path", "/big.bin"])?;
+    assert!(
+        output.stdout == model,
+        "block size {}: patched file differs",
+        block_size
+    );
+
+    // Indirect blocks are freed along with the data
+    ctx.run_bellande_command(&["remove", "--path", "/big.bin"])?;
+    assert_eq!(
+        read_stats(&ctx)?,
+        empty,
+        "block size {}: blocks leaked",
+        block_size
+    );
+    Ok(())
+}
+
+fn indirect_boundaries_1k() -> io::Result<()> {
+    indirect_boundaries(1024)
+}
+
+fn indirect_boundaries_4k() -> io::Result<()> {
+    indirect_boundaries(4096)
+}
+
+scenarios! {
+    #[contract]
+    indirect_boundaries_1k(),
+    #[contract]
+    indirect_boundaries_4k(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries() {
+        let offsets = boundaries(1024);
+        // Direct limits, then single indirect limits for 8- and 4-byte pointers
+        assert_eq!(offsets[..3], [10 * 1024, 12 * 1024, 15 * 1024]);
+        assert!(offsets.contains(&((12 + 128) * 1024)));
+        assert!(offsets.contains(&((12 + 256) * 1024)));
+        assert_eq!(offsets.len(), 9);
+        assert!(boundaries(4096).last() == Some(&((15 + 1024) * 4096)));
+    }
+}
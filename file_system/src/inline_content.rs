@@ -0,0 +1,201 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Inline payloads: `create`/`write` with `--content <text>` or
+// `--content-base64 <data>` write the argument itself, honoring `--append`,
+// and refuse to be combined with each other or with `--input`. Everything
+// read back must match byte for byte; no newline is added.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const UTF8_PAYLOADS: &[&str] = &[
+    "bellande",
+    "",
+    "two\nlines\n",
+    "tab\tand trailing space ",
+    "caf\u{00E9} \u{65E5}\u{672C} \u{1F4C1}",
+    "\u{05E9}\u{05DC}\u{05D5}\u{05DD} mixed direction",
+    "quotes \" ' and backslash \\",
+];
+
+// Standard alphabet with padding, the form `--content-base64` accepts
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(BASE64_ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn read_back(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn utf8_content_round_trips(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    for (index, payload) in UTF8_PAYLOADS.iter().enumerate() {
+        let created = format!("/created{}", index);
+        ctx.run_bellande_command(&["create", "--path", &created, "--content", payload])?;
+        assert_eq!(
+            read_back(ctx, &created)?,
+            payload.as_bytes(),
+            "create {:?}",
+            payload
+        );
+
+        // write replaces, --append extends
+        let written = format!("/written{}", index);
+        ctx.run_bellande_command(&["create", "--path", &written, "--content", "old content"])?;
+        ctx.run_bellande_command(&["write", "--path", &written, "--content", payload])?;
+        assert_eq!(
+            read_back(ctx, &written)?,
+            payload.as_bytes(),
+            "write {:?}",
+            payload
+        );
+        ctx.run_bellande_command(&[
+            "write",
+            "--append",
+            "--path",
+            &written,
+            "--content",
+            payload,
+        ])?;
+        assert_eq!(
+            read_back(ctx, &written)?,
+            payload.repeat(2).as_bytes(),
+            "write --append {:?}",
+            payload
+        );
+    }
+
+    // The argument wins; a pipe on stdin is not read
+    ctx.command(&["write", "--path", "/created0", "--content", "from argv"])
+        .write_stdin("from stdin")
+        .assert()
+        .success();
+    assert_eq!(read_back(ctx, "/created0")?, b"from argv");
+    Ok(())
+}
+
+pub(crate) fn base64_content_round_trips(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let every_byte: Vec<u8> = (0..=255).collect();
+
+    for (index, payload) in [
+        every_byte,
+        content(7, 1),
+        content(8, 2),
+        content(9, 3),
+        content(10, 64 * 1024),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let path = format!("/blob{}", index);
+        let encoded = base64_encode(payload);
+        ctx.run_bellande_command(&["create", "--path", &path, "--content-base64", &encoded])?;
+        assert_eq!(&read_back(ctx, &path)?, payload, "{} bytes", payload.len());
+
+        ctx.run_bellande_command(&[
+            "write",
+            "--append",
+            "--path",
+            &path,
+            "--content-base64",
+            &encoded,
+        ])?;
+        assert_eq!(read_back(ctx, &path)?, payload.repeat(2));
+    }
+
+    for invalid in ["not base64!", "abc", "ab=c"] {
+        ctx.command(&["create", "--path", "/bad", "--content-base64", invalid])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--content-base64"));
+    }
+    ctx.command(&["read", "--path", "/bad"]).assert().failure();
+    Ok(())
+}
+
+pub(crate) fn content_sources_exclusive(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let input = ctx.temp_dir.path().join("input.txt");
+    fs::write(&input, "from a host file")?;
+    let input = input.to_string_lossy().into_owned();
+
+    for args in [
+        &["--content", "a", "--content-base64", "YQ=="][..],
+        &["--content", "a", "--input", &input][..],
+        &["--content-base64", "YQ==", "--input", &input][..],
+    ] {
+        let mut full = vec!["create", "--path", "/exclusive"];
+        full.extend_from_slice(args);
+        ctx.command(&full)
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--content"));
+    }
+    ctx.command(&["read", "--path", "/exclusive"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    utf8_content_round_trips,
+    #[contract]
+    base64_content_round_trips,
+    #[contract]
+    content_sources_exclusive,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0x00]), "//4A");
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Device I/O backends, chosen with the global `--io-backend
+// sync|direct|io-uring`. `sync` is the default pread/pwrite path; `direct`
+// opens the device with O_DIRECT, bypassing the host page cache as a raw
+// block device would be driven; `io-uring` submits large sequential and
+// batched random I/O asynchronously and exists only in Linux builds with the
+// `io-uring` feature. When a backend cannot work here (no ring, or a host
+// filesystem that refuses O_DIRECT) asking for it exits EXIT_NOT_SUPPORTED
+// naming the backend instead of quietly falling back. Whatever the backend,
+// the results on the device are the same.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_NOT_SUPPORTED;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::import_export::import_export_round_trip;
+use crate::journal::tree_state;
+use crate::partial_io::{read_ranges_exact, writes_patch_in_place};
+use predicates::prelude::*;
+use std::io;
+
+const IO_URING: &str = "io-uring";
+const DIRECT: &str = "direct";
+const BLOCK_SIZE: u32 = 4096;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const FILE_LEN: usize = 256 * BLOCK;
+const RANDOM_WRITES: usize = 24;
+
+fn backend_context(backend: &'static str) -> io::Result<TestContext> {
+    Ok(TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?.with_io_backend(backend))
+}
+
+// Why `backend` cannot run here, if it cannot
+fn unavailable(backend: &'static str) -> io::Result<Option<String>> {
+    let ctx = backend_context(backend)?;
+    let output = ctx.run_raw(&["stats"])?;
+    if output.status.code() != Some(EXIT_NOT_SUPPORTED) {
+        return Ok(None);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        stderr.contains(backend),
+        "unsupported backend not named: {:?}",
+        stderr
+    );
+    Ok(Some(stderr.trim().to_string()))
+}
+
+// Offsets spread over the file in no particular order, some mid-block
+fn random_offsets() -> Vec<usize> {
+    (0..RANDOM_WRITES)
+        .map(|index| (index * 7919 * BLOCK + index * 131) % (FILE_LEN - 2 * BLOCK))
+        .collect()
+}
+
+fn batched_workload(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/bulk.bin"])?;
+    assert!(write_file(ctx, "/bulk.bin", &content(1, FILE_LEN))?
+        .status
+        .success());
+    for (index, offset) in random_offsets().into_iter().enumerate() {
+        ctx.command(&[
+            "write",
+            "--path",
+            "/bulk.bin",
+            "--offset",
+            &offset.to_string(),
+        ])
+        .write_stdin(content(index as u64 + 2, BLOCK + index))
+        .assert()
+        .success();
+    }
+    for index in 0..8 {
+        let path = format!("/small/{}.bin", index);
+        ctx.run_bellande_command(&["create", "--parents", "--path", &path])?;
+        assert!(write_file(ctx, &path, &content(index + 50, 3 * BLOCK / 2))?
+            .status
+            .success());
+    }
+    ctx.run_bellande_command(&["remove", "--path", "/small/3.bin"])?;
+    Ok(())
+}
+
+pub(crate) fn io_uring_matches_sync() -> io::Result<()> {
+    if let Some(reason) = unavailable(IO_URING)? {
+        println!("Skipping io-uring backend: {}", reason);
+        return Ok(());
+    }
+
+    let sync = backend_context("sync")?;
+    batched_workload(&sync)?;
+    let uring = backend_context(IO_URING)?;
+    batched_workload(&uring)?;
+    assert_eq!(tree_state(&uring)?, tree_state(&sync)?);
+    assert_eq!(read_stats(&uring)?, read_stats(&sync)?);
+    assert_fsck_clean(&uring)?;
+
+    // Unchanged scenarios, every command carrying `--io-backend io-uring`
+    import_export_round_trip(&backend_context(IO_URING)?)?;
+    read_ranges_exact(&backend_context(IO_URING)?)?;
+    writes_patch_in_place(&backend_context(IO_URING)?)
+}
+
+pub(crate) fn direct_io_matches_sync() -> io::Result<()> {
+    if let Some(reason) = unavailable(DIRECT)? {
+        println!("Skipping direct backend: {}", reason);
+        return Ok(());
+    }
+    let sync = backend_context("sync")?;
+    batched_workload(&sync)?;
+    let direct = backend_context(DIRECT)?;
+    batched_workload(&direct)?;
+    assert_eq!(tree_state(&direct)?, tree_state(&sync)?);
+    assert_eq!(read_stats(&direct)?, read_stats(&sync)?);
+    assert_fsck_clean(&direct)?;
+    // Unaligned offsets and lengths are the caller's, not the device's
+    read_ranges_exact(&backend_context(DIRECT)?)
+}
+
+pub(crate) fn images_move_between_backends() -> io::Result<()> {
+    if let Some(reason) = unavailable(IO_URING)? {
+        println!("Skipping io-uring backend: {}", reason);
+        return Ok(());
+    }
+    let uring = backend_context(IO_URING)?;
+    batched_workload(&uring)?;
+    let expected = tree_state(&uring)?;
+
+    // Written through the ring, read synchronously, and back
+    let sync = TestContext::from_image(&uring.device_path)?.with_io_backend("sync");
+    assert_eq!(tree_state(&sync)?, expected);
+    assert_fsck_clean(&sync)?;
+    sync.run_bellande_command(&["remove", "--path", "/small/0.bin"])?;
+    let uring = TestContext::from_image(&sync.device_path)?.with_io_backend(IO_URING);
+    let mut after = expected;
+    after.remove("/small/0.bin");
+    assert_eq!(tree_state(&uring)?, after);
+    Ok(())
+}
+
+pub(crate) fn io_backend_selection(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/file.txt"])?;
+    // `sync` is the default
+    let default = ctx.run_bellande_command(&["stats"])?.stdout;
+    let explicit = ctx.run_bellande_command(&["--io-backend", "sync", "stats"])?;
+    assert_eq!(explicit.stdout, default);
+
+    for value in ["uring", "SYNC", "o_direct", ""] {
+        ctx.command(&["--io-backend", value, "stats"])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--io-backend"));
+    }
+    if !cfg!(target_os = "linux") {
+        ctx.command(&["--io-backend", IO_URING, "stats"])
+            .assert()
+            .code(EXIT_NOT_SUPPORTED)
+            .stderr(predicate::str::contains(IO_URING));
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    io_uring_matches_sync(),
+    #[contract]
+    direct_io_matches_sync(),
+    #[contract]
+    images_move_between_backends(),
+    #[contract]
+    io_backend_selection,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_random_offsets_spread() {
+        let offsets = random_offsets();
+        assert!(offsets.iter().all(|offset| offset + 2 * BLOCK <= FILE_LEN));
+        let blocks: BTreeSet<usize> = offsets.iter().map(|offset| offset / BLOCK).collect();
+        assert!(blocks.len() > RANDOM_WRITES / 2);
+        assert!(offsets.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Metadata journaling. Each operation writes an intent record to a journal
+// region reserved at format time before it touches metadata, and the next
+// open replays or discards it, so a crash at any point leaves the tree as it
+// was before the operation or as it is after it. Journaled images carry
+// format version 2; version 1 images still open, but read-only.
+//
+// Crashes are injected with BELLANDE_FS_FAIL_AFTER_WRITES=N, which makes the
+// binary stop dead after N block writes to the device. `fsck --replay` does
+// the recovery explicitly, exiting 1 when it replayed or discarded anything.
+
+use crate::differential::content;
+use crate::fsck::{assert_fsck_clean, EXIT_FSCK_CLEAN, EXIT_FSCK_CORRECTED, EXIT_FSCK_UNCORRECTED};
+use crate::golden::checksum;
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+pub(crate) const FAIL_AFTER_ENV: &str = "BELLANDE_FS_FAIL_AFTER_WRITES";
+
+const BLOCK_SIZE: u32 = 4096;
+// Far more block writes than any single operation below needs
+const MAX_CRASH_POINTS: u64 = 10_000;
+// Early enough that every operation below is still incomplete
+const REPLAY_CRASH_POINTS: &[u64] = &[1, 2];
+
+struct Operation {
+    name: &'static str,
+    args: &'static [&'static str],
+    stdin_len: usize,
+}
+
+// Each touches several metadata structures at once
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        name: "create with parents",
+        args: &["create", "--parents", "--path", "/a/b/c/new.txt"],
+        stdin_len: 0,
+    },
+    Operation {
+        name: "mkdir",
+        args: &["mkdir", "--path", "/dir/new_dir"],
+        stdin_len: 0,
+    },
+    Operation {
+        name: "write into an empty file",
+        args: &["write", "--path", "/empty.bin"],
+        stdin_len: 20_000,
+    },
+    Operation {
+        name: "append across blocks",
+        args: &["write", "--append", "--path", "/keep.bin"],
+        stdin_len: 3 * BLOCK_SIZE as usize + 11,
+    },
+    Operation {
+        name: "move between directories",
+        args: &["move", "--from", "/dir/moved.bin", "--to", "/moved.bin"],
+        stdin_len: 0,
+    },
+    Operation {
+        name: "recursive remove",
+        args: &["remove", "--recursive", "--path", "/tree"],
+        stdin_len: 0,
+    },
+];
+
+fn journal_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn base_state(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, len, seed) in [("/keep.bin", 9000, 1), ("/dir/moved.bin", 5000, 2)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &content(seed, len))?.status.success());
+    }
+    ctx.run_bellande_command(&["create", "--path", "/empty.bin"])?;
+    for index in 0..10 {
+        let path = format!("/tree/sub{}/file{}", index % 3, index);
+        ctx.run_bellande_command(&["create", "--parents", "--path", &path])?;
+        assert!(
+            write_file(ctx, &path, &content(index + 10, 700 * index as usize))?
+                .status
+                .success()
+        );
+    }
+    Ok(())
+}
+
+// Every path in the tree with the checksum of its contents (files only)
+pub(crate) fn tree_state(ctx: &TestContext) -> io::Result<BTreeMap<String, Option<u64>>> {
+    let output = ctx.run_bellande_command(&["list", "--recursive", "--path", "/"])?;
+    let mut tree = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let path = line.split(" (inode").next().unwrap_or("").trim_end();
+        if path.is_empty() || path.ends_with(':') {
+            continue;
+        }
+        let read = ctx.run_raw(&["read", "--path", path])?;
+        let digest = read.status.success().then(|| checksum(&read.stdout));
+        tree.insert(path.to_string(), digest);
+    }
+    Ok(tree)
+}
+
+fn run_operation(
+    ctx: &TestContext,
+    operation: &Operation,
+    fail_after: Option<u64>,
+) -> io::Result<bool> {
+    let mut command = ctx.command(operation.args);
+    if let Some(limit) = fail_after {
+        command.env(FAIL_AFTER_ENV, limit.to_string());
+    }
+    if operation.stdin_len > 0 {
+        command.write_stdin(content(99, operation.stdin_len));
+    }
+    Ok(command.output()?.status.success())
+}
+
+pub(crate) fn crash_at_every_write(base: &TestContext) -> io::Result<()> {
+    base_state(base)?;
+    let before = tree_state(base)?;
+    let image = base.temp_dir.path().join("base.img");
+    fs::copy(&base.device_path, &image)?;
+
+    for operation in OPERATIONS {
+        let complete = TestContext::from_image(&image)?;
+        assert!(
+            run_operation(&complete, operation, None)?,
+            "{} failed",
+            operation.name
+        );
+        let after = tree_state(&complete)?;
+        assert_ne!(before, after, "{} changed nothing", operation.name);
+
+        let mut crash_points = 0;
+        for limit in 0..MAX_CRASH_POINTS {
+            let ctx = TestContext::from_image(&image)?;
+            if run_operation(&ctx, operation, Some(limit))? {
+                break;
+            }
+            crash_points += 1;
+
+            // The next open replays or discards the journal
+            ctx.run_bellande_command(&["stats"])?;
+            let output = ctx.run_raw(&["fsck"])?;
+            assert_eq!(
+                output.status.code(),
+                Some(EXIT_FSCK_CLEAN),
+                "{} crashed after {} writes: fsck reports {}",
+                operation.name,
+                limit,
+                String::from_utf8_lossy(&output.stdout)
+            );
+            let state = tree_state(&ctx)?;
+            assert!(
+                state == before || state == after,
+                "{} crashed after {} writes: the tree is neither before nor after it",
+                operation.name,
+                limit
+            );
+        }
+        assert!(
+            crash_points > 0,
+            "{} never hit the failure hook",
+            operation.name
+        );
+        assert!(
+            crash_points < MAX_CRASH_POINTS,
+            "{} never completed under the failure hook",
+            operation.name
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn unreplayed_journal_detected(ctx: &TestContext) -> io::Result<()> {
+    base_state(ctx)?;
+    let operation = &OPERATIONS[0];
+    assert!(!run_operation(ctx, operation, Some(1))?);
+
+    // A read-only check reports the pending journal instead of replaying it
+    let before = fs::read(&ctx.device_path)?;
+    let output = ctx.run_raw(&["fsck"])?;
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "fsck replayed the journal"
+    );
+    let code = output.status.code();
+    assert!(
+        code == Some(EXIT_FSCK_CLEAN) || code == Some(EXIT_FSCK_UNCORRECTED),
+        "fsck exited {:?}",
+        code
+    );
+    let report = String::from_utf8_lossy(&output.stdout).to_ascii_lowercase();
+    assert!(
+        code == Some(EXIT_FSCK_CLEAN) || report.contains("journal"),
+        "fsck did not say the journal is pending: {:?}",
+        report
+    );
+
+    // So does a --read-only open
+    let before = fs::read(&ctx.device_path)?;
+    ctx.run_bellande_command(&["--read-only", "list", "--path", "/"])?;
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "--read-only replayed the journal"
+    );
+
+    ctx.run_bellande_command(&["stats"])?;
+    assert_eq!(ctx.run_raw(&["fsck"])?.status.code(), Some(EXIT_FSCK_CLEAN));
+    Ok(())
+}
+
+pub(crate) fn replay_command_recovers(base: &TestContext) -> io::Result<()> {
+    base_state(base)?;
+    let before = tree_state(base)?;
+    let image = base.temp_dir.path().join("base.img");
+    fs::copy(&base.device_path, &image)?;
+
+    // Nothing pending: a clean no-op
+    let healthy = fs::read(&base.device_path)?;
+    base.command(&["fsck", "--replay"])
+        .assert()
+        .code(EXIT_FSCK_CLEAN);
+    assert!(
+        fs::read(&base.device_path)? == healthy,
+        "--replay wrote with nothing pending"
+    );
+
+    for operation in OPERATIONS {
+        let complete = TestContext::from_image(&image)?;
+        assert!(run_operation(&complete, operation, None)?);
+        let after = tree_state(&complete)?;
+
+        for &limit in REPLAY_CRASH_POINTS {
+            let ctx = TestContext::from_image(&image)?;
+            assert!(
+                !run_operation(&ctx, operation, Some(limit))?,
+                "{} completed within {} writes",
+                operation.name,
+                limit
+            );
+            ctx.command(&["fsck", "--replay"])
+                .assert()
+                .code(EXIT_FSCK_CORRECTED);
+
+            // Recovered for good: nothing left to replay, and listing works
+            let recovered = fs::read(&ctx.device_path)?;
+            ctx.command(&["fsck", "--replay"])
+                .assert()
+                .code(EXIT_FSCK_CLEAN);
+            assert_fsck_clean(&ctx)?;
+            assert!(fs::read(&ctx.device_path)? == recovered);
+            let state = tree_state(&ctx)?;
+            assert!(
+                state == before || state == after,
+                "{} crashed after {} writes: --replay left neither state",
+                operation.name,
+                limit
+            );
+        }
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    crash_at_every_write(journal_context()?),
+    #[contract]
+    unreplayed_journal_detected(journal_context()?),
+    #[contract]
+    replay_command_recovers(journal_context()?),
+}
@@ -0,0 +1,199 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Just enough JSON to read the flat objects `--format json` prints: scalar
+// fields are looked up by key and returned as text, strings unescaped. There
+// is no JSON dependency in the harness and the outputs are small.
+
+use std::collections::BTreeMap;
+
+fn skip_whitespace(text: &str) -> &str {
+    text.trim_start_matches([' ', '\t', '\n', '\r'])
+}
+
+// The string literal `text` starts with, unescaped, and what follows it
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'u' => {
+                    let hex: String = (0..4)
+                        .filter_map(|_| chars.next())
+                        .map(|(_, c)| c)
+                        .collect();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+// Every key with a scalar value, at any depth, in document order: a string's
+// contents, or a number, `true`, `false` or `null` as written. Keys holding
+// objects or arrays are descended into rather than listed.
+pub(crate) fn json_scalars(text: &str) -> Vec<(String, String)> {
+    let mut scalars = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        let Some((name, after)) = parse_string(&rest[start..]) else {
+            break;
+        };
+        rest = after;
+        let Some(value) = skip_whitespace(after).strip_prefix(':') else {
+            continue;
+        };
+        let value = skip_whitespace(value);
+        if value.starts_with('"') {
+            let Some((value, after)) = parse_string(value) else {
+                break;
+            };
+            scalars.push((name, value));
+            rest = after;
+            continue;
+        }
+        let end = value
+            .find([',', '}', ']', ' ', '\n', '\r', '\t'])
+            .unwrap_or(value.len());
+        let token = &value[..end];
+        if !token.is_empty() && !token.starts_with(['{', '[']) {
+            scalars.push((name, token.to_string()));
+        }
+        rest = value;
+    }
+    scalars
+}
+
+// The first scalar value of `key` anywhere in `text`
+pub(crate) fn json_field(text: &str, key: &str) -> Option<String> {
+    json_scalars(text)
+        .into_iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value)
+}
+
+pub(crate) fn json_u64(text: &str, key: &str) -> Option<u64> {
+    json_field(text, key)?.parse().ok()
+}
+
+// The strings of the first array of strings held by `key`
+pub(crate) fn json_strings(text: &str, key: &str) -> Option<Vec<String>> {
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        let (name, after) = parse_string(&rest[start..])?;
+        rest = after;
+        let Some(value) = skip_whitespace(after).strip_prefix(':') else {
+            continue;
+        };
+        let value = skip_whitespace(value);
+        if name != key {
+            if value.starts_with('"') {
+                rest = parse_string(value)?.1;
+            }
+            continue;
+        }
+        let mut items = Vec::new();
+        let mut rest = skip_whitespace(value.strip_prefix('[')?);
+        if rest.starts_with(']') {
+            return Some(items);
+        }
+        loop {
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            let after = skip_whitespace(after);
+            if after.starts_with(']') {
+                return Some(items);
+            }
+            rest = skip_whitespace(after.strip_prefix(',')?);
+        }
+    }
+    None
+}
+
+// The objects of a flat array such as `[{"name": ..}, {"name": ..}]`; a new
+// record starts whenever a key repeats within the current one
+pub(crate) fn json_records(text: &str) -> Vec<BTreeMap<String, String>> {
+    let mut records = Vec::new();
+    let mut current = BTreeMap::new();
+    for (name, value) in json_scalars(text) {
+        if current.contains_key(&name) {
+            records.push(std::mem::take(&mut current));
+        }
+        current.insert(name, value);
+    }
+    if !current.is_empty() {
+        records.push(current);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_field() {
+        let text = r#"{"version": "0.1.0", "note": "say \"hi\"!",
+            "count":42, "ok": true, "nested": {"inner": 1}, "label": "count"}"#;
+        assert_eq!(json_field(text, "version").as_deref(), Some("0.1.0"));
+        assert_eq!(json_field(text, "note").as_deref(), Some("say \"hi\"!"));
+        assert_eq!(json_u64(text, "count"), Some(42));
+        assert_eq!(json_field(text, "ok").as_deref(), Some("true"));
+        assert_eq!(json_u64(text, "inner"), Some(1));
+        assert_eq!(json_field(text, "nested"), None);
+        assert_eq!(json_field(text, "missing"), None);
+        // A value equal to a key name is not mistaken for the key
+        assert_eq!(
+            json_field(r#"{"a": "b", "b": 2}"#, "b").as_deref(),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn test_json_strings() {
+        let text = r#"{"event": "args", "args": ["create", "--path", "/a \"b\""], "n": []}"#;
+        assert_eq!(
+            json_strings(text, "args"),
+            Some(vec![
+                "create".to_string(),
+                "--path".to_string(),
+                "/a \"b\"".to_string()
+            ])
+        );
+        assert_eq!(json_strings(text, "n"), Some(Vec::new()));
+        assert_eq!(json_strings(text, "event"), None);
+        assert_eq!(json_strings(text, "missing"), None);
+    }
+
+    #[test]
+    fn test_json_records() {
+        let text = r#"[{"name": "a", "type": "file", "size": 3},
+            {"name": "b", "type": "directory", "size": 0}]"#;
+        let records = json_records(text);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["name"], "a");
+        assert_eq!(records[1]["type"], "directory");
+        assert_eq!(records[1]["size"], "0");
+        assert!(json_records("[]").is_empty());
+    }
+}
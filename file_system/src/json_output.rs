@@ -0,0 +1,331 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The global `--json` flag. Every command prints one JSON document on
+// stdout instead of its human text, and failures print one JSON object on
+// stderr with a stable `error` code and a `message`; exit codes do not
+// change. The plain text stays the default. `--output json|text` is the same
+// switch spelled as an option; it goes before the subcommand, so `read
+// --output PATH` keeps its meaning. `fsck` reports `status` (clean,
+// corrected or uncorrected) and an `errors` count.
+
+use crate::capacity::{tiny_context, EXIT_NO_SPACE};
+use crate::cli::EXIT_USAGE;
+use crate::errors::{
+    EXIT_ALREADY_EXISTS, EXIT_INVALID, EXIT_IS_DIRECTORY, EXIT_LOOP, EXIT_NOT_DIRECTORY,
+    EXIT_NOT_EMPTY, EXIT_NOT_FOUND, EXIT_NOT_PERMITTED, EXIT_NO_ATTRIBUTE, EXIT_PERMISSION_DENIED,
+    EXIT_QUOTA_EXCEEDED,
+};
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use crate::json::{json_field, json_records, json_u64};
+use predicates::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+// (exit code, `error` field) shared by every command
+const ERROR_CODES: &[(i32, &str)] = &[
+    (EXIT_NOT_PERMITTED, "not_permitted"),
+    (EXIT_NOT_FOUND, "not_found"),
+    (EXIT_PERMISSION_DENIED, "permission_denied"),
+    (EXIT_ALREADY_EXISTS, "already_exists"),
+    (EXIT_NOT_DIRECTORY, "not_a_directory"),
+    (EXIT_IS_DIRECTORY, "is_a_directory"),
+    (EXIT_INVALID, "invalid_path"),
+    (EXIT_NO_SPACE, "no_space"),
+    (EXIT_NOT_EMPTY, "not_empty"),
+    (EXIT_LOOP, "symlink_loop"),
+    (EXIT_NO_ATTRIBUTE, "no_attribute"),
+    (EXIT_QUOTA_EXCEEDED, "quota_exceeded"),
+    (EXIT_USAGE, "usage"),
+];
+
+fn error_code(exit: i32) -> &'static str {
+    ERROR_CODES
+        .iter()
+        .find(|(code, _)| *code == exit)
+        .map(|(_, name)| *name)
+        .unwrap_or_else(|| panic!("no JSON error code for exit {}", exit))
+}
+
+fn json_stdout(ctx: &TestContext, args: &[&str]) -> io::Result<String> {
+    let mut full = vec!["--json"];
+    full.extend(args);
+    let output = ctx.run_bellande_command(&full)?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    assert!(
+        (stdout.starts_with('{') && stdout.ends_with('}'))
+            || (stdout.starts_with('[') && stdout.ends_with(']')),
+        "--json {:?} did not print one JSON document: {:?}",
+        args,
+        stdout
+    );
+    Ok(stdout)
+}
+
+// A failing command under --json: expected exit, nothing on stdout, and one
+// object on stderr naming the error
+fn assert_json_error(ctx: &TestContext, args: &[&str], exit: i32) -> io::Result<()> {
+    let mut full = vec!["--json"];
+    full.extend(args);
+    let output = ctx.run_raw(&full)?;
+    assert_eq!(output.status.code(), Some(exit), "--json {:?}", args);
+    assert!(
+        output.stdout.is_empty(),
+        "--json {:?} printed to stdout",
+        args
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    assert!(
+        stderr.starts_with('{') && stderr.ends_with('}') && stderr.lines().count() == 1,
+        "--json {:?} stderr is not one JSON object: {:?}",
+        args,
+        stderr
+    );
+    assert_eq!(
+        json_field(&stderr, "error").as_deref(),
+        Some(error_code(exit)),
+        "--json {:?}",
+        args
+    );
+    let message = json_field(&stderr, "message").unwrap_or_default();
+    assert!(
+        !message.is_empty(),
+        "--json {:?} error has no message",
+        args
+    );
+    Ok(())
+}
+
+pub(crate) fn stats_and_list_json(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/file.txt"])?;
+    assert!(write_file(ctx, "/file.txt", b"twelve bytes")?
+        .status
+        .success());
+
+    let stats = read_stats(ctx)?;
+    let json = json_stdout(ctx, &["stats"])?;
+    for (key, value) in [
+        ("total_blocks", stats.total_blocks),
+        ("free_blocks", stats.free_blocks),
+        ("total_inodes", stats.total_inodes),
+        ("free_inodes", stats.free_inodes),
+    ] {
+        assert_eq!(json_u64(&json, key), Some(value), "stats {}", key);
+    }
+    // The flag also works after the subcommand
+    let output = ctx.run_bellande_command(&["stats", "--json"])?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), json);
+
+    let json = json_stdout(ctx, &["list", "--path", "/"])?;
+    assert!(
+        json.starts_with('['),
+        "list --json is not an array: {:?}",
+        json
+    );
+    let entries: BTreeMap<String, BTreeMap<String, String>> = json_records(&json)
+        .into_iter()
+        .map(|record| (record["name"].clone(), record))
+        .collect();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), ["dir", "file.txt"]);
+    assert_eq!(entries["dir"]["type"], "directory");
+    assert_eq!(entries["file.txt"]["type"], "file");
+    assert_eq!(entries["file.txt"]["size"], "12");
+
+    let json = json_stdout(ctx, &["list", "--path", "/dir"])?;
+    assert!(json_records(&json).is_empty());
+    Ok(())
+}
+
+pub(crate) fn mutations_report_json(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for args in [
+        &["create", "--path", "/made.txt"][..],
+        &["mkdir", "--path", "/made"][..],
+        &["remove", "--path", "/made.txt"][..],
+        &["rmdir", "--path", "/made"][..],
+    ] {
+        let json = json_stdout(ctx, args)?;
+        assert!(json.starts_with('{'), "{:?}: {:?}", args, json);
+        assert_eq!(
+            json_field(&json, "path").as_deref(),
+            Some(args[2]),
+            "{:?}",
+            args
+        );
+        assert!(
+            !json.contains("successfully"),
+            "{:?} printed prose: {:?}",
+            args,
+            json
+        );
+    }
+
+    // Without the flag the human text is unchanged
+    let output = ctx.run_bellande_command(&["create", "--path", "/plain.txt"])?;
+    assert!(!String::from_utf8_lossy(&output.stdout)
+        .trim_start()
+        .starts_with('{'));
+    Ok(())
+}
+
+pub(crate) fn errors_report_json(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/inside.txt"])?;
+
+    assert_json_error(ctx, &["read", "--path", "/missing"], EXIT_NOT_FOUND)?;
+    assert_json_error(ctx, &["remove", "--path", "/missing"], EXIT_NOT_FOUND)?;
+    assert_json_error(ctx, &["stat", "--path", "/missing"], EXIT_NOT_FOUND)?;
+    assert_json_error(ctx, &["create", "--path", "/dir"], EXIT_ALREADY_EXISTS)?;
+    assert_json_error(ctx, &["mkdir", "--path", "/dir"], EXIT_ALREADY_EXISTS)?;
+    assert_json_error(
+        ctx,
+        &["create", "--path", "/dir/inside.txt/x"],
+        EXIT_NOT_DIRECTORY,
+    )?;
+    assert_json_error(ctx, &["read", "--path", "/dir"], EXIT_IS_DIRECTORY)?;
+    assert_json_error(ctx, &["create", "--path", "relative/path"], EXIT_INVALID)?;
+    assert_json_error(ctx, &["rmdir", "--path", "/dir"], EXIT_NOT_EMPTY)?;
+    ctx.run_bellande_command(&["link", "--symbolic", "--target", "/loop", "--path", "/loop"])?;
+    assert_json_error(ctx, &["read", "--path", "/loop"], EXIT_LOOP)?;
+    ctx.run_bellande_command(&["create", "--mode", "000", "--path", "/locked"])?;
+    assert_json_error(
+        ctx,
+        &[
+            "--user", "1000", "--group", "100", "read", "--path", "/locked",
+        ],
+        EXIT_PERMISSION_DENIED,
+    )?;
+    assert_json_error(
+        ctx,
+        &[
+            "--user", "1000", "--group", "100", "chown", "--path", "/locked", "--uid", "1000",
+        ],
+        EXIT_NOT_PERMITTED,
+    )?;
+    assert_json_error(
+        ctx,
+        &["xattr", "get", "--path", "/dir", "--name", "user.missing"],
+        EXIT_NO_ATTRIBUTE,
+    )?;
+    ctx.run_bellande_command(&[
+        "quota", "set", "--dir", "/dir", "--blocks", "100", "--inodes", "1",
+    ])?;
+    assert_json_error(
+        ctx,
+        &["create", "--path", "/dir/over_quota"],
+        EXIT_QUOTA_EXCEEDED,
+    )?;
+    assert_json_error(ctx, &["list", "--bogus"], EXIT_USAGE)?;
+    Ok(())
+}
+
+pub(crate) fn output_option_selects_json(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/file.txt"])?;
+    assert!(write_file(ctx, "/file.txt", b"twelve bytes")?
+        .status
+        .success());
+
+    for args in [&["stats"][..], &["list", "--path", "/"][..]] {
+        let mut full = vec!["--output", "json"];
+        full.extend(args);
+        let output = ctx.run_bellande_command(&full)?;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            json_stdout(ctx, args)?,
+            "{:?}",
+            args
+        );
+        let mut full = vec!["--output", "text"];
+        full.extend(args);
+        let text = ctx.run_bellande_command(&full)?.stdout;
+        assert_eq!(text, ctx.run_bellande_command(args)?.stdout, "{:?}", args);
+    }
+
+    let json = json_stdout(ctx, &["fsck"])?;
+    assert_eq!(json_field(&json, "status").as_deref(), Some("clean"));
+    assert_eq!(json_u64(&json, "errors"), Some(0));
+
+    // The subcommand's own --output still names a host file
+    let host = ctx.temp_dir.path().join("read_back.txt");
+    ctx.run_bellande_command(&[
+        "--output",
+        "json",
+        "read",
+        "--path",
+        "/file.txt",
+        "--output",
+        &host.to_string_lossy(),
+    ])?;
+    assert_eq!(fs::read(&host)?, b"twelve bytes");
+
+    for value in ["yaml", "JSON", ""] {
+        ctx.command(&["--output", value, "stats"])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--output"));
+    }
+    Ok(())
+}
+
+pub(crate) fn no_space_reports_json(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/fill.bin"])?;
+    let free = read_stats(ctx)?.free_blocks as usize;
+    let data = vec![0xAB; (free + 8) * ctx.options.block_size.unwrap_or(1024) as usize];
+
+    let output = ctx
+        .command(&["--json", "write", "--path", "/fill.bin"])
+        .write_stdin(data)
+        .output()?;
+    assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(json_field(&stderr, "error").as_deref(), Some("no_space"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    stats_and_list_json,
+    #[contract]
+    mutations_report_json,
+    #[contract]
+    errors_report_json,
+    #[contract]
+    output_option_selects_json,
+    #[contract]
+    no_space_reports_json(tiny_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_distinct() {
+        for (index, (exit, name)) in ERROR_CODES.iter().enumerate() {
+            for (other_exit, other_name) in &ERROR_CODES[index + 1..] {
+                assert_ne!(exit, other_exit);
+                assert_ne!(name, other_name);
+            }
+        }
+        assert_eq!(error_code(EXIT_NOT_FOUND), "not_found");
+    }
+}
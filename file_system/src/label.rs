@@ -0,0 +1,212 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Filesystem label and UUID. `format --label` stores a label of up to
+// LABEL_MAX_BYTES bytes of UTF-8 and a freshly generated random UUID in the
+// superblock; `label` prints the label and `label --set` rewrites it in place.
+// `stats` shows both as `Label:` and `UUID:` lines. Over-long labels are
+// usage errors, never truncated.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+pub(crate) const LABEL_MAX_BYTES: usize = 32;
+
+// The text after `key:` on its own line of `stats` output
+fn stats_line(stdout: &str, key: &str) -> Option<String> {
+    stdout.lines().find_map(|line| {
+        let value = line.strip_prefix(key)?.strip_prefix(':')?;
+        Some(value.strip_prefix(' ').unwrap_or(value).to_string())
+    })
+}
+
+// Lowercase hyphenated form with the version 4 and RFC 4122 variant bits
+fn is_random_uuid(text: &str) -> bool {
+    let groups: Vec<&str> = text.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    lengths == [8, 4, 4, 4, 12]
+        && groups
+            .iter()
+            .all(|group| group.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')))
+        && groups[2].starts_with('4')
+        && groups[3].starts_with(['8', '9', 'a', 'b'])
+}
+
+struct Identity {
+    label: String,
+    uuid: String,
+    // Every other stats line, in order
+    rest: Vec<String>,
+}
+
+fn read_identity(ctx: &TestContext) -> io::Result<Identity> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let missing = |key: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Missing {:?} in stats output: {:?}", key, stdout),
+        )
+    };
+    Ok(Identity {
+        label: stats_line(&stdout, "Label").ok_or_else(|| missing("Label"))?,
+        uuid: stats_line(&stdout, "UUID").ok_or_else(|| missing("UUID"))?,
+        rest: stdout
+            .lines()
+            .filter(|line| !line.starts_with("Label:") && !line.starts_with("UUID:"))
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+fn printed_label(ctx: &TestContext) -> io::Result<String> {
+    let output = ctx.run_bellande_command(&["label"])?;
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "label is not UTF-8"))?;
+    Ok(stdout.strip_suffix('\n').unwrap_or(&stdout).to_string())
+}
+
+fn labelled_context(label: &str) -> io::Result<TestContext> {
+    Ok(TestContext::new()?.with_format_args(&["--label", label]))
+}
+
+// `ctx` formats with `--label mydata`
+pub(crate) fn label_and_uuid_recorded(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let identity = read_identity(ctx)?;
+    assert_eq!(identity.label, "mydata");
+    assert!(
+        is_random_uuid(&identity.uuid),
+        "bad UUID {:?}",
+        identity.uuid
+    );
+    assert_eq!(printed_label(ctx)?, "mydata");
+
+    // No --label gives the empty label, and every format a new UUID
+    let mut uuids = BTreeSet::from([identity.uuid]);
+    for _ in 0..3 {
+        ctx.run_bellande_command(&["format", "--yes"])?;
+        let identity = read_identity(ctx)?;
+        assert_eq!(identity.label, "");
+        assert_eq!(printed_label(ctx)?, "");
+        assert!(
+            is_random_uuid(&identity.uuid),
+            "bad UUID {:?}",
+            identity.uuid
+        );
+        assert!(uuids.insert(identity.uuid), "a reformat reused a UUID");
+    }
+    Ok(())
+}
+
+// `ctx` formats with `--label before`
+pub(crate) fn relabel_keeps_superblock(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/docs"])?;
+    ctx.run_bellande_command(&["create", "--path", "/docs/kept.bin"])?;
+    let data = content(1, 5000);
+    assert!(write_file(ctx, "/docs/kept.bin", &data)?.status.success());
+    let before = read_identity(ctx)?;
+
+    for label in ["after", "", "before"] {
+        ctx.run_bellande_command(&["label", "--set", label])?;
+        let after = read_identity(ctx)?;
+        assert_eq!(after.label, label);
+        assert_eq!(printed_label(ctx)?, label);
+        assert_eq!(after.uuid, before.uuid, "relabelling changed the UUID");
+        assert_eq!(after.rest, before.rest, "relabelling changed other fields");
+    }
+
+    let output = ctx.run_bellande_command(&["read", "--path", "/docs/kept.bin"])?;
+    assert!(output.stdout == data, "relabelling disturbed file data");
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn label_lengths(ctx: &TestContext) -> io::Result<()> {
+    // The limit is in bytes: 16 two-byte characters fit, 17 do not
+    let fits = "é".repeat(LABEL_MAX_BYTES / 2);
+    let too_long = "é".repeat(LABEL_MAX_BYTES / 2 + 1);
+    let too_long_ascii = "x".repeat(LABEL_MAX_BYTES + 1);
+
+    format_device(ctx)?;
+    for label in ["données-🦀", fits.as_str(), "with spaces", "x"] {
+        ctx.run_bellande_command(&["label", "--set", label])?;
+        assert_eq!(printed_label(ctx)?, label);
+        assert_eq!(read_identity(ctx)?.label, label);
+    }
+
+    let limit = LABEL_MAX_BYTES.to_string();
+    let device = fs::read(&ctx.device_path)?;
+    for label in [&too_long, &too_long_ascii] {
+        ctx.command(&["label", "--set", label])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains(limit.as_str()));
+        assert_eq!(printed_label(ctx)?, "x", "a rejected label was stored");
+
+        // Refused before anything is written, so the old filesystem survives
+        ctx.command(&["format", "--yes", "--label", label])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(
+                predicate::str::contains("--label").and(predicate::str::contains(limit.as_str())),
+            );
+    }
+    assert!(
+        fs::read(&ctx.device_path)? == device,
+        "a rejected label touched the device"
+    );
+
+    ctx.command(&["label", "--set"]).assert().code(EXIT_USAGE);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    label_and_uuid_recorded(labelled_context("mydata")?),
+    #[contract]
+    relabel_keeps_superblock(labelled_context("before")?),
+    #[contract]
+    label_lengths,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_line() {
+        let stdout = "Total blocks: 10\nLabel: \nUUID: 1234\nLabel x: no\n";
+        assert_eq!(stats_line(stdout, "Label").as_deref(), Some(""));
+        assert_eq!(stats_line("Label:\n", "Label").as_deref(), Some(""));
+        assert_eq!(stats_line(stdout, "UUID").as_deref(), Some("1234"));
+        assert_eq!(stats_line(stdout, "Free blocks"), None);
+    }
+
+    #[test]
+    fn test_is_random_uuid() {
+        assert!(is_random_uuid("3f2b8c1e-9a4d-4e7f-b123-0c9d8e7f6a5b"));
+        assert!(!is_random_uuid("3f2b8c1e-9a4d-1e7f-b123-0c9d8e7f6a5b"));
+        assert!(!is_random_uuid("3f2b8c1e-9a4d-4e7f-7123-0c9d8e7f6a5b"));
+        assert!(!is_random_uuid("3F2B8C1E-9A4D-4E7F-B123-0C9D8E7F6A5B"));
+        assert!(!is_random_uuid("3f2b8c1e9a4d4e7fb1230c9d8e7f6a5b"));
+    }
+}
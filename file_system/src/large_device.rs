@@ -0,0 +1,227 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Slow tier on an 8 GiB sparse device, meant to catch 32-bit truncation of
+// block numbers, offsets and sizes. Ignored unless run with `--ignored` or the
+// `slow-tests` feature, and skipped when the host cannot back the device.
+
+use crate::differential::content;
+use crate::fsck::assert_fsck_clean;
+use crate::golden::{checksum_update, CHECKSUM_INIT};
+use crate::harness::{format_device, TestContext};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const LARGE_DEVICE_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+const LARGE_BLOCK_SIZE: u32 = 4096;
+const OVER_4GIB: u64 = 4 * 1024 * 1024 * 1024 + 1024 * 1024;
+pub(crate) const CHUNK_LEN: usize = 1024 * 1024;
+// Head room for the filesystem's own metadata on top of the file data
+const HOST_SPACE_MARGIN: u64 = 512 * 1024 * 1024;
+
+// Deterministic stream of `len` bytes built from per-chunk seeds
+pub(crate) fn stream_chunk(seed: u64, index: u64, len: usize) -> Vec<u8> {
+    content(seed ^ index.wrapping_mul(0x9E37_79B9), len)
+}
+
+pub(crate) fn stream_checksum(seed: u64, len: u64) -> u64 {
+    let mut hash = CHECKSUM_INIT;
+    let mut done = 0;
+    let mut index = 0;
+    while done < len {
+        let chunk_len = (len - done).min(CHUNK_LEN as u64) as usize;
+        hash = checksum_update(hash, &stream_chunk(seed, index, chunk_len));
+        done += chunk_len as u64;
+        index += 1;
+    }
+    hash
+}
+
+// Writes a generated stream through stdin without holding it in memory
+pub(crate) fn write_stream(ctx: &TestContext, path: &str, seed: u64, len: u64) -> io::Result<()> {
+    let mut child = ctx.spawn(&["write", "--path", path], Stdio::piped(), Stdio::null())?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut done = 0;
+        let mut index = 0;
+        while done < len {
+            let chunk_len = (len - done).min(CHUNK_LEN as u64) as usize;
+            stdin.write_all(&stream_chunk(seed, index, chunk_len))?;
+            done += chunk_len as u64;
+            index += 1;
+        }
+    }
+
+    let output = child.wait()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Streaming write to {} failed: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(())
+}
+
+// Reads a file back through stdout, returning its length and checksum
+pub(crate) fn read_stream(ctx: &TestContext, path: &str) -> io::Result<(u64, u64)> {
+    let mut child = ctx.spawn(&["read", "--path", path], Stdio::null(), Stdio::piped())?;
+
+    let mut hash = CHECKSUM_INIT;
+    let mut len = 0;
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut buffer = vec![0u8; CHUNK_LEN];
+        loop {
+            let read = stdout.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hash = checksum_update(hash, &buffer[..read]);
+            len += read as u64;
+        }
+    }
+
+    let output = child.wait()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Streaming read of {} failed: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok((len, hash))
+}
+
+#[cfg(unix)]
+pub(crate) fn allocated_bytes(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(std::fs::metadata(path)?.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn allocated_bytes(path: &Path) -> io::Result<u64> {
+    Ok(std::fs::metadata(path)?.len())
+}
+
+// Free space on the filesystem holding `path`, if `df` can tell us
+fn host_free_bytes(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let available_kb: u64 = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(available_kb * 1024)
+}
+
+// Returns why the host cannot run the tier, if it cannot
+fn skip_reason(ctx: &TestContext) -> Option<String> {
+    match allocated_bytes(&ctx.device_path) {
+        Ok(allocated) if allocated < LARGE_DEVICE_SIZE / 2 => None,
+        Ok(allocated) => Some(format!(
+            "host does not support sparse files ({} bytes allocated for an empty {} byte device)",
+            allocated, LARGE_DEVICE_SIZE
+        )),
+        Err(e) => Some(format!("cannot inspect the backing file: {}", e)),
+    }
+}
+
+pub(crate) fn large_sparse_device() -> io::Result<()> {
+    let ctx = match TestContext::with_options(LARGE_DEVICE_SIZE, Some(LARGE_BLOCK_SIZE)) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            println!(
+                "Skipping large device tier: cannot create an 8 GiB backing file: {}",
+                e
+            );
+            return Ok(());
+        }
+    };
+    if let Some(reason) = skip_reason(&ctx) {
+        println!("Skipping large device tier: {}", reason);
+        return Ok(());
+    }
+
+    format_device(&ctx)?;
+
+    let mut files: Vec<(String, u64, u64)> = Vec::new();
+    let free = host_free_bytes(&ctx.device_path);
+    if free.is_some_and(|free| free > OVER_4GIB + HOST_SPACE_MARGIN) {
+        // Everything written after this file lives beyond the 4 GiB mark
+        ctx.run_bellande_command(&["create", "--path", "/over_4gib.bin"])?;
+        write_stream(&ctx, "/over_4gib.bin", 1, OVER_4GIB)?;
+        files.push(("/over_4gib.bin".to_string(), 1, OVER_4GIB));
+    } else {
+        println!(
+            "Skipping the >4 GiB file: host free space {:?} is below {} bytes",
+            free,
+            OVER_4GIB + HOST_SPACE_MARGIN
+        );
+    }
+
+    for index in 0..4u64 {
+        let path = format!("/tail{}.bin", index);
+        let len = 3 * CHUNK_LEN as u64 + 17 * index;
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        write_stream(&ctx, &path, index + 10, len)?;
+        files.push((path, index + 10, len));
+    }
+
+    for (path, seed, len) in &files {
+        let (actual_len, actual_checksum) = read_stream(&ctx, path)?;
+        assert_eq!(actual_len, *len, "length of {} was truncated", path);
+        assert_eq!(
+            actual_checksum,
+            stream_checksum(*seed, *len),
+            "checksum of {} does not match",
+            path
+        );
+    }
+    // Block numbers past 32 bits must be accounted for in the bitmaps too,
+    // once the binary can check them with `fsck`
+    if cfg!(feature = "contract-tests") {
+        assert_fsck_clean(&ctx)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_checksum_matches_chunks() {
+        let len = 2 * CHUNK_LEN as u64 + 5;
+        let mut hash = CHECKSUM_INIT;
+        for index in 0..3 {
+            let chunk_len = if index < 2 { CHUNK_LEN } else { 5 };
+            hash = checksum_update(hash, &stream_chunk(9, index, chunk_len));
+        }
+        assert_eq!(stream_checksum(9, len), hash);
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(feature = "slow-tests"),
+        ignore = "slow; run with cargo test -- --ignored or --features slow-tests"
+    )]
+    fn test_large_sparse_device() -> io::Result<()> {
+        large_sparse_device()
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Symbolic and hard links. `link --target T --path P --symbolic` makes P a
+// symlink inode holding T, absolute or relative to P's directory; without
+// `--symbolic` P becomes a second directory entry for the regular file T and
+// its link count goes up. `read` and `write` follow symlinks at every path
+// component unless `--no-follow` is given for the last one, giving up after
+// SYMLINK_DEPTH_LIMIT links with EXIT_LOOP. `stat` never follows, and reports
+// `Links:` for every inode and `Target:` for symlinks.
+
+use crate::differential::content;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_IS_DIRECTORY, EXIT_LOOP, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use predicates::prelude::*;
+use std::collections::BTreeMap;
+use std::io;
+
+// As on Linux: a chain this long resolves, one more link does not
+pub(crate) const SYMLINK_DEPTH_LIMIT: usize = 40;
+
+fn symlink(ctx: &TestContext, target: &str, path: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["link", "--symbolic", "--target", target, "--path", path])?;
+    Ok(())
+}
+
+fn hard_link(ctx: &TestContext, target: &str, path: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["link", "--target", target, "--path", path])?;
+    Ok(())
+}
+
+// `stat` as `Key: value` lines
+fn stat(ctx: &TestContext, path: &str) -> io::Result<BTreeMap<String, String>> {
+    let output = ctx.run_bellande_command(&["stat", "--path", path])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
+
+fn links(ctx: &TestContext, path: &str) -> io::Result<u64> {
+    let fields = stat(ctx, path)?;
+    fields
+        .get("Links")
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("stat {} has no link count: {:?}", path, fields),
+            )
+        })
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn symlinks_followed(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/v1"])?;
+    ctx.run_bellande_command(&["create", "--path", "/v1/config"])?;
+    let data = content(1, 3000);
+    assert!(write_file(ctx, "/v1/config", &data)?.status.success());
+
+    symlink(ctx, "/v1", "/current")?;
+    symlink(ctx, "/v1/config", "/config")?;
+    symlink(ctx, "v1/config", "/relative")?;
+    for path in ["/current/config", "/config", "/relative"] {
+        assert!(
+            read(ctx, path)? == data,
+            "{} did not reach /v1/config",
+            path
+        );
+    }
+
+    let fields = stat(ctx, "/config")?;
+    assert_eq!(fields["Type"], "symlink");
+    assert_eq!(fields["Target"], "/v1/config");
+    assert_eq!(fields["Size"], "/v1/config".len().to_string());
+    assert_eq!(stat(ctx, "/relative")?["Target"], "v1/config");
+    assert_eq!(stat(ctx, "/current/config")?["Type"], "file");
+    assert!(!stat(ctx, "/v1/config")?.contains_key("Target"));
+
+    // Writes land in the target; --no-follow reads the link itself
+    let update = content(2, 5000);
+    assert!(write_file(ctx, "/current/config", &update)?
+        .status
+        .success());
+    assert!(read(ctx, "/v1/config")? == update);
+    let output = ctx.run_bellande_command(&["read", "--no-follow", "--path", "/config"])?;
+    assert_eq!(output.stdout, b"/v1/config");
+    ctx.command(&["write", "--no-follow", "--path", "/config"])
+        .write_stdin(b"clobber".to_vec())
+        .assert()
+        .code(EXIT_LOOP);
+    assert!(read(ctx, "/v1/config")? == update);
+
+    // Removing a link leaves its target; a dangling link is an error to read
+    ctx.run_bellande_command(&["remove", "--path", "/config"])?;
+    assert!(read(ctx, "/v1/config")? == update);
+    symlink(ctx, "/nowhere", "/dangling")?;
+    assert_eq!(stat(ctx, "/dangling")?["Target"], "/nowhere");
+    ctx.command(&["read", "--path", "/dangling"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&[
+        "link",
+        "--symbolic",
+        "--target",
+        "/v1",
+        "--path",
+        "/current",
+    ])
+    .assert()
+    .code(EXIT_ALREADY_EXISTS);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn hard_links_share_inode(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = read_stats(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let with_dir = read_stats(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/a.txt"])?;
+    let data = content(3, 20_000);
+    assert!(write_file(ctx, "/a.txt", &data)?.status.success());
+    let with_file = read_stats(ctx)?;
+    assert_eq!(links(ctx, "/a.txt")?, 1);
+
+    hard_link(ctx, "/a.txt", "/dir/b.txt")?;
+    assert_eq!(read_stats(ctx)?, with_file, "a hard link allocated space");
+    assert_eq!(
+        stat(ctx, "/a.txt")?["Inode"],
+        stat(ctx, "/dir/b.txt")?["Inode"]
+    );
+    assert_eq!(links(ctx, "/a.txt")?, 2);
+    assert_eq!(links(ctx, "/dir/b.txt")?, 2);
+
+    let update = content(4, 200);
+    assert!(write_file(ctx, "/dir/b.txt", &update)?.status.success());
+    assert!(
+        read(ctx, "/a.txt")? == update,
+        "the links do not share data"
+    );
+    assert!(write_file(ctx, "/a.txt", &data)?.status.success());
+
+    // Blocks are freed with the last link only
+    ctx.run_bellande_command(&["remove", "--path", "/a.txt"])?;
+    assert_eq!(links(ctx, "/dir/b.txt")?, 1);
+    assert!(read(ctx, "/dir/b.txt")? == data);
+    assert_eq!(read_stats(ctx)?.free_blocks, with_file.free_blocks);
+    ctx.run_bellande_command(&["remove", "--path", "/dir/b.txt"])?;
+    assert_eq!(
+        read_stats(ctx)?,
+        with_dir,
+        "the last link did not free the file"
+    );
+
+    ctx.command(&["link", "--target", "/dir", "--path", "/dir2"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    ctx.command(&["link", "--target", "/missing", "--path", "/m"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.run_bellande_command(&["rmdir", "--path", "/dir"])?;
+    assert_eq!(read_stats(ctx)?, empty);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn symlink_loops_bounded(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/end"])?;
+    assert!(write_file(ctx, "/end", b"end\n")?.status.success());
+
+    symlink(ctx, "/self", "/self")?;
+    symlink(ctx, "/loop_b", "/loop_a")?;
+    symlink(ctx, "/loop_a", "/loop_b")?;
+    symlink(ctx, "/loop_a/x", "/through")?;
+    for path in ["/self", "/loop_a", "/through", "/self/child"] {
+        ctx.command(&["read", "--path", path])
+            .assert()
+            .code(EXIT_LOOP)
+            .stderr(predicate::str::contains(path));
+        ctx.command(&["write", "--path", path])
+            .write_stdin(b"x".to_vec())
+            .assert()
+            .code(EXIT_LOOP);
+    }
+
+    // chain_0 -> chain_1 -> ... -> /end
+    let chain = |index: usize| format!("/chain_{}", index);
+    symlink(ctx, "/end", &chain(SYMLINK_DEPTH_LIMIT))?;
+    for index in (0..SYMLINK_DEPTH_LIMIT).rev() {
+        symlink(ctx, &chain(index + 1), &chain(index))?;
+    }
+    assert_eq!(read(ctx, &chain(1))?, b"end\n");
+    ctx.command(&["read", "--path", &chain(0)])
+        .assert()
+        .code(EXIT_LOOP);
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    symlinks_followed,
+    #[contract]
+    hard_links_share_inode,
+    #[contract]
+    symlink_loops_bounded,
+}
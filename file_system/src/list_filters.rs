@@ -0,0 +1,404 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Filter flags for `list` and `find`: `--newer-than`/`--older-than` take an
+// RFC 3339 time or a relative age, `--larger-than`/`--smaller-than` a size,
+// `--type` f, d or l, and `--name` a glob over the basename (`*` and `?`);
+// several combine with AND. Every bound is strict. `find --json` prints an
+// array of path/type records instead of one path per line.
+// Mtimes are pinned with `touch --date` and the expected sets come from the
+// reference parsers in `times` and `sizes`.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::listed_names;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::json_records;
+use crate::sizes::parse_size;
+use crate::times::{format_rfc3339, now_seconds, parse_relative_time, parse_rfc3339};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::io;
+
+enum Age {
+    At(&'static str),
+    SecondsAgo(i64),
+}
+
+struct Node {
+    path: &'static str,
+    is_dir: bool,
+    len: usize,
+    age: Age,
+}
+
+// Directories come last so creating their children cannot move their mtimes
+const NODES: &[Node] = &[
+    Node {
+        path: "/old.log",
+        is_dir: false,
+        len: 100,
+        age: Age::At("2023-03-01T12:00:00Z"),
+    },
+    Node {
+        path: "/mid.bin",
+        is_dir: false,
+        len: 5000,
+        age: Age::At("2024-06-15T08:30:00Z"),
+    },
+    Node {
+        path: "/recent.txt",
+        is_dir: false,
+        len: 20,
+        age: Age::SecondsAgo(2 * 3600),
+    },
+    Node {
+        path: "/fresh.dat",
+        is_dir: false,
+        len: 200_000,
+        age: Age::SecondsAgo(10 * 60),
+    },
+    Node {
+        path: "/dir/inner.txt",
+        is_dir: false,
+        len: 3000,
+        age: Age::SecondsAgo(30 * 3600),
+    },
+    Node {
+        path: "/dir",
+        is_dir: true,
+        len: 0,
+        age: Age::SecondsAgo(3 * 86_400),
+    },
+];
+
+const FILTER_CASES: &[&[&str]] = &[
+    &[],
+    &["--type", "f"],
+    &["--type", "d"],
+    &["--type", "l"],
+    &["--newer-than", "1h"],
+    &["--newer-than", "1d"],
+    &["--newer-than", "2d"],
+    &["--older-than", "1d"],
+    &["--older-than", "2024-01-01T00:00:00Z"],
+    // Exactly mid.bin's mtime, which neither strict bound includes
+    &["--newer-than", "2024-06-15T08:30:00Z"],
+    &["--older-than", "2024-06-15T08:30:00Z"],
+    &["--newer-than", "2024-06-15T10:30:00+02:00"],
+    &["--newer-than", "2023-01-01T00:00:00Z", "--older-than", "1d"],
+    &["--type", "f", "--larger-than", "4K"],
+    &["--type", "f", "--smaller-than", "100"],
+    &[
+        "--type",
+        "f",
+        "--larger-than",
+        "20",
+        "--smaller-than",
+        "5000",
+    ],
+    &["--type", "f", "--larger-than", "1K", "--newer-than", "1d"],
+    &["--type", "d", "--older-than", "2d"],
+    &["--name", "*.log"],
+    &["--name", "*"],
+    &["--name", "?ld.*"],
+    &["--name", "dir"],
+    &["--name", "*.txt", "--type", "f"],
+    &["--name", "*.txt", "--newer-than", "1d"],
+    &["--name", "*.TXT"],
+];
+
+struct Filter {
+    newer_than: Option<i64>,
+    older_than: Option<i64>,
+    larger_than: Option<u64>,
+    smaller_than: Option<u64>,
+    kind: Option<char>,
+    name: Option<String>,
+}
+
+// `*` matches any run of characters and `?` exactly one
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+    matches(&pattern, &name)
+}
+
+fn parse_time(text: &str, now: i64) -> Option<i64> {
+    parse_rfc3339(text).or_else(|| Some(now - parse_relative_time(text)?.as_secs() as i64))
+}
+
+impl Filter {
+    fn from_args(args: &[&str], now: i64) -> Option<Filter> {
+        let mut filter = Filter {
+            newer_than: None,
+            older_than: None,
+            larger_than: None,
+            smaller_than: None,
+            kind: None,
+            name: None,
+        };
+        for pair in args.chunks(2) {
+            let [flag, value] = pair else {
+                return None;
+            };
+            match *flag {
+                "--newer-than" => filter.newer_than = Some(parse_time(value, now)?),
+                "--older-than" => filter.older_than = Some(parse_time(value, now)?),
+                "--larger-than" => filter.larger_than = Some(parse_size(value)?),
+                "--smaller-than" => filter.smaller_than = Some(parse_size(value)?),
+                "--type" => match *value {
+                    "f" | "d" | "l" => filter.kind = value.chars().next(),
+                    _ => return None,
+                },
+                "--name" if !value.is_empty() => filter.name = Some(value.to_string()),
+                _ => return None,
+            }
+        }
+        Some(filter)
+    }
+
+    fn keeps(&self, node: &Node, mtime: i64) -> bool {
+        let kind = if node.is_dir { 'd' } else { 'f' };
+        let len = node.len as u64;
+        self.kind.is_none_or(|wanted| wanted == kind)
+            && self.newer_than.is_none_or(|bound| mtime > bound)
+            && self.older_than.is_none_or(|bound| mtime < bound)
+            && self.larger_than.is_none_or(|bound| len > bound)
+            && self.smaller_than.is_none_or(|bound| len < bound)
+            && self.name.as_ref().is_none_or(|pattern| {
+                glob_matches(pattern, node.path.rsplit('/').next().unwrap_or(""))
+            })
+    }
+}
+
+fn mtime(node: &Node, now: i64) -> i64 {
+    match node.age {
+        Age::At(text) => parse_rfc3339(text).expect("valid fixture time"),
+        Age::SecondsAgo(seconds) => now - seconds,
+    }
+}
+
+fn populated(ctx: &TestContext, now: i64) -> io::Result<()> {
+    format_device(ctx)?;
+    for node in NODES.iter().filter(|node| node.is_dir) {
+        ctx.run_bellande_command(&["mkdir", "--path", node.path])?;
+    }
+    for node in NODES.iter().filter(|node| !node.is_dir) {
+        ctx.run_bellande_command(&["create", "--path", node.path])?;
+        let output = write_file(ctx, node.path, &vec![b'f'; node.len])?;
+        assert!(output.status.success(), "write {} failed", node.path);
+    }
+    for node in NODES {
+        let date = format_rfc3339(mtime(node, now));
+        ctx.run_bellande_command(&["touch", "--date", &date, "--path", node.path])?;
+    }
+    Ok(())
+}
+
+fn expected(args: &[&str], now: i64, top_level_only: bool) -> BTreeSet<String> {
+    let filter = Filter::from_args(args, now).expect("valid filter case");
+    NODES
+        .iter()
+        .filter(|node| !top_level_only || node.path.matches('/').count() == 1)
+        .filter(|node| filter.keeps(node, mtime(node, now)))
+        .map(|node| {
+            let path = node.path.to_string();
+            if top_level_only {
+                path.trim_start_matches('/').to_string()
+            } else {
+                path
+            }
+        })
+        .collect()
+}
+
+fn with_filters<'a>(base: &[&'a str], filters: &[&'a str]) -> Vec<&'a str> {
+    let mut args = base.to_vec();
+    args.extend_from_slice(filters);
+    args
+}
+
+pub(crate) fn find_filters_exact(ctx: &TestContext) -> io::Result<()> {
+    let now = now_seconds();
+    populated(ctx, now)?;
+
+    for filters in FILTER_CASES {
+        let output = ctx.run_bellande_command(&with_filters(&["find", "--path", "/"], filters))?;
+        let found: BTreeSet<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty() && *line != "/")
+            .map(str::to_string)
+            .collect();
+        assert_eq!(found, expected(filters, now, false), "find {:?}", filters);
+    }
+    Ok(())
+}
+
+pub(crate) fn list_filters_exact(ctx: &TestContext) -> io::Result<()> {
+    let now = now_seconds();
+    populated(ctx, now)?;
+
+    for filters in FILTER_CASES {
+        let output = ctx.run_bellande_command(&with_filters(&["list", "--path", "/"], filters))?;
+        let listed = listed_names(&String::from_utf8_lossy(&output.stdout));
+        assert_eq!(listed, expected(filters, now, true), "list {:?}", filters);
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid_filters_rejected(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for (flag, value) in [
+        ("--newer-than", "5"),
+        ("--newer-than", "yesterday"),
+        ("--older-than", "2024-02-30T00:00:00Z"),
+        ("--larger-than", "4Q"),
+        ("--smaller-than", "-1"),
+        ("--type", "x"),
+        ("--name", ""),
+    ] {
+        for command in ["list", "find"] {
+            ctx.command(&[command, flag, value, "--path", "/"])
+                .assert()
+                .code(EXIT_USAGE)
+                .stderr(predicate::str::contains(flag));
+        }
+    }
+    Ok(())
+}
+
+// Paths under `--path` only, and the same matches as records under --json
+pub(crate) fn find_json_and_subtrees(ctx: &TestContext) -> io::Result<()> {
+    let now = now_seconds();
+    populated(ctx, now)?;
+
+    let output = ctx.run_bellande_command(&["find", "--path", "/dir", "--name", "*.txt"])?;
+    let found: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(found, ["/dir/inner.txt"], "find below /dir escaped it");
+
+    for filters in FILTER_CASES {
+        let args = with_filters(&["--json", "find", "--path", "/"], filters);
+        let output = ctx.run_bellande_command(&args)?;
+        let json = String::from_utf8_lossy(&output.stdout);
+        let json = json.trim();
+        assert!(
+            json.starts_with('[') && json.ends_with(']'),
+            "find --json {:?} is not an array: {:?}",
+            filters,
+            json
+        );
+        let mut found = BTreeSet::new();
+        for record in json_records(json) {
+            let path = record.get("path").cloned().unwrap_or_default();
+            let wanted = if NODES.iter().any(|node| node.is_dir && node.path == path) {
+                "directory"
+            } else {
+                "file"
+            };
+            assert_eq!(
+                record.get("type").map(String::as_str),
+                Some(wanted),
+                "find --json {:?}: type of {}",
+                filters,
+                path
+            );
+            found.insert(path);
+        }
+        found.remove("/");
+        assert_eq!(
+            found,
+            expected(filters, now, false),
+            "find --json {:?}",
+            filters
+        );
+    }
+
+    ctx.command(&["find", "--path", "/missing", "--name", "*"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    find_filters_exact,
+    #[contract]
+    list_filters_exact,
+    #[contract]
+    invalid_filters_rejected,
+    #[contract]
+    find_json_and_subtrees,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_model() {
+        let now = parse_rfc3339("2025-01-10T00:00:00Z").unwrap();
+        let recent: BTreeSet<String> = ["/fresh.dat", "/recent.txt"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        assert_eq!(expected(&["--newer-than", "1d"], now, false), recent);
+        assert!(expected(&["--type", "l"], now, false).is_empty());
+        assert!(
+            !expected(&["--newer-than", "2024-06-15T08:30:00Z"], now, false).contains("/mid.bin")
+        );
+        assert!(
+            !expected(&["--older-than", "2024-06-15T08:30:00Z"], now, false).contains("/mid.bin")
+        );
+        assert_eq!(
+            expected(&["--type", "d"], now, true),
+            ["dir".to_string()].into()
+        );
+        assert!(Filter::from_args(&["--type", "x"], now).is_none());
+        assert!(Filter::from_args(&["--newer-than"], now).is_none());
+        for filters in FILTER_CASES {
+            assert!(Filter::from_args(filters, now).is_some(), "{:?}", filters);
+        }
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.log", "old.log"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("?ld.*", "old.log"));
+        assert!(glob_matches("a*b*c", "abxbc"));
+        assert!(!glob_matches("?ld.*", "ld.log"));
+        assert!(!glob_matches("*.TXT", "recent.txt"));
+        assert!(!glob_matches("dir", "dir2"));
+        let now = parse_rfc3339("2025-01-10T00:00:00Z").unwrap();
+        let text: BTreeSet<String> = ["/dir/inner.txt", "/recent.txt"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        assert_eq!(expected(&["--name", "*.txt"], now, false), text);
+        assert!(Filter::from_args(&["--name", ""], now).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `list --long` and `list --recursive`. The long form is a table headed by
+// its column names, one row per entry with type, size and modification time
+// and the name last. The recursive form prints every entry below the path as
+// a full path, depth first with siblings in byte order, so runs can be diffed.
+
+use crate::differential::content;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::json_u64;
+use crate::times::parse_rfc3339;
+use std::collections::BTreeMap;
+use std::io;
+
+const DEEP_LEVELS: usize = 10;
+const FILES_PER_LEVEL: usize = 20;
+const CHAIN_DEPTH: usize = 200;
+
+fn stdout_of(ctx: &TestContext, args: &[&str]) -> io::Result<String> {
+    let output = ctx.run_bellande_command(args)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Rows keyed by the lowercased header names; the last column takes the rest
+// of the line so names may contain spaces
+fn long_rows(stdout: &str) -> Vec<BTreeMap<String, String>> {
+    let mut lines = stdout.lines().filter(|line| !line.trim().is_empty());
+    let columns: Vec<String> = match lines.next() {
+        Some(header) => header
+            .split_whitespace()
+            .map(|column| column.to_ascii_lowercase())
+            .collect(),
+        None => return Vec::new(),
+    };
+    lines
+        .map(|line| {
+            let mut rest = line.trim_start();
+            let mut row = BTreeMap::new();
+            for (index, column) in columns.iter().enumerate() {
+                let value = if index + 1 == columns.len() {
+                    rest
+                } else {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    let (value, tail) = rest.split_at(end);
+                    rest = tail.trim_start();
+                    value
+                };
+                row.insert(column.clone(), value.to_string());
+            }
+            row
+        })
+        .collect()
+}
+
+// Full paths from a recursive listing, in printed order
+fn recursive_paths(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .map(|line| line.split(" (inode").next().unwrap_or("").trim_end())
+        .filter(|path| !path.is_empty() && !path.ends_with(':'))
+        .map(|path| path.to_string())
+        .collect()
+}
+
+// The order a recursive listing must follow: depth first, a directory
+// before its contents, siblings in byte order
+fn walk_order(paths: &[String]) -> Vec<String> {
+    fn visit(dir: &str, paths: &[String], out: &mut Vec<String>) {
+        let prefix = if dir == "/" {
+            "/".to_string()
+        } else {
+            format!("{}/", dir)
+        };
+        let mut children: Vec<&String> = paths
+            .iter()
+            .filter(|path| {
+                path.strip_prefix(&prefix)
+                    .is_some_and(|rest| !rest.is_empty() && !rest.contains('/'))
+            })
+            .collect();
+        children.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        for child in children {
+            out.push(child.clone());
+            visit(child, paths, out);
+        }
+    }
+    let mut out = Vec::new();
+    visit("/", paths, &mut out);
+    out
+}
+
+pub(crate) fn long_format_columns(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let files = [("a.txt", 0), ("b.bin", 5000), ("with space.txt", 12)];
+    for (name, len) in files {
+        let path = format!("/{}", name);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        assert!(write_file(ctx, &path, &content(len as u64, len))?
+            .status
+            .success());
+    }
+
+    let rows = long_rows(&stdout_of(ctx, &["list", "--long", "--path", "/"])?);
+    let names: Vec<&str> = rows.iter().map(|row| row["name"].as_str()).collect();
+    assert_eq!(
+        names,
+        ["a.txt", "b.bin", "dir", "with space.txt"],
+        "long rows"
+    );
+
+    for row in &rows {
+        let path = format!("/{}", row["name"]);
+        let stat = stdout_of(ctx, &["stat", "--format", "json", "--path", &path])?;
+        let expected_type = if row["name"] == "dir" {
+            "directory"
+        } else {
+            "file"
+        };
+        assert_eq!(row["type"], expected_type, "{}", path);
+        if expected_type == "file" {
+            assert_eq!(
+                row["size"].parse::<u64>().ok(),
+                json_u64(&stat, "size"),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            parse_rfc3339(&row["mtime"]),
+            json_u64(&stat, "mtime").map(|mtime| mtime as i64),
+            "{} mtime",
+            path
+        );
+    }
+
+    // An empty directory is an empty listing, not an error
+    let rows = long_rows(&stdout_of(ctx, &["list", "--long", "--path", "/dir"])?);
+    assert!(rows.is_empty(), "rows for an empty directory: {:?}", rows);
+    Ok(())
+}
+
+pub(crate) fn recursive_listing_order(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let mut paths = Vec::new();
+    let mut dir = String::new();
+    for level in 0..DEEP_LEVELS {
+        dir.push_str(&format!("/level{}", level));
+        ctx.run_bellande_command(&["mkdir", "--path", &dir])?;
+        paths.push(dir.clone());
+        // Names chosen so byte order differs from naive string sorts
+        for index in 0..FILES_PER_LEVEL {
+            let path = format!(
+                "{}/{}{}",
+                dir,
+                ["B", "a", "a-", "a.b", "_"][index % 5],
+                index
+            );
+            ctx.run_bellande_command(&["create", "--path", &path])?;
+            paths.push(path);
+        }
+    }
+    ctx.run_bellande_command(&["mkdir", "--path", "/empty"])?;
+    paths.push("/empty".to_string());
+
+    let stdout = stdout_of(ctx, &["list", "--recursive", "--path", "/"])?;
+    assert_eq!(recursive_paths(&stdout), walk_order(&paths));
+    assert_eq!(
+        stdout_of(ctx, &["list", "--recursive", "--path", "/"])?,
+        stdout,
+        "recursive listing is not deterministic"
+    );
+
+    // A subtree lists with full paths too
+    let subtree = stdout_of(ctx, &["list", "--recursive", "--path", "/level0/level1"])?;
+    let expected: Vec<String> = walk_order(&paths)
+        .into_iter()
+        .filter(|path| path.starts_with("/level0/level1/"))
+        .collect();
+    assert_eq!(recursive_paths(&subtree), expected);
+
+    assert!(recursive_paths(&stdout_of(
+        ctx,
+        &["list", "--recursive", "--path", "/empty"]
+    )?)
+    .is_empty());
+    Ok(())
+}
+
+pub(crate) fn recursive_deep_chain(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let chain: String = (0..CHAIN_DEPTH)
+        .map(|level| format!("/d{}", level))
+        .collect();
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", &chain])?;
+
+    let listed = recursive_paths(&stdout_of(ctx, &["list", "--recursive", "--path", "/"])?);
+    assert_eq!(listed.len(), CHAIN_DEPTH);
+    assert_eq!(listed.last(), Some(&chain));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    long_format_columns,
+    #[contract]
+    recursive_listing_order,
+    #[contract]
+    recursive_deep_chain,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_rows() {
+        let stdout = "TYPE       SIZE  MTIME                 NAME\n\
+                      file       12    2024-01-02T03:04:05Z  with space.txt\n\
+                      directory  0     2024-01-02T03:04:05Z  dir\n";
+        let rows = long_rows(stdout);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["name"], "with space.txt");
+        assert_eq!(rows[0]["size"], "12");
+        assert_eq!(rows[1]["type"], "directory");
+        assert!(long_rows("").is_empty());
+        assert!(long_rows("TYPE SIZE MTIME NAME\n").is_empty());
+    }
+
+    #[test]
+    fn test_walk_order() {
+        let paths: Vec<String> = ["/b", "/a/z", "/a-", "/a", "/a/B"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        assert_eq!(walk_order(&paths), ["/a", "/a/B", "/a/z", "/a-", "/b"]);
+        assert_eq!(
+            recursive_paths("/a (inode 3)\n/a/B (inode 4)\n\n"),
+            ["/a", "/a/B"]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Cursor-based paging for `list`. `--limit N` prints at most N entries and
+// `--start-after NAME` starts with the first name after NAME, which need not
+// exist any more, so a listing can be walked a page at a time with the last
+// name of each page as the next cursor; a short page is the last one. Paged
+// listings come in byte order of name, without `.` and `..`, and filters
+// apply before the limit. The directory is streamed rather than read into
+// memory, so pages of a huge directory stay cheap; `--json` keeps printing
+// an array of records.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::listed_names;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::json::json_records;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const HUGE_DIR_ENTRIES: usize = 20_000;
+const HUGE_DEVICE_SIZE: u64 = 64 * 1024 * 1024;
+const HUGE_PAGE: usize = 1000;
+const PAGE_LIMITS: &[usize] = &[1, 3, 7, 1000];
+// Byte order puts digits before uppercase before lowercase before non-ASCII
+const NAMES: &[&str] = &[
+    "alpha", "Beta", "beta", "beta.txt", "gamma", "0zero", "9nine", "_under", "zeta", "éclair",
+    "mid", "mid1", "mid10", "mid2",
+];
+
+fn pages_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn huge_context() -> io::Result<TestContext> {
+    TestContext::with_options(HUGE_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// The page the binary should print for a directory holding `names`
+fn expected_page(names: &BTreeSet<String>, start_after: Option<&str>, limit: usize) -> Vec<String> {
+    names
+        .iter()
+        .filter(|name| start_after.is_none_or(|cursor| name.as_str() > cursor))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+fn page(
+    ctx: &TestContext,
+    dir: &str,
+    extra: &[&str],
+    start_after: Option<&str>,
+    limit: usize,
+) -> io::Result<Vec<String>> {
+    let limit_arg = limit.to_string();
+    let mut args = vec!["list", "--path", dir, "--limit", &limit_arg];
+    if let Some(cursor) = start_after {
+        args.extend(["--start-after", cursor]);
+    }
+    args.extend(extra);
+    let output = ctx.run_bellande_command(&args)?;
+    let names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.split(" (inode").next().unwrap_or("").to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    assert!(
+        names.len() <= limit,
+        "{:?} printed {} entries",
+        args,
+        names.len()
+    );
+    Ok(names)
+}
+
+// Every name in `dir`, a page at a time, checking each page is in order
+fn walk(ctx: &TestContext, dir: &str, limit: usize) -> io::Result<Vec<String>> {
+    let mut all: Vec<String> = Vec::new();
+    loop {
+        let names = page(ctx, dir, &[], all.last().map(String::as_str), limit)?;
+        assert!(
+            names.windows(2).all(|pair| pair[0] < pair[1]),
+            "page of {} is not in byte order: {:?}",
+            dir,
+            names
+        );
+        if let (Some(last), Some(first)) = (all.last(), names.first()) {
+            assert!(first > last, "page after {:?} starts at {:?}", last, first);
+        }
+        let done = names.len() < limit;
+        all.extend(names);
+        if done {
+            return Ok(all);
+        }
+    }
+}
+
+fn populate(ctx: &TestContext, dir: &str, names: &[&str]) -> io::Result<()> {
+    ctx.run_bellande_command(&["mkdir", "--path", dir])?;
+    for name in names {
+        ctx.run_bellande_command(&["create", "--path", &format!("{}/{}", dir, name)])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn pages_cover_directory(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    populate(ctx, "/dir", NAMES)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir/subdir"])?;
+    let names: BTreeSet<String> = NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(["subdir".to_string()])
+        .collect();
+    let output = ctx.run_bellande_command(&["list", "--path", "/dir"])?;
+    assert_eq!(
+        listed_names(&String::from_utf8_lossy(&output.stdout)),
+        names
+    );
+
+    for &limit in PAGE_LIMITS {
+        assert_eq!(
+            walk(ctx, "/dir", limit)?,
+            names.iter().cloned().collect::<Vec<_>>(),
+            "pages of {}",
+            limit
+        );
+    }
+
+    // Cursors that are not names in the directory
+    for cursor in ["m", "mid0", "Zz", "zzz"] {
+        assert_eq!(
+            page(ctx, "/dir", &[], Some(cursor), 3)?,
+            expected_page(&names, Some(cursor), 3),
+            "after {:?}",
+            cursor
+        );
+    }
+    assert_eq!(page(ctx, "/dir", &[], Some("zeta"), 5)?, ["éclair"]);
+    assert!(page(ctx, "/dir", &[], Some("éclair"), 5)?.is_empty());
+
+    // Filters apply before the limit
+    let dirs = page(ctx, "/dir", &["--type", "d"], None, 5)?;
+    assert_eq!(dirs, ["subdir"]);
+    let globbed = page(ctx, "/dir", &["--name", "mid*"], Some("mid1"), 2)?;
+    assert_eq!(globbed, ["mid10", "mid2"]);
+
+    let output = ctx.run_bellande_command(&[
+        "--json",
+        "list",
+        "--path",
+        "/dir",
+        "--limit",
+        "2",
+        "--start-after",
+        "beta",
+    ])?;
+    let records = json_records(&String::from_utf8_lossy(&output.stdout));
+    let json_names: Vec<&str> = records
+        .iter()
+        .map(|record| record["name"].as_str())
+        .collect();
+    assert_eq!(json_names, expected_page(&names, Some("beta"), 2));
+    Ok(())
+}
+
+pub(crate) fn cursor_survives_changes(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    populate(ctx, "/dir", NAMES)?;
+    let mut names: BTreeSet<String> = NAMES.iter().map(|name| name.to_string()).collect();
+    let first = page(ctx, "/dir", &[], None, 5)?;
+    assert_eq!(first, expected_page(&names, None, 5));
+    let cursor = first.last().cloned().unwrap_or_default();
+
+    // The cursor's own entry goes away, and names appear on both sides of it
+    ctx.run_bellande_command(&["remove", "--path", &format!("/dir/{}", cursor)])?;
+    names.remove(&cursor);
+    for name in ["Aardvark", "mia", "zz_last"] {
+        ctx.run_bellande_command(&["create", "--path", &format!("/dir/{}", name)])?;
+        names.insert(name.to_string());
+    }
+    let rest = page(ctx, "/dir", &[], Some(&cursor), NAMES.len())?;
+    assert_eq!(rest, expected_page(&names, Some(&cursor), NAMES.len()));
+    assert!(!rest.contains(&"Aardvark".to_string()));
+    assert!(rest.contains(&"zz_last".to_string()));
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn huge_directory_pages(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    // Populated in one import rather than 20,000 invocations
+    let host = ctx.temp_dir.path().join("huge");
+    fs::create_dir_all(&host)?;
+    for index in 0..HUGE_DIR_ENTRIES {
+        fs::write(host.join(format!("file{:05}", index)), b"")?;
+    }
+    ctx.run_bellande_command(&["import", "--from", &host.to_string_lossy(), "--to", "/huge"])?;
+
+    let names = walk(ctx, "/huge", HUGE_PAGE)?;
+    assert_eq!(names.len(), HUGE_DIR_ENTRIES);
+    assert!(names
+        .iter()
+        .enumerate()
+        .all(|(index, name)| *name == format!("file{:05}", index)));
+
+    // A page deep inside the directory, and one past its end
+    let deep = page(ctx, "/huge", &[], Some("file17499"), 3)?;
+    assert_eq!(deep, ["file17500", "file17501", "file17502"]);
+    assert!(page(ctx, "/huge", &[], Some("file19999"), 10)?.is_empty());
+
+    // Changes between pages still leave every page in order
+    ctx.run_bellande_command(&["create", "--path", "/huge/file10000a"])?;
+    let after = page(ctx, "/huge", &[], Some("file10000"), 2)?;
+    assert_eq!(after, ["file10000a", "file10001"]);
+    Ok(())
+}
+
+pub(crate) fn paging_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    populate(ctx, "/dir", &["a", "b"])?;
+    for args in [
+        &["list", "--path", "/dir", "--limit", "0"][..],
+        &["list", "--path", "/dir", "--limit", "-1"],
+        &["list", "--path", "/dir", "--limit", "many"],
+        &["list", "--path", "/dir", "--limit"],
+        &["list", "--path", "/dir", "--start-after", ""],
+        &["list", "--path", "/dir", "--start-after", "a/b"],
+        &["list", "--path", "/dir", "--start-after"],
+        // A cursor names one directory's entries, not a whole tree's
+        &["list", "--recursive", "--path", "/", "--limit", "5"],
+        &["list", "--recursive", "--path", "/", "--start-after", "a"],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    ctx.command(&["list", "--path", "/missing", "--limit", "5"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    pages_cover_directory(pages_context()?),
+    #[contract]
+    cursor_survives_changes(pages_context()?),
+    #[contract]
+    huge_directory_pages(huge_context()?),
+    #[contract]
+    paging_errors(pages_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_page() {
+        let names: BTreeSet<String> = NAMES.iter().map(|name| name.to_string()).collect();
+        assert_eq!(expected_page(&names, None, 3), ["0zero", "9nine", "Beta"]);
+        assert_eq!(expected_page(&names, Some("mid1"), 2), ["mid10", "mid2"]);
+        assert_eq!(expected_page(&names, Some("zeta"), 5), ["éclair"]);
+        assert!(expected_page(&names, Some("éclair"), 5).is_empty());
+        assert_eq!(expected_page(&names, Some("m"), 100).len(), 6);
+    }
+}
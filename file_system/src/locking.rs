@@ -0,0 +1,369 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Device locking. Every command locks the device file for as long as it has
+// it open: exclusively for anything that can modify it, shared for `list`,
+// `read`, `stat` and `stats`. A command that cannot get its lock fails with
+// EXIT_DEVICE_BUSY and DEVICE_BUSY_MESSAGE, or with `--wait-lock SECONDS`
+// blocks up to that long first, printing WAITING_MESSAGE on stderr as it
+// starts to wait. The held locks come from std's `File::lock`,
+// which is flock on Unix and LockFileEx on Windows, like the binary's.
+//
+// The lock does not travel with a copied image, so writers also record
+// themselves in the superblock's mount state for as long as they run, and
+// `stats` prints "Mount state: clean" or "Mount state: in use by pid N".
+// A state left by a process that has since died is cleared by the next
+// command on the same host; one naming a live process makes commands exit
+// EXIT_DEVICE_BUSY naming the pid, unless the global `--force` clears it.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    command_timeout, format_device, read_stats, scenarios, write_file, TestContext,
+};
+use crate::journal::FAIL_AFTER_ENV;
+use predicates::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) const EXIT_DEVICE_BUSY: i32 = 16;
+pub(crate) const DEVICE_BUSY_MESSAGE: &str = "Device is in use by another process";
+const WAITING_MESSAGE: &str = "Waiting for the device lock";
+
+const READ_ONLY: &[&[&str]] = &[
+    &["list", "--path", "/"],
+    &["read", "--path", "/held.txt"],
+    &["stat", "--path", "/held.txt"],
+    &["stats"],
+];
+const MUTATING: &[&[&str]] = &[
+    &["create", "--path", "/new.txt"],
+    &["mkdir", "--path", "/new_dir"],
+    &["remove", "--path", "/held.txt"],
+    &["format", "--yes"],
+];
+
+const WRITERS: usize = 8;
+const WRITER_FILE_LEN: usize = 40_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn assert_busy(ctx: &TestContext, args: &[&str]) {
+    ctx.command(args)
+        .assert()
+        .code(EXIT_DEVICE_BUSY)
+        .stderr(predicate::str::contains(DEVICE_BUSY_MESSAGE));
+}
+
+fn spawn(ctx: &TestContext, args: &[&str]) -> io::Result<Child> {
+    Command::new(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+}
+
+fn held_state(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/held.txt"])?;
+    assert!(write_file(ctx, "/held.txt", b"locked\n")?.status.success());
+    Ok(())
+}
+
+pub(crate) fn exclusive_lock_blocks_everything(ctx: &TestContext) -> io::Result<()> {
+    held_state(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+
+    let device = File::open(&ctx.device_path)?;
+    device.lock()?;
+    for args in READ_ONLY.iter().chain(MUTATING) {
+        assert_busy(ctx, args);
+    }
+    device.unlock()?;
+
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a refused command wrote"
+    );
+    for args in READ_ONLY {
+        ctx.run_bellande_command(args)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn shared_lock_allows_readers(ctx: &TestContext) -> io::Result<()> {
+    held_state(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+
+    let device = File::open(&ctx.device_path)?;
+    device.lock_shared()?;
+    for args in READ_ONLY {
+        ctx.run_bellande_command(args)?;
+    }
+    for args in MUTATING {
+        assert_busy(ctx, args);
+    }
+    // Readers also share with each other
+    let readers: Vec<Child> = (0..4)
+        .map(|_| spawn(ctx, &["read", "--path", "/held.txt"]))
+        .collect::<io::Result<_>>()?;
+    for reader in readers {
+        let output = reader.wait_with_output()?;
+        assert!(
+            output.status.success(),
+            "concurrent reader failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    device.unlock()?;
+
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a refused command wrote"
+    );
+    Ok(())
+}
+
+pub(crate) fn wait_lock(ctx: &TestContext) -> io::Result<()> {
+    held_state(ctx)?;
+
+    // Released once the command has started waiting, so it goes ahead
+    let device = File::open(&ctx.device_path)?;
+    device.lock()?;
+    let mut child = spawn(
+        ctx,
+        &["--wait-lock", "30", "create", "--path", "/waited.txt"],
+    )?;
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped"));
+    let mut line = String::new();
+    while !line.contains(WAITING_MESSAGE) {
+        line.clear();
+        if stderr.read_line(&mut line)? == 0 {
+            let status = child.wait()?;
+            return Err(io::Error::other(format!(
+                "--wait-lock exited {} without waiting",
+                status
+            )));
+        }
+    }
+    device.unlock()?;
+    let status = child.wait()?;
+    let mut rest = String::new();
+    stderr.read_to_string(&mut rest)?;
+    assert!(status.success(), "--wait-lock gave up: {}", rest);
+    ctx.command(&["stat", "--path", "/waited.txt"])
+        .assert()
+        .success();
+
+    // Held for longer than the wait, so it times out with the same error
+    device.lock()?;
+    let started = Instant::now();
+    assert_busy(
+        ctx,
+        &["--wait-lock", "1", "create", "--path", "/timed_out.txt"],
+    );
+    assert!(started.elapsed() >= Duration::from_secs(1), "gave up early");
+    assert_busy(ctx, &["--wait-lock", "0", "stats"]);
+    device.unlock()?;
+
+    for value in ["-1", "soon", ""] {
+        ctx.command(&["--wait-lock", value, "stats"])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--wait-lock"));
+    }
+    Ok(())
+}
+
+pub(crate) fn concurrent_writers_serialised(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let before = read_stats(ctx)?;
+
+    let inputs: Vec<_> = (0..WRITERS)
+        .map(|index| {
+            let input = ctx.temp_dir.path().join(format!("writer_{}.bin", index));
+            fs::write(&input, content(index as u64, WRITER_FILE_LEN))?;
+            Ok(input)
+        })
+        .collect::<io::Result<_>>()?;
+
+    // Each writer creates and fills its own file, all started at once
+    let writers: Vec<Child> = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let path = format!("/writer_{}.bin", index);
+            let script = format!(
+                "\"$0\" --device \"$1\" --wait-lock 60 create --path {path} && \
+                 \"$0\" --device \"$1\" --wait-lock 60 write --path {path} --input \"$2\""
+            );
+            Command::new("sh")
+                .args(["-c", &script])
+                .arg(&ctx.binary_path)
+                .arg(&ctx.device_path)
+                .arg(input)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+        })
+        .collect::<io::Result<_>>()?;
+    for writer in writers {
+        let output = writer.wait_with_output()?;
+        assert!(
+            output.status.success(),
+            "concurrent writer failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    assert_fsck_clean(ctx)?;
+    for index in 0..WRITERS {
+        let path = format!("/writer_{}.bin", index);
+        let output = ctx.run_bellande_command(&["read", "--path", &path])?;
+        assert!(
+            output.stdout == content(index as u64, WRITER_FILE_LEN),
+            "{} was cross-linked or lost",
+            path
+        );
+    }
+    let after = read_stats(ctx)?;
+    assert_eq!(before.free_inodes - after.free_inodes, WRITERS as u64);
+    Ok(())
+}
+
+fn mount_state(ctx: &TestContext, args: &[&str]) -> io::Result<String> {
+    let mut full = args.to_vec();
+    full.push("stats");
+    let output = ctx.run_bellande_command(&full)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Mount state: "))
+        .unwrap_or_else(|| panic!("stats has no mount state: {:?}", stdout))
+        .to_string())
+}
+
+pub(crate) fn mount_state_tracked(ctx: &TestContext) -> io::Result<()> {
+    held_state(ctx)?;
+    assert_eq!(mount_state(ctx, &[])?, "clean");
+
+    // A writer that dies leaves its state behind
+    let crashed = Command::new(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(["create", "--path", "/crashed.txt"])
+        .env(FAIL_AFTER_ENV, "1")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let pid = crashed.id();
+    assert!(!crashed.wait_with_output()?.status.success());
+    assert_eq!(
+        mount_state(ctx, &["--read-only"])?,
+        format!("in use by pid {}", pid)
+    );
+
+    // Its process is gone, so the next command takes over without --force
+    assert_eq!(mount_state(ctx, &[])?, "clean");
+    assert_fsck_clean(ctx)
+}
+
+// Copies the device until the copy carries `pid`'s mount state, since the
+// writer records it some time after it starts
+fn copy_in_use(ctx: &TestContext, pid: &str) -> io::Result<TestContext> {
+    let started = Instant::now();
+    loop {
+        let copy = TestContext::from_image(&ctx.device_path)?;
+        let output = copy.run_raw(&["stats"])?;
+        if output.status.code() == Some(EXIT_DEVICE_BUSY)
+            && String::from_utf8_lossy(&output.stderr).contains(pid)
+        {
+            return Ok(copy);
+        }
+        if started.elapsed() > command_timeout() {
+            return Err(io::Error::other(
+                "the writer never recorded its mount state",
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+pub(crate) fn live_mount_state_needs_force(ctx: &TestContext) -> io::Result<()> {
+    held_state(ctx)?;
+
+    // Copied while a writer is still waiting for its input
+    let mut writer = Command::new(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(["write", "--append", "--path", "/held.txt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let pid = writer.id().to_string();
+    let copy = copy_in_use(ctx, &pid)?;
+
+    let before = fs::read(&copy.device_path)?;
+    for args in [&["stats"][..], &["create", "--path", "/new.txt"][..]] {
+        copy.command(args).assert().code(EXIT_DEVICE_BUSY).stderr(
+            predicate::str::contains(pid.as_str()).and(predicate::str::contains("--force")),
+        );
+    }
+    assert!(
+        fs::read(&copy.device_path)? == before,
+        "a refused command wrote"
+    );
+
+    copy.run_bellande_command(&["--force", "create", "--path", "/new.txt"])?;
+    assert_eq!(mount_state(&copy, &[])?, "clean");
+    assert_fsck_clean(&copy)?;
+
+    // The original writer finishes undisturbed
+    if let Some(mut stdin) = writer.stdin.take() {
+        stdin.write_all(b"appended\n")?;
+    }
+    let output = writer.wait_with_output()?;
+    assert!(
+        output.status.success(),
+        "writer failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = ctx.run_bellande_command(&["read", "--path", "/held.txt"])?;
+    assert_eq!(output.stdout, b"locked\nappended\n");
+    assert_eq!(mount_state(ctx, &[])?, "clean");
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    exclusive_lock_blocks_everything,
+    #[contract]
+    shared_lock_allows_readers,
+    #[contract]
+    wait_lock,
+    #[contract]
+    mount_state_tracked,
+    #[contract]
+    live_mount_state_needs_force,
+    #[contract]
+    concurrent_writers_serialised,
+}
@@ -0,0 +1,225 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Diagnostics. `--log-level error|warn|info|debug|trace` (default warn) sets
+// what goes to stderr; stdout never changes with it. At `trace` there is a
+// span per block read and write, allocation and directory operation, named
+// block_read, block_write, alloc, dir_lookup, dir_insert and dir_remove.
+// `--trace-file PATH` appends the same events as JSON lines whatever the
+// level: a `command` event whose `args` are the subcommand and its
+// arguments, one object per operation with an `event` name, and an `exit`
+// event with its `code`. Re-running the recorded commands in order on a
+// fresh device reproduces the image.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_NOT_FOUND;
+use crate::harness::{format_device, scenarios, TestContext};
+use crate::journal::tree_state;
+use crate::json::{json_field, json_strings, json_u64};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+const TRACE_EVENTS: &[&str] = &["block_read", "block_write", "alloc", "dir_insert"];
+
+fn stderr_lines(ctx: &TestContext, level: &str, args: &[&str]) -> io::Result<(Vec<u8>, usize)> {
+    let mut full = vec!["--log-level", level];
+    full.extend(args);
+    let output = ctx.run_bellande_command(&full)?;
+    let lines = String::from_utf8_lossy(&output.stderr).lines().count();
+    Ok((output.stdout, lines))
+}
+
+// Every line of the trace file parsed as one event
+fn trace_events(path: &Path) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)?;
+    let events: Vec<String> = text.lines().map(str::to_string).collect();
+    for line in &events {
+        assert!(
+            line.starts_with('{') && line.ends_with('}') && json_field(line, "event").is_some(),
+            "trace line is not an event object: {:?}",
+            line
+        );
+    }
+    Ok(events)
+}
+
+fn event_name(line: &str) -> String {
+    json_field(line, "event").unwrap_or_default()
+}
+
+pub(crate) fn log_levels_filter_stderr(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/file.bin"])?;
+
+    // Successful commands are silent by default and below warn
+    let output = ctx.run_bellande_command(&["list", "--path", "/dir"])?;
+    assert!(output.stderr.is_empty(), "default level logged on success");
+    let (stdout, lines) = stderr_lines(ctx, "error", &["list", "--path", "/dir"])?;
+    assert_eq!((stdout, lines), (output.stdout.clone(), 0));
+
+    // More detail at each level, the same stdout at all of them
+    let mut previous = 0;
+    for level in LEVELS {
+        let (stdout, lines) = stderr_lines(ctx, level, &["list", "--path", "/dir"])?;
+        assert_eq!(
+            stdout, output.stdout,
+            "--log-level {} changed stdout",
+            level
+        );
+        assert!(lines >= previous, "--log-level {} logged less", level);
+        previous = lines;
+    }
+
+    let mut command = ctx.command(&["--log-level", "trace", "write", "--path", "/dir/file.bin"]);
+    let output = command.write_stdin(content(1, 20_000)).output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for event in ["block_write", "alloc"] {
+        assert!(
+            stderr.contains(event),
+            "no {} span at trace: {:?}",
+            event,
+            stderr
+        );
+    }
+    ctx.command(&["--log-level", "trace", "remove", "--path", "/dir/file.bin"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("dir_remove"));
+    Ok(())
+}
+
+pub(crate) fn trace_file_records_operations(ctx: &TestContext) -> io::Result<()> {
+    let trace = ctx.temp_dir.path().join("ops.trace");
+    let trace_arg = trace.to_string_lossy().into_owned();
+    let traced = |args: &[&str]| {
+        let mut full = vec!["--trace-file", trace_arg.as_str()];
+        full.extend(args);
+        ctx.run_raw(&full)
+    };
+    format_device(ctx)?;
+    assert!(traced(&["mkdir", "--path", "/dir"])?.status.success());
+    assert!(traced(&["create", "--path", "/dir/data.bin"])?
+        .status
+        .success());
+    let input = ctx.temp_dir.path().join("input.bin");
+    fs::write(&input, content(2, 9000))?;
+    assert!(traced(&[
+        "write",
+        "--path",
+        "/dir/data.bin",
+        "--input",
+        &input.to_string_lossy()
+    ])?
+    .status
+    .success());
+    let failed = traced(&["read", "--path", "/missing"])?;
+    assert_eq!(failed.status.code(), Some(EXIT_NOT_FOUND));
+    // Traced in full, while stderr keeps to the default level
+    assert!(!String::from_utf8_lossy(&failed.stderr).contains("block_read"));
+
+    let events = trace_events(&trace)?;
+    let names: Vec<String> = events.iter().map(|line| event_name(line)).collect();
+    for event in TRACE_EVENTS {
+        assert!(
+            names.iter().any(|name| name == event),
+            "no {} event: {:?}",
+            event,
+            names
+        );
+    }
+    let commands: Vec<Vec<String>> = events
+        .iter()
+        .filter(|line| event_name(line) == "command")
+        .map(|line| json_strings(line, "args").expect("command event without args"))
+        .collect();
+    assert_eq!(commands.len(), 4);
+    assert_eq!(commands[0], ["mkdir", "--path", "/dir"]);
+    let exits: Vec<u64> = events
+        .iter()
+        .filter(|line| event_name(line) == "exit")
+        .map(|line| json_u64(line, "code").expect("exit event without code"))
+        .collect();
+    assert_eq!(exits, [0, 0, 0, EXIT_NOT_FOUND as u64]);
+    assert!(events.iter().any(|line| event_name(line) == "dir_insert"
+        && json_field(line, "name").as_deref() == Some("data.bin")));
+    Ok(())
+}
+
+pub(crate) fn trace_reproduces_image(ctx: &TestContext) -> io::Result<()> {
+    let trace = ctx.temp_dir.path().join("repro.trace");
+    let trace_arg = trace.to_string_lossy().into_owned();
+    let input = ctx.temp_dir.path().join("input.bin");
+    fs::write(&input, content(3, 30_000))?;
+    let input_arg = input.to_string_lossy().into_owned();
+    for args in [
+        &["format", "--yes"][..],
+        &["mkdir", "--parents", "--path", "/a/b"][..],
+        &["create", "--path", "/a/b/f.bin"][..],
+        &["write", "--path", "/a/b/f.bin", "--input", &input_arg][..],
+        &["create", "--path", "/a/gone"][..],
+        &["move", "--from", "/a/b/f.bin", "--to", "/a/f.bin"][..],
+        &["remove", "--path", "/a/gone"][..],
+    ] {
+        let mut full = vec!["--trace-file", trace_arg.as_str()];
+        full.extend(args);
+        ctx.run_bellande_command(&full)?;
+    }
+    let expected = tree_state(ctx)?;
+
+    let fresh = TestContext::new()?;
+    for line in trace_events(&trace)? {
+        if event_name(&line) != "command" {
+            continue;
+        }
+        let args = json_strings(&line, "args").expect("command event without args");
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        fresh.run_bellande_command(&args)?;
+    }
+    assert_eq!(tree_state(&fresh)?, expected);
+    Ok(())
+}
+
+pub(crate) fn logging_argument_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for level in ["chatty", "TRACE", ""] {
+        ctx.command(&["--log-level", level, "stats"])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--log-level"));
+    }
+    let unwritable = ctx.temp_dir.path().join("no_such_dir").join("ops.trace");
+    ctx.command(&["--trace-file", &unwritable.to_string_lossy(), "stats"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("--trace-file"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    log_levels_filter_stderr,
+    #[contract]
+    trace_file_records_operations,
+    #[contract]
+    trace_reproduces_image,
+    #[contract]
+    logging_argument_errors,
+}
@@ -0,0 +1,204 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// CRC32C metadata checksums. `format --checksums crc32c` checksums the
+// superblock, the inode table and every other metadata block as well as
+// data, and `--no-data-checksums` limits it to metadata. A bad metadata
+// block fails whatever needs it with EXIT_CHECKSUM_MISMATCH, its message
+// naming the structure, and `scrub` reports "Checksum mismatch at block N"
+// followed by the structure and every path the block affects. Data blocks
+// behave as with `--checksums crc32`.
+
+use crate::block_checksums::{bit_rot_detected, checksummed_round_trip, populated, FILE_BLOCKS};
+use crate::differential::{bytes_contain, content};
+use crate::errors::EXIT_CHECKSUM_MISMATCH;
+use crate::fsck::{changed_blocks, EXIT_FSCK_CLEAN, EXIT_FSCK_UNCORRECTED};
+use crate::harness::{format_device, scenarios, TestContext, DEFAULT_DEVICE_SIZE};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::process::Output;
+
+pub(crate) const CRC32C_FORMAT_ARGS: &[&str] = &["--checksums", "crc32c"];
+const METADATA_ONLY_ARGS: &[&str] = &["--checksums", "crc32c", "--no-data-checksums"];
+// Stored in the superblock, so it locates the superblock by value
+const PROBE_LABEL: &str = "superblock-probe-label";
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+
+fn context(format_args: &[&str]) -> io::Result<TestContext> {
+    Ok(
+        TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?
+            .with_format_args(format_args)
+            .with_format_args(&["--label", PROBE_LABEL]),
+    )
+}
+
+fn crc32c_context() -> io::Result<TestContext> {
+    context(CRC32C_FORMAT_ARGS)
+}
+
+fn metadata_only_context() -> io::Result<TestContext> {
+    context(METADATA_ONLY_ARGS)
+}
+
+fn report(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+// Flips a byte of the first device block containing `needle`
+fn corrupt_containing(ctx: &TestContext, needle: &[u8]) -> io::Result<usize> {
+    let mut device = fs::read(&ctx.device_path)?;
+    let index = device
+        .chunks(BLOCK)
+        .position(|block| bytes_contain(block, needle))
+        .expect("nothing on the device holds the probe");
+    let offset = device[index * BLOCK..(index + 1) * BLOCK]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .unwrap_or(0);
+    device[index * BLOCK + offset] ^= 0x01;
+    fs::write(&ctx.device_path, device)?;
+    Ok(index)
+}
+
+pub(crate) fn crc32c_data_checksums() -> io::Result<()> {
+    let ctx = crc32c_context()?;
+    checksummed_round_trip(&ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Checksums: crc32c"))
+        .stdout(predicate::str::contains("Data checksums: yes"));
+    bit_rot_detected(&crc32c_context()?)
+}
+
+pub(crate) fn superblock_corruption_detected(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let block = corrupt_containing(ctx, PROBE_LABEL.as_bytes())?;
+    let damaged = fs::read(&ctx.device_path)?;
+
+    // A flipped label byte would otherwise go unnoticed
+    for args in [&["stats"][..], &["list", "--path", "/"][..]] {
+        ctx.command(args)
+            .assert()
+            .code(EXIT_CHECKSUM_MISMATCH)
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::contains("superblock"));
+    }
+
+    let output = ctx.run_raw(&["scrub"])?;
+    assert_eq!(output.status.code(), Some(EXIT_FSCK_UNCORRECTED));
+    let report = report(&output);
+    let line = report
+        .lines()
+        .find(|line| line.contains(&format!("Checksum mismatch at block {}", block)))
+        .unwrap_or_else(|| panic!("scrub did not report block {}: {:?}", block, report));
+    assert!(line.contains("superblock"), "{:?}", line);
+    assert!(fs::read(&ctx.device_path)? == damaged, "scrub wrote");
+    Ok(())
+}
+
+pub(crate) fn inode_corruption_names_paths(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/victim.txt"])?;
+    let after = fs::read(&ctx.device_path)?;
+    ctx.command(&["scrub"]).assert().code(EXIT_FSCK_CLEAN);
+
+    // An empty file has no data blocks, so everything the create wrote is
+    // metadata; every one of those blocks must be covered
+    let changed = changed_blocks(&before, &after, BLOCK);
+    assert!(!changed.is_empty());
+    let mut named = 0;
+    for block in &changed {
+        let mut device = after.clone();
+        device[block * BLOCK + 5] ^= 0x01;
+        let image = ctx.temp_dir.path().join(format!("flipped{}.img", block));
+        fs::write(&image, &device)?;
+        let flipped = TestContext::from_image(&image)?;
+
+        let output = flipped.run_raw(&["scrub"])?;
+        let report = report(&output);
+        assert_eq!(
+            output.status.code(),
+            Some(EXIT_FSCK_UNCORRECTED),
+            "a flipped byte in metadata block {} went unreported: {:?}",
+            block,
+            report
+        );
+        assert!(report.contains(&format!("Checksum mismatch at block {}", block)));
+
+        let stat = flipped.run_raw(&["stat", "--path", "/dir/victim.txt"])?;
+        if stat.status.code() == Some(EXIT_CHECKSUM_MISMATCH) {
+            assert!(
+                report.contains("/dir/victim.txt"),
+                "the block stat needed is reported without the path: {:?}",
+                report
+            );
+            named += 1;
+        }
+    }
+    // At least the inode table block holding the new inode
+    assert!(named > 0, "no flipped block made stat of the new file fail");
+    Ok(())
+}
+
+pub(crate) fn metadata_only_checksums(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Checksums: crc32c"))
+        .stdout(predicate::str::contains("Data checksums: no"));
+
+    // Data rot reads back as stored
+    let mut data = content(1, FILE_BLOCKS * BLOCK);
+    corrupt_containing(ctx, &data[BLOCK..2 * BLOCK])?;
+    data[BLOCK] ^= 0x01;
+    assert!(
+        ctx.run_bellande_command(&["read", "--path", "/a.bin"])?
+            .stdout
+            == data
+    );
+    ctx.command(&["scrub"]).assert().code(EXIT_FSCK_CLEAN);
+
+    // Metadata is still covered
+    corrupt_containing(ctx, PROBE_LABEL.as_bytes())?;
+    ctx.command(&["stats"])
+        .assert()
+        .code(EXIT_CHECKSUM_MISMATCH);
+
+    let plain = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?;
+    format_device(&plain)?;
+    plain
+        .command(&["format", "--yes", "--no-data-checksums"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--checksums"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    crc32c_data_checksums(),
+    #[contract]
+    superblock_corruption_detected(crc32c_context()?),
+    #[contract]
+    inode_corruption_names_paths(crc32c_context()?),
+    #[contract]
+    metadata_only_checksums(metadata_only_context()?),
+}
@@ -0,0 +1,556 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Change notifications. `--remote SOCKET monitor --path P` subscribes to the
+// daemon's event stream, prints "Monitoring P" on stderr once subscribed,
+// then one stdout line per event under P, in commit order:
+//   created PATH | modified PATH offset=N length=M | removed PATH |
+//   renamed FROM -> TO | attr-changed PATH
+// `--recursive` covers all descendants rather than direct children, a rename
+// is reported if either name is covered, `--events` keeps the listed types
+// and `--format json` prints one object per line instead. Each subscriber
+// has a bounded queue (`--queue N`); when it is full the daemon drops the
+// event rather than stall the writer. The monitor exits 0 when the daemon
+// shuts down, printing "Dropped events: N" on stderr.
+
+use crate::cli::EXIT_USAGE;
+use crate::daemon::{exchange, request, serve_timeout, Daemon};
+use crate::errors::EXIT_NOT_FOUND;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, TestContext};
+use crate::json::{json_field, json_u64};
+use std::io::{self, BufRead, BufReader};
+use std::process::{self, Child, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const SUBSCRIBED: &str = "Monitoring ";
+const DROPPED: &str = "Dropped events: ";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const OVERFLOW_QUEUE: &str = "4";
+const OVERFLOW_WRITES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    Created(String),
+    Modified {
+        path: String,
+        offset: u64,
+        length: u64,
+    },
+    Removed(String),
+    Renamed {
+        from: String,
+        to: String,
+    },
+    AttrChanged(String),
+}
+
+impl Event {
+    fn kind(&self) -> &'static str {
+        match self {
+            Event::Created(_) => "created",
+            Event::Modified { .. } => "modified",
+            Event::Removed(_) => "removed",
+            Event::Renamed { .. } => "renamed",
+            Event::AttrChanged(_) => "attr-changed",
+        }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Event::Created(path) | Event::Removed(path) | Event::AttrChanged(path) => vec![path],
+            Event::Modified { path, .. } => vec![path],
+            Event::Renamed { from, to } => vec![from, to],
+        }
+    }
+
+    fn line(&self) -> String {
+        match self {
+            Event::Modified {
+                path,
+                offset,
+                length,
+            } => format!("modified {} offset={} length={}", path, offset, length),
+            Event::Renamed { from, to } => format!("renamed {} -> {}", from, to),
+            event => format!("{} {}", event.kind(), event.names()[0]),
+        }
+    }
+
+    // One `--format json` line; a rename carries its old name in "path"
+    fn from_json(line: &str) -> Option<Event> {
+        let path = json_field(line, "path")?;
+        Some(match json_field(line, "event")?.as_str() {
+            "created" => Event::Created(path),
+            "modified" => Event::Modified {
+                path,
+                offset: json_u64(line, "offset")?,
+                length: json_u64(line, "length")?,
+            },
+            "removed" => Event::Removed(path),
+            "renamed" => Event::Renamed {
+                from: path,
+                to: json_field(line, "to")?,
+            },
+            "attr-changed" => Event::AttrChanged(path),
+            _ => return None,
+        })
+    }
+
+    fn covered_by(&self, prefix: &str, recursive: bool) -> bool {
+        self.names()
+            .iter()
+            .any(|name| within(name, prefix, recursive))
+    }
+}
+
+// Whether `name` is `prefix` itself, a direct child, or with `recursive`
+// any descendant
+fn within(name: &str, prefix: &str, recursive: bool) -> bool {
+    if name == prefix {
+        return true;
+    }
+    let rest = if prefix == "/" {
+        name.strip_prefix('/')
+    } else {
+        name.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+    };
+    match rest {
+        Some(rest) => recursive || !rest.contains('/'),
+        None => false,
+    }
+}
+
+// A subscribed monitor process; killed on drop like the daemon it follows,
+// which also drops its connection
+pub(crate) struct Monitor {
+    child: Child,
+    stdout: Option<JoinHandle<Vec<String>>>,
+    stderr: Receiver<String>,
+}
+
+impl Monitor {
+    // `args` is the whole subcommand, so a monitor may run inside with-lock
+    pub(crate) fn start(ctx: &TestContext, daemon: &Daemon, args: &[&str]) -> io::Result<Monitor> {
+        let mut child = process::Command::new(&ctx.binary_path)
+            .arg("--remote")
+            .arg(&daemon.socket)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().map(|stdout| {
+            thread::spawn(move || {
+                BufReader::new(stdout)
+                    .lines()
+                    .map_while(Result::ok)
+                    .collect()
+            })
+        });
+        let (sender, stderr) = mpsc::channel();
+        if let Some(pipe) = child.stderr.take() {
+            thread::spawn(move || {
+                for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let monitor = Monitor {
+            child,
+            stdout,
+            stderr,
+        };
+        loop {
+            match monitor.stderr.recv_timeout(serve_timeout()) {
+                Ok(line) if line.starts_with(SUBSCRIBED) => return Ok(monitor),
+                Ok(_) => continue,
+                Err(_) => {
+                    return Err(io::Error::other(format!(
+                        "monitor {:?} never subscribed",
+                        args
+                    )))
+                }
+            }
+        }
+    }
+
+    // Pauses or resumes the monitor so its queue backs up in the daemon
+    fn signal(&self, signal: &str) -> io::Result<()> {
+        let status = process::Command::new("kill")
+            .arg(signal)
+            .arg(self.child.id().to_string())
+            .status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("kill {} failed", signal)))
+        }
+    }
+
+    // Waits for the monitor to exit once the daemon is gone; returns its
+    // stdout lines and the dropped-events count it reported
+    pub(crate) fn finish(&mut self) -> io::Result<(Vec<String>, u64)> {
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() > serve_timeout() {
+                return Err(io::Error::other("monitor outlived the daemon"));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        assert!(status.success(), "monitor exited {}", status);
+
+        let lines = self
+            .stdout
+            .take()
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default();
+        let dropped = self
+            .stderr
+            .iter()
+            .find_map(|line| line.strip_prefix(DROPPED)?.trim().parse().ok())
+            .ok_or_else(|| io::Error::other("monitor did not report its dropped events"))?;
+        Ok((lines, dropped))
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn text(size: usize) -> String {
+    "m".repeat(size)
+}
+
+// The scripted batch run through the daemon, with the events it commits
+fn mutation_batch() -> Vec<(Vec<&'static str>, Option<String>, Event)> {
+    let path = |path: &str| path.to_string();
+    vec![
+        (
+            vec!["create", "--path", "/watched/a.txt"],
+            None,
+            Event::Created(path("/watched/a.txt")),
+        ),
+        (
+            vec!["write", "--path", "/watched/a.txt"],
+            Some(text(100)),
+            Event::Modified {
+                path: path("/watched/a.txt"),
+                offset: 0,
+                length: 100,
+            },
+        ),
+        (
+            vec!["write", "--path", "/watched/a.txt", "--append"],
+            Some(text(50)),
+            Event::Modified {
+                path: path("/watched/a.txt"),
+                offset: 100,
+                length: 50,
+            },
+        ),
+        (
+            vec!["chmod", "--path", "/watched/a.txt", "--mode", "0600"],
+            None,
+            Event::AttrChanged(path("/watched/a.txt")),
+        ),
+        (
+            vec!["mkdir", "--path", "/watched/sub"],
+            None,
+            Event::Created(path("/watched/sub")),
+        ),
+        (
+            vec!["create", "--path", "/watched/sub/c.txt"],
+            None,
+            Event::Created(path("/watched/sub/c.txt")),
+        ),
+        (
+            vec!["create", "--path", "/outside.txt"],
+            None,
+            Event::Created(path("/outside.txt")),
+        ),
+        (
+            vec![
+                "move",
+                "--from",
+                "/watched/a.txt",
+                "--to",
+                "/watched/sub/b.txt",
+            ],
+            None,
+            Event::Renamed {
+                from: path("/watched/a.txt"),
+                to: path("/watched/sub/b.txt"),
+            },
+        ),
+        (
+            vec!["remove", "--path", "/watched/sub/c.txt"],
+            None,
+            Event::Removed(path("/watched/sub/c.txt")),
+        ),
+    ]
+}
+
+// Runs the batch through the daemon and returns the events in commit order
+fn run_batch(ctx: &TestContext, daemon: &Daemon) -> io::Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for (args, stdin, event) in mutation_batch() {
+        let mut command = daemon.remote(ctx, &args);
+        if let Some(stdin) = stdin {
+            command.write_stdin(stdin);
+        }
+        command.assert().success();
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn watched(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/watched"])?;
+    Ok(())
+}
+
+pub(crate) fn monitor_event_sequence(ctx: &TestContext) -> io::Result<()> {
+    watched(ctx)?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    let mut monitor = Monitor::start(
+        ctx,
+        &daemon,
+        &["monitor", "--path", "/watched", "--recursive"],
+    )?;
+
+    let events = run_batch(ctx, &daemon)?;
+    daemon.shutdown(ctx)?;
+    let (lines, dropped) = monitor.finish()?;
+
+    let expected: Vec<String> = events
+        .iter()
+        .filter(|event| event.covered_by("/watched", true))
+        .map(Event::line)
+        .collect();
+    assert_eq!(lines, expected);
+    assert_eq!(dropped, 0);
+    assert!(!lines.iter().any(|line| line.contains("/outside.txt")));
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn monitor_json_and_filters(ctx: &TestContext) -> io::Result<()> {
+    watched(ctx)?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    let mut json = Monitor::start(
+        ctx,
+        &daemon,
+        &["monitor", "--path", "/", "--recursive", "--format", "json"],
+    )?;
+    let mut children = Monitor::start(ctx, &daemon, &["monitor", "--path", "/watched"])?;
+    let mut typed = Monitor::start(
+        ctx,
+        &daemon,
+        &[
+            "monitor",
+            "--path",
+            "/watched",
+            "--recursive",
+            "--events",
+            "created,removed",
+        ],
+    )?;
+
+    let events = run_batch(ctx, &daemon)?;
+    daemon.shutdown(ctx)?;
+
+    let (lines, _) = json.finish()?;
+    let parsed: Vec<Event> = lines
+        .iter()
+        .map(|line| Event::from_json(line).ok_or_else(|| io::Error::other(line.clone())))
+        .collect::<io::Result<_>>()?;
+    assert_eq!(parsed, events);
+
+    // Without --recursive /watched/sub/c.txt is out of scope, but the rename
+    // out of /watched still shows under its old name
+    let (lines, _) = children.finish()?;
+    let expected: Vec<String> = events
+        .iter()
+        .filter(|event| event.covered_by("/watched", false))
+        .map(Event::line)
+        .collect();
+    assert_eq!(lines, expected);
+    assert!(lines.iter().any(|line| line.starts_with("renamed ")));
+    assert!(!lines.iter().any(|line| line.contains("/watched/sub/c.txt")));
+
+    let (lines, _) = typed.finish()?;
+    let expected: Vec<String> = events
+        .iter()
+        .filter(|event| event.covered_by("/watched", true))
+        .filter(|event| matches!(event, Event::Created(_) | Event::Removed(_)))
+        .map(Event::line)
+        .collect();
+    assert_eq!(lines, expected);
+    Ok(())
+}
+
+pub(crate) fn monitor_queue_overflow(ctx: &TestContext) -> io::Result<()> {
+    if cfg!(not(unix)) {
+        println!("Skipping the queue overflow test: no Unix signals on this host");
+        return Ok(());
+    }
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/log"])?;
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    let mut monitor = Monitor::start(
+        ctx,
+        &daemon,
+        &["monitor", "--path", "/log", "--queue", OVERFLOW_QUEUE],
+    )?;
+
+    // A stopped subscriber must not stall writers: every pipelined append
+    // is answered while nothing drains the queue
+    monitor.signal("-STOP")?;
+    let append = "{\"args\": [\"write\", \"--path\", \"/log\", \"--append\"], \"stdin\": \"x\"}";
+    let messages = vec![append.to_string(); OVERFLOW_WRITES];
+    let responses = exchange(&daemon.socket, &messages);
+    monitor.signal("-CONT")?;
+    for response in responses? {
+        assert_eq!(json_u64(&response, "exit"), Some(0), "{}", response);
+    }
+
+    daemon.shutdown(ctx)?;
+    let (lines, dropped) = monitor.finish()?;
+    assert!(
+        dropped > 0,
+        "a stopped monitor with a tiny queue dropped nothing"
+    );
+    assert_eq!(lines.len() as u64 + dropped, OVERFLOW_WRITES as u64);
+
+    // What survives is still in commit order, with gaps only where events
+    // were dropped
+    let mut last = None;
+    for line in &lines {
+        let offset: u64 = line
+            .strip_prefix("modified /log offset=")
+            .and_then(|rest| rest.strip_suffix(" length=1"))
+            .and_then(|offset| offset.parse().ok())
+            .ok_or_else(|| io::Error::other(line.clone()))?;
+        assert!(last < Some(offset), "{} arrived out of order", line);
+        last = Some(offset);
+    }
+    let log = ctx.command(&["read", "--path", "/log"]).output()?;
+    assert_eq!(log.stdout, "x".repeat(OVERFLOW_WRITES).into_bytes());
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn monitor_errors(ctx: &TestContext) -> io::Result<()> {
+    watched(ctx)?;
+    // Nothing else shares a one-shot device, so there is nothing to watch
+    ctx.command(&["monitor", "--path", "/watched"])
+        .assert()
+        .code(EXIT_USAGE);
+
+    let daemon = Daemon::start(ctx, "fs.sock")?;
+    daemon
+        .remote(ctx, &["monitor", "--path", "/watched", "--events", "bogus"])
+        .assert()
+        .code(EXIT_USAGE);
+    daemon
+        .remote(ctx, &["monitor", "--path", "/watched", "--queue", "0"])
+        .assert()
+        .code(EXIT_USAGE);
+    daemon
+        .remote(ctx, &["monitor", "--path", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    // The refused subscriptions leave the daemon serving
+    daemon
+        .remote(ctx, &["list", "--path", "/watched"])
+        .assert()
+        .success();
+    let responses = exchange(&daemon.socket, &[request(&["stat", "--path", "/watched"])])?;
+    assert_eq!(json_u64(&responses[0], "exit"), Some(0));
+    daemon.shutdown(ctx)
+}
+
+scenarios! {
+    #[contract]
+    monitor_event_sequence,
+    #[contract]
+    monitor_json_and_filters,
+    #[contract]
+    monitor_queue_overflow,
+    #[contract]
+    monitor_errors,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines_and_scope() {
+        let rename = Event::Renamed {
+            from: "/watched/a.txt".to_string(),
+            to: "/elsewhere/b.txt".to_string(),
+        };
+        assert_eq!(rename.line(), "renamed /watched/a.txt -> /elsewhere/b.txt");
+        assert!(rename.covered_by("/watched", false));
+        assert!(rename.covered_by("/elsewhere", false));
+        assert!(!rename.covered_by("/other", true));
+
+        let nested = Event::Created("/watched/sub/c.txt".to_string());
+        assert!(!nested.covered_by("/watched", false));
+        assert!(nested.covered_by("/watched", true));
+        assert!(nested.covered_by("/", true));
+        assert!(!Event::Created("/watchedness".to_string()).covered_by("/watched", true));
+        assert!(Event::Created("/top".to_string()).covered_by("/", false));
+        assert!(Event::AttrChanged("/watched".to_string()).covered_by("/watched", false));
+    }
+
+    #[test]
+    fn test_event_json_round_trip() {
+        let line = r#"{"event": "modified", "path": "/a", "offset": 100, "length": 50}"#;
+        let event = Event::from_json(line);
+        assert_eq!(
+            event,
+            Some(Event::Modified {
+                path: "/a".to_string(),
+                offset: 100,
+                length: 50,
+            })
+        );
+        assert_eq!(
+            event.map(|event| event.line()).as_deref(),
+            Some("modified /a offset=100 length=50")
+        );
+        let line = r#"{"event": "renamed", "path": "/a", "to": "/b"}"#;
+        assert_eq!(
+            Event::from_json(line).map(|event| event.line()).as_deref(),
+            Some("renamed /a -> /b")
+        );
+        assert_eq!(
+            Event::from_json(r#"{"event": "exploded", "path": "/a"}"#),
+            None
+        );
+    }
+}
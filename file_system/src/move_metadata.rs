@@ -0,0 +1,231 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Metadata across `move`. Each decorated entry is stat'ed before and after
+// moving it to another directory: everything but ctime must come through
+// unchanged, a moved directory's `..` must point at its new parent, and both
+// parents' link counts and mtimes must follow. New kinds of metadata get a
+// row in DECORATED.
+
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::json_scalars;
+use crate::times::format_rfc3339;
+use std::collections::BTreeMap;
+use std::io;
+
+// Fields a rename is allowed to change on the entry itself
+const VOLATILE_FIELDS: &[&str] = &["path", "name", "ctime"];
+
+// Parents are backdated to this before each move, so the mtime the move
+// sets is later whatever the timestamp granularity
+const BACKDATED: i64 = 946_684_800;
+
+struct Decorated {
+    label: &'static str,
+    is_dir: bool,
+    setup: fn(&TestContext, &str) -> io::Result<()>,
+}
+
+const DECORATED: &[Decorated] = &[
+    Decorated {
+        label: "empty file",
+        is_dir: false,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "file with data",
+        is_dir: false,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            let output = write_file(ctx, path, &[7u8; 5000])?;
+            assert!(output.status.success(), "write {} failed", path);
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "file with mode and owner",
+        is_dir: false,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            ctx.run_bellande_command(&["chmod", "--path", path, "--mode", "0604"])?;
+            ctx.run_bellande_command(&["chown", "--path", path, "--uid", "1000", "--gid", "100"])?;
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "hard-linked file",
+        is_dir: false,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["create", "--path", path])?;
+            let twin = format!("{}_twin", path);
+            ctx.run_bellande_command(&["link", "--target", path, "--path", &twin])?;
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "symlink",
+        is_dir: false,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["link", "--symbolic", "--target", "/old", "--path", path])?;
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "empty directory",
+        is_dir: true,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["mkdir", "--path", path])?;
+            Ok(())
+        },
+    },
+    Decorated {
+        label: "directory with children",
+        is_dir: true,
+        setup: |ctx, path| {
+            ctx.run_bellande_command(&["mkdir", "--path", path])?;
+            ctx.run_bellande_command(&["mkdir", "--path", &format!("{}/sub", path)])?;
+            ctx.run_bellande_command(&["create", "--path", &format!("{}/child", path)])?;
+            Ok(())
+        },
+    },
+];
+
+fn stat_fields(ctx: &TestContext, path: &str) -> io::Result<BTreeMap<String, String>> {
+    let output = ctx.run_bellande_command(&["stat", "--format", "json", "--path", path])?;
+    Ok(json_scalars(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .collect())
+}
+
+fn field(fields: &BTreeMap<String, String>, name: &str) -> u64 {
+    fields
+        .get(name)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("stat has no numeric {}: {:?}", name, fields))
+}
+
+// The inode a listing gives for `..`, from lines like ".. (inode 2)"
+fn parent_inode(listing: &str) -> Option<u64> {
+    listing
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(".. (inode "))
+        .and_then(|rest| rest.trim_end_matches(')').parse().ok())
+}
+
+fn stable(fields: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    fields
+        .iter()
+        .filter(|(name, _)| !VOLATILE_FIELDS.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+pub(crate) fn move_preserves_metadata(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/old"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/new"])?;
+
+    for (index, case) in DECORATED.iter().enumerate() {
+        let source = format!("/old/entry{}", index);
+        let destination = format!("/new/entry{}", index);
+        (case.setup)(ctx, &source)?;
+
+        let backdated = format_rfc3339(BACKDATED);
+        for parent in ["/old", "/new"] {
+            ctx.run_bellande_command(&["touch", "--date", &backdated, "--path", parent])?;
+        }
+        let before = stat_fields(ctx, &source)?;
+        let old_parent = stat_fields(ctx, "/old")?;
+        let new_parent = stat_fields(ctx, "/new")?;
+
+        ctx.run_bellande_command(&["move", "--from", &source, "--to", &destination])?;
+
+        let after = stat_fields(ctx, &destination)?;
+        assert_eq!(
+            stable(&before),
+            stable(&after),
+            "{}: metadata changed",
+            case.label
+        );
+        ctx.command(&["stat", "--path", &source]).assert().failure();
+
+        let link_delta = u64::from(case.is_dir);
+        let old_after = stat_fields(ctx, "/old")?;
+        let new_after = stat_fields(ctx, "/new")?;
+        assert_eq!(
+            field(&old_after, "nlink") + link_delta,
+            field(&old_parent, "nlink"),
+            "{}: old parent link count",
+            case.label
+        );
+        assert_eq!(
+            field(&new_after, "nlink"),
+            field(&new_parent, "nlink") + link_delta,
+            "{}: new parent link count",
+            case.label
+        );
+        for (parent, was, now) in [
+            ("/old", &old_parent, &old_after),
+            ("/new", &new_parent, &new_after),
+        ] {
+            assert!(
+                field(now, "mtime") > field(was, "mtime"),
+                "{}: {} mtime not updated",
+                case.label,
+                parent
+            );
+        }
+
+        if case.is_dir {
+            let output = ctx.run_bellande_command(&["list", "--path", &destination])?;
+            assert_eq!(
+                parent_inode(&String::from_utf8_lossy(&output.stdout)),
+                Some(field(&new_after, "inode")),
+                "{}: .. does not point at /new",
+                case.label
+            );
+        }
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    move_preserves_metadata,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_inode() {
+        let listing = "Contents of /new/d:\n. (inode 9)\n.. (inode 4)\nchild (inode 12)\n";
+        assert_eq!(parent_inode(listing), Some(4));
+        assert_eq!(parent_inode("child (inode 12)\n"), None);
+    }
+
+    #[test]
+    fn test_stable_drops_volatile_fields() {
+        let fields: BTreeMap<String, String> = [("inode", "5"), ("ctime", "1"), ("path", "/a")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(stable(&fields).keys().collect::<Vec<_>>(), ["inode"]);
+    }
+}
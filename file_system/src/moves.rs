@@ -0,0 +1,301 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `move --from <path> --to <path>`: relinks the directory entry without
+// touching data blocks, for files and whole directories. An existing
+// destination needs --force, and a directory cannot move into itself.
+// `rename --from --to` is another spelling of `move`, with the same
+// --force rule and the same errors; only the batch form of `rename` (see
+// batch_rename.rs) differs.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, inode_of, listed_names};
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_INVALID, EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use crate::journal::{tree_state, FAIL_AFTER_ENV};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+// More block writes than a move can take
+const MAX_CRASH_POINTS: u64 = 1000;
+
+fn create_with(ctx: &TestContext, path: &str, seed: u64, len: usize) -> io::Result<()> {
+    ctx.run_bellande_command(&["create", "--path", path])?;
+    let output = write_file(ctx, path, &content(seed, len))?;
+    assert!(output.status.success(), "write {} failed", path);
+    Ok(())
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+fn listing(ctx: &TestContext, dir: &str) -> io::Result<String> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    Ok(listed_names(&listing(ctx, dir)?))
+}
+
+fn move_entry(ctx: &TestContext, from: &str, to: &str) -> io::Result<()> {
+    ctx.command(&["move", "--from", from, "--to", to])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "Moved {} to {}",
+            from, to
+        )));
+    Ok(())
+}
+
+pub(crate) fn move_file_and_directory(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/new"])?;
+    create_with(ctx, "/old.txt", 1, 20_000)?;
+    let inode = inode_of(&listing(ctx, "/")?, "old.txt");
+    let before = read_stats(ctx)?;
+
+    move_entry(ctx, "/old.txt", "/new/location.txt")?;
+    assert_eq!(read(ctx, "/new/location.txt")?, content(1, 20_000));
+    assert_eq!(inode_of(&listing(ctx, "/new")?, "location.txt"), inode);
+    assert!(!names_in(ctx, "/")?.contains("old.txt"));
+    // Only directory entries changed, so no blocks were allocated or freed
+    assert_eq!(read_stats(ctx)?, before);
+
+    ctx.run_bellande_command(&["mkdir", "--path", "/tree"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/tree/sub"])?;
+    create_with(ctx, "/tree/sub/leaf", 2, 3000)?;
+    move_entry(ctx, "/tree", "/new/tree2")?;
+    assert_eq!(read(ctx, "/new/tree2/sub/leaf")?, content(2, 3000));
+    assert_eq!(names_in(ctx, "/")?, ["new".to_string()].into());
+
+    // Renaming in place is a move too
+    move_entry(ctx, "/new/location.txt", "/new/renamed.txt")?;
+    assert_eq!(read(ctx, "/new/renamed.txt")?, content(1, 20_000));
+    Ok(())
+}
+
+pub(crate) fn existing_destination_needs_force(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    create_with(ctx, "/a", 1, 100)?;
+    create_with(ctx, "/b", 2, 200)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+
+    ctx.command(&["move", "--from", "/a", "--to", "/b"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(predicate::str::contains("/b").and(predicate::str::contains("--force")));
+    assert_eq!(read(ctx, "/a")?, content(1, 100));
+    assert_eq!(read(ctx, "/b")?, content(2, 200));
+
+    ctx.run_bellande_command(&["move", "--force", "--from", "/a", "--to", "/b"])?;
+    assert_eq!(read(ctx, "/b")?, content(1, 100));
+    assert_eq!(
+        names_in(ctx, "/")?,
+        ["b", "dir"].iter().map(|n| n.to_string()).collect()
+    );
+
+    // --force replaces files, never directories
+    ctx.command(&["move", "--force", "--from", "/b", "--to", "/dir"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    assert_eq!(read(ctx, "/b")?, content(1, 100));
+    Ok(())
+}
+
+pub(crate) fn move_into_descendant_rejected(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/a"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/a/b"])?;
+    create_with(ctx, "/a/b/f", 3, 10)?;
+
+    for to in ["/a/b/c", "/a/b/../b/c", "/a/new"] {
+        ctx.command(&["move", "--from", "/a", "--to", to])
+            .assert()
+            .code(EXIT_INVALID)
+            .stderr(predicate::str::contains("/a"));
+    }
+    ctx.command(&["move", "--from", "/a", "--to", "/a/b"])
+        .assert()
+        .failure();
+
+    assert_eq!(names_in(ctx, "/")?, ["a".to_string()].into());
+    assert_eq!(names_in(ctx, "/a/b")?, ["f".to_string()].into());
+    assert_eq!(read(ctx, "/a/b/f")?, content(3, 10));
+    Ok(())
+}
+
+pub(crate) fn move_argument_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    create_with(ctx, "/f", 4, 10)?;
+
+    ctx.command(&["move", "--from", "/missing", "--to", "/x"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("/missing"));
+    ctx.command(&["move", "--from", "/f", "--to", "/no/parent"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("/no"));
+    ctx.command(&["move", "--from", "/f"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--to"));
+    ctx.command(&["move", "--to", "/x"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--from"));
+    ctx.command(&["move", "--from", "/", "--to", "/root"])
+        .assert()
+        .code(EXIT_INVALID);
+
+    assert_eq!(names_in(ctx, "/")?, ["f".to_string()].into());
+    Ok(())
+}
+
+// Arguments after the subcommand name, each covering one `move` rule: a
+// refusal without --force, a replacement, a directory target, a descendant,
+// a missing source, a missing --to and a plain move
+const ALIAS_CASES: &[&[&str]] = &[
+    &["--from", "/a", "--to", "/dir/b"],
+    &["--force", "--from", "/a", "--to", "/dir/b"],
+    &["--force", "--from", "/a", "--to", "/dir"],
+    &["--from", "/dir", "--to", "/dir/inside"],
+    &["--from", "/missing", "--to", "/x"],
+    &["--from", "/a"],
+    &["--from", "/dir", "--to", "/moved"],
+];
+
+fn alias_state(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    create_with(ctx, "/a", 1, 9000)?;
+    create_with(ctx, "/dir/b", 2, 30_000)
+}
+
+pub(crate) fn rename_matches_move(base: &TestContext) -> io::Result<()> {
+    alias_state(base)?;
+    let image = base.temp_dir.path().join("before_alias.img");
+    fs::copy(&base.device_path, &image)?;
+
+    for args in ALIAS_CASES {
+        let mut outcomes = Vec::new();
+        for command in ["move", "rename"] {
+            let ctx = TestContext::from_image(&image)?;
+            let full: Vec<&str> = [command].iter().chain(args.iter()).copied().collect();
+            let output = ctx.run_raw(&full)?;
+            outcomes.push((output.status.code(), tree_state(&ctx)?));
+        }
+        assert!(
+            outcomes[0] == outcomes[1],
+            "rename {:?} exited {:?} where move exited {:?}, or left another tree",
+            args,
+            outcomes[1].0,
+            outcomes[0].0
+        );
+    }
+
+    // Spot checks that the shared behavior is move's own
+    base.command(&["rename", "--from", "/a", "--to", "/dir/b"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(predicate::str::contains("--force"));
+    base.command(&["rename", "--force", "--from", "/a", "--to", "/dir"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    base.command(&["rename", "--from", "/dir", "--to", "/dir/inside"])
+        .assert()
+        .code(EXIT_INVALID);
+    base.command(&["rename", "--from", "/missing", "--to", "/x"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+
+    let inode = inode_of(&listing(base, "/")?, "a");
+    base.run_bellande_command(&["rename", "--force", "--from", "/a", "--to", "/dir/b"])?;
+    assert_eq!(read(base, "/dir/b")?, content(1, 9000));
+    assert_eq!(inode_of(&listing(base, "/dir")?, "b"), inode);
+
+    // Not to be mixed with the batch form
+    base.command(&["rename", "--from", "/dir/b", "--to", "/c", "--match", "*"])
+        .assert()
+        .code(EXIT_USAGE);
+    assert_fsck_clean(base)
+}
+
+fn rename_state(ctx: &TestContext) -> io::Result<()> {
+    ctx.run_bellande_command(&["mkdir", "--path", "/from"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/to"])?;
+    create_with(ctx, "/from/a", 5, 5000)?;
+    create_with(ctx, "/to/b", 6, 12_000)
+}
+
+pub(crate) fn rename_survives_crashes(base: &TestContext) -> io::Result<()> {
+    format_device(base)?;
+    rename_state(base)?;
+    let before = tree_state(base)?;
+    let image = base.temp_dir.path().join("before_rename.img");
+    fs::copy(&base.device_path, &image)?;
+    let args = ["rename", "--force", "--from", "/from/a", "--to", "/to/b"];
+
+    let complete = TestContext::from_image(&image)?;
+    complete.run_bellande_command(&args)?;
+    let after = tree_state(&complete)?;
+
+    let mut crash_points = 0;
+    for limit in 0..MAX_CRASH_POINTS {
+        let ctx = TestContext::from_image(&image)?;
+        if ctx
+            .command(&args)
+            .env(FAIL_AFTER_ENV, limit.to_string())
+            .output()?
+            .status
+            .success()
+        {
+            break;
+        }
+        crash_points += 1;
+        ctx.run_bellande_command(&["stats"])?;
+        assert_fsck_clean(&ctx)?;
+        let state = tree_state(&ctx)?;
+        assert!(
+            state == before || state == after,
+            "rename crashed after {} writes: {:?}",
+            limit,
+            state
+        );
+    }
+    assert!(crash_points > 0, "rename never hit the failure hook");
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    move_file_and_directory,
+    #[contract]
+    existing_destination_needs_force,
+    #[contract]
+    move_into_descendant_rejected,
+    #[contract]
+    move_argument_errors,
+    #[contract]
+    rename_matches_move,
+    #[contract]
+    rename_survives_crashes,
+}
@@ -0,0 +1,135 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Several paths in one invocation. Globs expand first, the operation is then
+// applied to each path with one result line per path, and the exit status is
+// non-zero if any failed; `--fail-fast` stops at the first failure.
+
+use crate::differential::listed_names;
+use crate::harness::{format_device, scenarios, TestContext};
+use std::collections::BTreeSet;
+use std::io;
+use std::process::Output;
+
+fn root_names(ctx: &TestContext) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn names(list: &[&str]) -> BTreeSet<String> {
+    list.iter().map(|name| name.to_string()).collect()
+}
+
+// Every path gets its own result line on stdout or stderr
+fn assert_line_per_path(output: &Output, paths: &[&str]) {
+    let combined = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for path in paths {
+        let lines = combined
+            .lines()
+            .filter(|line| {
+                line.split_whitespace()
+                    .any(|word| word.trim_matches(|c| c == ':' || c == '\'' || c == '"') == *path)
+            })
+            .count();
+        assert_eq!(
+            lines, 1,
+            "expected one result line for {} in {:?}",
+            path, combined
+        );
+    }
+}
+
+pub(crate) fn create_many(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    let output = ctx.run_bellande_command(&["create", "/a", "/b", "/c"])?;
+    assert_line_per_path(&output, &["/a", "/b", "/c"]);
+    let output = ctx.run_bellande_command(&["create", "--path", "/d", "--path", "/e"])?;
+    assert_line_per_path(&output, &["/d", "/e"]);
+    let output = ctx.run_bellande_command(&["touch", "/a", "/f"])?;
+    assert_line_per_path(&output, &["/a", "/f"]);
+
+    assert_eq!(root_names(ctx)?, names(&["a", "b", "c", "d", "e", "f"]));
+    Ok(())
+}
+
+pub(crate) fn mixed_results_keep_going(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    let output = ctx.run_raw(&["create", "/ok1", "/missing/x", "/ok2"])?;
+    assert!(
+        !output.status.success(),
+        "a failed path must fail the command"
+    );
+    assert_line_per_path(&output, &["/ok1", "/missing/x", "/ok2"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("/missing/x"));
+    assert_eq!(root_names(ctx)?, names(&["ok1", "ok2"]));
+
+    let output = ctx.run_raw(&["remove", "/ok1", "/nope", "/ok2"])?;
+    assert!(!output.status.success());
+    assert_line_per_path(&output, &["/ok1", "/nope", "/ok2"]);
+    assert!(root_names(ctx)?.is_empty());
+
+    ctx.run_bellande_command(&["create", "/sum1", "/sum2"])?;
+    let output = ctx.run_raw(&["checksum", "/sum1", "/absent", "/sum2"])?;
+    assert!(!output.status.success());
+    assert_line_per_path(&output, &["/sum1", "/absent", "/sum2"]);
+    Ok(())
+}
+
+pub(crate) fn fail_fast_stops(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "/r1", "/r2"])?;
+
+    let output = ctx.run_raw(&["remove", "--fail-fast", "/r1", "/nope", "/r2"])?;
+    assert!(!output.status.success());
+    assert_eq!(root_names(ctx)?, names(&["r2"]));
+
+    let output = ctx.run_raw(&["create", "--fail-fast", "/missing/x", "/never"])?;
+    assert!(!output.status.success());
+    assert_eq!(root_names(ctx)?, names(&["r2"]));
+    Ok(())
+}
+
+pub(crate) fn globs_expand_before_applying(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "/g1.tmp", "/g2.tmp", "/keep.txt", "/other"])?;
+
+    let output = ctx.run_bellande_command(&["remove", "/g*.tmp", "/other"])?;
+    assert_line_per_path(&output, &["/g1.tmp", "/g2.tmp", "/other"]);
+    assert_eq!(root_names(ctx)?, names(&["keep.txt"]));
+
+    // A glob that matches nothing is a failure for that argument only
+    ctx.run_bellande_command(&["create", "/again"])?;
+    let output = ctx.run_raw(&["remove", "/none*.tmp", "/again"])?;
+    assert!(!output.status.success());
+    assert_eq!(root_names(ctx)?, names(&["keep.txt"]));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    create_many,
+    #[contract]
+    mixed_results_keep_going,
+    #[contract]
+    fail_fast_stops,
+    #[contract]
+    globs_expand_before_applying,
+}
@@ -0,0 +1,275 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Column selection. `--output-fields` picks and orders the columns of both
+// the `--long` table and `--format tsv`; TSV rows have no header and escape
+// tabs, newlines and backslashes so every entry stays on one line. JSON
+// always carries every field whatever was selected.
+
+use crate::cli::EXIT_USAGE;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::json_field;
+use predicates::prelude::*;
+use std::io;
+
+pub(crate) const FIELDS: &[&str] = &["name", "size", "mtime", "inode"];
+
+const ORDERINGS: &[&str] = &["name", "size,name", "inode,name,mtime,size", "mtime,inode"];
+
+const DATA_FILES: &[(&str, usize)] = &[("a.txt", 3), ("b.bin", 1500), ("empty", 0)];
+
+// Names that would break a naive one-row-per-line reader
+const AWKWARD_NAMES: &[&str] = &["tab\there", "new\nline", "back\\slash", "cr\rhere"];
+
+// What the binary must print for a name inside a TSV cell
+pub(crate) fn tsv_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn tsv_rows(stdout: &str) -> Vec<Vec<&str>> {
+    stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split('\t').collect())
+        .collect()
+}
+
+fn stdout_of(ctx: &TestContext, args: &[&str]) -> String {
+    let output = ctx.command(args).assert().success();
+    String::from_utf8_lossy(&output.get_output().stdout).into_owned()
+}
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/data"])?;
+    for (name, len) in DATA_FILES {
+        let path = format!("/data/{}", name);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let output = write_file(ctx, &path, &vec![b'x'; *len])?;
+        assert!(output.status.success(), "write {} failed", path);
+    }
+    Ok(())
+}
+
+pub(crate) fn field_order_honored(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+
+    for fields in ORDERINGS {
+        let columns: Vec<&str> = fields.split(',').collect();
+        for command in ["list", "find"] {
+            let stdout = stdout_of(
+                ctx,
+                &[
+                    command,
+                    "--format",
+                    "tsv",
+                    "--output-fields",
+                    fields,
+                    "--path",
+                    "/data",
+                ],
+            );
+            let rows = tsv_rows(&stdout);
+            assert_eq!(
+                rows.len(),
+                DATA_FILES.len(),
+                "{} {}: {:?}",
+                command,
+                fields,
+                stdout
+            );
+            for row in &rows {
+                assert_eq!(
+                    row.len(),
+                    columns.len(),
+                    "{} {}: {:?}",
+                    command,
+                    fields,
+                    row
+                );
+            }
+
+            let (Some(name_at), Some(size_at)) = (
+                columns.iter().position(|c| *c == "name"),
+                columns.iter().position(|c| *c == "size"),
+            ) else {
+                continue;
+            };
+            for (name, len) in DATA_FILES {
+                // find prints paths, list prints bare names
+                let row = rows
+                    .iter()
+                    .find(|row| row[name_at].rsplit('/').next() == Some(*name))
+                    .unwrap_or_else(|| panic!("{} {}: no row for {}", command, fields, name));
+                assert_eq!(row[size_at], len.to_string(), "{} {}", command, fields);
+            }
+        }
+    }
+
+    // The human table follows the same selection, headed by the field names
+    let stdout = stdout_of(
+        ctx,
+        &[
+            "list",
+            "--long",
+            "--output-fields",
+            "size,name",
+            "--path",
+            "/data",
+        ],
+    );
+    let header = stdout
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let (size_at, name_at) = (header.find("size"), header.find("name"));
+    assert!(
+        size_at.is_some() && name_at.is_some() && size_at < name_at,
+        "header does not follow size,name: {:?}",
+        header
+    );
+    assert!(!header.contains("inode") && !header.contains("mtime"));
+    Ok(())
+}
+
+pub(crate) fn tsv_escapes_control_characters(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/odd"])?;
+    for name in AWKWARD_NAMES {
+        ctx.run_bellande_command(&["create", "--path", &format!("/odd/{}", name)])?;
+    }
+
+    let args = [
+        "list",
+        "--format",
+        "tsv",
+        "--output-fields",
+        "name,size",
+        "--path",
+        "/odd",
+    ];
+    let stdout = stdout_of(ctx, &args);
+    let rows = tsv_rows(&stdout);
+    assert_eq!(
+        rows.len(),
+        AWKWARD_NAMES.len(),
+        "entries split across lines: {:?}",
+        stdout
+    );
+    let mut names: Vec<&str> = rows.iter().map(|row| row[0]).collect();
+    let mut expected: Vec<String> = AWKWARD_NAMES.iter().map(|name| tsv_escape(name)).collect();
+    names.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(names, expected);
+    assert!(rows.iter().all(|row| row.len() == 2 && row[1] == "0"));
+
+    // Same tree, same bytes
+    assert_eq!(stdout_of(ctx, &args), stdout);
+    Ok(())
+}
+
+pub(crate) fn json_includes_every_field(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let stdout = stdout_of(
+        ctx,
+        &[
+            "list",
+            "--format",
+            "json",
+            "--output-fields",
+            "name",
+            "--path",
+            "/data",
+        ],
+    );
+    for field in FIELDS {
+        assert!(
+            json_field(&stdout, field).is_some(),
+            "JSON is missing {}: {:?}",
+            field,
+            stdout
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn unknown_fields_rejected(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    for fields in ["name,szie", "", "name,,size"] {
+        let output = ctx
+            .command(&[
+                "list",
+                "--long",
+                "--output-fields",
+                fields,
+                "--path",
+                "/data",
+            ])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--output-fields"))
+            .stdout(predicate::str::is_empty());
+        let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+        for field in FIELDS {
+            assert!(
+                stderr.contains(field),
+                "valid field {} not listed: {:?}",
+                field,
+                stderr
+            );
+        }
+    }
+    ctx.command(&["list", "--format", "csv", "--path", "/data"])
+        .assert()
+        .code(EXIT_USAGE);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    field_order_honored,
+    #[contract]
+    tsv_escapes_control_characters,
+    #[contract]
+    json_includes_every_field,
+    #[contract]
+    unknown_fields_rejected,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tsv_escape() {
+        assert_eq!(tsv_escape("plain.txt"), "plain.txt");
+        assert_eq!(tsv_escape("tab\there"), "tab\\there");
+        assert_eq!(tsv_escape("new\nline\r"), "new\\nline\\r");
+        // Backslashes are escaped first, so "\\t" cannot be mistaken for a tab
+        assert_eq!(tsv_escape("a\\tb"), "a\\\\tb");
+        assert_ne!(tsv_escape("a\\tb"), tsv_escape("a\tb"));
+    }
+}
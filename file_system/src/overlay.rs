@@ -0,0 +1,195 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Overlay images. With `--overlay delta.img` the `--device` image becomes a
+// read-only base: every block write goes to the delta, which is created on
+// first use, and reads take the delta's copy of a block when it has one. The
+// delta records which base it was made on and is refused against any other.
+// `flatten` merges the delta into the base and empties it; `flatten --output
+// PATH` writes the merged image to a new file and leaves both alone.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_INVALID, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::journal::tree_state;
+use crate::large_device::allocated_bytes;
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+
+fn base_context() -> io::Result<TestContext> {
+    let ctx = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?;
+    format_device(&ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/drivers"])?;
+    for (path, blocks, seed) in [("/drivers/net.ko", 40, 1), ("/drivers/usb.ko", 25, 2)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(&ctx, path, &content(seed, blocks * BLOCK))?
+            .status
+            .success());
+    }
+    Ok(ctx)
+}
+
+// A private copy of `base` with commands going through a fresh delta
+fn overlay_on(base: &TestContext) -> io::Result<(TestContext, PathBuf)> {
+    let ctx = TestContext::from_image(&base.device_path)?;
+    let delta = ctx.temp_dir.path().join("delta.img");
+    Ok((ctx.with_overlay(delta.clone()), delta))
+}
+
+// Machine-local changes of every kind
+fn customize(ctx: &TestContext, seed: u64) -> io::Result<()> {
+    ctx.run_bellande_command(&["mkdir", "--path", "/etc"])?;
+    ctx.run_bellande_command(&["create", "--path", "/etc/machine-id"])?;
+    assert!(write_file(ctx, "/etc/machine-id", &content(seed, 33))?
+        .status
+        .success());
+    ctx.command(&["write", "--path", "/drivers/net.ko", "--offset", "5000"])
+        .write_stdin(content(seed + 1, 2 * BLOCK))
+        .assert()
+        .success();
+    ctx.run_bellande_command(&["remove", "--path", "/drivers/usb.ko"])?;
+    Ok(())
+}
+
+pub(crate) fn writes_go_to_delta(base: &TestContext) -> io::Result<()> {
+    let pristine = fs::read(&base.device_path)?;
+    let before = tree_state(base)?;
+    let (ctx, delta) = overlay_on(base)?;
+
+    customize(&ctx, 10)?;
+    let after = tree_state(&ctx)?;
+    assert_ne!(after, before);
+    assert!(
+        fs::read(&ctx.device_path)? == pristine,
+        "a write through the overlay reached the base"
+    );
+    assert!(delta.exists(), "the delta was not created");
+    assert!(
+        allocated_bytes(&delta)? < DEFAULT_DEVICE_SIZE / 8,
+        "the delta holds far more than the changed blocks"
+    );
+
+    // Each new process sees the merged view again
+    assert_eq!(tree_state(&ctx)?, after);
+    assert_fsck_clean(&ctx)?;
+    assert!(fs::read(&ctx.device_path)? == pristine);
+    Ok(())
+}
+
+pub(crate) fn overlays_are_independent(base: &TestContext) -> io::Result<()> {
+    let (first, _) = overlay_on(base)?;
+    let (second, _) = overlay_on(base)?;
+    customize(&first, 20)?;
+    customize(&second, 30)?;
+    second.run_bellande_command(&["create", "--path", "/second-only"])?;
+
+    let first_state = tree_state(&first)?;
+    let second_state = tree_state(&second)?;
+    assert_ne!(first_state, second_state);
+    assert!(!first_state.contains_key("/second-only"));
+    assert_eq!(
+        first
+            .run_bellande_command(&["read", "--path", "/etc/machine-id"])?
+            .stdout,
+        content(20, 33)
+    );
+    assert_eq!(
+        second
+            .run_bellande_command(&["read", "--path", "/etc/machine-id"])?
+            .stdout,
+        content(30, 33)
+    );
+    assert_fsck_clean(&first)?;
+    assert_fsck_clean(&second)
+}
+
+pub(crate) fn flatten_merges_delta(base: &TestContext) -> io::Result<()> {
+    let (ctx, delta) = overlay_on(base)?;
+    customize(&ctx, 40)?;
+    let merged_state = tree_state(&ctx)?;
+
+    // To a new file, leaving base and delta as they were
+    let pristine = fs::read(&ctx.device_path)?;
+    let delta_bytes = fs::read(&delta)?;
+    let merged = ctx.temp_dir.path().join("merged.img");
+    ctx.run_bellande_command(&["flatten", "--output", &merged.to_string_lossy()])?;
+    assert!(fs::read(&ctx.device_path)? == pristine);
+    assert!(fs::read(&delta)? == delta_bytes);
+    let standalone = TestContext::from_image(&merged)?;
+    assert_eq!(tree_state(&standalone)?, merged_state);
+    assert_fsck_clean(&standalone)?;
+
+    // In place: the base takes the changes and the delta no longer differs
+    ctx.run_bellande_command(&["flatten"])?;
+    let flattened = TestContext::from_image(&ctx.device_path)?;
+    assert_eq!(tree_state(&flattened)?, merged_state);
+    assert_fsck_clean(&flattened)?;
+    assert_eq!(tree_state(&ctx)?, merged_state);
+
+    // The emptied delta now belongs to the flattened base
+    let flattened_bytes = fs::read(&ctx.device_path)?;
+    ctx.run_bellande_command(&["create", "--path", "/after-flatten"])?;
+    assert!(fs::read(&ctx.device_path)? == flattened_bytes);
+    assert!(tree_state(&ctx)?.contains_key("/after-flatten"));
+    Ok(())
+}
+
+pub(crate) fn delta_bound_to_base(base: &TestContext) -> io::Result<()> {
+    let (ctx, delta) = overlay_on(base)?;
+    customize(&ctx, 50)?;
+    let delta_bytes = fs::read(&delta)?;
+
+    // A different base, formatted afresh
+    let other = TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))?;
+    format_device(&other)?;
+    let other_bytes = fs::read(&other.device_path)?;
+    let mismatched = other.with_overlay(delta.clone());
+    mismatched
+        .command(&["list", "--path", "/"])
+        .assert()
+        .code(EXIT_INVALID)
+        .stderr(predicate::str::contains("base"));
+    assert!(fs::read(&mismatched.device_path)? == other_bytes);
+    assert!(fs::read(&delta)? == delta_bytes);
+
+    // Missing files and a flatten without an overlay
+    let missing = TestContext::from_image(&base.device_path)?;
+    let no_dir = missing.temp_dir.path().join("no/such/delta.img");
+    missing
+        .with_overlay(no_dir)
+        .command(&["list", "--path", "/"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    base.command(&["flatten"]).assert().code(EXIT_USAGE);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    writes_go_to_delta(base_context()?),
+    #[contract]
+    overlays_are_independent(base_context()?),
+    #[contract]
+    flatten_merges_delta(base_context()?),
+    #[contract]
+    delta_bound_to_base(base_context()?),
+}
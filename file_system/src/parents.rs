@@ -0,0 +1,204 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `--parents` on `mkdir` and `create` creates every missing directory on the
+// way. An existing directory at the full path is not an error for `mkdir`, a
+// regular file anywhere on the way is, and a call that fails partway rolls
+// back the directories it made.
+
+use crate::capacity::{inode_limited_context, EXIT_NO_SPACE, NO_INODES_MESSAGE};
+use crate::differential::listed_names;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_NOT_DIRECTORY, EXIT_NOT_FOUND};
+use crate::harness::{format_device, read_stats, scenarios, TestContext};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::io;
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Each directory on `path` lists exactly the next component
+fn assert_chain(ctx: &TestContext, path: &str) -> io::Result<()> {
+    let mut dir = String::from("/");
+    for component in path.trim_start_matches('/').split('/') {
+        assert!(
+            names_in(ctx, &dir)?.contains(component),
+            "{} is missing from {}",
+            component,
+            dir
+        );
+        if !dir.ends_with('/') {
+            dir.push('/');
+        }
+        dir.push_str(component);
+    }
+    Ok(())
+}
+
+fn chain(depth: usize) -> String {
+    (0..depth).map(|level| format!("/level{}", level)).collect()
+}
+
+pub(crate) fn mkdir_parents_creates_chain(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    ctx.command(&["mkdir", "--path", "/a/b/c"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+
+    let empty = read_stats(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", "/a/b/c/d"])?;
+    assert_chain(ctx, "/a/b/c/d")?;
+    assert!(names_in(ctx, "/a/b/c/d")?.is_empty());
+    let created = read_stats(ctx)?;
+    assert_eq!(created.free_inodes, empty.free_inodes - 4);
+
+    // Already there: silent success, nothing allocated
+    ctx.command(&["mkdir", "--parents", "--path", "/a/b/c/d"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", "/a/b"])?;
+    assert_eq!(read_stats(ctx)?, created);
+
+    // Only the missing tail is created
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", "/a/b/x/y"])?;
+    assert_chain(ctx, "/a/b/x/y")?;
+    assert_eq!(names_in(ctx, "/a/b")?.len(), 2);
+    assert_eq!(read_stats(ctx)?.free_inodes, created.free_inodes - 2);
+
+    let deep = chain(32);
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", &deep])?;
+    assert_chain(ctx, &deep)?;
+    Ok(())
+}
+
+pub(crate) fn files_block_parents(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/a"])?;
+    ctx.run_bellande_command(&["create", "--path", "/a/file"])?;
+    let before = read_stats(ctx)?;
+
+    for path in ["/a/file/sub", "/a/file/sub/deeper"] {
+        ctx.command(&["mkdir", "--parents", "--path", path])
+            .assert()
+            .code(EXIT_NOT_DIRECTORY)
+            .stderr(predicate::str::contains("/a/file"));
+        ctx.command(&[
+            "create",
+            "--parents",
+            "--path",
+            &format!("{}/new.txt", path),
+        ])
+        .assert()
+        .code(EXIT_NOT_DIRECTORY)
+        .stderr(predicate::str::contains("/a/file"));
+    }
+    // The full path existing as a file is not "already a directory"
+    ctx.command(&["mkdir", "--parents", "--path", "/a/file"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS)
+        .stderr(predicate::str::contains("/a/file"));
+
+    assert_eq!(read_stats(ctx)?, before);
+    assert_eq!(names_in(ctx, "/a")?, BTreeSet::from(["file".to_string()]));
+    Ok(())
+}
+
+pub(crate) fn failed_parents_roll_back(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let before = read_stats(ctx)?;
+    let too_deep = chain(before.free_inodes as usize + 2);
+
+    for command in ["mkdir", "create"] {
+        let path = match command {
+            "mkdir" => too_deep.clone(),
+            _ => format!("{}/file.txt", chain(before.free_inodes as usize)),
+        };
+        ctx.command(&[command, "--parents", "--path", &path])
+            .assert()
+            .code(EXIT_NO_SPACE)
+            .stderr(predicate::str::contains(NO_INODES_MESSAGE));
+        assert_eq!(
+            read_stats(ctx)?,
+            before,
+            "{} --parents leaked inodes",
+            command
+        );
+        assert!(
+            names_in(ctx, "/")?.is_empty(),
+            "{} --parents left directories",
+            command
+        );
+    }
+
+    // With room to spare the same chain succeeds
+    let fits = chain(before.free_inodes as usize);
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", &fits])?;
+    assert_chain(ctx, &fits)?;
+    assert_eq!(read_stats(ctx)?.free_inodes, 0);
+    Ok(())
+}
+
+pub(crate) fn create_parents(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    ctx.command(&["create", "--path", "/x/y/z.txt"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.run_bellande_command(&[
+        "create",
+        "--parents",
+        "--path",
+        "/x/y/z.txt",
+        "--content",
+        "nested",
+    ])?;
+    assert_chain(ctx, "/x/y/z.txt")?;
+    let output = ctx.run_bellande_command(&["read", "--path", "/x/y/z.txt"])?;
+    assert_eq!(output.stdout, b"nested");
+
+    // Parents existing is fine, the file itself existing still is not
+    ctx.run_bellande_command(&["create", "--parents", "--path", "/x/y/other.txt"])?;
+    assert_eq!(names_in(ctx, "/x/y")?.len(), 2);
+    ctx.command(&["create", "--parents", "--path", "/x/y/z.txt"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    mkdir_parents_creates_chain,
+    #[contract]
+    files_block_parents,
+    #[contract]
+    failed_parents_roll_back(inode_limited_context()?),
+    #[contract]
+    create_parents,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain() {
+        assert_eq!(chain(0), "");
+        assert_eq!(chain(3), "/level0/level1/level2");
+    }
+}
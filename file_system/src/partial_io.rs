@@ -0,0 +1,247 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `read`/`write` with `--offset` and `--length`. A read returns exactly the
+// requested range and errors if it reaches past EOF; a write patches bytes
+// in place and only grows the file, and allocates blocks, past EOF. Writes
+// starting mid-block are the interesting case for the allocator. A patch
+// rewrites only the blocks it lands in, plus metadata, and `write --length`
+// takes at most that many bytes of the input.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_INVALID;
+use crate::fsck::changed_blocks;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 4096;
+const BIG_LEN: usize = 3 * 1024 * 1024;
+// Inode, bitmap and journal blocks a small patch may also touch
+const MAX_METADATA_BLOCKS: usize = 6;
+
+fn partial_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn big_file(ctx: &TestContext) -> io::Result<Vec<u8>> {
+    format_device(ctx)?;
+    let data = content(1, BIG_LEN);
+    ctx.run_bellande_command(&["create", "--path", "/big.bin"])?;
+    let output = write_file(ctx, "/big.bin", &data)?;
+    assert!(output.status.success(), "write /big.bin failed");
+    Ok(data)
+}
+
+fn read_range(ctx: &TestContext, offset: usize, length: Option<usize>) -> io::Result<Vec<u8>> {
+    let offset = offset.to_string();
+    let length = length.map(|length| length.to_string());
+    let mut args = vec!["read", "--path", "/big.bin", "--offset", &offset];
+    if let Some(length) = &length {
+        args.extend(["--length", length]);
+    }
+    Ok(ctx.run_bellande_command(&args)?.stdout)
+}
+
+fn write_at(ctx: &TestContext, offset: usize, data: &[u8]) -> io::Result<()> {
+    ctx.command(&[
+        "write",
+        "--path",
+        "/big.bin",
+        "--offset",
+        &offset.to_string(),
+    ])
+    .write_stdin(data.to_vec())
+    .assert()
+    .success();
+    Ok(())
+}
+
+fn whole(ctx: &TestContext) -> io::Result<Vec<u8>> {
+    Ok(ctx
+        .run_bellande_command(&["read", "--path", "/big.bin"])?
+        .stdout)
+}
+
+pub(crate) fn read_ranges_exact(ctx: &TestContext) -> io::Result<()> {
+    let data = big_file(ctx)?;
+    let block = BLOCK_SIZE as usize;
+
+    for (offset, length) in [
+        (4096, 512),
+        (0, 1),
+        (1, block),
+        (block - 1, 2),
+        (block + 1, 3 * block),
+        (BIG_LEN - 10, 10),
+        (BIG_LEN, 0),
+        (12_345, 0),
+    ] {
+        assert!(
+            read_range(ctx, offset, Some(length))? == data[offset..offset + length],
+            "read --offset {} --length {}",
+            offset,
+            length
+        );
+    }
+    // Without --length the read runs to EOF
+    assert!(read_range(ctx, BIG_LEN - 5000, None)? == data[BIG_LEN - 5000..]);
+
+    let output = ctx.run_bellande_command(&[
+        "read", "--path", "/big.bin", "--offset", "4K", "--length", "1K",
+    ])?;
+    assert!(output.stdout == data[4096..5120]);
+    Ok(())
+}
+
+pub(crate) fn writes_patch_in_place(ctx: &TestContext) -> io::Result<()> {
+    let mut model = big_file(ctx)?;
+    let before = read_stats(ctx)?;
+
+    // Mid-block, block-spanning and block-aligned patches inside the file
+    for (seed, offset, len) in [(2, 4096 + 17, 37), (3, 3 * 4096 - 5, 4106), (4, 8192, 4096)] {
+        let patch = content(seed, len);
+        write_at(ctx, offset, &patch)?;
+        model[offset..offset + len].copy_from_slice(&patch);
+    }
+    assert!(whole(ctx)? == model, "in-place patches differ");
+    assert_eq!(read_stats(ctx)?, before, "in-place writes allocated blocks");
+
+    // Starting inside the last block and running past EOF grows the file
+    let tail = content(5, 10_000);
+    write_at(ctx, BIG_LEN - 100, &tail)?;
+    model.truncate(BIG_LEN - 100);
+    model.extend_from_slice(&tail);
+    assert!(whole(ctx)? == model, "extending write differs");
+
+    let grown_blocks =
+        (model.len().div_ceil(BLOCK_SIZE as usize) - BIG_LEN / BLOCK_SIZE as usize) as u64;
+    let after = read_stats(ctx)?;
+    assert!(
+        before.free_blocks - after.free_blocks >= grown_blocks,
+        "{} blocks freed by growing {} blocks",
+        before.free_blocks - after.free_blocks,
+        grown_blocks
+    );
+
+    // Writing at EOF appends
+    let end = model.len();
+    write_at(ctx, end, b"appended")?;
+    model.extend_from_slice(b"appended");
+    assert!(whole(ctx)? == model);
+    Ok(())
+}
+
+pub(crate) fn untouched_blocks_not_rewritten(ctx: &TestContext) -> io::Result<()> {
+    let mut model = big_file(ctx)?;
+    let block = BLOCK_SIZE as usize;
+
+    for (seed, offset, len) in [(6, 100 * block + 700, 37), (7, 200 * block - 3, 6)] {
+        let before = fs::read(&ctx.device_path)?;
+        let patch = content(seed, len);
+        write_at(ctx, offset, &patch)?;
+        model[offset..offset + len].copy_from_slice(&patch);
+        let after = fs::read(&ctx.device_path)?;
+
+        // No block still holding untouched file data was written
+        let first = offset / block;
+        let last = (offset + len - 1) / block;
+        let untouched: BTreeSet<&[u8]> = model
+            .chunks(block)
+            .enumerate()
+            .filter(|(index, _)| *index < first || *index > last)
+            .map(|(_, chunk)| chunk)
+            .collect();
+        let changed = changed_blocks(&before, &after, block);
+        for index in &changed {
+            let old = &before[index * block..(index + 1) * block];
+            assert!(
+                !untouched.contains(old),
+                "patching {}+{} rewrote device block {} of untouched data",
+                offset,
+                len,
+                index
+            );
+        }
+        assert!(
+            changed.len() <= last - first + 1 + MAX_METADATA_BLOCKS,
+            "patching {}+{} wrote {} blocks",
+            offset,
+            len,
+            changed.len()
+        );
+    }
+    assert!(whole(ctx)? == model);
+
+    // --length caps how much of the input is written
+    let input = content(8, 100);
+    ctx.command(&[
+        "write", "--path", "/big.bin", "--offset", "5000", "--length", "10",
+    ])
+    .write_stdin(input.clone())
+    .assert()
+    .success();
+    model[5000..5010].copy_from_slice(&input[..10]);
+    assert!(whole(ctx)? == model, "write --length wrote past its length");
+    Ok(())
+}
+
+pub(crate) fn reads_past_eof_error(ctx: &TestContext) -> io::Result<()> {
+    big_file(ctx)?;
+    let size = BIG_LEN.to_string();
+    let past = (BIG_LEN + 1).to_string();
+    let near_end = (BIG_LEN - 10).to_string();
+
+    for (offset, length) in [(&past, "1"), (&near_end, "11"), (&size, "1")] {
+        ctx.command(&[
+            "read", "--path", "/big.bin", "--offset", offset, "--length", length,
+        ])
+        .assert()
+        .code(EXIT_INVALID)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("end of file").and(predicate::str::contains("/big.bin")));
+    }
+    ctx.command(&["read", "--path", "/big.bin", "--offset", &past])
+        .assert()
+        .code(EXIT_INVALID);
+
+    for (flag, value) in [("--offset", "-1"), ("--offset", "abc"), ("--length", "1.5")] {
+        ctx.command(&["read", "--path", "/big.bin", flag, value])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains(flag));
+    }
+    ctx.command(&["write", "--path", "/big.bin", "--offset", "-1"])
+        .write_stdin("x")
+        .assert()
+        .code(EXIT_USAGE);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    read_ranges_exact(partial_context()?),
+    #[contract]
+    writes_patch_in_place(partial_context()?),
+    #[contract]
+    untouched_blocks_not_rewritten(partial_context()?),
+    #[contract]
+    reads_past_eof_error(partial_context()?),
+}
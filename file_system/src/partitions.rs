@@ -0,0 +1,264 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Partition tables. `partition create --size BYTES` adds a partition to the
+// device, writing the table first if there is none, `partition list` prints
+// "<index>\t<start>\t<size>" per partition in bytes, and `partition delete
+// --index N` drops one. Indexes start at 1 and are never reused while the
+// partition exists. The global `--partition N` makes every other command
+// operate inside partition N as if it were the whole device.
+
+use crate::capacity::EXIT_NO_SPACE;
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, listed_names};
+use crate::errors::EXIT_NOT_FOUND;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, stat_field, write_file, TestContext};
+use crate::recursive_remove::recursive_remove_frees_everything;
+use crate::stat::stat_reports_file;
+use std::collections::BTreeSet;
+use std::io;
+use std::process::Output;
+
+const DEVICE_SIZE: u64 = 32 * 1024 * 1024;
+const PARTITION_SIZE: u64 = 12 * 1024 * 1024;
+const BLOCK_SIZE: u32 = 4096;
+
+#[derive(Debug, PartialEq)]
+struct Partition {
+    index: u32,
+    start: u64,
+    size: u64,
+}
+
+// None unless every line is three tab-separated numbers
+fn parse_partitions(stdout: &str) -> Option<Vec<Partition>> {
+    stdout
+        .lines()
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let partition = Partition {
+                index: fields.next()?.parse().ok()?,
+                start: fields.next()?.parse().ok()?,
+                size: fields.next()?.parse().ok()?,
+            };
+            fields.next().is_none().then_some(partition)
+        })
+        .collect()
+}
+
+fn partitions(ctx: &TestContext) -> io::Result<Vec<Partition>> {
+    let output = ctx.run_bellande_command(&["partition", "list"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let listed = parse_partitions(&stdout)
+        .unwrap_or_else(|| panic!("partition list is not index/start/size lines: {:?}", stdout));
+    for (index, partition) in listed.iter().enumerate() {
+        assert!(
+            partition.start + partition.size <= DEVICE_SIZE,
+            "{:?}",
+            partition
+        );
+        if let Some(next) = listed.get(index + 1) {
+            assert!(
+                partition.start + partition.size <= next.start,
+                "{:?} overlaps {:?}",
+                partition,
+                next
+            );
+        }
+    }
+    Ok(listed)
+}
+
+fn create_partition(ctx: &TestContext, size: u64) -> io::Result<()> {
+    ctx.run_bellande_command(&["partition", "create", "--size", &size.to_string()])?;
+    Ok(())
+}
+
+fn raw_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// A device with two partitions, every command of the result inside `index`
+fn partitioned_context(index: u32) -> io::Result<TestContext> {
+    let ctx = raw_context()?;
+    create_partition(&ctx, PARTITION_SIZE)?;
+    create_partition(&ctx, PARTITION_SIZE)?;
+    Ok(ctx.with_partition(index))
+}
+
+// `args` inside partition `index`
+fn inside(index: u32, args: &[&str]) -> Vec<String> {
+    ["--partition".to_string(), index.to_string()]
+        .into_iter()
+        .chain(args.iter().map(|arg| arg.to_string()))
+        .collect()
+}
+
+fn run_inside(ctx: &TestContext, index: u32, args: &[&str]) -> io::Result<Output> {
+    let args = inside(index, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    ctx.run_bellande_command(&args)
+}
+
+pub(crate) fn partitions_are_independent(ctx: &TestContext) -> io::Result<()> {
+    for _ in 0..2 {
+        create_partition(ctx, PARTITION_SIZE)?;
+    }
+    let listed = partitions(ctx)?;
+    assert_eq!(listed.iter().map(|p| p.index).collect::<Vec<_>>(), [1, 2]);
+    assert!(listed.iter().all(|p| p.size >= PARTITION_SIZE));
+
+    for index in [1, 2] {
+        let block_size = BLOCK_SIZE.to_string();
+        run_inside(
+            ctx,
+            index,
+            &["format", "--yes", "--block-size", &block_size],
+        )?;
+        let path = format!("/only_in_{}", index);
+        run_inside(ctx, index, &["create", "--path", &path])?;
+        let args = inside(index, &["write", "--path", &path]);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        ctx.command(&args)
+            .write_stdin(content(u64::from(index), 50_000))
+            .assert()
+            .success();
+    }
+
+    // Each partition sees only its own tree and its own size
+    for index in [1, 2] {
+        let output = run_inside(ctx, index, &["list", "--path", "/"])?;
+        assert_eq!(
+            listed_names(&String::from_utf8_lossy(&output.stdout)),
+            BTreeSet::from([format!("only_in_{}", index)])
+        );
+        let output = run_inside(
+            ctx,
+            index,
+            &["read", "--path", &format!("/only_in_{}", index)],
+        )?;
+        assert!(output.stdout == content(u64::from(index), 50_000));
+    }
+    let output = run_inside(ctx, 1, &["stats"])?;
+    let total_blocks = stat_field(&String::from_utf8_lossy(&output.stdout), "Total blocks")?;
+    assert!(total_blocks * u64::from(BLOCK_SIZE) <= listed[0].size);
+
+    // Filling one partition leaves the other untouched
+    let filler = vec![0x5A; listed[0].size as usize];
+    run_inside(ctx, 1, &["create", "--path", "/fill"])?;
+    let args = inside(1, &["write", "--path", "/fill"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    ctx.command(&args)
+        .write_stdin(filler)
+        .assert()
+        .code(EXIT_NO_SPACE);
+    let output = run_inside(ctx, 2, &["read", "--path", "/only_in_2"])?;
+    assert!(output.stdout == content(2, 50_000));
+    run_inside(ctx, 2, &["fsck"])?;
+
+    // Deleting one keeps the other, and its index
+    ctx.run_bellande_command(&["partition", "delete", "--index", "1"])?;
+    let listed = partitions(ctx)?;
+    assert_eq!(listed.iter().map(|p| p.index).collect::<Vec<_>>(), [2]);
+    let output = run_inside(ctx, 2, &["read", "--path", "/only_in_2"])?;
+    assert!(output.stdout == content(2, 50_000));
+    Ok(())
+}
+
+pub(crate) fn commands_work_in_partition() -> io::Result<()> {
+    // Unchanged scenarios, every command carrying `--partition 2`
+    stat_reports_file(&partitioned_context(2)?)?;
+    recursive_remove_frees_everything(&partitioned_context(2)?)?;
+
+    let ctx = partitioned_context(2)?;
+    format_device(&ctx)?;
+    let empty = read_stats(&ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/in_two"])?;
+    assert!(write_file(&ctx, "/in_two", &content(3, 20_000))?
+        .status
+        .success());
+    assert!(read_stats(&ctx)?.free_blocks < empty.free_blocks);
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn partition_errors(ctx: &TestContext) -> io::Result<()> {
+    // No table yet
+    ctx.command(&["--partition", "1", "stats"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert!(partitions(ctx)?.is_empty());
+
+    create_partition(ctx, PARTITION_SIZE)?;
+    ctx.command(&["--partition", "2", "stats"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["partition", "delete", "--index", "7"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["partition", "create", "--size", &DEVICE_SIZE.to_string()])
+        .assert()
+        .code(EXIT_NO_SPACE);
+    for args in [
+        &["--partition", "0", "stats"][..],
+        &["--partition", "first", "stats"][..],
+        &["partition", "create"][..],
+        &["partition", "create", "--size", "lots"][..],
+        &["partition", "delete"][..],
+        &["partition", "bogus"][..],
+        &["partition"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    assert_eq!(partitions(ctx)?.len(), 1);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    partitions_are_independent(raw_context()?),
+    #[contract]
+    commands_work_in_partition(),
+    #[contract]
+    partition_errors(raw_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partitions() {
+        assert_eq!(
+            parse_partitions("1\t4096\t1048576\n2\t1052672\t2048\n"),
+            Some(vec![
+                Partition {
+                    index: 1,
+                    start: 4096,
+                    size: 1_048_576
+                },
+                Partition {
+                    index: 2,
+                    start: 1_052_672,
+                    size: 2048
+                },
+            ])
+        );
+        assert_eq!(parse_partitions(""), Some(Vec::new()));
+        assert_eq!(parse_partitions("1\t4096\n"), None);
+        assert_eq!(parse_partitions("1\t4096\t10\t9\n"), None);
+        assert_eq!(parse_partitions("one\t0\t10\n"), None);
+    }
+}
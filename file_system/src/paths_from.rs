@@ -0,0 +1,137 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `--paths-from <file or ->`: bulk operations read their targets from a
+// newline- (or with `--null`, NUL-) delimited list instead of argv.
+
+use crate::differential::listed_names;
+use crate::harness::{format_device, read_stats, scenarios, TestContext};
+use predicates::prelude::*;
+use std::fs;
+use std::io;
+
+const BULK_DEVICE_SIZE: u64 = 64 * 1024 * 1024;
+const BULK_INODES: &str = "16384";
+const BULK_DIRS: usize = 10;
+const BULK_PATHS: usize = 10_000;
+
+fn bulk_context() -> io::Result<TestContext> {
+    Ok(TestContext::with_options(BULK_DEVICE_SIZE, None)?
+        .with_format_args(&["--inodes", BULK_INODES]))
+}
+
+fn bulk_paths() -> Vec<String> {
+    (0..BULK_PATHS)
+        .map(|index| format!("/bulk{}/file{}", index % BULK_DIRS, index))
+        .collect()
+}
+
+pub(crate) fn bulk_remove_frees_inodes(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for dir in 0..BULK_DIRS {
+        ctx.run_bellande_command(&["mkdir", "--path", &format!("/bulk{}", dir)])?;
+    }
+    let empty = read_stats(ctx)?;
+
+    // Created from a list file, removed from stdin
+    let paths = bulk_paths();
+    let list = ctx.temp_dir.path().join("paths.txt");
+    fs::write(&list, paths.join("\n") + "\n")?;
+    ctx.command(&["touch", "--paths-from"])
+        .arg(&list)
+        .assert()
+        .success();
+    let populated = read_stats(ctx)?;
+    assert_eq!(populated.free_inodes, empty.free_inodes - BULK_PATHS as u64);
+
+    ctx.command(&["remove", "--paths-from", "-"])
+        .write_stdin(paths.join("\n"))
+        .assert()
+        .success();
+    assert_eq!(
+        read_stats(ctx)?,
+        empty,
+        "removing the list did not free every inode"
+    );
+    Ok(())
+}
+
+pub(crate) fn null_delimited_paths(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let paths = ["/with space", "/with\nnewline", "/plain"];
+    ctx.command(&["touch", "--null", "--paths-from", "-"])
+        .write_stdin(paths.join("\0"))
+        .assert()
+        .success();
+
+    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    let names = listed_names(&String::from_utf8_lossy(&output.stdout));
+    assert!(names.contains("with space") && names.contains("plain"));
+
+    ctx.command(&["checksum", "--null", "--paths-from", "-"])
+        .write_stdin(paths.join("\0") + "\0")
+        .assert()
+        .success();
+    ctx.command(&["remove", "--null", "--paths-from", "-"])
+        .write_stdin(paths.join("\0"))
+        .assert()
+        .success();
+    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    assert!(listed_names(&String::from_utf8_lossy(&output.stdout)).is_empty());
+    Ok(())
+}
+
+pub(crate) fn malformed_lines_report_line_numbers(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "/m1", "/m2"])?;
+
+    ctx.command(&["remove", "--paths-from", "-"])
+        .write_stdin("/m1\nrelative\n/m2\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 2"));
+
+    // The well-formed lines around it were still processed
+    let output = ctx.run_bellande_command(&["list", "--path", "/"])?;
+    assert!(listed_names(&String::from_utf8_lossy(&output.stdout)).is_empty());
+
+    ctx.command(&["stat", "--paths-from", "-"])
+        .write_stdin("/\n\0bad\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 2"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    bulk_remove_frees_inodes(bulk_context()?),
+    #[contract]
+    null_delimited_paths,
+    #[contract]
+    malformed_lines_report_line_numbers,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulk_paths() {
+        let paths = bulk_paths();
+        assert_eq!(paths.len(), BULK_PATHS);
+        assert_eq!(paths[11], "/bulk1/file11");
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Ownership and permission checks. Every inode has a uid, gid and mode;
+// `chmod --mode` and `chown --uid/--gid` change them. Commands run with no
+// identity, or as uid 0, are not checked, as before. With `--user UID
+// --group GID`, new inodes belong to that identity and the usual POSIX rules
+// apply: owner, group or other bits by identity, search permission on every
+// directory on the way, write and search on the parent to remove, and under
+// the sticky bit only the owner removes. Failed checks exit 13 (EACCES);
+// chmod by a non-owner and chown by anyone but root exit 1 (EPERM).
+
+use crate::cli::EXIT_USAGE;
+use crate::create_mode::{parse_octal_mode, INVALID_MODES};
+use crate::differential::content;
+use crate::errors::{EXIT_NOT_FOUND, EXIT_NOT_PERMITTED, EXIT_PERMISSION_DENIED};
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::{json_field, json_u64};
+use std::io;
+
+const OWNER: (&str, &str) = ("1000", "100");
+const SAME_GROUP: (&str, &str) = ("2000", "100");
+const OTHER: (&str, &str) = ("2000", "200");
+const ROOT: (&str, &str) = ("0", "0");
+
+#[derive(Debug, PartialEq)]
+struct Ownership {
+    mode: u32,
+    uid: u64,
+    gid: u64,
+}
+
+fn ownership(ctx: &TestContext, path: &str) -> io::Result<Ownership> {
+    let output = ctx.run_bellande_command(&["stat", "--format", "json", "--path", path])?;
+    let json = String::from_utf8_lossy(&output.stdout);
+    let missing = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("stat {} is missing ownership: {:?}", path, json),
+        )
+    };
+    Ok(Ownership {
+        mode: json_field(&json, "mode")
+            .and_then(|mode| parse_octal_mode(&mode))
+            .ok_or_else(missing)?,
+        uid: json_u64(&json, "uid").ok_or_else(missing)?,
+        gid: json_u64(&json, "gid").ok_or_else(missing)?,
+    })
+}
+
+// `args` run as `identity`
+fn as_user(identity: (&str, &str), args: &[&str]) -> Vec<String> {
+    let (user, group) = identity;
+    ["--user", user, "--group", group]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+fn exit_as(ctx: &TestContext, identity: (&str, &str), args: &[&str]) -> io::Result<Option<i32>> {
+    let args = as_user(identity, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut command = ctx.command(&args);
+    if args.contains(&"write") {
+        command.write_stdin(b"intruder".to_vec());
+    }
+    Ok(command.output()?.status.code())
+}
+
+fn chmod(ctx: &TestContext, path: &str, mode: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["chmod", "--path", path, "--mode", mode])?;
+    Ok(())
+}
+
+fn chown(ctx: &TestContext, path: &str, identity: (&str, &str)) -> io::Result<()> {
+    let (uid, gid) = identity;
+    ctx.run_bellande_command(&["chown", "--path", path, "--uid", uid, "--gid", gid])?;
+    Ok(())
+}
+
+pub(crate) fn chmod_chown_recorded(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/f"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/d"])?;
+    assert_eq!(
+        ownership(ctx, "/f")?.uid,
+        0,
+        "unowned inodes belong to root"
+    );
+
+    for (mode, expected) in [
+        ("0640", 0o640),
+        ("4755", 0o4755),
+        ("0", 0),
+        ("1777", 0o1777),
+    ] {
+        chmod(ctx, "/d", mode)?;
+        assert_eq!(ownership(ctx, "/d")?.mode, expected, "chmod {}", mode);
+    }
+    let mode = ownership(ctx, "/f")?.mode;
+    chown(ctx, "/f", OWNER)?;
+    assert_eq!(
+        ownership(ctx, "/f")?,
+        Ownership {
+            mode,
+            uid: 1000,
+            gid: 100,
+        },
+        "chown changed the mode"
+    );
+    // Either half alone leaves the other
+    ctx.run_bellande_command(&["chown", "--path", "/f", "--gid", "300"])?;
+    let after = ownership(ctx, "/f")?;
+    assert_eq!((after.uid, after.gid), (1000, 300));
+    ctx.run_bellande_command(&["chown", "--path", "/f", "--uid", "7"])?;
+    let after = ownership(ctx, "/f")?;
+    assert_eq!((after.uid, after.gid), (7, 300));
+
+    for mode in INVALID_MODES {
+        ctx.command(&["chmod", "--path", "/f", "--mode", mode])
+            .assert()
+            .code(EXIT_USAGE);
+    }
+    for args in [
+        &["chown", "--path", "/f"][..],
+        &["chown", "--path", "/f", "--uid", "alice"],
+        &["chown", "--path", "/f", "--gid", "-1"],
+        &["chmod", "--path", "/f"],
+        &["--user", "1000", "stats"],
+        &["--group", "100", "stats"],
+        &["--user", "root", "--group", "0", "stats"],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    ctx.command(&["chmod", "--path", "/missing", "--mode", "0644"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["chown", "--path", "/missing", "--uid", "1"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    Ok(())
+}
+
+pub(crate) fn access_enforced(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/home"])?;
+    ctx.run_bellande_command(&["create", "--path", "/home/notes"])?;
+    let data = content(1, 3000);
+    assert!(write_file(ctx, "/home/notes", &data)?.status.success());
+    chown(ctx, "/home", OWNER)?;
+    chown(ctx, "/home/notes", OWNER)?;
+    chmod(ctx, "/home", "0755")?;
+    chmod(ctx, "/home/notes", "0640")?;
+
+    let read = ["read", "--path", "/home/notes"];
+    let write = ["write", "--path", "/home/notes"];
+    let list = ["list", "--path", "/home"];
+    for (identity, args, allowed) in [
+        (OWNER, &read[..], true),
+        (OWNER, &write[..], true),
+        (SAME_GROUP, &read[..], true),
+        (SAME_GROUP, &write[..], false),
+        (OTHER, &read[..], false),
+        (OTHER, &write[..], false),
+        (OTHER, &list[..], true),
+        (ROOT, &read[..], true),
+    ] {
+        let expected = if allowed {
+            Some(0)
+        } else {
+            Some(EXIT_PERMISSION_DENIED)
+        };
+        assert_eq!(
+            exit_as(ctx, identity, args)?,
+            expected,
+            "{:?} {:?}",
+            identity,
+            args
+        );
+    }
+    // Put back what the owner's write replaced
+    assert!(write_file(ctx, "/home/notes", &data)?.status.success());
+
+    // Without search permission on /home, group read access is useless
+    chmod(ctx, "/home", "0700")?;
+    assert_eq!(
+        exit_as(ctx, SAME_GROUP, &read)?,
+        Some(EXIT_PERMISSION_DENIED)
+    );
+    assert_eq!(
+        exit_as(ctx, SAME_GROUP, &list)?,
+        Some(EXIT_PERMISSION_DENIED)
+    );
+    assert_eq!(exit_as(ctx, OWNER, &read)?, Some(0));
+
+    // Removing needs write on the parent, not on the file
+    let remove = ["remove", "--path", "/home/notes"];
+    chmod(ctx, "/home", "0755")?;
+    chmod(ctx, "/home/notes", "0666")?;
+    assert_eq!(
+        exit_as(ctx, SAME_GROUP, &remove)?,
+        Some(EXIT_PERMISSION_DENIED)
+    );
+    chmod(ctx, "/home", "0775")?;
+    chmod(ctx, "/home/notes", "0000")?;
+    assert_eq!(exit_as(ctx, SAME_GROUP, &remove)?, Some(0));
+
+    // Sticky /tmp: anyone creates, only the owner removes
+    ctx.run_bellande_command(&["mkdir", "--path", "/tmp"])?;
+    chmod(ctx, "/tmp", "1777")?;
+    assert_eq!(
+        exit_as(ctx, OWNER, &["create", "--path", "/tmp/mine"])?,
+        Some(0)
+    );
+    let mine = ownership(ctx, "/tmp/mine")?;
+    assert_eq!(
+        (mine.uid, mine.gid),
+        (1000, 100),
+        "new inodes take the identity"
+    );
+    let remove = ["remove", "--path", "/tmp/mine"];
+    assert_eq!(exit_as(ctx, OTHER, &remove)?, Some(EXIT_PERMISSION_DENIED));
+    assert_eq!(exit_as(ctx, OWNER, &remove)?, Some(0));
+
+    // No identity means no checks, as before
+    chmod(ctx, "/home/notes", "0000")?;
+    ctx.run_bellande_command(&["read", "--path", "/home/notes"])?;
+    Ok(())
+}
+
+pub(crate) fn ownership_changes_restricted(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for path in ["/mine", "/theirs"] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+    }
+    chown(ctx, "/mine", OWNER)?;
+    chown(ctx, "/theirs", OTHER)?;
+
+    let chmod_as =
+        |identity, path| exit_as(ctx, identity, &["chmod", "--path", path, "--mode", "0600"]);
+    assert_eq!(chmod_as(OWNER, "/mine")?, Some(0));
+    assert_eq!(ownership(ctx, "/mine")?.mode, 0o600);
+    assert_eq!(chmod_as(OWNER, "/theirs")?, Some(EXIT_NOT_PERMITTED));
+    assert_eq!(chmod_as(ROOT, "/theirs")?, Some(0));
+
+    // Giving a file away is root's alone, even for its owner
+    let give = ["chown", "--path", "/mine", "--uid", "2000"];
+    assert_eq!(exit_as(ctx, OWNER, &give)?, Some(EXIT_NOT_PERMITTED));
+    assert_eq!(ownership(ctx, "/mine")?.uid, 1000);
+    assert_eq!(exit_as(ctx, ROOT, &give)?, Some(0));
+    assert_eq!(ownership(ctx, "/mine")?.uid, 2000);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    chmod_chown_recorded,
+    #[contract]
+    access_enforced,
+    #[contract]
+    ownership_changes_restricted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_user() {
+        assert_eq!(
+            as_user(OWNER, &["stats"]),
+            ["--user", "1000", "--group", "100", "stats"]
+        );
+    }
+}
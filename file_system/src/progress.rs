@@ -0,0 +1,103 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Progress reporting. Under the harness stderr is a pipe, so long operations
+// must log single-line percentages there (never on stdout), and `--quiet`
+// must silence them.
+
+use crate::harness::{scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+const PROGRESS_DEVICE_SIZE: u64 = 64 * 1024 * 1024;
+
+// Percentages from lines like "format: 42% (zeroing blocks)"
+pub(crate) fn progress_percentages(stderr: &str) -> Vec<u32> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let (before, _) = line.split_once('%')?;
+            let digits: String = before
+                .chars()
+                .rev()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            digits.chars().rev().collect::<String>().parse().ok()
+        })
+        .collect()
+}
+
+fn progress_context() -> io::Result<TestContext> {
+    TestContext::with_options(PROGRESS_DEVICE_SIZE, None)
+}
+
+pub(crate) fn full_format_reports_progress(ctx: &TestContext) -> io::Result<()> {
+    let output = ctx
+        .command(&["format", "--yes", "--full"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Device formatted successfully"))
+        .stdout(predicate::str::contains("%").not());
+
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+    let percentages = progress_percentages(&stderr);
+    assert!(
+        !percentages.is_empty(),
+        "no progress lines on stderr: {:?}",
+        stderr
+    );
+    assert!(
+        percentages.windows(2).all(|pair| pair[0] <= pair[1]),
+        "progress went backwards: {:?}",
+        percentages
+    );
+    assert_eq!(percentages.last(), Some(&100));
+
+    // Not a TTY, so no carriage-return redraws or escape sequences
+    assert!(!stderr.contains('\r') && !stderr.contains('\x1b'));
+    Ok(())
+}
+
+pub(crate) fn quiet_suppresses_progress(ctx: &TestContext) -> io::Result<()> {
+    let output = ctx
+        .command(&["--quiet", "format", "--yes", "--full"])
+        .assert()
+        .success();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+    assert!(
+        progress_percentages(&stderr).is_empty(),
+        "--quiet still reported progress: {:?}",
+        stderr
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    full_format_reports_progress(progress_context()?),
+    #[contract]
+    quiet_suppresses_progress(progress_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_percentages() {
+        let stderr = "format: 5% (zeroing)\nwarning: slow device\nformat: 100%\n";
+        assert_eq!(progress_percentages(stderr), vec![5, 100]);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Block and inode quotas, per user and per directory. `quota set` with
+// `--user UID` or `--dir PATH`, and `--blocks N` and/or `--inodes N` (0 for
+// no limit), records a limit in the quota file; `quota get` prints "Blocks
+// used", "Blocks limit", "Inodes used" and "Inodes limit" lines, and `quota
+// report` prints "<kind>\t<id>\t<blocks used>\t<blocks limit>\t<inodes
+// used>\t<inodes limit>" per quota, users then directories. A user quota
+// counts what that uid owns, a directory quota everything below it, and an
+// allocation over either fails with EXIT_QUOTA_EXCEEDED and "quota exceeded"
+// without taking any of the blocks it asked for.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_NOT_FOUND, EXIT_NOT_PERMITTED, EXIT_QUOTA_EXCEEDED};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const QUOTA_MESSAGE: &str = "quota exceeded";
+const USER: &[&str] = &["--user", "1000", "--group", "100"];
+const OTHER_USER: &[&str] = &["--user", "2000", "--group", "100"];
+
+#[derive(Debug, PartialEq)]
+struct Usage {
+    blocks_used: u64,
+    blocks_limit: u64,
+    inodes_used: u64,
+    inodes_limit: u64,
+}
+
+fn quota_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn as_identity(identity: &[&str], args: &[&str]) -> Vec<String> {
+    identity
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+fn quota_get(ctx: &TestContext, target: &[&str]) -> io::Result<Usage> {
+    let mut args = vec!["quota", "get"];
+    args.extend(target);
+    let output = ctx.run_bellande_command(&args)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(Usage {
+        blocks_used: stat_field(&stdout, "Blocks used")?,
+        blocks_limit: stat_field(&stdout, "Blocks limit")?,
+        inodes_used: stat_field(&stdout, "Inodes used")?,
+        inodes_limit: stat_field(&stdout, "Inodes limit")?,
+    })
+}
+
+fn quota_set(ctx: &TestContext, target: &[&str], blocks: u64, inodes: u64) -> io::Result<()> {
+    let (blocks, inodes) = (blocks.to_string(), inodes.to_string());
+    let mut args = vec!["quota", "set"];
+    args.extend(target);
+    args.extend(["--blocks", &blocks, "--inodes", &inodes]);
+    ctx.run_bellande_command(&args)?;
+    Ok(())
+}
+
+// Runs `args` as `identity` writing `data`, returning the exit code
+fn write_as(
+    ctx: &TestContext,
+    identity: &[&str],
+    args: &[&str],
+    data: &[u8],
+) -> io::Result<Option<i32>> {
+    let args = as_identity(identity, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = ctx.command(&args).write_stdin(data.to_vec()).output()?;
+    if output.status.code() == Some(EXIT_QUOTA_EXCEEDED) {
+        assert!(
+            String::from_utf8_lossy(&output.stderr)
+                .to_lowercase()
+                .contains(QUOTA_MESSAGE),
+            "{:?}: {:?}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.status.code())
+}
+
+fn parse_report(stdout: &str) -> Option<Vec<(String, String, [u64; 4])>> {
+    stdout
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [kind, id, rest @ ..] = fields.as_slice() else {
+                return None;
+            };
+            let numbers: Vec<u64> = rest
+                .iter()
+                .map(|field| field.parse().ok())
+                .collect::<Option<_>>()?;
+            Some((kind.to_string(), id.to_string(), numbers.try_into().ok()?))
+        })
+        .collect()
+}
+
+pub(crate) fn user_quota_enforced(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--mode", "777", "--path", "/shared"])?;
+    quota_set(ctx, &["--user", "1000"], 20, 3)?;
+    assert_eq!(
+        quota_get(ctx, &["--user", "1000"])?,
+        Usage {
+            blocks_used: 0,
+            blocks_limit: 20,
+            inodes_used: 0,
+            inodes_limit: 3
+        }
+    );
+
+    // Within the limit, usage follows what the user owns
+    for path in ["/shared/a", "/shared/b"] {
+        assert_eq!(
+            write_as(ctx, USER, &["create", "--path", path], &[])?,
+            Some(0)
+        );
+    }
+    assert_eq!(
+        write_as(
+            ctx,
+            USER,
+            &["write", "--path", "/shared/a"],
+            &content(1, 12 * BLOCK)
+        )?,
+        Some(0)
+    );
+    let usage = quota_get(ctx, &["--user", "1000"])?;
+    assert_eq!((usage.inodes_used, usage.blocks_used >= 12), (2, true));
+
+    // Over the block limit: refused whole, nothing taken
+    let free = read_stats(ctx)?.free_blocks;
+    assert_eq!(
+        write_as(
+            ctx,
+            USER,
+            &["write", "--path", "/shared/b"],
+            &content(2, 12 * BLOCK)
+        )?,
+        Some(EXIT_QUOTA_EXCEEDED)
+    );
+    assert_eq!(read_stats(ctx)?.free_blocks, free);
+    assert_eq!(quota_get(ctx, &["--user", "1000"])?, usage);
+
+    // Over the inode limit
+    assert_eq!(
+        write_as(ctx, USER, &["create", "--path", "/shared/c"], &[])?,
+        Some(0)
+    );
+    assert_eq!(
+        write_as(ctx, USER, &["create", "--path", "/shared/d"], &[])?,
+        Some(EXIT_QUOTA_EXCEEDED)
+    );
+
+    // Other identities are not counted against it
+    assert_eq!(
+        write_as(ctx, OTHER_USER, &["create", "--path", "/shared/other"], &[])?,
+        Some(0)
+    );
+    assert_eq!(
+        write_as(
+            ctx,
+            OTHER_USER,
+            &["write", "--path", "/shared/other"],
+            &content(3, 30 * BLOCK)
+        )?,
+        Some(0)
+    );
+
+    // Removing gives the quota back
+    assert_eq!(
+        write_as(ctx, USER, &["remove", "--path", "/shared/a"], &[])?,
+        Some(0)
+    );
+    assert_eq!(
+        write_as(
+            ctx,
+            USER,
+            &["write", "--path", "/shared/b"],
+            &content(2, 12 * BLOCK)
+        )?,
+        Some(0)
+    );
+    let output = ctx.run_bellande_command(&["read", "--path", "/shared/b"])?;
+    assert!(output.stdout == content(2, 12 * BLOCK));
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn directory_quota_enforced(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--parents", "--path", "/project/sub"])?;
+    ctx.run_bellande_command(&["create", "--path", "/project/existing"])?;
+    assert!(
+        write_file(ctx, "/project/existing", &content(4, 5 * BLOCK))?
+            .status
+            .success()
+    );
+    // Existing contents count from the moment the quota is set
+    quota_set(ctx, &["--dir", "/project"], 16, 0)?;
+    let usage = quota_get(ctx, &["--dir", "/project"])?;
+    assert!(
+        usage.blocks_used >= 5 && usage.inodes_used >= 2,
+        "{:?}",
+        usage
+    );
+    assert_eq!(usage.inodes_limit, 0);
+
+    // Anywhere below the directory, appends included
+    ctx.run_bellande_command(&["create", "--path", "/project/sub/deep"])?;
+    let append = ["write", "--append", "--path", "/project/sub/deep"];
+    assert_eq!(
+        write_as(ctx, &[], &append, &content(5, 20 * BLOCK))?,
+        Some(EXIT_QUOTA_EXCEEDED)
+    );
+    assert_eq!(
+        write_as(ctx, &[], &append, &content(5, 4 * BLOCK))?,
+        Some(0)
+    );
+
+    // Outside it nothing changes
+    ctx.run_bellande_command(&["create", "--path", "/outside"])?;
+    assert!(write_file(ctx, "/outside", &content(6, 40 * BLOCK))?
+        .status
+        .success());
+
+    // Raising the limit lets the same write through
+    quota_set(ctx, &["--dir", "/project"], 64, 0)?;
+    assert_eq!(
+        write_as(ctx, &[], &append, &content(5, 20 * BLOCK))?,
+        Some(0)
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn quota_report_and_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/project"])?;
+    quota_set(ctx, &["--dir", "/project"], 100, 10)?;
+    quota_set(ctx, &["--user", "1000"], 50, 0)?;
+    quota_set(ctx, &["--user", "2000"], 0, 5)?;
+
+    let output = ctx.run_bellande_command(&["quota", "report"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let report = parse_report(&stdout)
+        .unwrap_or_else(|| panic!("quota report is not kind/id/usage lines: {:?}", stdout));
+    let ids: Vec<(&str, &str)> = report
+        .iter()
+        .map(|(kind, id, _)| (kind.as_str(), id.as_str()))
+        .collect();
+    assert_eq!(
+        ids,
+        [("user", "1000"), ("user", "2000"), ("dir", "/project")]
+    );
+    assert_eq!(report[1].2[3], 5);
+    assert_eq!(report[2].2[1], 100);
+
+    // A limit of 0 removes it
+    quota_set(ctx, &["--user", "2000"], 0, 0)?;
+    let output = ctx.run_bellande_command(&["quota", "report"])?;
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("2000"));
+
+    ctx.command(&["quota", "get", "--dir", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    let args = as_identity(USER, &["quota", "set", "--user", "1000", "--blocks", "0"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    ctx.command(&args).assert().code(EXIT_NOT_PERMITTED);
+    for args in [
+        &["quota", "set", "--blocks", "10"][..],
+        &[
+            "quota", "set", "--user", "1000", "--dir", "/project", "--blocks", "1",
+        ][..],
+        &["quota", "set", "--user", "1000", "--blocks", "-1"][..],
+        &["quota", "set", "--user", "1000"][..],
+        &["quota", "bogus"][..],
+        &["quota"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    user_quota_enforced(quota_context()?),
+    #[contract]
+    directory_quota_enforced(quota_context()?),
+    #[contract]
+    quota_report_and_errors(quota_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_report() {
+        let report = parse_report("user\t1000\t12\t50\t2\t0\ndir\t/a b\t0\t100\t1\t10\n").unwrap();
+        assert_eq!(
+            report[0],
+            ("user".to_string(), "1000".to_string(), [12, 50, 2, 0])
+        );
+        assert_eq!(report[1].1, "/a b");
+        assert!(parse_report("user\t1000\t12\t50\t2\n").is_none());
+        assert!(parse_report("user\t1000\tx\t50\t2\t0\n").is_none());
+        assert_eq!(parse_report(""), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `copy --recursive`: a directory is replicated under the destination and
+// the copy must match the source fixture entry for entry and byte for byte.
+// Copying a directory into itself or a descendant is refused before anything
+// is written.
+
+use crate::differential::listed_names;
+use crate::errors::{EXIT_INVALID, EXIT_IS_DIRECTORY};
+use crate::fixtures::{
+    cached_fixture, deep_nesting_spec, medium_spec, pathological_names_spec, populate, tiny_spec,
+    verify_contents, verify_tree, FixtureSpec,
+};
+use crate::harness::{scenarios, TestContext};
+use crate::progress::progress_percentages;
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::io;
+
+fn copy_tree(ctx: &TestContext, source: &str, destination: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["copy", "--recursive", "--from", source, "--to", destination])?;
+    Ok(())
+}
+
+// The source under /src and its copy under /dst, side by side
+fn copied(spec: &FixtureSpec) -> FixtureSpec {
+    let mut both = spec.under("/src");
+    both.entries.extend(spec.under("/dst").entries);
+    both
+}
+
+pub(crate) fn recursive_copy_round_trip(ctx: &TestContext) -> io::Result<()> {
+    for spec in [tiny_spec(), pathological_names_spec(), deep_nesting_spec()] {
+        populate(ctx, &spec.under("/src"))?;
+        copy_tree(ctx, "/src", "/dst")?;
+
+        let both = copied(&spec);
+        verify_tree(ctx, &both)?;
+        verify_contents(ctx, &both)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn copy_into_descendant_rejected(ctx: &TestContext) -> io::Result<()> {
+    let source = tiny_spec().under("/src");
+    populate(ctx, &source)?;
+
+    for destination in ["/src/etc/inner", "/src", "/src/"] {
+        ctx.command(&["copy", "--recursive", "--from", "/src", "--to", destination])
+            .assert()
+            .code(EXIT_INVALID)
+            .stderr(predicate::str::contains("/src"));
+    }
+
+    // A directory needs --recursive, and nothing is half-copied without it
+    ctx.command(&["copy", "--from", "/src", "--to", "/dst"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY)
+        .stderr(predicate::str::contains("--recursive"));
+
+    verify_tree(ctx, &source)?;
+    Ok(())
+}
+
+pub(crate) fn recursive_copy_reports_progress() -> io::Result<()> {
+    let ctx = cached_fixture(&medium_spec())?;
+
+    let output = ctx
+        .command(&["copy", "--recursive", "--from", "/dir0", "--to", "/copy0"])
+        .assert()
+        .success();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+    let percentages = progress_percentages(&stderr);
+    assert!(
+        percentages.windows(2).all(|pair| pair[0] <= pair[1]),
+        "progress went backwards: {:?}",
+        percentages
+    );
+    assert_eq!(percentages.last(), Some(&100), "stderr: {:?}", stderr);
+
+    let output = ctx
+        .command(&[
+            "--quiet",
+            "copy",
+            "--recursive",
+            "--from",
+            "/dir1",
+            "--to",
+            "/copy1",
+        ])
+        .assert()
+        .success();
+    assert!(progress_percentages(&String::from_utf8_lossy(&output.get_output().stderr)).is_empty());
+
+    for (source, copy) in [("/dir0", "/copy0"), ("/dir1", "/copy1")] {
+        // Names only: the copies have their own inodes
+        let listing = |dir: &str| -> io::Result<BTreeSet<String>> {
+            let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+            Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+        };
+        assert_eq!(
+            listing(source)?,
+            listing(copy)?,
+            "{} and {} differ",
+            source,
+            copy
+        );
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    recursive_copy_round_trip,
+    #[contract]
+    copy_into_descendant_rejected,
+    #[contract]
+    recursive_copy_reports_progress(),
+}
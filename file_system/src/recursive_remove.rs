@@ -0,0 +1,169 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `remove --recursive` deletes a directory with everything below it, depth
+// first, and must give back every block and inode the tree held. Removing
+// `/` this way empties the root but keeps it, and needs `--force`. Like a
+// recursive copy, it logs progress percentages on stderr unless `--quiet`.
+
+use crate::differential::{content, listed_names};
+use crate::errors::{EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fixtures::{cached_fixture, medium_spec};
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use crate::progress::progress_percentages;
+use predicates::prelude::*;
+use std::collections::BTreeSet;
+use std::io;
+
+const TREE_FILES: u64 = 50;
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// 50 files of mixed sizes spread over nested directories under `root`
+fn build_tree(ctx: &TestContext, root: &str) -> io::Result<()> {
+    for index in 0..TREE_FILES {
+        let dir = format!("{}/d{}/sub{}", root, index % 5, index % 3);
+        ctx.run_bellande_command(&["mkdir", "--parents", "--path", &dir])?;
+        let path = format!("{}/file{}.bin", dir, index);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let data = content(index, (index as usize * 997) % 20_000);
+        assert!(
+            write_file(ctx, &path, &data)?.status.success(),
+            "write {}",
+            path
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn recursive_remove_frees_everything(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/keep.txt", "--content", "kept"])?;
+    let before = read_stats(ctx)?;
+
+    build_tree(ctx, "/testdir")?;
+    let built = read_stats(ctx)?;
+    assert!(built.free_inodes < before.free_inodes - TREE_FILES);
+    assert!(built.free_blocks < before.free_blocks);
+
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/testdir"])?;
+    assert_eq!(read_stats(ctx)?, before);
+    assert_eq!(
+        names_in(ctx, "/")?,
+        BTreeSet::from(["keep.txt".to_string()])
+    );
+    let output = ctx.run_bellande_command(&["read", "--path", "/keep.txt"])?;
+    assert_eq!(output.stdout, b"kept");
+
+    // Freed space is usable again for the same tree
+    build_tree(ctx, "/testdir")?;
+    assert_eq!(read_stats(ctx)?, built);
+    Ok(())
+}
+
+pub(crate) fn recursive_flag_required(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    build_tree(ctx, "/tree")?;
+    let built = read_stats(ctx)?;
+
+    ctx.command(&["remove", "--path", "/tree"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY)
+        .stderr(predicate::str::contains("/tree").and(predicate::str::contains("--recursive")));
+    assert_eq!(read_stats(ctx)?, built);
+
+    // Removing a subtree leaves its siblings alone
+    let mut expected = names_in(ctx, "/tree")?;
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/tree/d0"])?;
+    expected.remove("d0");
+    assert_eq!(names_in(ctx, "/tree")?, expected);
+
+    // --recursive on a file or an empty directory is an ordinary remove
+    ctx.run_bellande_command(&["create", "--path", "/lone.txt"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/empty"])?;
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/lone.txt"])?;
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/empty"])?;
+    assert_eq!(names_in(ctx, "/")?, BTreeSet::from(["tree".to_string()]));
+
+    ctx.command(&["remove", "--recursive", "--path", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("/missing"));
+    Ok(())
+}
+
+pub(crate) fn root_needs_force(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = read_stats(ctx)?;
+    build_tree(ctx, "/tree")?;
+    ctx.run_bellande_command(&["create", "--path", "/top.txt"])?;
+    let built = read_stats(ctx)?;
+
+    for path in ["/", "//"] {
+        ctx.command(&["remove", "--recursive", "--path", path])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--force"));
+    }
+    assert_eq!(read_stats(ctx)?, built);
+
+    ctx.run_bellande_command(&["remove", "--recursive", "--force", "--path", "/"])?;
+    assert!(names_in(ctx, "/")?.is_empty());
+    assert_eq!(read_stats(ctx)?, empty);
+    ctx.run_bellande_command(&["create", "--path", "/after.txt"])?;
+    Ok(())
+}
+
+pub(crate) fn recursive_remove_reports_progress() -> io::Result<()> {
+    let ctx = cached_fixture(&medium_spec())?;
+
+    let output = ctx
+        .command(&["remove", "--recursive", "--path", "/dir0"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("%").not());
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+    let percentages = progress_percentages(&stderr);
+    assert!(
+        percentages.windows(2).all(|pair| pair[0] <= pair[1]),
+        "progress went backwards: {:?}",
+        percentages
+    );
+    assert_eq!(percentages.last(), Some(&100), "stderr: {:?}", stderr);
+
+    let output = ctx
+        .command(&["--quiet", "remove", "--recursive", "--path", "/dir1"])
+        .assert()
+        .success();
+    assert!(progress_percentages(&String::from_utf8_lossy(&output.get_output().stderr)).is_empty());
+
+    let remaining = names_in(&ctx, "/")?;
+    assert!(!remaining.contains("dir0") && !remaining.contains("dir1"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    recursive_remove_frees_everything,
+    #[contract]
+    recursive_flag_required,
+    #[contract]
+    root_needs_force,
+    #[contract]
+    recursive_remove_reports_progress(),
+}
@@ -0,0 +1,483 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Replay files (`.bfsrepro`). A failing operation sequence is saved with the
+// outcome the std::fs model gave for every step; replaying it on a fresh
+// device must reproduce those outcomes. Files in tests/regressions run as
+// part of the suite.
+
+use crate::differential::{apply_bellande, content, Model, Op, PATHS};
+use crate::golden::checksum;
+use crate::harness::{format_device, scenarios, TestContext};
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// Bump when the line format changes or operations are added; older files
+// must keep parsing under the version they declare
+const REPRO_VERSION: u32 = 4;
+const REPRO_HEADER: &str = "bfsrepro";
+const REPRO_EXTENSION: &str = "bfsrepro";
+const REPRO_DIR_ENV: &str = "BELLANDE_FS_REPRO_DIR";
+
+// Error kinds the model and classify_error agree on; anything else is Other
+const ERROR_KINDS: &[(&str, ErrorKind)] = &[
+    ("NotFound", ErrorKind::NotFound),
+    ("AlreadyExists", ErrorKind::AlreadyExists),
+    ("NotADirectory", ErrorKind::NotADirectory),
+    ("IsADirectory", ErrorKind::IsADirectory),
+    ("DirectoryNotEmpty", ErrorKind::DirectoryNotEmpty),
+    ("InvalidInput", ErrorKind::InvalidInput),
+    ("Other", ErrorKind::Other),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Outcome {
+    Ok,
+    Data { len: usize, digest: u64 },
+    Error(&'static str),
+}
+
+impl Outcome {
+    fn from_result(result: &Result<Option<Vec<u8>>, ErrorKind>) -> Self {
+        match result {
+            Ok(None) => Outcome::Ok,
+            Ok(Some(data)) => Outcome::Data {
+                len: data.len(),
+                digest: checksum(data),
+            },
+            Err(kind) => Outcome::Error(
+                ERROR_KINDS
+                    .iter()
+                    .find(|(_, known)| known == kind)
+                    .map_or("Other", |(name, _)| name),
+            ),
+        }
+    }
+
+    // Reads are compared like the differential test: exact length and bytes
+    fn matches(&self, result: &Result<Option<Vec<u8>>, ErrorKind>) -> bool {
+        *self == Outcome::from_result(result)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Repro {
+    pub(crate) seed: Option<u64>,
+    pub(crate) steps: Vec<(Op, Outcome)>,
+}
+
+impl Repro {
+    // Records what the model does for each operation
+    pub(crate) fn record(seed: Option<u64>, ops: &[Op]) -> io::Result<Self> {
+        let model = Model::new()?;
+        let steps = ops
+            .iter()
+            .map(|op| (op.clone(), Outcome::from_result(&model.apply(op))))
+            .collect();
+        Ok(Repro { seed, steps })
+    }
+}
+
+fn render_op(op: &Op) -> String {
+    match op {
+        Op::Create(path) => format!("create {}", path),
+        Op::Mkdir(path) => format!("mkdir {}", path),
+        Op::Write { path, seed, len } => format!(
+            "write {} {} {} {:016x}",
+            path,
+            seed,
+            len,
+            checksum(&content(*seed, *len))
+        ),
+        Op::Remove(path) => format!("remove {}", path),
+        Op::Rmdir(path) => format!("rmdir {}", path),
+        Op::Read(path) => format!("read {}", path),
+        Op::Move { from, to } => format!("move {} {}", from, to),
+        Op::WriteAt {
+            path,
+            offset,
+            seed,
+            len,
+        } => format!(
+            "write-at {} {} {} {} {:016x}",
+            path,
+            offset,
+            seed,
+            len,
+            checksum(&content(*seed, *len))
+        ),
+        Op::ReadAt { path, offset, len } => format!("read-at {} {} {}", path, offset, len),
+        Op::Truncate { path, size } => format!("truncate {} {}", path, size),
+    }
+}
+
+fn render_outcome(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Ok => String::from("ok"),
+        Outcome::Data { len, digest } => format!("data {} {:016x}", len, digest),
+        Outcome::Error(kind) => format!("error {}", kind),
+    }
+}
+
+pub(crate) fn render_repro(repro: &Repro, failure: Option<&str>) -> String {
+    let mut text = format!("{} {}\n", REPRO_HEADER, REPRO_VERSION);
+    if let Some(seed) = repro.seed {
+        text.push_str(&format!("# seed {}\n", seed));
+    }
+    if let Some(failure) = failure {
+        for line in failure.lines() {
+            text.push_str(&format!("# {}\n", line));
+        }
+    }
+    for (op, outcome) in &repro.steps {
+        text.push_str(&format!(
+            "{} => {}\n",
+            render_op(op),
+            render_outcome(outcome)
+        ));
+    }
+    text
+}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn parse_path(word: Option<&str>) -> Result<&'static str, String> {
+    let word = word.ok_or("missing path")?;
+    PATHS
+        .iter()
+        .find(|path| **path == word)
+        .copied()
+        .ok_or_else(|| format!("path {:?} is not in the generator's namespace", word))
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+    word.and_then(|word| word.parse().ok())
+        .ok_or_else(|| format!("missing or invalid {}", what))
+}
+
+fn parse_hex(word: Option<&str>, what: &str) -> Result<u64, String> {
+    word.and_then(|word| u64::from_str_radix(word, 16).ok())
+        .ok_or_else(|| format!("missing or invalid {}", what))
+}
+
+// "<seed> <len> <digest>"; the digest guards against the content generator
+// changing under old files
+fn parse_content<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<(u64, usize), String> {
+    let seed = parse_number(words.next(), "seed")?;
+    let len = parse_number(words.next(), "length")?;
+    let digest = parse_hex(words.next(), "digest")?;
+    if checksum(&content(seed, len)) != digest {
+        return Err(format!(
+            "digest {:016x} does not match the generated content",
+            digest
+        ));
+    }
+    Ok((seed, len))
+}
+
+// Version 1 operations, plus `move` from version 2, `write-at`/`read-at`
+// from version 3 and `truncate` from version 4; later versions extend this match rather than changing how
+// existing operations are spelled
+fn parse_op(version: u32, text: &str) -> Result<Op, String> {
+    let mut words = text.split_whitespace();
+    let op = match words.next() {
+        Some("create") => Op::Create(parse_path(words.next())?),
+        Some("mkdir") => Op::Mkdir(parse_path(words.next())?),
+        Some("write") => {
+            let path = parse_path(words.next())?;
+            let (seed, len) = parse_content(&mut words)?;
+            Op::Write { path, seed, len }
+        }
+        Some("write-at") if version >= 3 => {
+            let path = parse_path(words.next())?;
+            let offset = parse_number(words.next(), "offset")?;
+            let (seed, len) = parse_content(&mut words)?;
+            Op::WriteAt {
+                path,
+                offset,
+                seed,
+                len,
+            }
+        }
+        Some("read-at") if version >= 3 => Op::ReadAt {
+            path: parse_path(words.next())?,
+            offset: parse_number(words.next(), "offset")?,
+            len: parse_number(words.next(), "length")?,
+        },
+        Some("truncate") if version >= 4 => Op::Truncate {
+            path: parse_path(words.next())?,
+            size: parse_number(words.next(), "size")?,
+        },
+        Some("remove") => Op::Remove(parse_path(words.next())?),
+        Some("rmdir") => Op::Rmdir(parse_path(words.next())?),
+        Some("read") => Op::Read(parse_path(words.next())?),
+        Some("move") if version >= 2 => Op::Move {
+            from: parse_path(words.next())?,
+            to: parse_path(words.next())?,
+        },
+        other => return Err(format!("unknown operation {:?}", other)),
+    };
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected {:?}", extra));
+    }
+    Ok(op)
+}
+
+fn parse_outcome(text: &str) -> Result<Outcome, String> {
+    let mut words = text.split_whitespace();
+    match words.next() {
+        Some("ok") => Ok(Outcome::Ok),
+        Some("data") => Ok(Outcome::Data {
+            len: parse_number(words.next(), "length")?,
+            digest: parse_hex(words.next(), "digest")?,
+        }),
+        Some("error") => {
+            let name = words.next().ok_or("missing error kind")?;
+            ERROR_KINDS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(known, _)| Outcome::Error(known))
+                .ok_or_else(|| format!("unknown error kind {:?}", name))
+        }
+        other => Err(format!("unknown outcome {:?}", other)),
+    }
+}
+
+pub(crate) fn parse_repro(text: &str) -> io::Result<Repro> {
+    let mut lines = text.lines().enumerate();
+    let version = match lines.next() {
+        Some((_, header)) => header
+            .strip_prefix(REPRO_HEADER)
+            .and_then(|rest| rest.trim().parse::<u32>().ok())
+            .ok_or_else(|| invalid(1, format!("expected {:?} header", REPRO_HEADER)))?,
+        None => return Err(invalid(1, String::from("empty replay file"))),
+    };
+    if version == 0 || version > REPRO_VERSION {
+        return Err(invalid(
+            1,
+            format!(
+                "format version {} is not supported (latest is {})",
+                version, REPRO_VERSION
+            ),
+        ));
+    }
+
+    let mut repro = Repro {
+        seed: None,
+        steps: Vec::new(),
+    };
+    for (index, line) in lines {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(seed) = comment.trim().strip_prefix("seed ") {
+                repro.seed = seed.trim().parse().ok();
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (op, outcome) = line
+            .split_once("=>")
+            .ok_or_else(|| invalid(index + 1, String::from("expected `<op> => <outcome>`")))?;
+        let op = parse_op(version, op).map_err(|e| invalid(index + 1, e))?;
+        let outcome = parse_outcome(outcome).map_err(|e| invalid(index + 1, e))?;
+        repro.steps.push((op, outcome));
+    }
+    Ok(repro)
+}
+
+// Runs the recorded steps on a fresh device, failing at the first step
+// whose outcome differs from the recording
+pub(crate) fn replay(repro: &Repro) -> io::Result<()> {
+    let ctx = TestContext::new()?;
+    format_device(&ctx)?;
+
+    for (step, (op, expected)) in repro.steps.iter().enumerate() {
+        let actual = apply_bellande(&ctx, op)?;
+        if !expected.matches(&actual) {
+            return Err(io::Error::other(format!(
+                "step {} {}: expected {}, got {}",
+                step,
+                render_op(op),
+                render_outcome(expected),
+                render_outcome(&Outcome::from_result(&actual))
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn replay_file(path: &Path) -> io::Result<()> {
+    let repro = parse_repro(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    replay(&repro).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))
+}
+
+// Saves a failing sequence where a maintainer can pick it up, returning the path
+pub(crate) fn save_repro(name: &str, repro: &Repro, failure: &str) -> io::Result<PathBuf> {
+    let dir = env::var_os(REPRO_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.{}", name, REPRO_EXTENSION));
+    fs::write(&path, render_repro(repro, Some(failure)))?;
+    Ok(path)
+}
+
+fn regressions_dir() -> PathBuf {
+    let root = option_env!("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    root.join("tests").join("regressions")
+}
+
+pub(crate) fn regression_replays() -> io::Result<()> {
+    let dir = regressions_dir();
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == REPRO_EXTENSION))
+        .collect();
+    files.sort();
+
+    for file in files {
+        replay_file(&file)?;
+    }
+    Ok(())
+}
+
+scenarios! {
+    regression_replays(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Repro {
+        Repro {
+            seed: Some(42),
+            steps: vec![
+                (Op::Mkdir("/a"), Outcome::Ok),
+                (
+                    Op::Write {
+                        path: "/f",
+                        seed: 7,
+                        len: 100,
+                    },
+                    Outcome::Error("NotFound"),
+                ),
+                (
+                    Op::Move {
+                        from: "/a",
+                        to: "/b/g",
+                    },
+                    Outcome::Error("AlreadyExists"),
+                ),
+                (
+                    Op::WriteAt {
+                        path: "/f",
+                        offset: 4095,
+                        seed: 8,
+                        len: 3,
+                    },
+                    Outcome::Ok,
+                ),
+                (
+                    Op::ReadAt {
+                        path: "/f",
+                        offset: 4000,
+                        len: 200,
+                    },
+                    Outcome::Error("InvalidInput"),
+                ),
+                (
+                    Op::Truncate {
+                        path: "/f",
+                        size: 10,
+                    },
+                    Outcome::Ok,
+                ),
+                (
+                    Op::Read("/a/f"),
+                    Outcome::Data {
+                        len: 3,
+                        digest: checksum(b"abc"),
+                    },
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_repro_round_trip() -> io::Result<()> {
+        let text = render_repro(&sample(), Some("step 2 differs"));
+        let parsed = parse_repro(&text)?;
+        assert_eq!(parsed.seed, Some(42));
+        assert_eq!(render_repro(&parsed, Some("step 2 differs")), text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_need_their_version() {
+        for (line, version) in [
+            ("move /a /b => ok\n", 2),
+            ("read-at /f 10 20 => error InvalidInput\n", 3),
+            ("truncate /f 10 => ok\n", 4),
+        ] {
+            let older = format!("{} {}\n{}", REPRO_HEADER, version - 1, line);
+            assert!(parse_repro(&older).is_err(), "{:?}", line);
+            assert!(parse_repro(&format!("{} {}\n{}", REPRO_HEADER, version, line)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_repro_rejects_newer_versions() {
+        let text = format!("{} {}\nmkdir /a => ok\n", REPRO_HEADER, REPRO_VERSION + 1);
+        assert!(parse_repro(&text).is_err());
+    }
+
+    #[test]
+    fn test_repro_rejects_changed_content() {
+        let text = format!(
+            "{} 1\nwrite /f 7 100 0000000000000000 => ok\n",
+            REPRO_HEADER
+        );
+        assert!(parse_repro(&text).is_err());
+    }
+
+    #[test]
+    fn test_outcome_matches_exact_data() {
+        let expected = Outcome::Data {
+            len: 3,
+            digest: checksum(b"abc"),
+        };
+        assert!(expected.matches(&Ok(Some(b"abc".to_vec()))));
+        assert!(!expected.matches(&Ok(Some(b"Contents: abc\n".to_vec()))));
+        assert!(!expected.matches(&Ok(Some(b"abcd".to_vec()))));
+        assert!(!expected.matches(&Ok(Some(b"abd".to_vec()))));
+    }
+}
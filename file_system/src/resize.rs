@@ -0,0 +1,186 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `resize --size BYTES` changes the filesystem to span that many bytes of
+// the device in place. Growing into space the device already has (say after
+// the image was extended) adds blocks and inodes to the bitmaps and the
+// superblock counters; shrinking is allowed when every block and inode past
+// the new end is free, and is otherwise refused with EXIT_NO_SPACE without
+// writing. Sizes take the usual suffixes and are rounded down to whole
+// blocks. A size past the end of the device, or too small to hold the
+// metadata, exits EXIT_INVALID.
+
+use crate::capacity::EXIT_NO_SPACE;
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::EXIT_INVALID;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, write_file, FsStats, TestContext};
+use crate::journal::tree_state;
+use std::fs::{self, OpenOptions};
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: u64 = BLOCK_SIZE as u64;
+const SMALL: u64 = 4 * 1024 * 1024;
+const LARGE: u64 = 8 * 1024 * 1024;
+// Blocks the grown filesystem may spend on its own bitmaps and inode table
+const MAX_GROWTH_OVERHEAD: u64 = 256;
+
+fn resize_context(size: u64) -> io::Result<TestContext> {
+    TestContext::with_options(size, Some(BLOCK_SIZE))
+}
+
+fn set_device_len(ctx: &TestContext, len: u64) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(&ctx.device_path)?
+        .set_len(len)
+}
+
+fn resize(ctx: &TestContext, size: &str) -> io::Result<FsStats> {
+    ctx.run_bellande_command(&["resize", "--size", size])?;
+    read_stats(ctx)
+}
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (path, seed, len) in [("/dir/a.bin", 1, 300_000), ("/b.bin", 2, 5000)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &content(seed, len))?.status.success());
+    }
+    Ok(())
+}
+
+pub(crate) fn grow_uses_new_space() -> io::Result<()> {
+    let ctx = resize_context(SMALL)?;
+    populated(&ctx)?;
+    let tree = tree_state(&ctx)?;
+    let before = read_stats(&ctx)?;
+
+    set_device_len(&ctx, LARGE)?;
+    // Extending the image alone changes nothing
+    assert_eq!(read_stats(&ctx)?, before);
+
+    let after = resize(&ctx, "8M")?;
+    let added = (LARGE - SMALL) / BLOCK;
+    assert_eq!(after.total_blocks, before.total_blocks + added);
+    assert!(
+        after.free_blocks + MAX_GROWTH_OVERHEAD >= before.free_blocks + added,
+        "{:?} -> {:?}",
+        before,
+        after
+    );
+    assert!(after.total_inodes > before.total_inodes);
+    assert_eq!(
+        after.total_inodes - after.free_inodes,
+        before.total_inodes - before.free_inodes
+    );
+    assert_eq!(tree_state(&ctx)?, tree);
+    assert_fsck_clean(&ctx)?;
+
+    // More than the old filesystem could ever have held
+    let big = content(3, (SMALL + SMALL / 2) as usize);
+    ctx.run_bellande_command(&["create", "--path", "/big.bin"])?;
+    assert!(write_file(&ctx, "/big.bin", &big)?.status.success());
+    let output = ctx.run_bellande_command(&["read", "--path", "/big.bin"])?;
+    assert!(output.stdout == big);
+
+    // Growing to the current size is a no-op
+    let grown = fs::read(&ctx.device_path)?;
+    resize(&ctx, &LARGE.to_string())?;
+    assert!(fs::read(&ctx.device_path)? == grown, "a no-op resize wrote");
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn shrink_when_tail_free() -> io::Result<()> {
+    let ctx = resize_context(LARGE)?;
+    populated(&ctx)?;
+    let tree = tree_state(&ctx)?;
+    let before = read_stats(&ctx)?;
+
+    let after = resize(&ctx, "4M")?;
+    assert_eq!(
+        after.total_blocks,
+        before.total_blocks - (LARGE - SMALL) / BLOCK
+    );
+    assert!(after.total_inodes <= before.total_inodes);
+    assert_eq!(tree_state(&ctx)?, tree);
+    assert_fsck_clean(&ctx)?;
+    // Nothing lives past the new end, so the image can be cut there
+    set_device_len(&ctx, SMALL)?;
+    assert_eq!(tree_state(&ctx)?, tree);
+    assert_fsck_clean(&ctx)?;
+
+    // Once the tail is in use, shrinking is refused untouched
+    set_device_len(&ctx, LARGE)?;
+    resize(&ctx, "8M")?;
+    let free = read_stats(&ctx)?.free_blocks;
+    let fill = content(4, ((free - 16) * BLOCK) as usize);
+    ctx.run_bellande_command(&["create", "--path", "/fill.bin"])?;
+    assert!(write_file(&ctx, "/fill.bin", &fill)?.status.success());
+    let full = fs::read(&ctx.device_path)?;
+    ctx.command(&["resize", "--size", "4M"])
+        .assert()
+        .code(EXIT_NO_SPACE);
+    assert!(
+        fs::read(&ctx.device_path)? == full,
+        "a refused shrink wrote"
+    );
+
+    ctx.run_bellande_command(&["remove", "--path", "/fill.bin"])?;
+    resize(&ctx, "4M")?;
+    assert_eq!(tree_state(&ctx)?, tree);
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn resize_errors() -> io::Result<()> {
+    let ctx = resize_context(SMALL)?;
+    populated(&ctx)?;
+    let before = fs::read(&ctx.device_path)?;
+    let stats = read_stats(&ctx)?;
+
+    for size in ["8M", "0", "1"] {
+        ctx.command(&["resize", "--size", size])
+            .assert()
+            .code(EXIT_INVALID);
+    }
+    for args in [
+        &["resize"][..],
+        &["resize", "--size", "lots"][..],
+        &["resize", "--size", "-4M"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a rejected resize wrote"
+    );
+
+    // Rounded down to whole blocks
+    let shrunk = resize(&ctx, &(SMALL - BLOCK / 2).to_string())?;
+    assert_eq!(shrunk.total_blocks, stats.total_blocks - 1);
+    assert_fsck_clean(&ctx)
+}
+
+scenarios! {
+    #[contract]
+    grow_uses_new_space(),
+    #[contract]
+    shrink_when_tail_free(),
+    #[contract]
+    resize_errors(),
+}
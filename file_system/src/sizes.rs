@@ -0,0 +1,250 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Human-readable sizes. `format_size` and `parse_size` are the reference the
+// binary's `--human` output and size arguments are checked against; the
+// harness also uses `parse_size` for its own size settings.
+
+use crate::harness::{format_device, read_stats, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+const UNITS: &[(&str, u64)] = &[
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+];
+
+const MAX_FRACTION_DIGITS: usize = 9;
+
+fn group_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// "3.4 MiB (3,567,616 bytes)"; the fraction is truncated rather than rounded
+// so a value just under a boundary never displays as the next unit
+pub(crate) fn format_size(bytes: u64) -> String {
+    for (unit, scale) in UNITS {
+        if bytes >= *scale {
+            let whole = bytes / scale;
+            let tenths = (bytes % scale) * 10 / scale;
+            return format!(
+                "{}.{} {} ({} bytes)",
+                whole,
+                tenths,
+                unit,
+                group_thousands(bytes)
+            );
+        }
+    }
+    format!("{} bytes", group_thousands(bytes))
+}
+
+// Accepts 512, 4K, 1M, 2G, 1.5G (binary multiples, optional "iB"/"B", any
+// case); fractions must come out to a whole number of bytes
+pub(crate) fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, suffix) = text.split_at(split);
+
+    let scale: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() || fraction.len() > MAX_FRACTION_DIGITS || fraction.contains('.') {
+        return None;
+    }
+    if number.ends_with('.') {
+        return None;
+    }
+
+    let whole: u128 = whole.parse().ok()?;
+    let mut bytes = whole.checked_mul(scale as u128)?;
+    if !fraction.is_empty() {
+        let denominator = 10u128.pow(fraction.len() as u32);
+        let numerator = fraction.parse::<u128>().ok()? * scale as u128;
+        if !numerator.is_multiple_of(denominator) {
+            return None;
+        }
+        bytes = bytes.checked_add(numerator / denominator)?;
+    }
+    u64::try_from(bytes).ok()
+}
+
+// Every "(<n> bytes)" in `output` must sit in exactly what format_size
+// gives for n; returns how many sizes were checked
+pub(crate) fn check_human_sizes(output: &str) -> usize {
+    let mut checked = 0;
+    for line in output.lines() {
+        for (end, _) in line.match_indices(" bytes)") {
+            let Some(open) = line[..end].rfind('(') else {
+                continue;
+            };
+            let Ok(bytes) = line[open + 1..end].replace(',', "").parse::<u64>() else {
+                continue;
+            };
+            assert!(
+                line.contains(&format_size(bytes)),
+                "expected {:?} in {:?}",
+                format_size(bytes),
+                line
+            );
+            checked += 1;
+        }
+    }
+    checked
+}
+
+pub(crate) fn human_and_byte_output(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    let output = ctx.command(&["--human", "stats"]).assert().success();
+    let human = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    assert!(
+        check_human_sizes(&human) > 0,
+        "--human stats shows no sizes: {:?}",
+        human
+    );
+
+    ctx.command(&["--bytes", "stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("iB").not());
+
+    // Under the harness stdout is not a TTY, so the default is --bytes and
+    // the raw counts read_stats relies on stay machine-readable
+    ctx.command(&["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("iB").not());
+    read_stats(ctx)?;
+    Ok(())
+}
+
+pub(crate) fn size_arguments(ctx: &TestContext) -> io::Result<()> {
+    ctx.run_bellande_command(&[
+        "format",
+        "--yes",
+        "--block-size",
+        "4096",
+        "--size",
+        "6291456",
+    ])?;
+    let plain = read_stats(ctx)?;
+    assert_eq!(plain.total_blocks, 6 * 1024 * 1024 / 4096);
+
+    for spelled in ["6M", "6m", "6MiB", "6144K", "0.5G"] {
+        let expected = parse_size(spelled).expect("valid size");
+        ctx.run_bellande_command(&["format", "--yes", "--block-size", "4K", "--size", spelled])?;
+        assert_eq!(
+            read_stats(ctx)?.total_blocks,
+            expected / 4096,
+            "--size {}",
+            spelled
+        );
+    }
+
+    for invalid in ["6X", "1.3K", "M", "-1M", "1..5M"] {
+        ctx.command(&["format", "--yes", "--size", invalid])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--size"));
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    human_and_byte_output,
+    #[contract]
+    size_arguments(TestContext::with_options(1024 * 1024 * 1024, None)?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1023), "1,023 bytes");
+        assert_eq!(format_size(1024), "1.0 KiB (1,024 bytes)");
+        assert_eq!(format_size(3_567_616), "3.4 MiB (3,567,616 bytes)");
+        assert_eq!(format_size((1 << 20) - 1), "1023.9 KiB (1,048,575 bytes)");
+        assert_eq!(format_size(1 << 30), "1.0 GiB (1,073,741,824 bytes)");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("4K"), Some(4096));
+        assert_eq!(parse_size("4kib"), Some(4096));
+        assert_eq!(parse_size(" 1M "), Some(1 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("1.5G"), Some(3 << 29));
+        assert_eq!(parse_size("16E"), None);
+        assert_eq!(parse_size("1.3K"), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("1."), None);
+        assert_eq!(parse_size("1..5M"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("99999999999T"), None);
+    }
+
+    #[test]
+    fn test_sizes_round_trip() {
+        for bytes in [
+            0,
+            1,
+            1023,
+            1024,
+            1025,
+            4096,
+            (1 << 20) - 1,
+            1 << 20,
+            3 << 29,
+        ] {
+            assert_eq!(parse_size(&bytes.to_string()), Some(bytes));
+            let shown = format_size(bytes);
+            let grouped = shown.rsplit('(').next().unwrap_or(&shown);
+            let digits: String = grouped.chars().filter(char::is_ascii_digit).collect();
+            assert_eq!(digits.parse::<u64>().ok(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn test_check_human_sizes() {
+        let output = "Total size: 10.0 MiB (10,485,760 bytes)\nFree: 1,023 bytes\n";
+        assert_eq!(check_human_sizes(output), 1);
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copy-on-write snapshots. `snapshot create --name` records the block bitmap
+// and inode table; from then on a write never overwrites a block a snapshot
+// references, and a block is freed only once neither the live tree nor any
+// snapshot uses it. `snapshot rollback` makes a snapshot the live tree again,
+// `snapshot delete` drops one, and `stats` reports "Pinned blocks": those
+// only snapshots still reference. The name may also be given positionally,
+// as in `snapshot create NAME`. The snapshot table lives in the image itself,
+// so a copied image carries its snapshots.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_INVALID, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::journal::tree_state;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+
+fn snapshot_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn snapshot(ctx: &TestContext, action: &str, name: &str) -> io::Result<()> {
+    ctx.run_bellande_command(&["snapshot", action, "--name", name])?;
+    Ok(())
+}
+
+fn pinned_blocks(ctx: &TestContext) -> io::Result<u64> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    stat_field(&String::from_utf8_lossy(&output.stdout), "Pinned blocks")
+}
+
+fn free_blocks(ctx: &TestContext) -> io::Result<u64> {
+    Ok(read_stats(ctx)?.free_blocks)
+}
+
+// Names from `snapshot list`, one snapshot per line, oldest first
+fn snapshot_names(ctx: &TestContext) -> io::Result<Vec<String>> {
+    let output = ctx.run_bellande_command(&["snapshot", "list"])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect())
+}
+
+fn write(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<()> {
+    assert!(
+        write_file(ctx, path, data)?.status.success(),
+        "write {} failed",
+        path
+    );
+    Ok(())
+}
+
+fn base_tree(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/etc"])?;
+    for (path, blocks, seed) in [
+        ("/etc/config", 3, 1),
+        ("/data.bin", 20, 2),
+        ("/notes", 1, 3),
+    ] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        write(ctx, path, &content(seed, blocks * BLOCK))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn rollback_restores_tree(ctx: &TestContext) -> io::Result<()> {
+    base_tree(ctx)?;
+    let before = tree_state(ctx)?;
+    let unsnapshotted = free_blocks(ctx)?;
+
+    snapshot(ctx, "create", "before-update")?;
+    let snapshotted = free_blocks(ctx)?;
+    assert_eq!(
+        pinned_blocks(ctx)?,
+        0,
+        "nothing is pinned until data changes"
+    );
+
+    // The risky update: every kind of change
+    write(ctx, "/etc/config", &content(4, 5 * BLOCK))?;
+    ctx.command(&["write", "--append", "--path", "/notes"])
+        .write_stdin(content(5, 2 * BLOCK))
+        .assert()
+        .success();
+    ctx.run_bellande_command(&["remove", "--path", "/data.bin"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/new"])?;
+    ctx.run_bellande_command(&["create", "--path", "/new/file"])?;
+    write(ctx, "/new/file", &content(6, 4 * BLOCK))?;
+    ctx.run_bellande_command(&["move", "--from", "/etc", "--to", "/etc.old"])?;
+    assert!(tree_state(ctx)? != before);
+    assert!(
+        pinned_blocks(ctx)? >= 20 + 3,
+        "removed and overwritten blocks stay pinned"
+    );
+    assert_fsck_clean(ctx)?;
+
+    snapshot(ctx, "rollback", "before-update")?;
+    assert_eq!(
+        tree_state(ctx)?,
+        before,
+        "rollback did not restore the tree"
+    );
+    assert_eq!(pinned_blocks(ctx)?, 0);
+    assert_eq!(
+        free_blocks(ctx)?,
+        snapshotted,
+        "post-snapshot blocks leaked"
+    );
+    assert_eq!(snapshot_names(ctx)?, ["before-update"]);
+    assert_fsck_clean(ctx)?;
+
+    // Rolling back again is harmless, and deleting gives everything back
+    snapshot(ctx, "rollback", "before-update")?;
+    assert_eq!(tree_state(ctx)?, before);
+    snapshot(ctx, "delete", "before-update")?;
+    assert_eq!(free_blocks(ctx)?, unsnapshotted);
+    assert!(snapshot_names(ctx)?.is_empty());
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn copy_on_write_pins_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = free_blocks(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/f"])?;
+    write(ctx, "/f", &content(1, 10 * BLOCK))?;
+
+    snapshot(ctx, "create", "one")?;
+    let with_one = free_blocks(ctx)?;
+
+    // Patching one block copies only that block
+    ctx.command(&[
+        "write",
+        "--path",
+        "/f",
+        "--offset",
+        &(4 * BLOCK + 10).to_string(),
+    ])
+    .write_stdin(vec![0xEE; 20])
+    .assert()
+    .success();
+    assert_eq!(pinned_blocks(ctx)?, 1);
+    assert_eq!(free_blocks(ctx)?, with_one - 1);
+
+    // A full overwrite pins the rest; with a second snapshot, pins stack up
+    write(ctx, "/f", &content(2, 10 * BLOCK))?;
+    assert_eq!(pinned_blocks(ctx)?, 10);
+    snapshot(ctx, "create", "two")?;
+    write(ctx, "/f", &content(3, 10 * BLOCK))?;
+    assert_eq!(pinned_blocks(ctx)?, 20);
+
+    // Removing the live file frees only what no snapshot holds
+    let before_remove = free_blocks(ctx)?;
+    ctx.run_bellande_command(&["remove", "--path", "/f"])?;
+    assert_eq!(pinned_blocks(ctx)?, 20);
+    assert_eq!(free_blocks(ctx)?, before_remove + 10);
+    snapshot(ctx, "delete", "one")?;
+    assert_eq!(pinned_blocks(ctx)?, 10);
+    assert_eq!(snapshot_names(ctx)?, ["two"]);
+    assert_fsck_clean(ctx)?;
+    snapshot(ctx, "delete", "two")?;
+    assert_eq!(pinned_blocks(ctx)?, 0);
+    assert_eq!(
+        free_blocks(ctx)?,
+        empty,
+        "the last delete kept blocks pinned"
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn snapshot_names_and_errors(ctx: &TestContext) -> io::Result<()> {
+    base_tree(ctx)?;
+    assert!(snapshot_names(ctx)?.is_empty());
+    for name in ["before-update", "nightly.1", "a"] {
+        snapshot(ctx, "create", name)?;
+    }
+    assert_eq!(snapshot_names(ctx)?, ["before-update", "nightly.1", "a"]);
+
+    ctx.command(&["snapshot", "create", "--name", "a"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    for action in ["delete", "rollback"] {
+        ctx.command(&["snapshot", action, "--name", "missing"])
+            .assert()
+            .code(EXIT_NOT_FOUND);
+        ctx.command(&["snapshot", action]).assert().code(EXIT_USAGE);
+    }
+    for name in ["", "with/slash", "with space"] {
+        ctx.command(&["snapshot", "create", "--name", name])
+            .assert()
+            .code(EXIT_INVALID);
+    }
+    ctx.command(&["snapshot", "bogus"])
+        .assert()
+        .code(EXIT_USAGE);
+    ctx.command(&["snapshot"]).assert().code(EXIT_USAGE);
+    assert_eq!(snapshot_names(ctx)?, ["before-update", "nightly.1", "a"]);
+
+    // The positional form is the same command; naming it twice is ambiguous
+    ctx.run_bellande_command(&["snapshot", "create", "positional"])?;
+    ctx.command(&["snapshot", "create", "a"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    ctx.command(&["snapshot", "create", "x", "--name", "y"])
+        .assert()
+        .code(EXIT_USAGE);
+    ctx.run_bellande_command(&["snapshot", "delete", "nightly.1"])?;
+    assert_eq!(snapshot_names(ctx)?, ["before-update", "a", "positional"]);
+    Ok(())
+}
+
+// The names of the files beside the device on the host
+fn host_files(ctx: &TestContext) -> io::Result<BTreeSet<String>> {
+    fs::read_dir(ctx.temp_dir.path())?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+pub(crate) fn snapshot_table_in_image(ctx: &TestContext) -> io::Result<()> {
+    base_tree(ctx)?;
+    let before = tree_state(ctx)?;
+    let files = host_files(ctx)?;
+    snapshot(ctx, "create", "base")?;
+    ctx.run_bellande_command(&["remove", "--recursive", "--path", "/etc"])?;
+
+    // Nothing outside the image records the snapshot: no sidecar file
+    // appears next to the device, and a bare copy of the image, alone in
+    // its own directory, still carries it
+    assert_eq!(
+        host_files(ctx)?,
+        files,
+        "snapshot left files beside the device"
+    );
+    let copy = TestContext::from_image(&ctx.device_path)?;
+    assert_eq!(snapshot_names(&copy)?, ["base"]);
+    copy.run_bellande_command(&["snapshot", "rollback", "base"])?;
+    assert_eq!(tree_state(&copy)?, before);
+    assert_fsck_clean(&copy)?;
+    assert_eq!(host_files(&copy)?, ["test_device".to_string()].into());
+
+    // The original is untouched by the copy's rollback
+    assert_ne!(tree_state(ctx)?, before);
+    assert_eq!(snapshot_names(ctx)?, ["base"]);
+    assert_eq!(host_files(ctx)?, files);
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    rollback_restores_tree(snapshot_context()?),
+    #[contract]
+    copy_on_write_pins_blocks(snapshot_context()?),
+    #[contract]
+    snapshot_names_and_errors(snapshot_context()?),
+    #[contract]
+    snapshot_table_in_image(snapshot_context()?),
+}
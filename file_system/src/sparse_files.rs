@@ -0,0 +1,218 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Sparse files. Whole blocks of zeros written to a file are left as holes,
+// as are ranges never written, and `punch-hole --path --offset --len` turns
+// a range back into a hole: blocks it covers entirely are freed, and the
+// covered parts of blocks at its edges read as zeros from then on. A hole
+// reads as zeros and never changes the size. `stats` reports the apparent
+// size of all files as `Logical bytes` and what they occupy as `Physical
+// bytes`.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_INVALID, EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::stat::{number, stat};
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const ZERO_BLOCKS: usize = 100;
+
+fn sparse_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn blocks_of(ctx: &TestContext, path: &str) -> io::Result<u64> {
+    Ok(number(&stat(ctx, path)?, "Blocks"))
+}
+
+// (apparent, allocated) bytes of all files
+fn apparent_and_allocated(ctx: &TestContext) -> io::Result<(u64, u64)> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok((
+        stat_field(&stdout, "Logical bytes")?,
+        stat_field(&stdout, "Physical bytes")?,
+    ))
+}
+
+fn punch(ctx: &TestContext, path: &str, offset: usize, len: usize) -> io::Result<()> {
+    ctx.run_bellande_command(&[
+        "punch-hole",
+        "--path",
+        path,
+        "--offset",
+        &offset.to_string(),
+        "--len",
+        &len.to_string(),
+    ])?;
+    Ok(())
+}
+
+fn read_all(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+pub(crate) fn zero_blocks_not_allocated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+
+    // Data, a hundred blocks of zeros, data
+    let mut data = content(1, BLOCK);
+    data.extend(vec![0; ZERO_BLOCKS * BLOCK]);
+    data.extend(content(2, BLOCK));
+    ctx.run_bellande_command(&["create", "--path", "/zeros.bin"])?;
+    let free = read_stats(ctx)?.free_blocks;
+    assert!(write_file(ctx, "/zeros.bin", &data)?.status.success());
+    assert_eq!(blocks_of(ctx, "/zeros.bin")?, 2);
+    assert_eq!(number(&stat(ctx, "/zeros.bin")?, "Size"), data.len() as u64);
+    assert!(read_all(ctx, "/zeros.bin")? == data);
+    assert!(free - read_stats(ctx)?.free_blocks < ZERO_BLOCKS as u64 / 10);
+
+    // Zeros sharing a block with data still need that block
+    let mut unaligned = vec![0; 3 * BLOCK + 10];
+    unaligned[BLOCK + 5] = 1;
+    ctx.run_bellande_command(&["create", "--path", "/unaligned.bin"])?;
+    assert!(write_file(ctx, "/unaligned.bin", &unaligned)?
+        .status
+        .success());
+    assert_eq!(blocks_of(ctx, "/unaligned.bin")?, 1);
+    assert!(read_all(ctx, "/unaligned.bin")? == unaligned);
+
+    // Writing past the end leaves the gap unallocated
+    ctx.run_bellande_command(&["create", "--path", "/gap.bin"])?;
+    ctx.command(&[
+        "write",
+        "--path",
+        "/gap.bin",
+        "--offset",
+        &(50 * BLOCK).to_string(),
+    ])
+    .write_stdin(content(3, BLOCK))
+    .assert()
+    .success();
+    assert_eq!(blocks_of(ctx, "/gap.bin")?, 1);
+    assert_eq!(number(&stat(ctx, "/gap.bin")?, "Size"), 51 * BLOCK as u64);
+
+    let (apparent, allocated) = apparent_and_allocated(ctx)?;
+    assert_eq!(apparent, (data.len() + unaligned.len() + 51 * BLOCK) as u64);
+    assert_eq!(allocated, 4 * BLOCK as u64);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn punch_hole_frees_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let mut model = content(4, 40 * BLOCK);
+    ctx.run_bellande_command(&["create", "--path", "/file.bin"])?;
+    assert!(write_file(ctx, "/file.bin", &model)?.status.success());
+    let full = read_stats(ctx)?;
+
+    // Block aligned: twenty blocks come back
+    punch(ctx, "/file.bin", 10 * BLOCK, 20 * BLOCK)?;
+    model[10 * BLOCK..30 * BLOCK].fill(0);
+    assert_eq!(blocks_of(ctx, "/file.bin")?, 20);
+    assert_eq!(read_stats(ctx)?.free_blocks, full.free_blocks + 20);
+    assert_eq!(number(&stat(ctx, "/file.bin")?, "Size"), model.len() as u64);
+    assert!(read_all(ctx, "/file.bin")? == model);
+
+    // Unaligned: only the three blocks covered whole are freed, the edges
+    // are zeroed in place
+    punch(ctx, "/file.bin", 100, 5000)?;
+    model[100..5100].fill(0);
+    assert_eq!(blocks_of(ctx, "/file.bin")?, 17);
+    assert!(read_all(ctx, "/file.bin")? == model);
+
+    // Past the end the size stays; punching everything leaves no blocks
+    punch(ctx, "/file.bin", 39 * BLOCK, 10 * BLOCK)?;
+    model[39 * BLOCK..].fill(0);
+    assert_eq!(number(&stat(ctx, "/file.bin")?, "Size"), model.len() as u64);
+    punch(ctx, "/file.bin", 0, model.len())?;
+    assert_eq!(blocks_of(ctx, "/file.bin")?, 0);
+    assert!(read_all(ctx, "/file.bin")? == vec![0; model.len()]);
+
+    // A hole refills on write
+    let patch = content(5, 2 * BLOCK);
+    ctx.command(&[
+        "write",
+        "--path",
+        "/file.bin",
+        "--offset",
+        &(5 * BLOCK).to_string(),
+    ])
+    .write_stdin(patch.clone())
+    .assert()
+    .success();
+    assert_eq!(blocks_of(ctx, "/file.bin")?, 2);
+    assert!(read_all(ctx, "/file.bin")?[5 * BLOCK..7 * BLOCK] == patch[..]);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn punch_hole_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/file.bin"])?;
+    assert!(write_file(ctx, "/file.bin", &content(6, 4 * BLOCK))?
+        .status
+        .success());
+
+    let punch_args = |path: &'static str, offset: &'static str, len: &'static str| {
+        [
+            "punch-hole",
+            "--path",
+            path,
+            "--offset",
+            offset,
+            "--len",
+            len,
+        ]
+    };
+    ctx.command(&punch_args("/missing", "0", "1024"))
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&punch_args("/dir", "0", "1024"))
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    ctx.command(&punch_args("/file.bin", "0", "0"))
+        .assert()
+        .code(EXIT_INVALID);
+    for args in [
+        &punch_args("/file.bin", "-1", "1024")[..],
+        &punch_args("/file.bin", "0", "lots")[..],
+        &["punch-hole", "--path", "/file.bin", "--offset", "0"][..],
+        &["punch-hole", "--offset", "0", "--len", "1024"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    let mut read_only = vec!["--read-only"];
+    read_only.extend(punch_args("/file.bin", "0", "1024"));
+    ctx.command(&read_only).assert().failure();
+
+    assert_eq!(blocks_of(ctx, "/file.bin")?, 4);
+    assert!(read_all(ctx, "/file.bin")? == content(6, 4 * BLOCK));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    zero_blocks_not_allocated(sparse_context()?),
+    #[contract]
+    punch_hole_frees_blocks(sparse_context()?),
+    #[contract]
+    punch_hole_errors(sparse_context()?),
+}
@@ -0,0 +1,306 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `stat --path` reports one inode as greppable `Key: value` lines: inode,
+// type, size, allocated blocks, link count, and creation, modification and access times
+// as RFC 3339. Writes move the modification time, reads the access time, and
+// directories also report how many entries they hold. `setattr --path P
+// --mtime T` and `--atime T`, T in RFC 3339, pin those times exactly, for
+// restores; the creation time never moves.
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, inode_of};
+use crate::errors::EXIT_NOT_FOUND;
+use crate::harness::{format_device, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::json::json_u64;
+use crate::times::{format_rfc3339, now_seconds, parse_rfc3339};
+use predicates::prelude::*;
+use std::collections::BTreeMap;
+use std::io;
+
+const BLOCK_SIZE: u32 = 4096;
+
+// Earlier than any time an operation in this run can set
+const BACKDATED: i64 = 946_684_800;
+
+const FILE_KEYS: &[&str] = &[
+    "Inode", "Type", "Size", "Blocks", "Links", "Created", "Modified", "Accessed",
+];
+
+fn stat_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// Every line must be `Key: value`; None if any is not
+pub(crate) fn parse_key_values(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut fields = BTreeMap::new();
+    for line in text.lines() {
+        let (key, value) = line.split_once(": ")?;
+        if key.is_empty() || key.trim() != key || fields.contains_key(key) {
+            return None;
+        }
+        fields.insert(key.to_string(), value.trim().to_string());
+    }
+    Some(fields)
+}
+
+pub(crate) fn stat(ctx: &TestContext, path: &str) -> io::Result<BTreeMap<String, String>> {
+    let output = ctx.run_bellande_command(&["stat", "--path", path])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_key_values(&stdout)
+        .unwrap_or_else(|| panic!("stat {} is not Key: value lines: {:?}", path, stdout)))
+}
+
+pub(crate) fn number(fields: &BTreeMap<String, String>, key: &str) -> u64 {
+    fields
+        .get(key)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("stat has no numeric {}: {:?}", key, fields))
+}
+
+fn time(fields: &BTreeMap<String, String>, key: &str) -> i64 {
+    fields
+        .get(key)
+        .and_then(|value| parse_rfc3339(value))
+        .unwrap_or_else(|| panic!("stat has no RFC 3339 {}: {:?}", key, fields))
+}
+
+fn list_inode(ctx: &TestContext, dir: &str, name: &str) -> io::Result<u64> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(inode_of(&String::from_utf8_lossy(&output.stdout), name)
+        .unwrap_or_else(|| panic!("{} is not listed in {}", name, dir)))
+}
+
+pub(crate) fn stat_reports_file(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/test.txt"])?;
+    assert!(write_file(ctx, "/test.txt", &content(1, 10_000))?
+        .status
+        .success());
+
+    let fields = stat(ctx, "/test.txt")?;
+    for key in FILE_KEYS {
+        assert!(
+            fields.contains_key(*key),
+            "stat is missing {}: {:?}",
+            key,
+            fields
+        );
+    }
+    assert!(!fields.contains_key("Entries"), "files have no entry count");
+    assert_eq!(fields["Type"], "file");
+    assert_eq!(number(&fields, "Size"), 10_000);
+    assert_eq!(number(&fields, "Blocks"), 3);
+    assert_eq!(number(&fields, "Links"), 1);
+    assert_eq!(number(&fields, "Inode"), list_inode(ctx, "/", "test.txt")?);
+
+    let now = now_seconds();
+    for key in ["Created", "Modified", "Accessed"] {
+        let at = time(&fields, key);
+        assert!(
+            (now - 120..=now + 1).contains(&at),
+            "{} {} is not recent",
+            key,
+            at
+        );
+    }
+
+    // The JSON form carries the same values
+    let output = ctx.run_bellande_command(&["stat", "--format", "json", "--path", "/test.txt"])?;
+    let json = String::from_utf8_lossy(&output.stdout);
+    for (key, json_key) in [("Inode", "inode"), ("Size", "size"), ("Blocks", "blocks")] {
+        assert_eq!(
+            json_u64(&json, json_key),
+            Some(number(&fields, key)),
+            "{}",
+            key
+        );
+    }
+    assert_eq!(
+        json_u64(&json, "mtime"),
+        Some(time(&fields, "Modified") as u64)
+    );
+
+    // An empty file holds no blocks
+    ctx.run_bellande_command(&["create", "--path", "/empty.txt"])?;
+    let empty = stat(ctx, "/empty.txt")?;
+    assert_eq!(number(&empty, "Size"), 0);
+    assert_eq!(number(&empty, "Blocks"), 0);
+    Ok(())
+}
+
+// Pins both times at BACKDATED, so whatever an operation sets is later
+fn backdate(ctx: &TestContext, path: &str) -> io::Result<()> {
+    let past = format_rfc3339(BACKDATED);
+    ctx.run_bellande_command(&[
+        "setattr", "--path", path, "--mtime", &past, "--atime", &past,
+    ])?;
+    Ok(())
+}
+
+pub(crate) fn timestamps_follow_operations(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/times.txt"])?;
+    let created = stat(ctx, "/times.txt")?;
+    assert_eq!(time(&created, "Created"), time(&created, "Modified"));
+
+    // stat itself is not an access
+    backdate(ctx, "/times.txt")?;
+    let backdated = stat(ctx, "/times.txt")?;
+    assert_eq!(time(&backdated, "Accessed"), BACKDATED);
+    assert_eq!(stat(ctx, "/times.txt")?, backdated);
+
+    assert!(write_file(ctx, "/times.txt", b"changed")?.status.success());
+    let written = stat(ctx, "/times.txt")?;
+    assert_eq!(time(&written, "Created"), time(&created, "Created"));
+    assert!(time(&written, "Modified") > BACKDATED);
+
+    backdate(ctx, "/times.txt")?;
+    ctx.run_bellande_command(&["read", "--path", "/times.txt"])?;
+    let read = stat(ctx, "/times.txt")?;
+    assert!(time(&read, "Accessed") > BACKDATED);
+    assert_eq!(time(&read, "Modified"), BACKDATED);
+    assert_eq!(time(&read, "Created"), time(&created, "Created"));
+    Ok(())
+}
+
+pub(crate) fn setattr_pins_times(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/restored.txt"])?;
+    assert!(write_file(ctx, "/dir/restored.txt", b"restored")?
+        .status
+        .success());
+    let created = time(&stat(ctx, "/dir/restored.txt")?, "Created");
+
+    // Past and future, files and directories
+    for (path, mtime, atime) in [
+        (
+            "/dir/restored.txt",
+            "2001-09-09T01:46:40Z",
+            "2010-01-01T00:00:00Z",
+        ),
+        ("/dir", "2038-01-19T03:14:08Z", "1999-12-31T23:59:59Z"),
+    ] {
+        ctx.run_bellande_command(&[
+            "setattr", "--path", path, "--mtime", mtime, "--atime", atime,
+        ])?;
+        let fields = stat(ctx, path)?;
+        assert_eq!(fields["Modified"], mtime, "{}", path);
+        assert_eq!(fields["Accessed"], atime, "{}", path);
+    }
+    // Either time alone leaves the other be
+    ctx.run_bellande_command(&[
+        "setattr",
+        "--path",
+        "/dir/restored.txt",
+        "--mtime",
+        &format_rfc3339(1_000_000),
+    ])?;
+    let fields = stat(ctx, "/dir/restored.txt")?;
+    assert_eq!(time(&fields, "Modified"), 1_000_000);
+    assert_eq!(fields["Accessed"], "2010-01-01T00:00:00Z");
+    assert_eq!(time(&fields, "Created"), created);
+    // Setting a child's times leaves its directory's alone
+    assert_eq!(stat(ctx, "/dir")?["Modified"], "2038-01-19T03:14:08Z");
+
+    // The next write takes the modification time back to now
+    assert!(write_file(ctx, "/dir/restored.txt", b"changed")?
+        .status
+        .success());
+    let now = now_seconds();
+    let modified = time(&stat(ctx, "/dir/restored.txt")?, "Modified");
+    assert!((now - 120..=now + 1).contains(&modified));
+
+    ctx.command(&[
+        "setattr",
+        "--path",
+        "/missing",
+        "--mtime",
+        "2001-09-09T01:46:40Z",
+    ])
+    .assert()
+    .code(EXIT_NOT_FOUND);
+    for value in ["yesterday", "2001-13-01T00:00:00Z", "1000000", ""] {
+        ctx.command(&["setattr", "--path", "/dir", "--mtime", value])
+            .assert()
+            .code(EXIT_USAGE)
+            .stderr(predicate::str::contains("--mtime"));
+    }
+    Ok(())
+}
+
+pub(crate) fn stat_reports_directory(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let empty = stat(ctx, "/dir")?;
+    assert_eq!(empty["Type"], "directory");
+    assert_eq!(number(&empty, "Entries"), 0);
+    assert_eq!(number(&empty, "Inode"), list_inode(ctx, "/", "dir")?);
+
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir/sub"])?;
+    for index in 0..5 {
+        ctx.run_bellande_command(&["create", "--path", &format!("/dir/file{}", index)])?;
+    }
+    let full = stat(ctx, "/dir")?;
+    assert_eq!(number(&full, "Entries"), 6);
+    assert!(time(&full, "Modified") >= time(&empty, "Modified"));
+
+    assert_eq!(stat(ctx, "/")?["Type"], "directory");
+    assert_eq!(number(&stat(ctx, "/")?, "Entries"), 1);
+    Ok(())
+}
+
+pub(crate) fn stat_missing_path(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for path in ["/missing.txt", "/no/such/dir"] {
+        ctx.command(&["stat", "--path", path])
+            .assert()
+            .code(EXIT_NOT_FOUND)
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::contains("stat").and(predicate::str::contains(path)));
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    stat_reports_file(stat_context()?),
+    #[contract]
+    timestamps_follow_operations(stat_context()?),
+    #[contract]
+    setattr_pins_times(stat_context()?),
+    #[contract]
+    stat_reports_directory(stat_context()?),
+    #[contract]
+    stat_missing_path(stat_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_values() {
+        let fields = parse_key_values("Inode: 12\nType: file\nModified: 2024-01-02T03:04:05Z\n")
+            .expect("valid lines");
+        assert_eq!(fields["Inode"], "12");
+        assert_eq!(fields["Modified"], "2024-01-02T03:04:05Z");
+        assert_eq!(parse_key_values(""), Some(BTreeMap::new()));
+        assert_eq!(parse_key_values("Inode 12"), None);
+        assert_eq!(parse_key_values(": 12"), None);
+        assert_eq!(parse_key_values("Size: 1\nSize: 2"), None);
+    }
+}
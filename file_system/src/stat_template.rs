@@ -0,0 +1,177 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `stat --template`: GNU `--printf` placeholders and escapes, with the
+// expected text built by the shared `template` engine from the same entry's
+// `stat --format json` fields.
+
+use crate::cli::EXIT_USAGE;
+use crate::create_mode::parse_octal_mode;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use crate::json::json_field;
+use crate::template::expand;
+use predicates::prelude::*;
+use std::io;
+
+// (placeholder, stat JSON field)
+const PLACEHOLDERS: &[(char, &str)] = &[
+    ('n', "path"),
+    ('s', "size"),
+    ('Y', "mtime"),
+    ('i', "inode"),
+    ('a', "mode"),
+    ('F', "type"),
+    ('b', "blocks"),
+    ('u', "uid"),
+    ('g', "gid"),
+    ('h', "nlink"),
+];
+
+const TEMPLATES: &[&str] = &[
+    "%n %s %Y",
+    "%i\\t%a\\n",
+    "%F|%h|%b|%u:%g",
+    "100%% of %n\\n",
+    "%%s is literal",
+    "\\x41\\101\\\\",
+    "no placeholders",
+    "%n%n",
+];
+
+struct Entry {
+    path: &'static str,
+    is_dir: bool,
+    len: usize,
+}
+
+const ENTRIES: &[Entry] = &[
+    Entry {
+        path: "/data.bin",
+        is_dir: false,
+        len: 5000,
+    },
+    Entry {
+        path: "/empty",
+        is_dir: false,
+        len: 0,
+    },
+    Entry {
+        path: "/dir",
+        is_dir: true,
+        len: 0,
+    },
+];
+
+// What GNU stat's %F prints
+fn type_name(entry: &Entry) -> &'static str {
+    match (entry.is_dir, entry.len) {
+        (true, _) => "directory",
+        (false, 0) => "regular empty file",
+        (false, _) => "regular file",
+    }
+}
+
+fn reference(ctx: &TestContext, entry: &Entry, template: &str) -> io::Result<String> {
+    let output = ctx.run_bellande_command(&["stat", "--format", "json", "--path", entry.path])?;
+    let json = String::from_utf8_lossy(&output.stdout).into_owned();
+    let expanded = expand(template, |letter| {
+        let (_, field) = PLACEHOLDERS.iter().find(|(c, _)| *c == letter)?;
+        match letter {
+            // As given on the command line, like GNU
+            'n' => Some(entry.path.to_string()),
+            'F' => Some(type_name(entry).to_string()),
+            'a' => Some(format!(
+                "{:o}",
+                parse_octal_mode(&json_field(&json, field)?)?
+            )),
+            _ => json_field(&json, field),
+        }
+    });
+    expanded.map_err(|e| io::Error::other(format!("{:?}: {}", template, e)))
+}
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for entry in ENTRIES {
+        if entry.is_dir {
+            ctx.run_bellande_command(&["mkdir", "--path", entry.path])?;
+            continue;
+        }
+        ctx.run_bellande_command(&["create", "--path", entry.path])?;
+        let output = write_file(ctx, entry.path, &vec![b's'; entry.len])?;
+        assert!(output.status.success(), "write {} failed", entry.path);
+    }
+    Ok(())
+}
+
+pub(crate) fn template_matches_reference(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    for entry in ENTRIES {
+        for template in TEMPLATES {
+            let expected = reference(ctx, entry, template)?;
+            ctx.command(&["stat", "--path", entry.path, "--template", template])
+                .assert()
+                .success()
+                .stdout(predicate::str::diff(expected));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn unknown_placeholders_rejected(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    for template in ["%q", "%n %Z", "ends with %"] {
+        let output = ctx
+            .command(&["stat", "--path", "/data.bin", "--template", template])
+            .assert()
+            .code(EXIT_USAGE)
+            .stdout(predicate::str::is_empty());
+        let stderr = String::from_utf8_lossy(&output.get_output().stderr).into_owned();
+        for (letter, _) in PLACEHOLDERS {
+            assert!(
+                stderr.contains(&format!("%{}", letter)),
+                "%{} not listed as supported: {:?}",
+                letter,
+                stderr
+            );
+        }
+    }
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    template_matches_reference,
+    #[contract]
+    unknown_placeholders_rejected,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_only_use_supported_placeholders() {
+        let supported = |letter: char| {
+            PLACEHOLDERS
+                .iter()
+                .any(|(c, _)| *c == letter)
+                .then(|| letter.to_string())
+        };
+        for template in TEMPLATES {
+            assert!(expand(template, supported).is_ok(), "{:?}", template);
+        }
+    }
+}
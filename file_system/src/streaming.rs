@@ -0,0 +1,205 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `write` and `read` stream block by block with bounded memory, so files
+// larger than RAM move through stdin and stdout. `--buffer-size BYTES` (one
+// block up to 64M, default a few blocks' worth) sets how much either side
+// holds at once; malformed values exit EXIT_USAGE and out-of-range ones
+// EXIT_INVALID. Peak memory is the child's VmHWM, sampled from /proc while
+// it waits on the pipe, so these checks run on Linux only.
+
+use crate::cli::EXIT_USAGE;
+use crate::errors::EXIT_INVALID;
+use crate::fsck::assert_fsck_clean;
+use crate::golden::{checksum_update, CHECKSUM_INIT};
+use crate::harness::{format_device, scenarios, StreamingChild, TestContext};
+use crate::large_device::{read_stream, stream_checksum, stream_chunk, write_stream, CHUNK_LEN};
+use predicates::prelude::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::Stdio;
+
+const DEVICE_SIZE: u64 = 320 * 1024 * 1024;
+const BLOCK_SIZE: u32 = 4096;
+const FILE_LEN: u64 = 256 * 1024 * 1024;
+// Well under the file, and above the binary's own footprint
+const MAX_PEAK_RSS: u64 = 48 * 1024 * 1024;
+const SMALL_FILE_LEN: u64 = 5 * 1024 * 1024 + 123;
+
+fn streaming_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// "VmHWM:     1234 kB" in /proc/<pid>/status, in bytes
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+fn peak_rss(child: &StreamingChild) -> u64 {
+    fs::read_to_string(format!("/proc/{}/status", child.id()))
+        .ok()
+        .and_then(|status| parse_vm_hwm(&status))
+        .unwrap_or(0)
+}
+
+// Feeds `len` generated bytes to the child's stdin and waits for it,
+// returning its peak memory while it still had input coming
+fn feed(mut child: StreamingChild, seed: u64, len: u64) -> io::Result<u64> {
+    let mut peak = 0;
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut done = 0;
+        let mut index = 0;
+        while done < len {
+            let chunk_len = (len - done).min(CHUNK_LEN as u64) as usize;
+            stdin.write_all(&stream_chunk(seed, index, chunk_len))?;
+            done += chunk_len as u64;
+            index += 1;
+            peak = peak.max(peak_rss(&child));
+        }
+    }
+    let output = child.wait()?;
+    assert!(
+        output.status.success(),
+        "streaming write failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(peak)
+}
+
+// Reads `path` out, returning its checksum and the reader's peak memory
+fn measured_read(ctx: &TestContext, args: &[&str]) -> io::Result<(u64, u64)> {
+    let mut child = ctx.spawn(args, Stdio::null(), Stdio::piped())?;
+    let mut hash = CHECKSUM_INIT;
+    let mut peak = 0;
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut buffer = vec![0u8; CHUNK_LEN];
+        loop {
+            let read = stdout.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hash = checksum_update(hash, &buffer[..read]);
+            peak = peak.max(peak_rss(&child));
+        }
+    }
+    let output = child.wait()?;
+    assert!(
+        output.status.success(),
+        "streaming read failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok((hash, peak))
+}
+
+pub(crate) fn large_files_stream_in_bounded_memory() -> io::Result<()> {
+    if !cfg!(target_os = "linux") {
+        println!("Skipping streaming memory checks: no /proc to sample");
+        return Ok(());
+    }
+    let ctx = streaming_context()?;
+    format_device(&ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/huge.bin"])?;
+
+    let writer = ctx.spawn(
+        &["write", "--path", "/huge.bin"],
+        Stdio::piped(),
+        Stdio::null(),
+    )?;
+    let peak = feed(writer, 7, FILE_LEN)?;
+    assert!(
+        peak > 0 && peak < MAX_PEAK_RSS,
+        "write peaked at {} bytes for a {} byte file",
+        peak,
+        FILE_LEN
+    );
+    let (checksum, peak) = measured_read(&ctx, &["read", "--path", "/huge.bin"])?;
+    assert_eq!(checksum, stream_checksum(7, FILE_LEN));
+    assert!(
+        peak > 0 && peak < MAX_PEAK_RSS,
+        "read peaked at {} bytes for a {} byte file",
+        peak,
+        FILE_LEN
+    );
+    assert_fsck_clean(&ctx)
+}
+
+pub(crate) fn buffer_size_option() -> io::Result<()> {
+    let ctx = streaming_context()?;
+    format_device(&ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/small.bin"])?;
+    write_stream(&ctx, "/small.bin", 3, SMALL_FILE_LEN)?;
+    let expected = stream_checksum(3, SMALL_FILE_LEN);
+
+    // Any buffer size gives the same bytes, including ones not a block multiple
+    for size in ["4K", "6000", "1M", "64M"] {
+        let path = format!("/copy_{}.bin", size);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let writer = ctx.spawn(
+            &["write", "--buffer-size", size, "--path", &path],
+            Stdio::piped(),
+            Stdio::null(),
+        )?;
+        feed(writer, 3, SMALL_FILE_LEN)?;
+        let (checksum, _) = measured_read(&ctx, &["read", "--buffer-size", size, "--path", &path])?;
+        assert_eq!(checksum, expected, "--buffer-size {}", size);
+        assert_eq!(read_stream(&ctx, &path)?, (SMALL_FILE_LEN, expected));
+    }
+
+    for (size, code) in [
+        ("0", EXIT_INVALID),
+        ("1", EXIT_INVALID),
+        ("1G", EXIT_INVALID),
+        ("lots", EXIT_USAGE),
+        ("-4K", EXIT_USAGE),
+    ] {
+        for command in ["read", "write"] {
+            ctx.command(&[command, "--buffer-size", size, "--path", "/small.bin"])
+                .assert()
+                .code(code)
+                .stderr(predicate::str::contains("--buffer-size"));
+        }
+    }
+    assert_eq!(read_stream(&ctx, "/small.bin")?, (SMALL_FILE_LEN, expected));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    large_files_stream_in_bounded_memory(),
+    #[contract]
+    buffer_size_option(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status =
+            "Name:\tfile_system\nVmPeak:\t  20000 kB\nVmHWM:\t    1234 kB\nVmRSS:\t 1000 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(1234 * 1024));
+        assert_eq!(parse_vm_hwm("Name:\tx\n"), None);
+        assert_eq!(parse_vm_hwm("VmHWM:\tlots kB\n"), None);
+    }
+}
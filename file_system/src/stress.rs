@@ -0,0 +1,170 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Concurrent-access stress: several threads drive the binary against one
+// shared device at the same time, each in its own directory plus a couple of
+// shared paths. Every thread remembers what it last wrote to its own files and
+// those contents must survive; a watchdog turns a deadlock into a failure.
+
+use crate::differential::{content, Rng};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, write_file, TestContext};
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS_ENV: &str = "BELLANDE_FS_STRESS_THREADS";
+const DURATION_ENV: &str = "BELLANDE_FS_STRESS_SECONDS";
+const DEFAULT_THREADS: usize = 4;
+const DEFAULT_DURATION_SECS: u64 = 10;
+const WATCHDOG_GRACE: Duration = Duration::from_secs(60);
+const FILES_PER_THREAD: u64 = 4;
+const SHARED_PATHS: [&str; 2] = ["/shared_a.txt", "/shared_b.txt"];
+
+type LastWritten = HashMap<String, Vec<u8>>;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid value for {}: {:?}", name, value)),
+        Err(_) => default,
+    }
+}
+
+fn worker(ctx: &TestContext, id: usize, deadline: Instant) -> io::Result<LastWritten> {
+    let mut rng = Rng::new(id as u64 + 1);
+    let dir = format!("/thread{}", id);
+    let mut last_written = LastWritten::new();
+
+    ctx.run_bellande_command(&["mkdir", "--path", &dir])?;
+    for index in 0..FILES_PER_THREAD {
+        let path = format!("{}/file{}.bin", dir, index);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        last_written.insert(path, Vec::new());
+    }
+
+    while Instant::now() < deadline {
+        let path = format!("{}/file{}.bin", dir, rng.below(FILES_PER_THREAD));
+        match rng.below(10) {
+            0 => {
+                // Shared paths are only checked for readability at the end
+                let shared = SHARED_PATHS[rng.below(SHARED_PATHS.len() as u64) as usize];
+                let _ = write_file(ctx, shared, &content(rng.next_u64(), 512));
+            }
+            1..=5 => {
+                let data = content(rng.next_u64(), rng.below(16 * 1024) as usize);
+                let output = write_file(ctx, &path, &data)?;
+                if !output.status.success() {
+                    return Err(io::Error::other(format!(
+                        "Thread {} failed to write {}: {}",
+                        id,
+                        path,
+                        String::from_utf8_lossy(&output.stderr)
+                    )));
+                }
+                last_written.insert(path, data);
+            }
+            6 => {
+                ctx.run_bellande_command(&["list", "--path", &dir])?;
+            }
+            _ => {
+                ctx.run_bellande_command(&["read", "--path", &path])?;
+            }
+        }
+    }
+
+    Ok(last_written)
+}
+
+pub(crate) fn concurrent_stress(threads: usize, duration: Duration) -> io::Result<()> {
+    let ctx = Arc::new(TestContext::new()?);
+    format_device(&ctx)?;
+    for shared in SHARED_PATHS {
+        ctx.run_bellande_command(&["create", "--path", shared])?;
+    }
+
+    let deadline = Instant::now() + duration;
+    let (sender, receiver) = mpsc::channel();
+    for id in 0..threads {
+        let ctx = Arc::clone(&ctx);
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send((id, worker(&ctx, id, deadline)));
+        });
+    }
+    drop(sender);
+
+    let mut expected = LastWritten::new();
+    for _ in 0..threads {
+        let remaining = deadline.saturating_duration_since(Instant::now()) + WATCHDOG_GRACE;
+        let (id, result) = receiver.recv_timeout(remaining).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Stress workers did not finish in time (possible deadlock)",
+            )
+        })?;
+        println!("Stress thread {} finished", id);
+        expected.extend(result?);
+    }
+
+    for (path, data) in &expected {
+        let output = ctx.run_bellande_command(&["read", "--path", path])?;
+        assert_eq!(
+            output.stdout.len(),
+            data.len(),
+            "Size of {} does not match the last write",
+            path
+        );
+        assert!(
+            output.stdout == *data,
+            "Contents of {} do not match the last write",
+            path
+        );
+    }
+    ctx.run_bellande_command(&["list", "--path", "/"])?;
+    for shared in SHARED_PATHS {
+        ctx.run_bellande_command(&["read", "--path", shared])?;
+    }
+
+    // Concurrent commands must not have left the metadata inconsistent,
+    // which only a binary with `fsck` can tell
+    if cfg!(feature = "contract-tests") {
+        assert_fsck_clean(&ctx)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn concurrent_stress_from_env() -> io::Result<()> {
+    let threads = env_or(THREADS_ENV, DEFAULT_THREADS);
+    let seconds = env_or(DURATION_ENV, DEFAULT_DURATION_SECS);
+    concurrent_stress(threads, Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "long-running; run with cargo test -- --ignored"]
+    fn test_concurrent_stress() -> io::Result<()> {
+        concurrent_stress_from_env()
+    }
+}
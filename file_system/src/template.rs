@@ -0,0 +1,173 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// GNU `stat --printf`-style templates: `%x` placeholders are looked up by
+// letter, `%%` is a literal percent, and backslash escapes (`\n`, `\t`,
+// `\\`, `\"`, `\NNN` octal, `\xHH` hex) are decoded. The placeholder set is
+// the caller's, so `stat --template` and a future `find --printf` share it.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum TemplateError {
+    UnknownPlaceholder(char),
+    TrailingPercent,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(c) => write!(f, "unknown placeholder %{}", c),
+            TemplateError::TrailingPercent => write!(f, "template ends with a lone %"),
+        }
+    }
+}
+
+// Up to `max` digits of `radix` from the front of `chars`
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max: usize) -> u32 {
+    let mut value = 0;
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                value = value * radix + digit;
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    value
+}
+
+fn escape(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+    let Some(c) = chars.next() else {
+        out.push('\\');
+        return;
+    };
+    match c {
+        'n' => out.push('\n'),
+        't' => out.push('\t'),
+        'r' => out.push('\r'),
+        'a' => out.push('\x07'),
+        'b' => out.push('\x08'),
+        'f' => out.push('\x0c'),
+        'v' => out.push('\x0b'),
+        'e' => out.push('\x1b'),
+        '\\' => out.push('\\'),
+        '"' => out.push('"'),
+        '0'..='7' => {
+            let value = c.to_digit(8).unwrap_or(0) * 64 + take_digits(chars, 8, 2);
+            // Octal escapes beyond one byte keep their low byte, as in GNU
+            out.push(char::from((value & 0xff) as u8));
+        }
+        'x' if chars.peek().is_some_and(char::is_ascii_hexdigit) => {
+            out.push(char::from(take_digits(chars, 16, 2) as u8));
+        }
+        // Unknown escapes are kept as written
+        other => {
+            out.push('\\');
+            out.push(other);
+        }
+    }
+}
+
+pub(crate) fn expand(
+    template: &str,
+    lookup: impl Fn(char) -> Option<String>,
+) -> Result<String, TemplateError> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => match chars.next() {
+                Some('%') => out.push('%'),
+                Some(letter) => {
+                    out.push_str(&lookup(letter).ok_or(TemplateError::UnknownPlaceholder(letter))?)
+                }
+                None => return Err(TemplateError::TrailingPercent),
+            },
+            '\\' => escape(&mut chars, &mut out),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(letter: char) -> Option<String> {
+        match letter {
+            'n' => Some("/etc/hosts".to_string()),
+            's' => Some("42".to_string()),
+            'e' => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(expand("%n %s", lookup), Ok("/etc/hosts 42".to_string()));
+        assert_eq!(expand("%s%s", lookup), Ok("4242".to_string()));
+        assert_eq!(expand("[%e]", lookup), Ok("[]".to_string()));
+        assert_eq!(expand("", lookup), Ok(String::new()));
+        assert_eq!(expand("plain text", lookup), Ok("plain text".to_string()));
+        assert_eq!(
+            expand("caf\u{00E9} %s", lookup),
+            Ok("caf\u{00E9} 42".to_string())
+        );
+    }
+
+    #[test]
+    fn test_literal_percent() {
+        assert_eq!(expand("100%%", lookup), Ok("100%".to_string()));
+        assert_eq!(expand("%%s", lookup), Ok("%s".to_string()));
+        assert_eq!(expand("%%%s%%", lookup), Ok("%42%".to_string()));
+        assert_eq!(expand("%%%%", lookup), Ok("%%".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            expand("%q", lookup),
+            Err(TemplateError::UnknownPlaceholder('q'))
+        );
+        assert_eq!(expand("%n %", lookup), Err(TemplateError::TrailingPercent));
+        assert_eq!(expand("%%%", lookup), Err(TemplateError::TrailingPercent));
+        assert_eq!(
+            expand("% n", lookup),
+            Err(TemplateError::UnknownPlaceholder(' '))
+        );
+        assert_eq!(
+            TemplateError::UnknownPlaceholder('q').to_string(),
+            "unknown placeholder %q"
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(expand("%s\\n", lookup), Ok("42\n".to_string()));
+        assert_eq!(
+            expand("a\\tb\\\\c\\\"", lookup),
+            Ok("a\tb\\c\"".to_string())
+        );
+        assert_eq!(expand("\\101\\x42\\0", lookup), Ok("AB\0".to_string()));
+        assert_eq!(expand("\\x4a\\x4", lookup), Ok("J\x04".to_string()));
+        assert_eq!(expand("\\q\\xz", lookup), Ok("\\q\\xz".to_string()));
+        assert_eq!(expand("end\\", lookup), Ok("end\\".to_string()));
+        // An unknown escape keeps its character, so `\%` never starts a placeholder
+        assert_eq!(expand("\\%s", lookup), Ok("\\%s".to_string()));
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Color and column alignment. Under the harness neither stream is a TTY, so
+// `--color auto` must stay plain; `always` and `never` force it either way.
+
+use crate::cli::EXIT_USAGE;
+use crate::harness::{format_device, scenarios, write_file, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+const ESCAPE: char = '\x1b';
+const RED: &str = "\x1b[31m";
+
+// Names last so the columns before them are plain ASCII
+const LONG_FILES: &[(&str, usize)] = &[
+    ("a.txt", 5),
+    ("\u{00FC}n\u{00EF}c\u{00F8}d\u{00E9}", 500),
+    ("\u{65E5}\u{672C}", 50000),
+];
+
+fn populated(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    for (name, len) in LONG_FILES {
+        let path = format!("/{}", name);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        let output = write_file(ctx, &path, &vec![b'x'; *len])?;
+        assert!(output.status.success(), "write {} failed", path);
+    }
+    Ok(())
+}
+
+fn stdout_of(ctx: &TestContext, args: &[&str]) -> String {
+    let output = ctx.command(args).assert().success();
+    String::from_utf8_lossy(&output.get_output().stdout).into_owned()
+}
+
+// Character column where `token` ends on the row naming `name`
+fn column_end(stdout: &str, name: &str, token: &str) -> Option<usize> {
+    let line = stdout.lines().find(|line| line.ends_with(name))?;
+    let start = line.find(&format!(" {} ", token))? + 1;
+    Some(line[..start + token.len()].chars().count())
+}
+
+pub(crate) fn color_modes(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+
+    let forced = stdout_of(ctx, &["--color", "always", "list", "--long", "--path", "/"]);
+    assert!(forced.contains(ESCAPE), "--color always printed no color");
+    for args in [
+        &["--color", "never", "list", "--long", "--path", "/"][..],
+        &["--color", "auto", "list", "--long", "--path", "/"][..],
+        &["list", "--long", "--path", "/"][..],
+    ] {
+        assert!(
+            !stdout_of(ctx, args).contains(ESCAPE),
+            "{:?} printed color",
+            args
+        );
+    }
+
+    // NO_COLOR only affects auto; an explicit `always` still wins
+    ctx.command(&["--color", "auto", "list", "--long", "--path", "/"])
+        .env("NO_COLOR", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b").not());
+    ctx.command(&["--color", "always", "list", "--long", "--path", "/"])
+        .env("NO_COLOR", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b"));
+
+    ctx.command(&["--color", "always", "read", "--path", "/missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(RED));
+    ctx.command(&["--color", "sometimes", "stats"])
+        .assert()
+        .code(EXIT_USAGE);
+    Ok(())
+}
+
+pub(crate) fn long_listing_alignment(ctx: &TestContext) -> io::Result<()> {
+    populated(ctx)?;
+    let stdout = stdout_of(ctx, &["--color", "never", "list", "--long", "--path", "/"]);
+
+    let header = stdout
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    assert!(header.contains("Name"), "no header row in {:?}", stdout);
+
+    let ends: Vec<Option<usize>> = LONG_FILES
+        .iter()
+        .map(|(name, len)| column_end(&stdout, name, &len.to_string()))
+        .collect();
+    assert!(
+        ends.iter().all(Option::is_some),
+        "missing rows in {:?}",
+        stdout
+    );
+    assert!(
+        ends.windows(2).all(|pair| pair[0] == pair[1]),
+        "size column is not right-aligned: {:?}\n{}",
+        ends,
+        stdout
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    color_modes,
+    #[contract]
+    long_listing_alignment,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_end() {
+        let stdout = "Size Name\n   5 a.txt\n 500 \u{00FC}n\n";
+        assert_eq!(column_end(stdout, "a.txt", "5"), Some(4));
+        assert_eq!(column_end(stdout, "\u{00FC}n", "500"), Some(4));
+        assert_eq!(column_end(stdout, "missing", "5"), None);
+    }
+}
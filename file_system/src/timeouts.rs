@@ -0,0 +1,121 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The global `--timeout <secs>` flag: a stalled operation must abort with
+// "operation timed out" and its own exit status instead of hanging.
+
+use crate::harness::{command_timeout, format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Matches coreutils `timeout`
+pub(crate) const EXIT_TIMED_OUT: i32 = 124;
+pub(crate) const TIMED_OUT_MESSAGE: &str = "operation timed out";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Runs a write whose stdin is never closed, so the binary waits on it until
+// its own timeout fires; the harness timeout still bounds the wait
+fn stalled_write(ctx: &TestContext, timeout_secs: &str) -> io::Result<(Output, Duration)> {
+    let started = Instant::now();
+    let mut child = Command::new(&ctx.binary_path)
+        .arg("--device")
+        .arg(&ctx.device_path)
+        .args(["--timeout", timeout_secs, "write", "--path", "/stalled.txt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+
+    let deadline = started + command_timeout();
+    while child.try_wait()?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill()?;
+            let output = child.wait_with_output()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "binary ignored --timeout {} and was killed after {:?}\nstdout: {}\nstderr: {}",
+                    timeout_secs,
+                    started.elapsed(),
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let elapsed = started.elapsed();
+    drop(stdin);
+    Ok((child.wait_with_output()?, elapsed))
+}
+
+pub(crate) fn timeout_flag_accepted(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.command(&["--timeout", "30", "create", "--path", "/quick.txt"])
+        .assert()
+        .success();
+    ctx.command(&["--timeout", "30", "list", "--path", "/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("quick.txt"));
+    Ok(())
+}
+
+pub(crate) fn timeout_rejects_invalid_values(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    for value in ["0", "-5", "soon", ""] {
+        ctx.command(&["--timeout", value, "list", "--path", "/"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--timeout"));
+    }
+    Ok(())
+}
+
+pub(crate) fn stalled_operation_times_out(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/stalled.txt"])?;
+    let before = ctx.run_bellande_command(&["read", "--path", "/stalled.txt"])?;
+
+    let (output, elapsed) = stalled_write(ctx, "1")?;
+    assert_eq!(output.status.code(), Some(EXIT_TIMED_OUT));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(TIMED_OUT_MESSAGE));
+    assert!(
+        elapsed >= Duration::from_secs(1),
+        "timed out early, after {:?}",
+        elapsed
+    );
+
+    // The aborted write leaves the device usable and the file unchanged
+    let after = ctx.run_bellande_command(&["read", "--path", "/stalled.txt"])?;
+    assert_eq!(after.stdout, before.stdout);
+    ctx.run_bellande_command(&["list", "--path", "/"])?;
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    timeout_flag_accepted,
+    #[contract]
+    timeout_rejects_invalid_values,
+    #[contract]
+    stalled_operation_times_out,
+}
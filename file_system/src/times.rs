@@ -0,0 +1,213 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Time arguments. `parse_relative_time` and the RFC 3339 helpers are the
+// reference for the binary's `--newer-than`/`--older-than` style flags and
+// `touch --date`; everything is whole seconds since the Unix epoch, in UTC.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+// "90s", "15m", "2h", "3d" or a sum of them such as "1d12h"; a bare number
+// is rejected because its unit would be a guess
+pub(crate) fn parse_relative_time(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let scale = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => SECONDS_PER_DAY as u64,
+            _ => return None,
+        };
+        let amount: u64 = digits.parse().ok()?;
+        total = total.checked_add(amount.checked_mul(scale)?)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// "2024-06-15T08:30:00Z"
+pub(crate) fn format_rfc3339(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+fn number(text: &str, range: std::ops::RangeInclusive<i64>) -> Option<i64> {
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let value = text.parse().ok()?;
+    range.contains(&value).then_some(value)
+}
+
+// Seconds since the epoch for "YYYY-MM-DDTHH:MM:SS" followed by "Z" or a
+// "+HH:MM"/"-HH:MM" offset; fractional seconds are not accepted
+pub(crate) fn parse_rfc3339(text: &str) -> Option<i64> {
+    let (date, rest) = text.split_once(['T', 't'])?;
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 0..=9999)?;
+    let month = number(parts.next()?, 1..=12)?;
+    let day = number(parts.next()?, 1..=31)?;
+    if parts.next().is_some() || rest.len() < 9 {
+        return None;
+    }
+
+    let (clock, zone) = rest.split_at(8);
+    let mut fields = clock.split(':');
+    let hour = number(fields.next()?, 0..=23)?;
+    let minute = number(fields.next()?, 0..=59)?;
+    let second = number(fields.next()?, 0..=59)?;
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = zone[1..].split_once(':')?;
+            sign * (number(hours, 0..=23)? * 3600 + number(minutes, 0..=59)? * 60)
+        }
+    };
+
+    // Rejects dates such as February 30th
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset)
+}
+
+pub(crate) fn now_seconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_relative_time() {
+        assert_eq!(parse_relative_time("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_relative_time("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_relative_time("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(
+            parse_relative_time(" 2d "),
+            Some(Duration::from_secs(172_800))
+        );
+        assert_eq!(
+            parse_relative_time("1d12h"),
+            Some(Duration::from_secs(129_600))
+        );
+        assert_eq!(parse_relative_time("0s"), Some(Duration::ZERO));
+        for invalid in [
+            "",
+            "5",
+            "d",
+            "2w",
+            "1.5h",
+            "-2d",
+            "2d3",
+            "99999999999999999999d",
+        ] {
+            assert_eq!(parse_relative_time(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2024-06-15T08:30:00Z"), Some(1_718_440_200));
+        assert_eq!(
+            parse_rfc3339("2024-06-15T10:30:00+02:00"),
+            Some(1_718_440_200)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-06-15T03:30:00-05:00"),
+            Some(1_718_440_200)
+        );
+        assert_eq!(parse_rfc3339("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+        for invalid in [
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-06-15 08:30:00Z",
+            "2024-06-15T08:30:00",
+            "2024-06-15T08:30:00.5Z",
+            "2024-06-15T24:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{:?}", invalid);
+        }
+
+        for seconds in [0, 1_718_440_200, 1_709_164_800, 951_782_400, -86_400] {
+            assert_eq!(parse_rfc3339(&format_rfc3339(seconds)), Some(seconds));
+        }
+        assert_eq!(format_rfc3339(1_718_440_200), "2024-06-15T08:30:00Z");
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Recovering removed files. `remove --trash` moves the path, file or whole
+// tree, into a trash kept outside the directory tree, so it disappears from
+// `list` but keeps its inodes and blocks. `trash list` prints one
+// "<id>\t<original path>" line per entry, oldest first; `restore --path`
+// puts back the newest entry removed from that path, or moves it to `--to`;
+// `trash empty` frees everything in the trash.
+//
+// Without the trash, `undelete --list` scans for freed inodes none of whose
+// blocks have been allocated since, printing "<inode>\t<size>\t<last name>"
+// per candidate, and `undelete --inode N --to PATH` links one back in. An
+// inode that is no longer intact is refused rather than recovered partially.
+
+use crate::capacity::{tiny_context, TINY_BLOCK_SIZE};
+use crate::cli::EXIT_USAGE;
+use crate::differential::{content, inode_of, listed_names};
+use crate::errors::{EXIT_ALREADY_EXISTS, EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use std::collections::BTreeSet;
+use std::io;
+
+const BLOCK: usize = TINY_BLOCK_SIZE as usize;
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<BTreeSet<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn inode(ctx: &TestContext, dir: &str, name: &str) -> io::Result<Option<u64>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(inode_of(&String::from_utf8_lossy(&output.stdout), name))
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+fn create(ctx: &TestContext, path: &str, data: &[u8]) -> io::Result<()> {
+    ctx.run_bellande_command(&["create", "--path", path])?;
+    assert!(
+        write_file(ctx, path, data)?.status.success(),
+        "write {} failed",
+        path
+    );
+    Ok(())
+}
+
+// Original paths from `trash list`, oldest first
+fn trashed(ctx: &TestContext) -> io::Result<Vec<String>> {
+    let output = ctx.run_bellande_command(&["trash", "list"])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(_, path)| path.to_string())
+        .collect())
+}
+
+struct Candidate {
+    inode: u64,
+    size: u64,
+    name: String,
+}
+
+fn parse_candidates(stdout: &str) -> Vec<Candidate> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(Candidate {
+                inode: fields.next()?.parse().ok()?,
+                size: fields.next()?.parse().ok()?,
+                name: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn candidates(ctx: &TestContext) -> io::Result<Vec<Candidate>> {
+    let output = ctx.run_bellande_command(&["undelete", "--list"])?;
+    Ok(parse_candidates(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Allocates every free block, halving the file size whenever one no longer fits
+fn consume_free_blocks(ctx: &TestContext) -> io::Result<()> {
+    let mut chunk = read_stats(ctx)?.free_blocks;
+    let mut index = 0;
+    while chunk > 0 {
+        let free = read_stats(ctx)?.free_blocks;
+        if free == 0 {
+            break;
+        }
+        chunk = chunk.min(free);
+        let path = format!("/fill{}", index);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        if write_file(ctx, &path, &vec![b'F'; chunk as usize * BLOCK])?
+            .status
+            .success()
+        {
+            index += 1;
+        } else {
+            ctx.run_bellande_command(&["remove", "--path", &path])?;
+            chunk /= 2;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn trash_and_restore(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/docs"])?;
+    create(ctx, "/docs/a.txt", &content(1, 3 * BLOCK))?;
+    create(ctx, "/docs/b.txt", &content(2, 5 * BLOCK + 7))?;
+    let before = read_stats(ctx)?;
+    let a_inode = inode(ctx, "/docs", "a.txt")?;
+
+    // Trashing keeps every inode and block allocated
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/docs/a.txt"])?;
+    assert_eq!(names_in(ctx, "/docs")?, ["b.txt".to_string()].into());
+    assert_eq!(read_stats(ctx)?, before);
+    ctx.run_bellande_command(&["remove", "--trash", "--recursive", "--path", "/docs"])?;
+    assert!(
+        names_in(ctx, "/")?.is_empty(),
+        "the trash shows in the tree"
+    );
+    assert_eq!(trashed(ctx)?, ["/docs/a.txt", "/docs"]);
+    assert_eq!(read_stats(ctx)?, before);
+    assert_fsck_clean(ctx)?;
+
+    // Restoring is a move back, so the inode is unchanged
+    ctx.run_bellande_command(&["restore", "--path", "/docs"])?;
+    ctx.run_bellande_command(&["restore", "--path", "/docs/a.txt"])?;
+    assert!(trashed(ctx)?.is_empty());
+    assert_eq!(inode(ctx, "/docs", "a.txt")?, a_inode);
+    assert!(read(ctx, "/docs/a.txt")? == content(1, 3 * BLOCK));
+    assert!(read(ctx, "/docs/b.txt")? == content(2, 5 * BLOCK + 7));
+    assert_eq!(read_stats(ctx)?, before);
+    assert_fsck_clean(ctx)?;
+
+    // The same path trashed twice: the newest comes back first
+    create(ctx, "/x", b"first")?;
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/x"])?;
+    create(ctx, "/x", b"second")?;
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/x"])?;
+    assert_eq!(trashed(ctx)?, ["/x", "/x"]);
+    ctx.run_bellande_command(&["restore", "--path", "/x"])?;
+    ctx.run_bellande_command(&["restore", "--path", "/x", "--to", "/x.old"])?;
+    assert_eq!(read(ctx, "/x")?, b"second");
+    assert_eq!(read(ctx, "/x.old")?, b"first");
+    assert!(trashed(ctx)?.is_empty());
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn trash_empty_frees_space(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let empty = read_stats(ctx)?;
+    create(ctx, "/big.bin", &content(3, 40 * BLOCK))?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/tree"])?;
+    for index in 0..5 {
+        create(ctx, &format!("/tree/f{}", index), &content(index, BLOCK))?;
+    }
+    let written = read_stats(ctx)?;
+
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/big.bin"])?;
+    ctx.run_bellande_command(&["remove", "--trash", "--recursive", "--path", "/tree"])?;
+    assert_eq!(read_stats(ctx)?, written);
+    ctx.run_bellande_command(&["trash", "empty"])?;
+    assert_eq!(read_stats(ctx)?, empty, "emptying the trash leaked space");
+    assert!(trashed(ctx)?.is_empty());
+    ctx.command(&["restore", "--path", "/big.bin"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert_fsck_clean(ctx)?;
+
+    // An empty trash empties again without complaint
+    ctx.run_bellande_command(&["trash", "empty"])?;
+    assert_eq!(read_stats(ctx)?, empty);
+    Ok(())
+}
+
+pub(crate) fn undelete_recovers_intact_inodes(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let data = content(4, 7 * BLOCK + 100);
+    create(ctx, "/lost.bin", &data)?;
+    let written = read_stats(ctx)?;
+    let lost_inode = inode(ctx, "/", "lost.bin")?.expect("lost.bin has an inode");
+    ctx.run_bellande_command(&["remove", "--path", "/lost.bin"])?;
+
+    let found = candidates(ctx)?;
+    let candidate = found
+        .iter()
+        .find(|candidate| candidate.inode == lost_inode)
+        .expect("the removed inode is not offered for recovery");
+    assert_eq!(candidate.size, data.len() as u64);
+    assert_eq!(candidate.name, "lost.bin");
+
+    let lost = lost_inode.to_string();
+    ctx.run_bellande_command(&["undelete", "--inode", &lost, "--to", "/found.bin"])?;
+    assert!(read(ctx, "/found.bin")? == data);
+    assert_eq!(
+        read_stats(ctx)?,
+        written,
+        "recovery did not reclaim the blocks"
+    );
+    assert!(candidates(ctx)?
+        .iter()
+        .all(|candidate| candidate.inode != lost_inode));
+    assert_fsck_clean(ctx)?;
+
+    // Recovered once is recovered for good
+    ctx.command(&["undelete", "--inode", &lost, "--to", "/again.bin"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    Ok(())
+}
+
+pub(crate) fn undelete_skips_reused_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    create(ctx, "/gone.bin", &content(5, 10 * BLOCK))?;
+    let gone_inode = inode(ctx, "/", "gone.bin")?.expect("gone.bin has an inode");
+    ctx.run_bellande_command(&["remove", "--path", "/gone.bin"])?;
+
+    // Once every block has been handed out again, nothing of it is intact
+    consume_free_blocks(ctx)?;
+    assert!(
+        candidates(ctx)?
+            .iter()
+            .all(|candidate| candidate.inode != gone_inode),
+        "an inode with reallocated blocks is offered for recovery"
+    );
+    let before = read_stats(ctx)?;
+    ctx.command(&[
+        "undelete",
+        "--inode",
+        &gone_inode.to_string(),
+        "--to",
+        "/gone.bin",
+    ])
+    .assert()
+    .code(EXIT_NOT_FOUND);
+    assert!(names_in(ctx, "/")?.iter().all(|name| name != "gone.bin"));
+    assert_eq!(read_stats(ctx)?, before);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn trash_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    create(ctx, "/dir/file", b"data")?;
+    create(ctx, "/keep", b"keep")?;
+
+    ctx.command(&["remove", "--trash", "--path", "/missing"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["remove", "--trash", "--path", "/dir"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    ctx.command(&["restore", "--path", "/keep"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+
+    // A restore never overwrites what took the old path
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/keep"])?;
+    create(ctx, "/keep", b"new")?;
+    ctx.command(&["restore", "--path", "/keep"])
+        .assert()
+        .code(EXIT_ALREADY_EXISTS);
+    assert_eq!(read(ctx, "/keep")?, b"new");
+    assert_eq!(trashed(ctx)?, ["/keep"]);
+
+    // Nor does it recreate a removed parent
+    ctx.run_bellande_command(&["remove", "--trash", "--path", "/dir/file"])?;
+    ctx.run_bellande_command(&["rmdir", "--path", "/dir"])?;
+    ctx.command(&["restore", "--path", "/dir/file"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert_eq!(trashed(ctx)?, ["/keep", "/dir/file"]);
+
+    for args in [
+        &["restore"][..],
+        &["trash"],
+        &["trash", "bogus"],
+        &["undelete"],
+        &["undelete", "--inode", "x", "--to", "/x"],
+        &["undelete", "--inode", "5"],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    trash_and_restore(tiny_context()?),
+    #[contract]
+    trash_empty_frees_space(tiny_context()?),
+    #[contract]
+    undelete_recovers_intact_inodes(tiny_context()?),
+    #[contract]
+    undelete_skips_reused_blocks(tiny_context()?),
+    #[contract]
+    trash_errors(tiny_context()?),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_candidates() {
+        let found = parse_candidates("12\t7268\tlost.bin\n13\t0\tname\twith tab\nnoise\n");
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].inode, found[0].size), (12, 7268));
+        assert_eq!(found[0].name, "lost.bin");
+        assert_eq!(found[1].name, "name\twith tab");
+    }
+}
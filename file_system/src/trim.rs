@@ -0,0 +1,228 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Discarding free space. `trim` punches holes in the backing file over every
+// free block range, so the image shrinks back on the host and freed data no
+// longer lingers in it, and prints "Trimmed: <blocks>". With
+// `--zero-metadata` it also zeroes free inodes and unused directory entries,
+// so removed names and inodes are gone too. The global `--discard` does the
+// same for each range as it is freed. Discarded blocks no longer count as
+// intact for `undelete`.
+
+use crate::differential::{bytes_contain, content};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, scenarios, stat_field, TestContext, DEFAULT_DEVICE_SIZE};
+use crate::journal::tree_state;
+use crate::large_device::allocated_bytes;
+use std::fs;
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const LARGE_LEN: usize = 4 * 1024 * 1024;
+const SAMPLE_LEN: usize = 64;
+// Sampled blocks per file when looking for stale data in the image
+const SAMPLE_STRIDE: usize = 37;
+const SECRET_NAME: &str = "secret-name-7f3a9c";
+
+fn trim_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+// Whether the host keeps the freshly created backing file sparse
+fn host_is_sparse(ctx: &TestContext) -> io::Result<bool> {
+    Ok(allocated_bytes(&ctx.device_path)? < DEFAULT_DEVICE_SIZE / 2)
+}
+
+// Whether any sampled block of `data` is still somewhere in the image
+fn stale_data(ctx: &TestContext, data: &[u8]) -> io::Result<bool> {
+    let image = fs::read(&ctx.device_path)?;
+    Ok(data
+        .chunks(BLOCK)
+        .step_by(SAMPLE_STRIDE)
+        .any(|block| bytes_contain(&image, &block[..SAMPLE_LEN.min(block.len())])))
+}
+
+fn write(ctx: &TestContext, args: &[&str], path: &str, data: &[u8]) -> io::Result<()> {
+    let mut create = args.to_vec();
+    create.extend(["create", "--path", path]);
+    ctx.run_bellande_command(&create)?;
+    let mut write = args.to_vec();
+    write.extend(["write", "--path", path]);
+    ctx.command(&write)
+        .write_stdin(data.to_vec())
+        .assert()
+        .success();
+    Ok(())
+}
+
+fn trim(ctx: &TestContext, args: &[&str]) -> io::Result<u64> {
+    let mut full = vec!["trim"];
+    full.extend_from_slice(args);
+    let output = ctx.run_bellande_command(&full)?;
+    stat_field(&String::from_utf8_lossy(&output.stdout), "Trimmed")
+}
+
+pub(crate) fn trim_punches_free_ranges(ctx: &TestContext) -> io::Result<()> {
+    let sparse = host_is_sparse(ctx)?;
+    format_device(ctx)?;
+    let keep = content(1, 20 * BLOCK);
+    write(ctx, &[], "/keep.bin", &keep)?;
+    let large = content(2, LARGE_LEN);
+    write(ctx, &[], "/large.bin", &large)?;
+    let written = allocated_bytes(&ctx.device_path)?;
+    ctx.run_bellande_command(&["remove", "--path", "/large.bin"])?;
+    let before = tree_state(ctx)?;
+
+    let trimmed = trim(ctx, &[])?;
+    assert!(
+        trimmed >= (LARGE_LEN / BLOCK) as u64,
+        "trimmed only {} blocks",
+        trimmed
+    );
+    assert!(
+        !stale_data(ctx, &large)?,
+        "freed data is still in the image"
+    );
+    if sparse {
+        assert!(
+            allocated_bytes(&ctx.device_path)? + (LARGE_LEN as u64) * 3 / 4 <= written,
+            "the backing file did not shrink"
+        );
+    }
+
+    // Live data and the tree are untouched, and trimming again is harmless
+    assert_eq!(tree_state(ctx)?, before);
+    assert!(
+        ctx.run_bellande_command(&["read", "--path", "/keep.bin"])?
+            .stdout
+            == keep
+    );
+    assert_fsck_clean(ctx)?;
+    trim(ctx, &[])?;
+    assert_eq!(tree_state(ctx)?, before);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn discard_on_every_free(ctx: &TestContext) -> io::Result<()> {
+    let sparse = host_is_sparse(ctx)?;
+    format_device(ctx)?;
+    let removed = content(3, LARGE_LEN / 2);
+    let truncated = content(4, LARGE_LEN / 2);
+    let replaced = content(5, 30 * BLOCK);
+    write(ctx, &["--discard"], "/removed.bin", &removed)?;
+    write(ctx, &["--discard"], "/truncated.bin", &truncated)?;
+    write(ctx, &["--discard"], "/replaced.bin", &replaced)?;
+    let written = allocated_bytes(&ctx.device_path)?;
+
+    ctx.run_bellande_command(&["--discard", "remove", "--path", "/removed.bin"])?;
+    ctx.run_bellande_command(&[
+        "--discard",
+        "truncate",
+        "--path",
+        "/truncated.bin",
+        "--size",
+        &BLOCK.to_string(),
+    ])?;
+    ctx.command(&["--discard", "write", "--path", "/replaced.bin"])
+        .write_stdin(content(6, 30 * BLOCK))
+        .assert()
+        .success();
+
+    assert!(
+        !stale_data(ctx, &removed)?,
+        "a removed file was not discarded"
+    );
+    assert!(
+        !stale_data(ctx, &truncated[BLOCK..])?,
+        "a truncated tail was not discarded"
+    );
+    assert!(
+        !stale_data(ctx, &replaced)?,
+        "overwritten blocks were not discarded"
+    );
+    if sparse {
+        assert!(
+            allocated_bytes(&ctx.device_path)? + (LARGE_LEN as u64) * 3 / 4 <= written,
+            "the backing file did not shrink"
+        );
+    }
+    assert!(
+        ctx.run_bellande_command(&["read", "--path", "/truncated.bin"])?
+            .stdout
+            == truncated[..BLOCK]
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn zero_metadata_erases_names(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    let path = format!("/dir/{}", SECRET_NAME);
+    write(ctx, &[], &path, &content(7, 5 * BLOCK))?;
+    write(ctx, &[], "/dir/kept", &content(8, 2 * BLOCK))?;
+    ctx.run_bellande_command(&["remove", "--path", &path])?;
+    let before = tree_state(ctx)?;
+
+    // A plain trim discards the data, so nothing is left to undelete
+    trim(ctx, &[])?;
+    let output = ctx.run_bellande_command(&["undelete", "--list"])?;
+    assert!(
+        !String::from_utf8_lossy(&output.stdout).contains(SECRET_NAME),
+        "a trimmed inode is offered for recovery"
+    );
+
+    trim(ctx, &["--zero-metadata"])?;
+    assert!(
+        !bytes_contain(&fs::read(&ctx.device_path)?, SECRET_NAME.as_bytes()),
+        "the removed name is still in the image"
+    );
+    assert_eq!(tree_state(ctx)?, before);
+    assert_fsck_clean(ctx)?;
+
+    // Zeroed inodes and entries are reused like any other free ones
+    write(ctx, &[], &path, &content(9, 3 * BLOCK))?;
+    assert!(ctx.run_bellande_command(&["read", "--path", &path])?.stdout == content(9, 3 * BLOCK));
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn trim_needs_write_access(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    write(ctx, &[], "/gone.bin", &content(10, 40 * BLOCK))?;
+    ctx.run_bellande_command(&["remove", "--path", "/gone.bin"])?;
+    let before = fs::read(&ctx.device_path)?;
+    for args in [
+        &["--read-only", "trim"][..],
+        &["--read-only", "trim", "--zero-metadata"],
+    ] {
+        ctx.command(args).assert().failure();
+    }
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "a read-only trim modified the image"
+    );
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    trim_punches_free_ranges(trim_context()?),
+    #[contract]
+    discard_on_every_free(trim_context()?),
+    #[contract]
+    zero_metadata_erases_names(trim_context()?),
+    #[contract]
+    trim_needs_write_access(trim_context()?),
+}
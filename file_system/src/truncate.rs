@@ -0,0 +1,199 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `truncate --path <file> --size <bytes>`. Shrinking frees every block past
+// the new end, including indirect blocks no longer needed, and zeroes the
+// rest of a partial last block so growing again never brings old bytes back.
+// Growing allocates zeroed blocks, or with `--sparse` leaves a hole; either
+// way the new tail reads as zeros. A differential `truncate` op runs the same
+// semantics against std::fs.
+
+use crate::capacity::{tiny_context, EXIT_NO_SPACE, TINY_BLOCK_SIZE};
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{format_device, read_stats, scenarios, write_file, TestContext};
+use std::fs;
+use std::io;
+
+const BLOCK: u64 = TINY_BLOCK_SIZE as u64;
+
+fn truncate(ctx: &TestContext, path: &str, size: u64, sparse: bool) -> io::Result<()> {
+    let size = size.to_string();
+    let mut args = vec!["truncate", "--path", path, "--size", &size];
+    if sparse {
+        args.push("--sparse");
+    }
+    ctx.run_bellande_command(&args)?;
+    Ok(())
+}
+
+fn read(ctx: &TestContext, path: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx.run_bellande_command(&["read", "--path", path])?.stdout)
+}
+
+fn free_blocks(ctx: &TestContext) -> io::Result<u64> {
+    Ok(read_stats(ctx)?.free_blocks)
+}
+
+pub(crate) fn shrinking_frees_blocks(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/f.dat"])?;
+    let empty = free_blocks(ctx)?;
+
+    // Already empty, then to its exact size: both succeed and change nothing
+    truncate(ctx, "/f.dat", 0, false)?;
+    assert_eq!(free_blocks(ctx)?, empty);
+    let data = content(1, 10 * BLOCK as usize);
+    assert!(write_file(ctx, "/f.dat", &data)?.status.success());
+    let full = free_blocks(ctx)?;
+    truncate(ctx, "/f.dat", data.len() as u64, false)?;
+    assert_eq!(free_blocks(ctx)?, full);
+    assert!(read(ctx, "/f.dat")? == data);
+
+    // On a block boundary, then part way into a block
+    for size in [5 * BLOCK, 4 * BLOCK + 100, 1, 0] {
+        truncate(ctx, "/f.dat", size, false)?;
+        assert!(
+            read(ctx, "/f.dat")? == data[..size as usize],
+            "truncate to {} kept the wrong bytes",
+            size
+        );
+        assert_eq!(
+            free_blocks(ctx)?,
+            empty - size.div_ceil(BLOCK),
+            "truncate to {} did not free the blocks past the end",
+            size
+        );
+    }
+
+    // A file reaching into indirect blocks gives those back too
+    let large = content(2, 150 * BLOCK as usize);
+    assert!(write_file(ctx, "/f.dat", &large)?.status.success());
+    truncate(ctx, "/f.dat", 3 * BLOCK, false)?;
+    assert_eq!(free_blocks(ctx)?, empty - 3);
+    truncate(ctx, "/f.dat", 0, false)?;
+    assert_eq!(free_blocks(ctx)?, empty);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn growing_reads_zeros(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let data = content(3, 3000);
+    for (path, sparse) in [("/dense.dat", false), ("/sparse.dat", true)] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+        assert!(write_file(ctx, path, &data)?.status.success());
+        let before = free_blocks(ctx)?;
+
+        // Still within the direct pointers, so no indirect block is needed
+        let size = 8 * BLOCK;
+        truncate(ctx, path, size, sparse)?;
+        let mut expected = data.clone();
+        expected.resize(size as usize, 0);
+        assert!(
+            read(ctx, path)? == expected,
+            "{}: the new tail is not zeros",
+            path
+        );
+        let output = ctx.run_bellande_command(&[
+            "read",
+            "--path",
+            path,
+            "--offset",
+            &(size - 10).to_string(),
+            "--length",
+            "10",
+        ])?;
+        assert_eq!(output.stdout, vec![0; 10], "{}", path);
+
+        let allocated = before - free_blocks(ctx)?;
+        if sparse {
+            assert_eq!(
+                allocated, 0,
+                "{}: --sparse allocated {} blocks",
+                path, allocated
+            );
+        } else {
+            assert_eq!(allocated, 8 - 3000u64.div_ceil(BLOCK), "{}", path);
+        }
+
+        // Bytes cut off in the middle of a block stay gone after growing back
+        truncate(ctx, path, 1500, false)?;
+        truncate(ctx, path, 3000, sparse)?;
+        let mut expected = data[..1500].to_vec();
+        expected.resize(3000, 0);
+        assert!(
+            read(ctx, path)? == expected,
+            "{}: old bytes came back",
+            path
+        );
+    }
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn truncate_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/f.dat"])?;
+    let data = content(4, 2000);
+    assert!(write_file(ctx, "/f.dat", &data)?.status.success());
+
+    ctx.command(&["truncate", "--path", "/missing", "--size", "0"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    ctx.command(&["truncate", "--path", "/dir", "--size", "0"])
+        .assert()
+        .code(EXIT_IS_DIRECTORY);
+    for size in [None, Some("-1"), Some("big"), Some("")] {
+        let mut args = vec!["truncate", "--path", "/f.dat"];
+        if let Some(size) = size {
+            args.extend(["--size", size]);
+        }
+        ctx.command(&args).assert().code(EXIT_USAGE);
+    }
+
+    // Growing past the free space fails whole; a hole of that size does not
+    let before = read_stats(ctx)?;
+    let device = fs::read(&ctx.device_path)?;
+    let too_big = (before.free_blocks + 8) * BLOCK;
+    ctx.command(&[
+        "truncate",
+        "--path",
+        "/f.dat",
+        "--size",
+        &too_big.to_string(),
+    ])
+    .assert()
+    .code(EXIT_NO_SPACE);
+    assert_eq!(read_stats(ctx)?, before);
+    assert!(read(ctx, "/f.dat")? == data);
+    assert!(
+        fs::read(&ctx.device_path)? == device,
+        "a refused truncate wrote"
+    );
+    truncate(ctx, "/f.dat", too_big, true)?;
+    assert_eq!(read_stats(ctx)?.free_blocks, before.free_blocks);
+    assert_fsck_clean(ctx)
+}
+
+scenarios! {
+    #[contract]
+    shrinking_frees_blocks(tiny_context()?),
+    #[contract]
+    growing_reads_zeros(tiny_context()?),
+    #[contract]
+    truncate_errors(tiny_context()?),
+}
@@ -0,0 +1,274 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Per-filesystem tunables, kept in a tunables area of the superblock and
+// applied whenever the device is opened, so they change behavior without a
+// re-format. `tune` prints them as `Key: value` lines and `tune` with any of
+// `--read-ahead BLOCKS`, `--reserved-percent P` (0 to 50) or `--compression
+// none|lz4|zstd` rewrites them. Read-ahead is how many blocks a sequential
+// read fetches per device read, 0 meaning one block at a time. Reserved
+// blocks are kept back from new allocations and `stats` reports them with
+// `Reserved blocks` and `Available blocks`. The default compression is
+// what newly created files start with.
+
+use crate::block_cache::syscalls;
+use crate::capacity::{tiny_context, EXIT_NO_SPACE, NO_SPACE_MESSAGE, TINY_BLOCK_SIZE};
+use crate::cli::EXIT_USAGE;
+use crate::compression::text;
+use crate::differential::content;
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, stat_field, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use crate::stat::{number, parse_key_values, stat};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BLOCK_SIZE: u32 = 1024;
+const READ_FILE_BLOCKS: u64 = 512;
+const READ_AHEAD: u64 = 64;
+const RESERVED_PERCENT: u64 = 20;
+const TUNABLE_KEYS: &[&str] = &["Read-ahead", "Reserved percent", "Default compression"];
+
+fn tune_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn tunables(ctx: &TestContext) -> io::Result<BTreeMap<String, String>> {
+    let output = ctx.run_bellande_command(&["tune"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields = parse_key_values(&stdout)
+        .unwrap_or_else(|| panic!("tune is not Key: value lines: {:?}", stdout));
+    for key in TUNABLE_KEYS {
+        assert!(
+            fields.contains_key(*key),
+            "tune has no {}: {:?}",
+            key,
+            fields
+        );
+    }
+    Ok(fields)
+}
+
+fn tune(ctx: &TestContext, args: &[&str]) -> io::Result<()> {
+    ctx.run_bellande_command(&[&["tune"], args].concat())?;
+    Ok(())
+}
+
+// (reserved, available) blocks as `stats` reports them
+fn reserve(ctx: &TestContext) -> io::Result<(u64, u64)> {
+    let output = ctx.run_bellande_command(&["stats"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok((
+        stat_field(&stdout, "Reserved blocks")?,
+        stat_field(&stdout, "Available blocks")?,
+    ))
+}
+
+pub(crate) fn tunables_persist(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let defaults = tunables(ctx)?;
+    assert_eq!(defaults["Default compression"], "none");
+    assert!(number(&defaults, "Reserved percent") <= 50);
+    number(&defaults, "Read-ahead");
+
+    // Only the named tunables change, and the change outlives the command
+    tune(ctx, &["--read-ahead", "16"])?;
+    let mut expected = defaults.clone();
+    expected.insert("Read-ahead".to_string(), "16".to_string());
+    assert_eq!(tunables(ctx)?, expected);
+    tune(ctx, &["--reserved-percent", "10", "--compression", "zstd"])?;
+    expected.insert("Reserved percent".to_string(), "10".to_string());
+    expected.insert("Default compression".to_string(), "zstd".to_string());
+    ctx.run_bellande_command(&["mkdir", "--path", "/after"])?;
+    assert_eq!(tunables(ctx)?, expected);
+
+    // A copy of the image carries them, so they live on the device
+    let image = ctx.temp_dir.path().join("tuned.img");
+    fs::copy(&ctx.device_path, &image)?;
+    assert_eq!(tunables(&TestContext::from_image(&image)?)?, expected);
+
+    // Rewriting a value with itself changes nothing on the device
+    let before = fs::read(&ctx.device_path)?;
+    tune(ctx, &["--read-ahead", "16"])?;
+    assert!(
+        fs::read(&ctx.device_path)? == before,
+        "an unchanged tunable rewrote the superblock"
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn default_compression_applies(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let data = text(64 * BLOCK_SIZE as usize);
+    ctx.run_bellande_command(&["create", "--path", "/before.txt"])?;
+
+    for algorithm in ["lz4", "zstd"] {
+        tune(ctx, &["--compression", algorithm])?;
+        let path = format!("/{}.txt", algorithm);
+        ctx.run_bellande_command(&["create", "--path", &path])?;
+        assert!(write_file(ctx, &path, &data)?.status.success());
+        let fields = stat(ctx, &path)?;
+        assert_eq!(fields["Compression"], algorithm);
+        assert!(
+            number(&fields, "Blocks") < 64,
+            "{} by default did not shrink the text",
+            algorithm
+        );
+        assert!(ctx.run_bellande_command(&["read", "--path", &path])?.stdout == data);
+    }
+
+    // Files that already exist keep what they had, and setattr still wins
+    assert_eq!(stat(ctx, "/before.txt")?["Compression"], "none");
+    ctx.run_bellande_command(&["create", "--path", "/plain.txt"])?;
+    ctx.run_bellande_command(&["setattr", "--path", "/plain.txt", "--compress", "off"])?;
+    assert_eq!(stat(ctx, "/plain.txt")?["Compression"], "none");
+    tune(ctx, &["--compression", "none"])?;
+    ctx.run_bellande_command(&["create", "--path", "/after.txt"])?;
+    assert_eq!(stat(ctx, "/after.txt")?["Compression"], "none");
+    assert_eq!(stat(ctx, "/zstd.txt")?["Compression"], "zstd");
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn reserved_blocks_held_back(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    tune(ctx, &["--reserved-percent", "0"])?;
+    let stats = read_stats(ctx)?;
+    assert_eq!(reserve(ctx)?, (0, stats.free_blocks));
+
+    let percent = RESERVED_PERCENT.to_string();
+    tune(ctx, &["--reserved-percent", &percent])?;
+    let reserved = stats.total_blocks * RESERVED_PERCENT / 100;
+    assert_eq!(reserve(ctx)?, (reserved, stats.free_blocks - reserved));
+
+    // A write reaching halfway into the reserve fails cleanly...
+    let blocks = (stats.free_blocks - reserved / 2) as usize;
+    let data = content(1, blocks * TINY_BLOCK_SIZE as usize);
+    ctx.run_bellande_command(&["create", "--path", "/big.bin"])?;
+    let before = read_stats(ctx)?;
+    let output = write_file(ctx, "/big.bin", &data)?;
+    assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(NO_SPACE_MESSAGE));
+    assert_eq!(read_stats(ctx)?, before);
+
+    // ...and fits once the reserve is released
+    tune(ctx, &["--reserved-percent", "0"])?;
+    assert!(write_file(ctx, "/big.bin", &data)?.status.success());
+
+    // Raising the reserve over used space is allowed: what is stored stays
+    // readable and removable, only new allocations are refused
+    tune(ctx, &["--reserved-percent", &percent])?;
+    assert_eq!(reserve(ctx)?.1, 0);
+    assert!(
+        ctx.run_bellande_command(&["read", "--path", "/big.bin"])?
+            .stdout
+            == data
+    );
+    ctx.run_bellande_command(&["create", "--path", "/small.bin"])?;
+    let output = write_file(ctx, "/small.bin", &content(2, TINY_BLOCK_SIZE as usize))?;
+    assert_eq!(output.status.code(), Some(EXIT_NO_SPACE));
+    ctx.run_bellande_command(&["remove", "--path", "/big.bin"])?;
+    assert!(
+        write_file(ctx, "/small.bin", &content(2, TINY_BLOCK_SIZE as usize))?
+            .status
+            .success()
+    );
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn read_ahead_batches_reads(ctx: &TestContext) -> io::Result<()> {
+    if !Path::new("/proc/self/io").exists() {
+        println!("Skipping read-ahead syscall count: /proc/self/io is not available");
+        return Ok(());
+    }
+    format_device(ctx)?;
+    let data = content(3, (READ_FILE_BLOCKS * u64::from(BLOCK_SIZE)) as usize);
+    ctx.run_bellande_command(&["create", "--path", "/seq.bin"])?;
+    assert!(write_file(ctx, "/seq.bin", &data)?.status.success());
+    let read = ["read", "--path", "/seq.bin"];
+
+    tune(ctx, &["--read-ahead", "0"])?;
+    let one_at_a_time = syscalls(ctx, "syscr", &read, &[])?;
+    assert!(
+        one_at_a_time >= READ_FILE_BLOCKS,
+        "{} blocks read in {} syscalls without read-ahead",
+        READ_FILE_BLOCKS,
+        one_at_a_time
+    );
+
+    tune(ctx, &["--read-ahead", &READ_AHEAD.to_string()])?;
+    let batched = syscalls(ctx, "syscr", &read, &[])?;
+    assert!(
+        batched <= READ_FILE_BLOCKS / 8,
+        "{} blocks read in {} syscalls with {} blocks of read-ahead",
+        READ_FILE_BLOCKS,
+        batched,
+        READ_AHEAD
+    );
+    assert!(
+        ctx.run_bellande_command(&["read", "--path", "/seq.bin"])?
+            .stdout
+            == data
+    );
+    Ok(())
+}
+
+pub(crate) fn tune_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    let before = tunables(ctx)?;
+    for args in [
+        &["tune", "--read-ahead", "-1"][..],
+        &["tune", "--read-ahead", "lots"],
+        &["tune", "--read-ahead"],
+        &["tune", "--reserved-percent", "51"],
+        &["tune", "--reserved-percent", "5.5"],
+        &["tune", "--compression", "gzip"],
+        &["tune", "--cache-size", "64"],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+    // Nothing is half-applied when one option in a command is bad
+    ctx.command(&["tune", "--read-ahead", "8", "--compression", "gzip"])
+        .assert()
+        .code(EXIT_USAGE);
+    assert_eq!(tunables(ctx)?, before);
+
+    let image = fs::read(&ctx.device_path)?;
+    ctx.command(&["--read-only", "tune", "--read-ahead", "8"])
+        .assert()
+        .failure();
+    assert!(
+        fs::read(&ctx.device_path)? == image,
+        "a read-only tune modified the image"
+    );
+    // Reading them needs no write access
+    ctx.command(&["--read-only", "tune"]).assert().success();
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    tunables_persist(tune_context()?),
+    #[contract]
+    default_compression_applies(tune_context()?),
+    #[contract]
+    reserved_blocks_held_back(tiny_context()?),
+    #[contract]
+    read_ahead_batches_reads(tune_context()?),
+    #[contract]
+    tune_errors(tune_context()?),
+}
@@ -0,0 +1,172 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Version reporting. `--version` and `version` print one line with the crate
+// version, the commit it was built from, the newest on-disk format it creates
+// and the range it mounts; `version --format json` gives the same fields as
+// an object. The range has to cover every golden image the mount check opens.
+
+use crate::cli::EXIT_USAGE;
+use crate::golden::golden_format_versions;
+use crate::harness::{command_timeout, format_device, scenarios, TestContext};
+use crate::json::{json_field, json_u64};
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io;
+
+#[derive(Debug, PartialEq)]
+struct VersionInfo {
+    version: String,
+    commit: String,
+    format_version: u64,
+    min_mount_version: u64,
+    max_mount_version: u64,
+}
+
+fn parse_version_json(stdout: &str) -> Option<VersionInfo> {
+    Some(VersionInfo {
+        version: json_field(stdout, "version")?,
+        commit: json_field(stdout, "commit")?,
+        format_version: json_u64(stdout, "format_version")?,
+        min_mount_version: json_u64(stdout, "min_mount_version")?,
+        max_mount_version: json_u64(stdout, "max_mount_version")?,
+    })
+}
+
+fn is_semver(text: &str) -> bool {
+    let core = text.split(['-', '+']).next().unwrap_or("");
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+// A build outside a git checkout reports "unknown" rather than failing
+fn is_commit(text: &str) -> bool {
+    text == "unknown" || (text.len() >= 7 && text.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn version_stdout(ctx: &TestContext, args: &[&str]) -> String {
+    let mut command = Command::new(&ctx.binary_path);
+    let output = command
+        .args(args)
+        .timeout(command_timeout())
+        .assert()
+        .success();
+    String::from_utf8_lossy(&output.get_output().stdout).into_owned()
+}
+
+pub(crate) fn version_line(ctx: &TestContext) -> io::Result<()> {
+    let line = version_stdout(ctx, &["--version"]);
+    assert_eq!(
+        line.trim_end().lines().count(),
+        1,
+        "--version is not one line: {:?}",
+        line
+    );
+    assert_eq!(version_stdout(ctx, &["version"]), line);
+
+    let info = parse_version_json(&version_stdout(ctx, &["version", "--format", "json"]))
+        .expect("version --format json is missing fields");
+    assert!(is_semver(&info.version), "bad version {:?}", info.version);
+    assert!(is_commit(&info.commit), "bad commit {:?}", info.commit);
+    for field in [
+        info.version.clone(),
+        info.commit.clone(),
+        info.format_version.to_string(),
+        format!("{}-{}", info.min_mount_version, info.max_mount_version),
+    ] {
+        assert!(line.contains(&field), "{:?} missing from {:?}", field, line);
+    }
+
+    // Works with a device too, without touching it
+    format_device(ctx)?;
+    ctx.command(&["--version"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(line));
+    Ok(())
+}
+
+pub(crate) fn format_versions_cover_golden_images(ctx: &TestContext) -> io::Result<()> {
+    let stdout = version_stdout(ctx, &["version", "--format", "json"]);
+    let info = parse_version_json(&stdout).expect("version --format json is missing fields");
+
+    let mountable = info.min_mount_version..=info.max_mount_version;
+    assert!(
+        mountable.contains(&info.format_version),
+        "creates format {} but mounts only {:?}",
+        info.format_version,
+        mountable
+    );
+    for version in golden_format_versions() {
+        assert!(
+            mountable.contains(&u64::from(version)),
+            "golden images include format {} outside {:?}",
+            version,
+            mountable
+        );
+    }
+    // The newest format written has a frozen image to keep it readable
+    assert_eq!(
+        golden_format_versions().iter().max().map(|v| u64::from(*v)),
+        Some(info.format_version),
+        "no golden image for format {}",
+        info.format_version
+    );
+
+    ctx.command(&["version", "--format", "yaml"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--format"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    version_line,
+    #[contract]
+    format_versions_cover_golden_images,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_json() {
+        let stdout = r#"{"version":"0.1.0","commit":"a4568fe","format_version":1,"min_mount_version":1,"max_mount_version":1}"#;
+        assert_eq!(
+            parse_version_json(stdout),
+            Some(VersionInfo {
+                version: "0.1.0".to_string(),
+                commit: "a4568fe".to_string(),
+                format_version: 1,
+                min_mount_version: 1,
+                max_mount_version: 1,
+            })
+        );
+        assert_eq!(parse_version_json(r#"{"version":"0.1.0"}"#), None);
+    }
+
+    #[test]
+    fn test_version_shapes() {
+        assert!(is_semver("0.1.0") && is_semver("1.2.3-rc.1"));
+        assert!(!is_semver("1.2") && !is_semver("v1.2.3"));
+        assert!(is_commit("a4568fe") && is_commit("unknown"));
+        assert!(!is_commit("a45") && !is_commit("zzzzzzz"));
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `with-lock --path <f> [--shared] [--try] -- <subcommand>`: runs one
+// subcommand while holding an advisory lock on `f`. The lock lives in the
+// open-file table, so it is gone once the command exits whatever the inner
+// result was; the inner exit code is passed through unchanged.
+//
+// Locks only meet when two commands run at once, i.e. through the daemon.
+// Exclusive excludes everything, shared only exclusive, locks on different
+// files never interact, and `--try` exits EXIT_DEVICE_BUSY instead of
+// waiting. A holder whose connection drops releases its lock. The tests
+// hold a lock with a `monitor` inside with-lock, which runs until its
+// client is killed.
+
+use crate::cli::EXIT_USAGE;
+use crate::daemon::{serve_timeout, Daemon};
+use crate::errors::{EXIT_IS_DIRECTORY, EXIT_NOT_FOUND};
+use crate::harness::{format_device, scenarios, TestContext};
+use crate::locking::EXIT_DEVICE_BUSY;
+use crate::monitor::Monitor;
+use assert_cmd::assert::Assert;
+use predicates::prelude::*;
+use std::io;
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn locked(path: &str, options: &[&'static str], inner: &[&'static str]) -> Vec<String> {
+    let mut args = vec![
+        "with-lock".to_string(),
+        "--path".to_string(),
+        path.to_string(),
+    ];
+    args.extend(options.iter().map(|option| option.to_string()));
+    args.push("--".to_string());
+    args.extend(inner.iter().map(|arg| arg.to_string()));
+    args
+}
+
+fn run_locked(ctx: &TestContext, args: &[String]) -> Assert {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    ctx.command(&args).assert()
+}
+
+fn run_remote(ctx: &TestContext, daemon: &Daemon, args: &[String]) -> Assert {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    daemon.remote(ctx, &args).assert()
+}
+
+// Takes the lock on /f through the daemon and keeps it until dropped
+fn hold(ctx: &TestContext, daemon: &Daemon, options: &[&'static str]) -> io::Result<Monitor> {
+    let args = locked("/f", options, &["monitor", "--path", "/f"]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Monitor::start(ctx, daemon, &args)
+}
+
+fn try_read(ctx: &TestContext, daemon: &Daemon, path: &str, shared: bool) -> Assert {
+    let options: &[&'static str] = if shared {
+        &["--shared", "--try"]
+    } else {
+        &["--try"]
+    };
+    let mut args = locked(path, options, &["read", "--path"]);
+    args.push(path.to_string());
+    run_remote(ctx, daemon, &args)
+}
+
+fn shared_files(ctx: &TestContext) -> io::Result<Daemon> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/f", "--content", "locked"])?;
+    ctx.run_bellande_command(&["create", "--path", "/g", "--content", "other"])?;
+    Daemon::start(ctx, "fs.sock")
+}
+
+pub(crate) fn lock_compatibility(ctx: &TestContext) -> io::Result<()> {
+    let daemon = shared_files(ctx)?;
+
+    let exclusive = hold(ctx, &daemon, &[])?;
+    try_read(ctx, &daemon, "/f", false).code(EXIT_DEVICE_BUSY);
+    try_read(ctx, &daemon, "/f", true).code(EXIT_DEVICE_BUSY);
+    try_read(ctx, &daemon, "/g", false)
+        .success()
+        .stdout(predicate::str::diff("other"));
+    drop(exclusive);
+
+    // A blocking request waits out the dropped connection, so the lock is
+    // known to be free before the matrix goes on
+    run_remote(ctx, &daemon, &locked("/f", &[], &["read", "--path", "/f"]))
+        .success()
+        .stdout(predicate::str::diff("locked"));
+
+    let first = hold(ctx, &daemon, &["--shared"])?;
+    let second = hold(ctx, &daemon, &["--shared"])?;
+    try_read(ctx, &daemon, "/f", true)
+        .success()
+        .stdout(predicate::str::diff("locked"));
+    try_read(ctx, &daemon, "/f", false).code(EXIT_DEVICE_BUSY);
+    drop(first);
+    try_read(ctx, &daemon, "/f", false).code(EXIT_DEVICE_BUSY);
+    drop(second);
+    run_remote(ctx, &daemon, &locked("/f", &[], &["read", "--path", "/f"])).success();
+    daemon.shutdown(ctx)
+}
+
+pub(crate) fn lock_blocks_until_released(ctx: &TestContext) -> io::Result<()> {
+    let daemon = shared_files(ctx)?;
+    let holder = hold(ctx, &daemon, &[])?;
+
+    let mut waiter = process::Command::new(&ctx.binary_path)
+        .arg("--remote")
+        .arg(&daemon.socket)
+        .args([
+            "with-lock",
+            "--path",
+            "/f",
+            "--",
+            "create",
+            "--path",
+            "/after",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Whether or not the waiter has reached the daemon yet, its inner
+    // command cannot have run while the lock is held
+    daemon
+        .remote(ctx, &["stat", "--path", "/after"])
+        .assert()
+        .code(EXIT_NOT_FOUND);
+    assert!(
+        waiter.try_wait()?.is_none(),
+        "with-lock did not wait for the holder"
+    );
+
+    drop(holder);
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = waiter.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > serve_timeout() {
+            let _ = waiter.kill();
+            return Err(io::Error::other(
+                "with-lock still waiting after the holder dropped",
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    assert!(status.success(), "waiting with-lock exited {}", status);
+    daemon
+        .remote(ctx, &["stat", "--path", "/after"])
+        .assert()
+        .success();
+    daemon.shutdown(ctx)
+}
+
+pub(crate) fn runs_inner_subcommand(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/f", "--content", "before"])?;
+
+    for options in [
+        &[][..],
+        &["--shared"][..],
+        &["--try"][..],
+        &["--shared", "--try"][..],
+    ] {
+        run_locked(ctx, &locked("/f", options, &["read", "--path", "/f"]))
+            .success()
+            .stdout(predicate::str::diff("before"));
+    }
+    run_locked(
+        ctx,
+        &locked("/f", &[], &["write", "--path", "/f", "--content", "after"]),
+    )
+    .success();
+    ctx.command(&["read", "--path", "/f"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("after"));
+
+    // The inner failure comes back as is, and the lock still goes away
+    run_locked(ctx, &locked("/f", &[], &["read", "--path", "/missing"])).code(EXIT_NOT_FOUND);
+    run_locked(ctx, &locked("/f", &["--try"], &["read", "--path", "/f"])).success();
+    Ok(())
+}
+
+pub(crate) fn invalid_lock_targets(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/f"])?;
+
+    run_locked(ctx, &locked("/missing", &[], &["stats"]))
+        .code(EXIT_NOT_FOUND)
+        .stderr(predicate::str::contains("/missing"));
+    run_locked(ctx, &locked("/dir", &[], &["stats"])).code(EXIT_IS_DIRECTORY);
+
+    ctx.command(&["with-lock", "--path", "/f", "stats"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--"));
+    ctx.command(&["with-lock", "--path", "/f", "--"])
+        .assert()
+        .code(EXIT_USAGE);
+    ctx.command(&[
+        "with-lock",
+        "--path",
+        "/f",
+        "--",
+        "with-lock",
+        "--path",
+        "/f",
+        "--",
+        "stats",
+    ])
+    .assert()
+    .code(EXIT_USAGE)
+    .stderr(predicate::str::contains("with-lock"));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    runs_inner_subcommand,
+    #[contract]
+    invalid_lock_targets,
+    #[contract]
+    lock_compatibility,
+    #[contract]
+    lock_blocks_until_released,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked() {
+        assert_eq!(
+            locked("/f", &["--shared"], &["read", "--path", "/f"]),
+            [
+                "with-lock",
+                "--path",
+                "/f",
+                "--shared",
+                "--",
+                "read",
+                "--path",
+                "/f"
+            ]
+        );
+    }
+}
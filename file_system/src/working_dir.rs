@@ -0,0 +1,159 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The global `--cwd` flag: relative `--path` values resolve against it
+// through the same normalization as absolute ones, and `..` clamps at `/`.
+
+use crate::differential::listed_names;
+use crate::harness::{format_device, scenarios, TestContext};
+use predicates::prelude::*;
+use std::io;
+
+fn names_in(ctx: &TestContext, dir: &str) -> io::Result<Vec<String>> {
+    let output = ctx.run_bellande_command(&["list", "--path", dir])?;
+    Ok(listed_names(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .collect())
+}
+
+fn etc_context(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/etc"])?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/etc/sub"])?;
+    Ok(())
+}
+
+pub(crate) fn relative_paths_resolve(ctx: &TestContext) -> io::Result<()> {
+    etc_context(ctx)?;
+
+    ctx.run_bellande_command(&["--cwd", "/etc", "create", "--path", "hosts"])?;
+    ctx.run_bellande_command(&["--cwd", "/etc", "create", "--path", "./sub/../fstab"])?;
+    ctx.run_bellande_command(&["--cwd", "/etc/", "create", "--path", "sub//motd"])?;
+    assert_eq!(names_in(ctx, "/etc")?, ["fstab", "hosts", "sub"]);
+    assert_eq!(names_in(ctx, "/etc/sub")?, ["motd"]);
+
+    // Absolute paths ignore the cwd
+    ctx.run_bellande_command(&["--cwd", "/etc", "create", "--path", "/top.txt"])?;
+    assert!(names_in(ctx, "/")?.contains(&"top.txt".to_string()));
+
+    // Without --cwd a relative path is still an error
+    ctx.command(&["create", "--path", "relative.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid path"));
+    Ok(())
+}
+
+pub(crate) fn parent_clamps_at_root(ctx: &TestContext) -> io::Result<()> {
+    etc_context(ctx)?;
+
+    ctx.run_bellande_command(&[
+        "--cwd",
+        "/etc/sub",
+        "create",
+        "--path",
+        "../../../../up.txt",
+    ])?;
+    ctx.run_bellande_command(&["--cwd", "/etc", "mkdir", "--path", "../opt"])?;
+    let root = names_in(ctx, "/")?;
+    assert!(root.contains(&"up.txt".to_string()));
+    assert!(root.contains(&"opt".to_string()));
+
+    ctx.command(&["--cwd", "/../..", "list", "--path", "etc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sub"));
+    Ok(())
+}
+
+pub(crate) fn cwd_must_be_directory(ctx: &TestContext) -> io::Result<()> {
+    etc_context(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/etc/hosts"])?;
+
+    ctx.command(&["--cwd", "/etc/hosts", "list", "--path", "."])
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::contains("/etc/hosts").and(predicate::str::contains("Not a directory")),
+        );
+    ctx.command(&["--cwd", "/missing", "create", "--path", "new.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("/missing").and(predicate::str::contains("not found")));
+    ctx.command(&["--cwd", "etc", "list", "--path", "."])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--cwd"));
+
+    // The failed commands created nothing
+    assert_eq!(names_in(ctx, "/")?, ["etc"]);
+    Ok(())
+}
+
+pub(crate) fn relative_output(ctx: &TestContext) -> io::Result<()> {
+    etc_context(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/etc/sub/motd"])?;
+
+    let output = ctx
+        .command(&[
+            "--cwd",
+            "/etc",
+            "list",
+            "--recursive",
+            "--relative",
+            "--path",
+            ".",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sub/motd"));
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    assert!(
+        !stdout.contains("/etc/"),
+        "--relative still printed absolute paths: {:?}",
+        stdout
+    );
+
+    ctx.command(&["--cwd", "/etc", "list", "--recursive", "--path", "."])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("/etc/sub/motd"));
+    Ok(())
+}
+
+pub(crate) fn globs_expand_in_cwd(ctx: &TestContext) -> io::Result<()> {
+    etc_context(ctx)?;
+    for path in ["/etc/a.tmp", "/etc/b.tmp", "/etc/keep.conf", "/c.tmp"] {
+        ctx.run_bellande_command(&["create", "--path", path])?;
+    }
+
+    ctx.run_bellande_command(&["--cwd", "/etc", "remove", "--path", "*.tmp"])?;
+    assert_eq!(names_in(ctx, "/etc")?, ["keep.conf", "sub"]);
+    assert!(names_in(ctx, "/")?.contains(&"c.tmp".to_string()));
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    relative_paths_resolve,
+    #[contract]
+    parent_clamps_at_root,
+    #[contract]
+    cwd_must_be_directory,
+    #[contract]
+    relative_output,
+    #[contract]
+    globs_expand_in_cwd,
+}
@@ -0,0 +1,279 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Extended attributes. `xattr set --path P --name N` stores `--value TEXT`,
+// or stdin when no value is given, `xattr get` prints the raw value, `xattr
+// list` prints the names one per line, sorted, and `xattr remove` drops one.
+// Names need a `user.`, `security.` or `trusted.` namespace prefix; values
+// larger than a block spill into their own blocks, freed with the attribute.
+// Attributes belong to the inode, so they follow moves and hard links. A
+// name the inode does not have exits 61 (ENODATA).
+
+use crate::cli::EXIT_USAGE;
+use crate::differential::content;
+use crate::errors::{EXIT_INVALID, EXIT_NOT_FOUND, EXIT_NO_ATTRIBUTE, EXIT_PERMISSION_DENIED};
+use crate::fsck::assert_fsck_clean;
+use crate::harness::{
+    format_device, read_stats, scenarios, write_file, TestContext, DEFAULT_DEVICE_SIZE,
+};
+use std::io;
+
+const BLOCK_SIZE: u32 = 1024;
+const BLOCK: usize = BLOCK_SIZE as usize;
+const MANY_ATTRIBUTES: usize = 50;
+// The largest value Linux passes through setxattr
+const MAX_VALUE_LEN: usize = 64 * 1024;
+
+const BAD_NAMES: &[&str] = &["", "label", "user.", "unknown.label", ".user.label"];
+
+fn xattr_context() -> io::Result<TestContext> {
+    TestContext::with_options(DEFAULT_DEVICE_SIZE, Some(BLOCK_SIZE))
+}
+
+fn set(ctx: &TestContext, path: &str, name: &str, value: &[u8]) -> io::Result<()> {
+    ctx.command(&["xattr", "set", "--path", path, "--name", name])
+        .write_stdin(value.to_vec())
+        .assert()
+        .success();
+    Ok(())
+}
+
+fn get(ctx: &TestContext, path: &str, name: &str) -> io::Result<Vec<u8>> {
+    Ok(ctx
+        .run_bellande_command(&["xattr", "get", "--path", path, "--name", name])?
+        .stdout)
+}
+
+fn names(ctx: &TestContext, path: &str) -> io::Result<Vec<String>> {
+    let output = ctx.run_bellande_command(&["xattr", "list", "--path", path])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+fn get_exit(ctx: &TestContext, path: &str, name: &str) -> io::Result<Option<i32>> {
+    Ok(ctx
+        .run_raw(&["xattr", "get", "--path", path, "--name", name])?
+        .status
+        .code())
+}
+
+pub(crate) fn xattrs_round_trip(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["mkdir", "--path", "/dir"])?;
+    ctx.run_bellande_command(&["create", "--path", "/dir/file"])?;
+    assert!(names(ctx, "/dir/file")?.is_empty());
+
+    ctx.run_bellande_command(&[
+        "xattr",
+        "set",
+        "--path",
+        "/dir/file",
+        "--name",
+        "user.label",
+        "--value",
+        "release",
+    ])?;
+    set(
+        ctx,
+        "/dir/file",
+        "security.selinux",
+        b"system_u:object_r:etc_t:s0",
+    )?;
+    // Binary values, NUL bytes included, come back byte for byte
+    let digest: Vec<u8> = (0..=255).collect();
+    set(ctx, "/dir/file", "trusted.checksum", &digest)?;
+    set(ctx, "/dir", "user.owner_team", b"drivers")?;
+
+    assert_eq!(
+        names(ctx, "/dir/file")?,
+        ["security.selinux", "trusted.checksum", "user.label"]
+    );
+    assert_eq!(get(ctx, "/dir/file", "user.label")?, b"release");
+    assert_eq!(get(ctx, "/dir/file", "trusted.checksum")?, digest);
+    assert_eq!(get(ctx, "/dir", "user.owner_team")?, b"drivers");
+
+    // Setting again replaces; an empty value is still an attribute
+    set(ctx, "/dir/file", "user.label", b"beta")?;
+    assert_eq!(get(ctx, "/dir/file", "user.label")?, b"beta");
+    set(ctx, "/dir/file", "user.empty", b"")?;
+    assert!(get(ctx, "/dir/file", "user.empty")?.is_empty());
+    ctx.run_bellande_command(&[
+        "xattr",
+        "remove",
+        "--path",
+        "/dir/file",
+        "--name",
+        "user.empty",
+    ])?;
+    assert_eq!(
+        get_exit(ctx, "/dir/file", "user.empty")?,
+        Some(EXIT_NO_ATTRIBUTE)
+    );
+
+    // Attributes do not touch the data, and belong to the inode
+    assert!(write_file(ctx, "/dir/file", b"contents")?.status.success());
+    ctx.run_bellande_command(&["move", "--from", "/dir/file", "--to", "/moved"])?;
+    ctx.run_bellande_command(&["link", "--target", "/moved", "--path", "/alias"])?;
+    assert_eq!(get(ctx, "/alias", "user.label")?, b"beta");
+    assert_eq!(
+        ctx.run_bellande_command(&["read", "--path", "/moved"])?
+            .stdout,
+        b"contents"
+    );
+
+    // A new inode in a reused slot starts with none
+    ctx.run_bellande_command(&["remove", "--path", "/alias"])?;
+    ctx.run_bellande_command(&["remove", "--path", "/moved"])?;
+    ctx.run_bellande_command(&["create", "--path", "/fresh"])?;
+    assert!(names(ctx, "/fresh")?.is_empty());
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn large_xattr_values(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--path", "/file"])?;
+    let empty = read_stats(ctx)?;
+
+    let spanning = content(1, 3 * BLOCK + 17);
+    let largest = content(2, MAX_VALUE_LEN);
+    set(ctx, "/file", "user.spanning", &spanning)?;
+    set(ctx, "/file", "user.largest", &largest)?;
+    assert!(get(ctx, "/file", "user.spanning")? == spanning);
+    assert!(get(ctx, "/file", "user.largest")? == largest);
+    let used = empty.free_blocks - read_stats(ctx)?.free_blocks;
+    assert!(
+        used as usize >= (spanning.len() + largest.len()) / BLOCK,
+        "{} bytes of values took only {} blocks",
+        spanning.len() + largest.len(),
+        used
+    );
+
+    // Shrinking a value below a block gives its blocks back
+    set(ctx, "/file", "user.largest", b"small")?;
+    assert_eq!(get(ctx, "/file", "user.largest")?, b"small");
+    ctx.run_bellande_command(&[
+        "xattr",
+        "remove",
+        "--path",
+        "/file",
+        "--name",
+        "user.spanning",
+    ])?;
+    assert!(read_stats(ctx)?.free_blocks + 1 >= empty.free_blocks);
+
+    // Values still spilled over when the file goes are freed with it
+    set(ctx, "/file", "user.spanning", &spanning)?;
+    ctx.run_bellande_command(&["remove", "--path", "/file"])?;
+    ctx.run_bellande_command(&["create", "--path", "/file"])?;
+    assert_eq!(read_stats(ctx)?.free_blocks, empty.free_blocks);
+
+    // Many small attributes on one inode
+    for index in 0..MANY_ATTRIBUTES {
+        set(
+            ctx,
+            "/file",
+            &format!("user.attr{:02}", index),
+            &content(index as u64, 40),
+        )?;
+    }
+    let listed = names(ctx, "/file")?;
+    assert_eq!(listed.len(), MANY_ATTRIBUTES);
+    assert!(listed.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(get(ctx, "/file", "user.attr37")?, content(37, 40));
+
+    ctx.command(&["xattr", "set", "--path", "/file", "--name", "user.too_big"])
+        .write_stdin(vec![0; MAX_VALUE_LEN + 1])
+        .assert()
+        .code(EXIT_INVALID);
+    assert_fsck_clean(ctx)
+}
+
+pub(crate) fn xattr_errors(ctx: &TestContext) -> io::Result<()> {
+    format_device(ctx)?;
+    ctx.run_bellande_command(&["create", "--mode", "644", "--path", "/file"])?;
+    set(ctx, "/file", "user.label", b"kept")?;
+
+    assert_eq!(
+        get_exit(ctx, "/missing", "user.label")?,
+        Some(EXIT_NOT_FOUND)
+    );
+    assert_eq!(
+        get_exit(ctx, "/file", "user.other")?,
+        Some(EXIT_NO_ATTRIBUTE)
+    );
+    ctx.command(&["xattr", "remove", "--path", "/file", "--name", "user.other"])
+        .assert()
+        .code(EXIT_NO_ATTRIBUTE);
+    for name in BAD_NAMES {
+        ctx.command(&[
+            "xattr", "set", "--path", "/file", "--name", name, "--value", "x",
+        ])
+        .assert()
+        .code(EXIT_INVALID);
+    }
+    for args in [
+        &["xattr"][..],
+        &["xattr", "bogus", "--path", "/file"][..],
+        &["xattr", "get", "--path", "/file"][..],
+        &["xattr", "list"][..],
+    ] {
+        ctx.command(args).assert().code(EXIT_USAGE);
+    }
+
+    // Setting is a write: refused read-only and without write permission
+    ctx.command(&[
+        "--read-only",
+        "xattr",
+        "set",
+        "--path",
+        "/file",
+        "--name",
+        "user.x",
+        "--value",
+        "x",
+    ])
+    .assert()
+    .failure();
+    ctx.command(&[
+        "--user", "2000", "--group", "200", "xattr", "set", "--path", "/file", "--name", "user.x",
+        "--value", "x",
+    ])
+    .assert()
+    .code(EXIT_PERMISSION_DENIED);
+    ctx.run_bellande_command(&[
+        "--read-only",
+        "xattr",
+        "get",
+        "--path",
+        "/file",
+        "--name",
+        "user.label",
+    ])?;
+
+    assert_eq!(names(ctx, "/file")?, ["user.label"]);
+    assert_eq!(get(ctx, "/file", "user.label")?, b"kept");
+    Ok(())
+}
+
+scenarios! {
+    #[contract]
+    xattrs_round_trip(xattr_context()?),
+    #[contract]
+    large_xattr_values(xattr_context()?),
+    #[contract]
+    xattr_errors(xattr_context()?),
+}
@@ -0,0 +1,270 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The package file. A body holding the manifest and every file it lists,
+// followed by a trailer with the signer's public key and an Ed25519
+// signature over the body. All integers are little-endian.
+//
+//     body:    "BDPKG01\n"  u32 manifest length, manifest text,
+//              u32 file count, then per file: u16 path length, path,
+//              u64 data length, data
+//     trailer: "SIG1"  32-byte public key  64-byte signature
+//
+// `open` checks the signature against a keyring before it parses anything,
+// so a package is either trusted and well formed or refused outright.
+
+use crate::manifest::{check_file_path, Manifest};
+use crate::signing::{PublicKey, SecretKey, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const PACKAGE_MAGIC: &[u8; 8] = b"BDPKG01\n";
+const SIGNATURE_TAG: &[u8; 4] = b"SIG1";
+const TRAILER_LEN: usize = SIGNATURE_TAG.len() + PUBLIC_KEY_LEN + SIGNATURE_LEN;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Package {
+    pub manifest: Manifest,
+    // Keyed by the paths in the manifest's `file` lines
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Package {
+    // Collects the manifest's files from `dir`, which mirrors their layout
+    pub fn from_dir(manifest: Manifest, dir: &Path) -> io::Result<Package> {
+        let mut files = BTreeMap::new();
+        for path in &manifest.files {
+            let data = fs::read(dir.join(path))
+                .map_err(|e| io::Error::new(e.kind(), format!("Cannot read {}: {}", path, e)))?;
+            files.insert(path.clone(), data);
+        }
+        Ok(Package { manifest, files })
+    }
+
+    fn encode_body(&self) -> io::Result<Vec<u8>> {
+        let too_large = || invalid("Package is too large to encode");
+        let manifest = self.manifest.render();
+        let mut body = PACKAGE_MAGIC.to_vec();
+        body.extend_from_slice(
+            &u32::try_from(manifest.len())
+                .map_err(|_| too_large())?
+                .to_le_bytes(),
+        );
+        body.extend_from_slice(manifest.as_bytes());
+        body.extend_from_slice(
+            &u32::try_from(self.files.len())
+                .map_err(|_| too_large())?
+                .to_le_bytes(),
+        );
+        for (path, data) in &self.files {
+            body.extend_from_slice(
+                &u16::try_from(path.len())
+                    .map_err(|_| too_large())?
+                    .to_le_bytes(),
+            );
+            body.extend_from_slice(path.as_bytes());
+            body.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            body.extend_from_slice(data);
+        }
+        Ok(body)
+    }
+
+    pub fn sign(&self, key: &SecretKey) -> io::Result<Vec<u8>> {
+        let mut bytes = self.encode_body()?;
+        let signature = key.sign(&bytes);
+        bytes.extend_from_slice(SIGNATURE_TAG);
+        bytes.extend_from_slice(&key.public_key().to_bytes());
+        bytes.extend_from_slice(&signature);
+        Ok(bytes)
+    }
+
+    // Verifies `bytes` against `keyring`, then decodes them. Returns the
+    // package and the key that signed it.
+    pub fn open(bytes: &[u8], keyring: &[PublicKey]) -> io::Result<(Package, PublicKey)> {
+        if bytes.len() < PACKAGE_MAGIC.len() + TRAILER_LEN || !bytes.starts_with(PACKAGE_MAGIC) {
+            return Err(invalid("Not a Bellande driver package"));
+        }
+        let (body, trailer) = bytes.split_at(bytes.len() - TRAILER_LEN);
+        let (tag, rest) = trailer.split_at(SIGNATURE_TAG.len());
+        if tag != SIGNATURE_TAG {
+            return Err(invalid("Package is not signed"));
+        }
+        let (signer, signature) = rest.split_at(PUBLIC_KEY_LEN);
+        let signer =
+            PublicKey::from_bytes(signer.try_into().map_err(|_| invalid("Truncated key"))?)?;
+        if !keyring.contains(&signer) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "Package is signed by untrusted key {}",
+                    signer.fingerprint()
+                ),
+            ));
+        }
+        signer.verify(
+            body,
+            signature
+                .try_into()
+                .map_err(|_| invalid("Truncated signature"))?,
+        )?;
+        Ok((Package::decode_body(&body[PACKAGE_MAGIC.len()..])?, signer))
+    }
+
+    fn decode_body(body: &[u8]) -> io::Result<Package> {
+        let mut reader = Reader(body);
+        let manifest_len = u32::from_le_bytes(reader.array()?) as usize;
+        let manifest = std::str::from_utf8(reader.take(manifest_len)?)
+            .map_err(|_| invalid("Manifest is not UTF-8"))?;
+        let manifest = Manifest::parse(manifest)?;
+
+        let mut files = BTreeMap::new();
+        for _ in 0..u32::from_le_bytes(reader.array()?) {
+            let path_len = u16::from_le_bytes(reader.array()?) as usize;
+            let path = std::str::from_utf8(reader.take(path_len)?)
+                .map_err(|_| invalid("File path is not UTF-8"))?
+                .to_string();
+            check_file_path(&path)?;
+            let data_len = usize::try_from(u64::from_le_bytes(reader.array()?))
+                .map_err(|_| invalid("File is too large"))?;
+            let data = reader.take(data_len)?.to_vec();
+            if files.insert(path.clone(), data).is_some() {
+                return Err(invalid(&format!("Package holds {} twice", path)));
+            }
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid("Trailing bytes after the last file"));
+        }
+
+        // Exactly the files the manifest promises, no more and no fewer
+        let mut listed: Vec<&String> = manifest.files.iter().collect();
+        listed.sort();
+        if !listed.into_iter().eq(files.keys()) {
+            return Err(invalid("Package files do not match its manifest"));
+        }
+        Ok(Package { manifest, files })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(invalid("Package is truncated"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("take returns N bytes"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::manifest::Version;
+
+    pub(crate) fn sample_package(name: &str, version: &str) -> Package {
+        let manifest = Manifest {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            abi: crate::CURRENT_ABI.to_string(),
+            depends: Vec::new(),
+            devices: vec!["virtio-blk".to_string()],
+            files: vec!["bin/driver".to_string(), "README".to_string()],
+        };
+        let mut files = BTreeMap::new();
+        files.insert(
+            "bin/driver".to_string(),
+            vec![0x7f, b'E', b'L', b'F', 1, 2, 3],
+        );
+        files.insert(
+            "README".to_string(),
+            format!("{} {}\n", name, version).into_bytes(),
+        );
+        Package { manifest, files }
+    }
+
+    #[test]
+    fn test_sign_and_open() -> io::Result<()> {
+        let key = SecretKey::from_seed(&[1; 32]);
+        let package = sample_package("file_system", "0.2.1");
+        let bytes = package.sign(&key)?;
+        let (opened, signer) = Package::open(&bytes, &[key.public_key()])?;
+        assert_eq!(opened, package);
+        assert_eq!(
+            opened.manifest.version,
+            Version {
+                major: 0,
+                minor: 2,
+                patch: 1
+            }
+        );
+        assert_eq!(signer, key.public_key());
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_and_tampered() -> io::Result<()> {
+        let key = SecretKey::from_seed(&[1; 32]);
+        let other = SecretKey::from_seed(&[2; 32]);
+        let bytes = sample_package("file_system", "0.2.1").sign(&key)?;
+
+        let untrusted = Package::open(&bytes, &[other.public_key()]).unwrap_err();
+        assert_eq!(untrusted.kind(), io::ErrorKind::PermissionDenied);
+        assert!(Package::open(&bytes, &[]).is_err());
+
+        // A flipped bit anywhere in the body breaks the signature
+        for index in [PACKAGE_MAGIC.len() + 4, bytes.len() - TRAILER_LEN - 1] {
+            let mut tampered = bytes.clone();
+            tampered[index] ^= 1;
+            let error = Package::open(&tampered, &[key.public_key()]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        // So does re-signing the trailer with a key the keyring does not hold
+        let mut swapped = bytes.clone();
+        let key_start = bytes.len() - PUBLIC_KEY_LEN - SIGNATURE_LEN;
+        swapped[key_start..key_start + PUBLIC_KEY_LEN]
+            .copy_from_slice(&other.public_key().to_bytes());
+        assert!(Package::open(&swapped, &[key.public_key()]).is_err());
+
+        assert!(Package::open(&bytes[..bytes.len() - 1], &[key.public_key()]).is_err());
+        assert!(Package::open(b"not a package", &[key.public_key()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_must_match_manifest() -> io::Result<()> {
+        let key = SecretKey::from_seed(&[1; 32]);
+        let mut extra = sample_package("file_system", "0.2.1");
+        extra.files.insert("bin/unlisted".to_string(), Vec::new());
+        let mut missing = sample_package("file_system", "0.2.1");
+        missing.files.remove("README");
+        for package in [extra, missing] {
+            let error = Package::open(&package.sign(&key)?, &[key.public_key()]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// bellande-driver: builds, checks and installs driver packages.
+//
+//     bellande-driver keygen --secret KEY --public KEY.pub
+//     bellande-driver pack --manifest FILE [--dir DIR] --key KEY --output PKG
+//     bellande-driver verify --keyring DIR PKG
+//     bellande-driver install --keyring DIR --root DIR [--abi ABI] [--force] PKG
+//     bellande-driver list --root DIR
+//
+// Exit codes follow sysexits: 64 for usage errors, 65 for a malformed or
+// tampered package, 66 for a missing input, 69 when the ABI or dependencies
+// rule an install out, 73 when the version is already installed and 77 when
+// the signer is not in the keyring.
+
+use package::{
+    install, installed, load_keyring, Manifest, Package, PublicKey, SecretKey, CURRENT_ABI,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_UNAVAILABLE: i32 = 69;
+const EXIT_CANNOT_CREATE: i32 = 73;
+const EXIT_NO_PERMISSION: i32 = 77;
+
+const USAGE: &str = "usage: bellande-driver <keygen|pack|verify|install|list> [options]";
+
+fn exit_code(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => EXIT_USAGE,
+        io::ErrorKind::InvalidData => EXIT_DATA,
+        io::ErrorKind::NotFound => EXIT_NO_INPUT,
+        io::ErrorKind::Unsupported => EXIT_UNAVAILABLE,
+        io::ErrorKind::AlreadyExists => EXIT_CANNOT_CREATE,
+        io::ErrorKind::PermissionDenied => EXIT_NO_PERMISSION,
+        _ => 1,
+    }
+}
+
+fn usage(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+struct Args {
+    options: BTreeMap<String, String>,
+    flags: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>, flags: &[&str]) -> io::Result<Args> {
+        let mut parsed = Args {
+            options: BTreeMap::new(),
+            flags: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg.as_str()) {
+                parsed.flags.push(arg);
+            } else if arg.starts_with("--") {
+                let value = args
+                    .next()
+                    .ok_or_else(|| usage(format!("{} needs a value", arg)))?;
+                parsed.options.insert(arg, value);
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn path(&self, option: &str) -> io::Result<PathBuf> {
+        self.options
+            .get(option)
+            .map(PathBuf::from)
+            .ok_or_else(|| usage(format!("missing {}", option)))
+    }
+
+    fn package(&self) -> io::Result<PathBuf> {
+        match self.positional.as_slice() {
+            [path] => Ok(PathBuf::from(path)),
+            _ => Err(usage("expected exactly one package file".to_string())),
+        }
+    }
+
+    // Rejects any option or flag the command does not take
+    fn only(&self, allowed: &[&str]) -> io::Result<()> {
+        match self
+            .options
+            .keys()
+            .chain(&self.flags)
+            .find(|option| !allowed.contains(&option.as_str()))
+        {
+            Some(option) => Err(usage(format!("unknown option {}", option))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn open_package(path: &Path, keyring: &Path) -> io::Result<(Package, PublicKey)> {
+    let keys = load_keyring(keyring)?;
+    if keys.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Keyring {:?} holds no .pub keys", keyring),
+        ));
+    }
+    Package::open(&fs::read(path)?, &keys)
+}
+
+fn run(command: &str, args: Args) -> io::Result<()> {
+    match command {
+        "keygen" => {
+            args.only(&["--secret", "--public"])?;
+            let key = SecretKey::generate()?;
+            key.save(&args.path("--secret")?)?;
+            key.public_key().save(&args.path("--public")?)?;
+            println!("{}", key.public_key().fingerprint());
+        }
+        "pack" => {
+            args.only(&["--manifest", "--dir", "--key", "--output"])?;
+            let manifest_path = args.path("--manifest")?;
+            let manifest = Manifest::parse(&fs::read_to_string(&manifest_path)?)?;
+            // Files are found next to the manifest unless told otherwise
+            let dir = match args.options.get("--dir") {
+                Some(dir) => PathBuf::from(dir),
+                None => manifest_path
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            };
+            let key = SecretKey::load(&args.path("--key")?)?;
+            let package = Package::from_dir(manifest, &dir)?;
+            fs::write(args.path("--output")?, package.sign(&key)?)?;
+            println!("{} {}", package.manifest.name, package.manifest.version);
+        }
+        "verify" => {
+            args.only(&["--keyring"])?;
+            let (package, signer) = open_package(&args.package()?, &args.path("--keyring")?)?;
+            let manifest = &package.manifest;
+            println!("Name: {}", manifest.name);
+            println!("Version: {}", manifest.version);
+            println!("ABI: {}", manifest.abi);
+            for dependency in &manifest.depends {
+                println!("Depends: {}", dependency);
+            }
+            for device in &manifest.devices {
+                println!("Device: {}", device);
+            }
+            println!("Files: {}", package.files.len());
+            println!("Signed by: {}", signer.fingerprint());
+        }
+        "install" => {
+            args.only(&["--keyring", "--root", "--abi", "--force"])?;
+            let (package, _) = open_package(&args.package()?, &args.path("--keyring")?)?;
+            let abi = args
+                .options
+                .get("--abi")
+                .map_or(CURRENT_ABI, String::as_str);
+            let force = args.flags.iter().any(|flag| flag == "--force");
+            let previous = install(&package, &args.path("--root")?, abi, force)?;
+            let manifest = &package.manifest;
+            match previous {
+                Some(previous) => println!(
+                    "Replaced {} {} with {}",
+                    manifest.name, previous, manifest.version
+                ),
+                None => println!("Installed {} {}", manifest.name, manifest.version),
+            }
+        }
+        "list" => {
+            args.only(&["--root"])?;
+            for manifest in installed(&args.path("--root")?)? {
+                println!("{}\t{}\t{}", manifest.name, manifest.version, manifest.abi);
+            }
+        }
+        other => return Err(usage(format!("unknown command {:?}", other))),
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let result = match args.next() {
+        Some(command) => Args::parse(args, &["--force"]).and_then(|args| run(&command, args)),
+        None => Err(usage("no command given".to_string())),
+    };
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        if error.kind() == io::ErrorKind::InvalidInput {
+            eprintln!("{}", USAGE);
+        }
+        process::exit(exit_code(&error));
+    }
+}
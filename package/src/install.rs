@@ -0,0 +1,379 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Installing into a driver root. Each package gets `<root>/<name>/`, holding
+// its files and the manifest it was installed from, which is what later
+// installs check dependencies against. A package is refused when it was
+// built for another ABI, when a dependency is missing or the wrong version,
+// when it would break the dependencies of something already installed, or
+// when it would not move the installed version forward; `force` overrides
+// the last two. Files are unpacked into a staging directory and swapped in
+// with two renames, the old version going to `.<name>.old` first. Until the
+// new one is in place that is still the installed version: it is listed and
+// checked against as such, and the next install moves it back before doing
+// anything else, so an interrupted install leaves the old version in place.
+
+use crate::archive::Package;
+use crate::manifest::{Manifest, Version};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "manifest";
+
+fn retired_dir(root: &Path, name: &str) -> PathBuf {
+    root.join(format!(".{}.old", name))
+}
+
+fn read_manifest(dir: &Path) -> io::Result<Option<Manifest>> {
+    match fs::read_to_string(dir.join(MANIFEST_FILE)) {
+        Ok(text) => Manifest::parse(&text).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// The manifest of `name` as installed under `root`, if it is, including a
+// version an interrupted install moved aside
+pub fn installed_manifest(root: &Path, name: &str) -> io::Result<Option<Manifest>> {
+    match read_manifest(&root.join(name))? {
+        Some(manifest) => Ok(Some(manifest)),
+        None => read_manifest(&retired_dir(root, name)),
+    }
+}
+
+// Every package installed under `root`, by name
+pub fn installed(root: &Path) -> io::Result<Vec<Manifest>> {
+    let mut names = BTreeSet::new();
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        // Packages are directories; any other file in the root is not one
+        if !entry.path().is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Staging directories are never installed; retired ones may be
+        match name.strip_prefix('.') {
+            Some(hidden) => {
+                if let Some(retired) = hidden.strip_suffix(".old") {
+                    names.insert(retired.to_string());
+                }
+            }
+            None => {
+                names.insert(name);
+            }
+        }
+    }
+    let mut manifests = Vec::new();
+    for name in names {
+        if let Some(manifest) = installed_manifest(root, &name)? {
+            manifests.push(manifest);
+        }
+    }
+    Ok(manifests)
+}
+
+// Finishes a swap an earlier install did not: the new version has to be in
+// place for the retired one to be thrown away, otherwise it goes back
+fn restore_retired(root: &Path, name: &str) -> io::Result<()> {
+    let target = root.join(name);
+    let retired = retired_dir(root, name);
+    if !retired.exists() {
+        return Ok(());
+    }
+    if read_manifest(&target)?.is_some() {
+        return fs::remove_dir_all(&retired);
+    }
+    if target.exists() {
+        fs::remove_dir_all(&target)?;
+    }
+    fs::rename(&retired, &target)
+}
+
+fn unsupported(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+fn check_installable(
+    manifest: &Manifest,
+    root: &Path,
+    abi: &str,
+    force: bool,
+) -> io::Result<Option<Version>> {
+    if manifest.abi != abi {
+        return Err(unsupported(format!(
+            "{} is built for ABI {}, not {}",
+            manifest.name, manifest.abi, abi
+        )));
+    }
+
+    let others = installed(root)?;
+    for dependency in &manifest.depends {
+        let found = others.iter().find(|other| other.name == dependency.name);
+        if !found.is_some_and(|other| dependency.req.matches(&other.version)) {
+            return Err(unsupported(format!(
+                "{} requires {}, but {}",
+                manifest.name,
+                dependency,
+                found.map_or("it is not installed".to_string(), |other| {
+                    format!("{} is installed", other.version)
+                })
+            )));
+        }
+    }
+
+    let previous = others
+        .iter()
+        .find(|other| other.name == manifest.name)
+        .map(|other| other.version);
+    if force {
+        return Ok(previous);
+    }
+    if let Some(previous) = previous.filter(|previous| *previous >= manifest.version) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} {} is already installed; use --force to install {}",
+                manifest.name, previous, manifest.version
+            ),
+        ));
+    }
+    for other in &others {
+        for dependency in &other.depends {
+            if dependency.name == manifest.name && !dependency.req.matches(&manifest.version) {
+                return Err(unsupported(format!(
+                    "{} {} would break {}, which requires {}",
+                    manifest.name, manifest.version, other.name, dependency
+                )));
+            }
+        }
+    }
+    Ok(previous)
+}
+
+// Installs a verified package, returning the version it replaced
+pub fn install(
+    package: &Package,
+    root: &Path,
+    abi: &str,
+    force: bool,
+) -> io::Result<Option<Version>> {
+    let manifest = &package.manifest;
+    if root.is_dir() {
+        restore_retired(root, &manifest.name)?;
+    }
+    let previous = check_installable(manifest, root, abi, force)?;
+
+    let staging = root.join(format!(".{}.new", manifest.name));
+    let retired = retired_dir(root, &manifest.name);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    for (path, data) in &package.files {
+        let target = staging.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, data)?;
+    }
+    // Written last: a directory without it is not an installed package
+    fs::write(staging.join(MANIFEST_FILE), manifest.render())?;
+
+    let target = root.join(&manifest.name);
+    if target.exists() {
+        fs::rename(&target, &retired)?;
+    }
+    if let Err(e) = fs::rename(&staging, &target) {
+        restore_retired(root, &manifest.name)?;
+        return Err(e);
+    }
+    if retired.exists() {
+        fs::remove_dir_all(&retired)?;
+    }
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::sample_package;
+    use crate::CURRENT_ABI;
+
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new(name: &str) -> Root {
+            let path = std::env::temp_dir().join(format!(
+                "bellande_driver_install_{}_{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Root(path)
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn with_dependency(name: &str, version: &str, dependency: &str) -> Package {
+        let mut package = sample_package(name, version);
+        package.manifest.depends.push(dependency.parse().unwrap());
+        package
+    }
+
+    fn kind(result: io::Result<Option<Version>>) -> io::ErrorKind {
+        result.unwrap_err().kind()
+    }
+
+    #[test]
+    fn test_install_and_upgrade() -> io::Result<()> {
+        let root = Root::new("upgrade");
+        let first = sample_package("block_driver", "0.1.0");
+        assert_eq!(install(&first, &root.0, CURRENT_ABI, false)?, None);
+        assert_eq!(
+            fs::read(root.0.join("block_driver/bin/driver"))?,
+            first.files["bin/driver"]
+        );
+        assert_eq!(installed(&root.0)?, std::slice::from_ref(&first.manifest));
+
+        // Other files in the root are not packages, even retired-looking ones
+        fs::write(root.0.join("NOTES"), "not a package")?;
+        fs::write(root.0.join(".notes.old"), "nor this")?;
+        assert_eq!(installed(&root.0)?, std::slice::from_ref(&first.manifest));
+
+        // Same version or older needs --force; newer replaces every file
+        let same = sample_package("block_driver", "0.1.0");
+        assert_eq!(
+            kind(install(&same, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::AlreadyExists
+        );
+        let mut newer = sample_package("block_driver", "0.1.1");
+        newer.files.remove("README");
+        newer.manifest.files.retain(|file| file != "README");
+        assert_eq!(
+            install(&newer, &root.0, CURRENT_ABI, false)?,
+            Some(first.manifest.version)
+        );
+        assert!(!root.0.join("block_driver/README").exists());
+        assert_eq!(
+            kind(install(&first, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            install(&first, &root.0, CURRENT_ABI, true)?,
+            Some(newer.manifest.version)
+        );
+        assert_eq!(
+            installed_manifest(&root.0, "block_driver")?,
+            Some(first.manifest)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_swap_keeps_old_version() -> io::Result<()> {
+        let root = Root::new("interrupted");
+        let first = sample_package("block_driver", "0.1.0");
+        install(&first, &root.0, CURRENT_ABI, false)?;
+
+        // As if killed between the two renames, after a new version staged
+        let retired = root.0.join(".block_driver.old");
+        fs::rename(root.0.join("block_driver"), &retired)?;
+        fs::create_dir_all(root.0.join(".block_driver.new"))?;
+        assert_eq!(installed(&root.0)?, std::slice::from_ref(&first.manifest));
+        assert_eq!(
+            installed_manifest(&root.0, "block_driver")?,
+            Some(first.manifest.clone())
+        );
+
+        // The next install puts it back, and refuses to repeat it
+        assert_eq!(
+            kind(install(&first, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(!retired.exists());
+        assert_eq!(
+            fs::read(root.0.join("block_driver/bin/driver"))?,
+            first.files["bin/driver"]
+        );
+        let newer = sample_package("block_driver", "0.1.1");
+        assert_eq!(
+            install(&newer, &root.0, CURRENT_ABI, false)?,
+            Some(first.manifest.version)
+        );
+        assert!(!retired.exists());
+        assert!(!root.0.join(".block_driver.new").exists());
+        assert_eq!(installed(&root.0)?, std::slice::from_ref(&newer.manifest));
+        Ok(())
+    }
+
+    #[test]
+    fn test_abi_and_dependencies() -> io::Result<()> {
+        let root = Root::new("dependencies");
+        let file_system = with_dependency("file_system", "0.2.0", "block_driver ^0.1.0");
+        assert_eq!(
+            kind(install(&file_system, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            kind(install(&file_system, &root.0, CURRENT_ABI, true)),
+            io::ErrorKind::Unsupported,
+            "--force does not skip dependencies"
+        );
+
+        let block_driver = sample_package("block_driver", "0.2.0");
+        install(&block_driver, &root.0, CURRENT_ABI, false)?;
+        assert_eq!(
+            kind(install(&file_system, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::Unsupported
+        );
+        install(
+            &sample_package("block_driver", "0.1.4"),
+            &root.0,
+            CURRENT_ABI,
+            true,
+        )?;
+        install(&file_system, &root.0, CURRENT_ABI, false)?;
+
+        // Upgrading a dependency past what its dependents accept is refused
+        assert_eq!(
+            kind(install(&block_driver, &root.0, CURRENT_ABI, false)),
+            io::ErrorKind::Unsupported
+        );
+        install(
+            &sample_package("block_driver", "0.1.5"),
+            &root.0,
+            CURRENT_ABI,
+            false,
+        )?;
+
+        let other_abi = sample_package("net_driver", "1.0.0");
+        assert_eq!(
+            kind(install(&other_abi, &root.0, "bellandeos-2", true)),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(installed(&root.0)?.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Bellande OS driver packages. A package is one file: a text manifest
+// (name, version, ABI, dependencies, supported devices and the files it
+// ships), the files themselves, and an Ed25519 signature over all of it.
+// `archive` builds and checks that file, `signing` loads keys and keyrings,
+// and `install` unpacks a verified package into a driver root after checking
+// its ABI and dependencies against what is already installed there.
+
+mod archive;
+mod install;
+mod manifest;
+mod signing;
+
+pub use archive::{Package, PACKAGE_MAGIC};
+pub use install::{install, installed, installed_manifest, MANIFEST_FILE};
+pub use manifest::{Dependency, Manifest, Version, VersionReq, MANIFEST_HEADER};
+pub use signing::{load_keyring, PublicKey, SecretKey};
+
+// The driver ABI this build of the tooling installs for by default
+pub const CURRENT_ABI: &str = "bellandeos-1";
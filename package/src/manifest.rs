@@ -0,0 +1,365 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The package manifest: a header line, then one `key = value` per line.
+// `name`, `version` and `abi` appear once; `depends`, `device` and `file`
+// repeat. A dependency is a package name with an optional requirement,
+// `= 1.2.0`, `>= 1.2.0` or `^1.2.0` (same major version, or same minor
+// while the major is 0). Lines starting with `#` are comments.
+//
+//     # bellande driver manifest v1
+//     name = file_system
+//     version = 0.2.1
+//     abi = bellandeos-1
+//     depends = block_driver ^0.1.0
+//     device = virtio-blk
+//     file = bin/file_system
+
+use crate::install::MANIFEST_FILE;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+pub const MANIFEST_HEADER: &str = "# bellande driver manifest v1";
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FromStr for Version {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Version> {
+        let error = || invalid(format!("Invalid version {:?}", text));
+        let number = |part: &str| {
+            // No signs, blanks or leading zeros, so every version has one spelling
+            if part.is_empty()
+                || !part.bytes().all(|byte| byte.is_ascii_digit())
+                || (part.len() > 1 && part.starts_with('0'))
+            {
+                return Err(error());
+            }
+            part.parse().map_err(|_| error())
+        };
+        match text.split('.').collect::<Vec<_>>().as_slice() {
+            [major, minor, patch] => Ok(Version {
+                major: number(major)?,
+                minor: number(minor)?,
+                patch: number(patch)?,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionReq {
+    Any,
+    Exact(Version),
+    AtLeast(Version),
+    Compatible(Version),
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: &Version) -> bool {
+        match self {
+            VersionReq::Any => true,
+            VersionReq::Exact(wanted) => version == wanted,
+            VersionReq::AtLeast(wanted) => version >= wanted,
+            VersionReq::Compatible(wanted) => {
+                version >= wanted
+                    && version.major == wanted.major
+                    && (wanted.major > 0 || version.minor == wanted.minor)
+            }
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionReq::Any => Ok(()),
+            VersionReq::Exact(version) => write!(f, "= {}", version),
+            VersionReq::AtLeast(version) => write!(f, ">= {}", version),
+            VersionReq::Compatible(version) => write!(f, "^{}", version),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub req: VersionReq,
+}
+
+impl FromStr for Dependency {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Dependency> {
+        let (name, req) = text
+            .split_once(char::is_whitespace)
+            .map_or((text, ""), |(name, req)| (name, req.trim()));
+        check_name(name)?;
+        let req = if req.is_empty() {
+            VersionReq::Any
+        } else if let Some(version) = req.strip_prefix(">=") {
+            VersionReq::AtLeast(version.trim().parse()?)
+        } else if let Some(version) = req.strip_prefix('=') {
+            VersionReq::Exact(version.trim().parse()?)
+        } else if let Some(version) = req.strip_prefix('^') {
+            VersionReq::Compatible(version.trim().parse()?)
+        } else {
+            return Err(invalid(format!("Invalid dependency {:?}", text)));
+        };
+        Ok(Dependency {
+            name: name.to_string(),
+            req,
+        })
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.req {
+            VersionReq::Any => write!(f, "{}", self.name),
+            _ => write!(f, "{} {}", self.name, self.req),
+        }
+    }
+}
+
+// Package names double as directory names in a driver root
+fn check_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.bytes().all(|byte| {
+            byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"_-".contains(&byte)
+        });
+    if !valid {
+        return Err(invalid(format!("Invalid package name {:?}", name)));
+    }
+    Ok(())
+}
+
+// A relative path of plain components, so nothing unpacks outside its
+// package, and none under `manifest`, which the installed manifest occupies
+pub fn check_file_path(path: &str) -> io::Result<()> {
+    let valid = !path.is_empty()
+        && path.split('/').all(|component| {
+            !component.is_empty()
+                && component != "."
+                && component != ".."
+                && !component.contains('\\')
+        });
+    if !valid || path.split('/').next() == Some(MANIFEST_FILE) {
+        return Err(invalid(format!("Invalid package file path {:?}", path)));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Version,
+    pub abi: String,
+    pub depends: Vec<Dependency>,
+    pub devices: Vec<String>,
+    pub files: Vec<String>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> io::Result<Manifest> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(invalid(format!(
+                "Manifest does not start with {:?}",
+                MANIFEST_HEADER
+            )));
+        }
+
+        let (mut name, mut version, mut abi) = (None, None, None);
+        let mut depends = Vec::new();
+        let mut devices = Vec::new();
+        let mut files = Vec::new();
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| invalid(format!("Invalid manifest line {:?}", line)))?;
+            let once = |slot: &mut Option<String>| {
+                if slot.replace(value.to_string()).is_some() {
+                    return Err(invalid(format!("Manifest repeats {:?}", key)));
+                }
+                Ok(())
+            };
+            match key {
+                "name" => once(&mut name)?,
+                "version" => once(&mut version)?,
+                "abi" => once(&mut abi)?,
+                "depends" => depends.push(value.parse()?),
+                "device" if !value.is_empty() => devices.push(value.to_string()),
+                "file" => {
+                    check_file_path(value)?;
+                    if files.iter().any(|file| file == value) {
+                        return Err(invalid(format!("Manifest lists {:?} twice", value)));
+                    }
+                    files.push(value.to_string());
+                }
+                _ => return Err(invalid(format!("Invalid manifest line {:?}", line))),
+            }
+        }
+
+        let missing = |key: &str| invalid(format!("Manifest has no {:?}", key));
+        let name = name.ok_or_else(|| missing("name"))?;
+        check_name(&name)?;
+        let abi = abi.ok_or_else(|| missing("abi"))?;
+        if abi.is_empty() || abi.contains(char::is_whitespace) {
+            return Err(invalid(format!("Invalid ABI {:?}", abi)));
+        }
+        Ok(Manifest {
+            name,
+            version: version.ok_or_else(|| missing("version"))?.parse()?,
+            abi,
+            depends,
+            devices,
+            files,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        let mut lines = vec![
+            MANIFEST_HEADER.to_string(),
+            format!("name = {}", self.name),
+            format!("version = {}", self.version),
+            format!("abi = {}", self.abi),
+        ];
+        lines.extend(self.depends.iter().map(|dep| format!("depends = {}", dep)));
+        lines.extend(
+            self.devices
+                .iter()
+                .map(|device| format!("device = {}", device)),
+        );
+        lines.extend(self.files.iter().map(|file| format!("file = {}", file)));
+        lines.join("\n") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "# bellande driver manifest v1
+name = file_system
+version = 0.2.1
+# comments and blank lines are ignored
+
+abi = bellandeos-1
+depends = block_driver ^0.1.0
+depends = journal >= 1.0.0
+depends = crc
+device = virtio-blk
+device = file
+file = bin/file_system
+file = share/README.md
+";
+
+    fn version(text: &str) -> Version {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_manifest_round_trip() -> io::Result<()> {
+        let manifest = Manifest::parse(SAMPLE)?;
+        assert_eq!(manifest.name, "file_system");
+        assert_eq!(manifest.version, version("0.2.1"));
+        assert_eq!(manifest.depends.len(), 3);
+        assert_eq!(
+            manifest.depends[0].req,
+            VersionReq::Compatible(version("0.1.0"))
+        );
+        assert_eq!(manifest.depends[2].req, VersionReq::Any);
+        assert_eq!(manifest.devices, ["virtio-blk", "file"]);
+        assert_eq!(manifest.files, ["bin/file_system", "share/README.md"]);
+        assert_eq!(Manifest::parse(&manifest.render())?, manifest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        let without = |key: &str| {
+            SAMPLE
+                .lines()
+                .filter(|line| !line.starts_with(key))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        for broken in [
+            SAMPLE.replacen(MANIFEST_HEADER, "# some other file", 1),
+            without("name"),
+            without("version"),
+            without("abi"),
+            SAMPLE.replace("name = file_system", "name = File System"),
+            SAMPLE.replace("version = 0.2.1", "version = 0.2"),
+            SAMPLE.replace("version = 0.2.1", "version = 0.2.1\nversion = 0.3.0"),
+            SAMPLE.replace("^0.1.0", "~0.1.0"),
+            SAMPLE.replace("bin/file_system", "../escape"),
+            SAMPLE.replace("bin/file_system", "/etc/passwd"),
+            SAMPLE.replace("bin/file_system", "manifest"),
+            SAMPLE.replace("bin/file_system", "manifest/file_system"),
+            SAMPLE.replace("share/README.md", "bin/file_system"),
+            SAMPLE.replace("device = file", "unknown = key"),
+            SAMPLE.replace("device = file", "no equals sign"),
+        ] {
+            assert!(Manifest::parse(&broken).is_err(), "{:?}", broken);
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        assert!(version("1.10.0") > version("1.9.3"));
+        assert_eq!(version("2.0.13").to_string(), "2.0.13");
+        for bad in ["1.2", "1.2.3.4", "01.2.3", "1.-2.3", "1.2.x", "+1.2.3", ""] {
+            assert!(bad.parse::<Version>().is_err(), "{:?}", bad);
+        }
+
+        let compatible = VersionReq::Compatible(version("1.2.0"));
+        assert!(compatible.matches(&version("1.2.0")));
+        assert!(compatible.matches(&version("1.9.9")));
+        assert!(!compatible.matches(&version("1.1.9")));
+        assert!(!compatible.matches(&version("2.0.0")));
+        let zero = VersionReq::Compatible(version("0.1.3"));
+        assert!(zero.matches(&version("0.1.7")));
+        assert!(!zero.matches(&version("0.2.0")));
+        assert!(VersionReq::AtLeast(version("1.0.0")).matches(&version("3.0.0")));
+        assert!(!VersionReq::Exact(version("1.0.0")).matches(&version("1.0.1")));
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Ed25519 keys for signing packages. Both halves are stored as one line of
+// hex: the 32-byte secret seed, or the 32-byte public key. A keyring is a
+// directory of `*.pub` files, and a package is only trusted when its signer
+// is one of them.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != 2 * N || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * index..2 * index + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn read_key<const N: usize>(path: &Path) -> io::Result<[u8; N]> {
+    from_hex(&fs::read_to_string(path)?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} does not hold a {}-byte hex key", path, N),
+        )
+    })
+}
+
+pub struct SecretKey(SigningKey);
+
+impl SecretKey {
+    // Seeds the key from the operating system's random source
+    pub fn generate() -> io::Result<SecretKey> {
+        let mut seed = [0u8; 32];
+        fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        Ok(SecretKey::from_seed(&seed))
+    }
+
+    pub fn from_seed(seed: &[u8; 32]) -> SecretKey {
+        SecretKey(SigningKey::from_bytes(seed))
+    }
+
+    pub fn load(path: &Path) -> io::Result<SecretKey> {
+        Ok(SecretKey::from_seed(&read_key(path)?))
+    }
+
+    // Written owner-only where the platform has permission bits
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        io::Write::write_all(
+            &mut options.open(path)?,
+            (to_hex(&self.0.to_bytes()) + "\n").as_bytes(),
+        )
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKey(VerifyingKey);
+
+impl PublicKey {
+    pub fn from_bytes(bytes: &[u8; PUBLIC_KEY_LEN]) -> io::Result<PublicKey> {
+        VerifyingKey::from_bytes(bytes)
+            .map(PublicKey)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid public key"))
+    }
+
+    pub fn load(path: &Path) -> io::Result<PublicKey> {
+        PublicKey::from_bytes(&read_key(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.fingerprint() + "\n")
+    }
+
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    // The hex form, as printed by `verify` and stored in `.pub` files
+    pub fn fingerprint(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> io::Result<()> {
+        self.0
+            .verify(message, &Signature::from_bytes(signature))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Package signature does not match its contents",
+                )
+            })
+    }
+}
+
+// Every `*.pub` file in `dir`; a missing directory is an empty keyring
+pub fn load_keyring(dir: &Path) -> io::Result<Vec<PublicKey>> {
+    let mut keys = Vec::new();
+    if !dir.is_dir() {
+        return Ok(keys);
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "pub") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    for path in paths {
+        keys.push(PublicKey::load(&path)?);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0xa5, 0xff];
+        assert_eq!(to_hex(&bytes), "007fa5ff");
+        assert_eq!(from_hex::<4>("007fa5ff\n"), Some(bytes));
+        assert_eq!(from_hex::<4>("007FA5FF"), Some(bytes));
+        assert_eq!(from_hex::<4>("007fa5"), None);
+        assert_eq!(from_hex::<4>("007fa5fg"), None);
+        assert_eq!(from_hex::<2>("ééé"), None);
+    }
+
+    #[test]
+    fn test_sign_and_verify() -> io::Result<()> {
+        let key = SecretKey::from_seed(&[7; 32]);
+        let public = key.public_key();
+        let signature = key.sign(b"driver");
+        public.verify(b"driver", &signature)?;
+        assert!(public.verify(b"drivers", &signature).is_err());
+        let other = SecretKey::from_seed(&[8; 32]).public_key();
+        assert!(other.verify(b"driver", &signature).is_err());
+        assert_eq!(PublicKey::from_bytes(&public.to_bytes())?, public);
+        Ok(())
+    }
+}